@@ -0,0 +1,448 @@
+//! # The B Source Formatter
+//!
+//! Rewrites B sources in a single consistent style:
+//!
+//! - Blocks are indented with 4 spaces per level. The bodies of `if`, `else` and `while` that start on
+//!   their own line without curly braces get an extra level until the end of the statement.
+//! - `case` and goto labels are outdented by one level, so they line up with the enclosing `switch`.
+//! - Binary operators are surrounded by single spaces, unary ones stick to their operand, commas and
+//!   semicolons are followed by a single space.
+//! - Lines that continue an unfinished statement get an extra level.
+//! - Every statement starts on its own line: a line break is inserted after `{`, `;` and `}` (except
+//!   the one followed by `else`) and before `}` and `case`, unless they are inside parentheses.
+//!   Empty blocks `{}` and the comments at the end of a line stay where they are.
+//!
+//! The formatter works on the token stream of the lexer rather than on the AST. The tokens themselves
+//! (including literals and comments) are copied from the source verbatim, only the whitespace between
+//! them is changed. The other line breaks of the source are kept as they are (several blank lines in a
+//! row collapse into one), so the formatted program compiles into exactly the same IR.
+#![no_main]
+#![no_std]
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(unused_macros)]
+
+#[macro_use]
+pub mod crust;
+#[macro_use]
+pub mod nob;
+pub mod flag;
+pub mod lexer;
+pub mod jim;
+
+use core::ffi::*;
+use core::mem::zeroed;
+use core::slice;
+use crust::libc::*;
+use nob::*;
+use flag::*;
+use lexer::{Lexer, Token};
+
+const INDENT: usize = 4;
+
+#[derive(Clone, Copy)]
+pub struct Fmt_Token {
+    pub token: Token,
+    pub start: *const c_char,
+    pub end: *const c_char,
+    pub newlines: usize, // Amount of line breaks between the previous token and this one
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Body_Kind {
+    If,
+    Else,
+    While,
+}
+
+// The body of `if`, `else` or `while` that is being formatted. Bodies that start on a new line
+// without curly braces are indented.
+#[derive(Clone, Copy)]
+pub struct Body {
+    pub kind: Body_Kind,
+    pub depth: usize,
+    pub indented: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct Formatter {
+    pub historical: bool,
+    pub tokens: Array<Fmt_Token>,
+    pub output: String_Builder,
+
+    pub depth: usize,              // Curly braces
+    pub parens: usize,             // Parenthesis and brackets
+    pub ternaries: usize,          // `?` that are still waiting for their `:`
+    pub bodies: Array<Body>,
+    pub header: Option<Body_Kind>, // `if` or `while` that is waiting for the `(` of its condition
+    pub header_kind: Body_Kind,
+    pub header_parens: usize,      // Depth of the parens the condition of the header started at
+    pub in_header: bool,
+    pub body_pending: Option<Body_Kind>,
+    pub boundary: bool,            // The next token starts a new statement
+    pub operand: bool,             // The last token ended an operand, so the next operator is binary
+    pub unary: bool,               // The last token was a prefix operator
+    pub binary: bool,              // The last token was a binary operator
+    pub line_break: bool,          // The next token (that is not a comment) must start a new line
+
+    // For checking whether the adjacent tokens would merge into a different one
+    pub scratch: String_Builder,
+    pub scratch_lexer: Lexer,
+}
+
+pub unsafe fn tokenize(f: *mut Formatter, input_path: *const c_char, input: String_Builder) -> Option<()> {
+    let eof = input.items.add(input.count);
+    lexer::diag_register_source(input_path, input.items, eof);
+    let mut l = lexer::new(input_path, input.items, eof, (*f).historical);
+    l.comments = true;
+    let mut prev_end: *const c_char = input.items;
+    loop {
+        lexer::get_token(&mut l)?;
+        if l.token == Token::EOF { break; }
+        let mut newlines = 0;
+        let mut p = prev_end;
+        while p < l.token_start {
+            if *p == '\n' as c_char { newlines += 1; }
+            p = p.add(1);
+        }
+        // The line break that ends `//` comment is not a part of it
+        let end = if l.token == Token::Comment { l.token_start.add(strlen(l.string)) } else { l.parse_point.current };
+        da_append(&mut (*f).tokens, Fmt_Token {
+            token: l.token,
+            start: l.token_start,
+            end,
+            newlines,
+        });
+        prev_end = end;
+    }
+    Some(())
+}
+
+pub unsafe fn token_len(t: Fmt_Token) -> usize {
+    t.end.offset_from(t.start) as usize
+}
+
+// Checks whether writing `a` and `b` without a space in between would make the lexer see
+// something else, like `- -x` turning into `--x`.
+pub unsafe fn tokens_merge(f: *mut Formatter, a: Fmt_Token, b: Fmt_Token) -> bool {
+    if a.token == Token::Comment { return false; }
+    if *a.end.sub(1) == '/' as c_char && (*b.start == '/' as c_char || *b.start == '*' as c_char) {
+        return true;
+    }
+
+    (*f).scratch.count = 0;
+    da_append_many(&mut (*f).scratch, slice::from_raw_parts(a.start, token_len(a)));
+    da_append_many(&mut (*f).scratch, slice::from_raw_parts(b.start, token_len(b)));
+    let storage = (*f).scratch_lexer.string_storage;
+    (*f).scratch_lexer = lexer::new(c!("<scratch>"), (*f).scratch.items, (*f).scratch.items.add((*f).scratch.count), (*f).historical);
+    (*f).scratch_lexer.string_storage = storage;
+    let ok = lexer::get_token(&mut (*f).scratch_lexer).is_some();
+    !ok || (*f).scratch_lexer.parse_point.current.offset_from((*f).scratch.items) as usize != token_len(a)
+}
+
+pub unsafe fn next_significant(f: *mut Formatter, mut i: usize) -> Option<Token> {
+    while i < (*f).tokens.count {
+        let t = *(*f).tokens.items.add(i);
+        if t.token != Token::Comment { return Some(t.token); }
+        i += 1;
+    }
+    None
+}
+
+pub unsafe fn is_binary(f: *mut Formatter, token: Token) -> bool {
+    match token {
+        Token::Mul | Token::And | Token::Minus | Token::Plus => (*f).operand,
+        Token::Colon => (*f).ternaries > 0,
+        Token::Div | Token::Mod | Token::Or | Token::Shl | Token::Shr |
+        Token::Less | Token::LessEq | Token::Greater | Token::GreaterEq | Token::EqEq | Token::NotEq |
+        Token::Eq | Token::ShlEq | Token::ShrEq | Token::ModEq | Token::OrEq | Token::AndEq |
+        Token::PlusEq | Token::MinusEq | Token::MulEq | Token::DivEq | Token::Question => true,
+        _ => false,
+    }
+}
+
+pub unsafe fn needs_space(f: *mut Formatter, prev: Fmt_Token, next: Fmt_Token, prev_binary: bool, next_binary: bool) -> bool {
+    match prev.token {
+        Token::Comment => return true,
+        Token::OParen | Token::OBracket => return false,
+        _ => {}
+    }
+    match next.token {
+        Token::Comment => return true,
+        Token::CParen | Token::CBracket | Token::Comma | Token::SemiColon => return false,
+        _ => {}
+    }
+    if prev_binary || next_binary { return true; }
+    if (*f).unary { return false; }
+    match next.token {
+        Token::Colon | Token::OBracket => false,
+        Token::PlusPlus | Token::MinusMinus => !(*f).operand,
+        Token::OParen => match prev.token {
+            Token::ID | Token::CParen | Token::CBracket | Token::Asm | Token::Variadic | Token::IfTarget => false,
+            _ => true,
+        },
+        Token::CCurly => prev.token != Token::OCurly,
+        _ => true,
+    }
+}
+
+// Ends the bodies of `if`, `else` and `while` the statement that has just ended belongs to. An `if`
+// followed by `else` is not over yet.
+pub unsafe fn end_statement(f: *mut Formatter, next: Option<Token>) {
+    while let Some(body) = da_last(&(*f).bodies) {
+        let body = *body;
+        if body.depth < (*f).depth { break; }
+        (*f).bodies.count -= 1;
+        if body.kind == Body_Kind::If && next == Some(Token::Else) { break; }
+    }
+}
+
+pub unsafe fn indentation(f: *mut Formatter) -> usize {
+    let mut level = (*f).depth;
+    for i in 0..(*f).bodies.count {
+        if (*(*f).bodies.items.add(i)).indented {
+            level += 1;
+        }
+    }
+    level
+}
+
+pub unsafe fn format_tokens(f: *mut Formatter) {
+    for i in 0..(*f).tokens.count {
+        let t = *(*f).tokens.items.add(i);
+        let next = next_significant(f, i + 1);
+
+        if t.token == Token::CCurly && (*f).depth > 0 {
+            (*f).depth -= 1;
+        }
+
+        let mut body_start = false;
+        if t.token != Token::Comment {
+            if let Some(kind) = (*f).body_pending {
+                (*f).body_pending = None;
+                body_start = true;
+                da_append(&mut (*f).bodies, Body {
+                    kind,
+                    depth: (*f).depth,
+                    indented: t.newlines > 0 && t.token != Token::OCurly,
+                });
+            }
+            if (*f).header.is_some() && t.token != Token::OParen {
+                (*f).header = None;
+            }
+        }
+
+        let binary = is_binary(f, t.token);
+        let line_break = i > 0 && match t.token {
+            Token::Comment => false,
+            Token::CCurly => (*(*f).tokens.items.add(i - 1)).token != Token::OCurly,
+            Token::Case => true,
+            _ => (*f).line_break,
+        };
+        if i == 0 || t.newlines > 0 || line_break {
+            if i > 0 {
+                da_append(&mut (*f).output, '\n' as c_char);
+                if t.newlines > 1 {
+                    da_append(&mut (*f).output, '\n' as c_char);
+                }
+            }
+
+            let mut level = indentation(f);
+            let continuation = match t.token {
+                Token::OCurly | Token::CCurly => (*f).parens > 0,
+                _ => (*f).parens > 0 || !((*f).boundary || body_start),
+            };
+            if continuation {
+                level += 1;
+            }
+            let label = t.token == Token::ID && next == Some(Token::Colon) && (*f).ternaries == 0 && (*f).boundary;
+            if (t.token == Token::Case || label) && level > 0 {
+                level -= 1;
+            }
+            for _ in 0..level*INDENT {
+                da_append(&mut (*f).output, ' ' as c_char);
+            }
+        } else {
+            let prev = *(*f).tokens.items.add(i - 1);
+            if needs_space(f, prev, t, (*f).binary, binary) || tokens_merge(f, prev, t) {
+                da_append(&mut (*f).output, ' ' as c_char);
+            }
+        }
+        da_append_many(&mut (*f).output, slice::from_raw_parts(t.start, token_len(t)));
+
+        if t.token == Token::Comment { continue; }
+
+        let was_operand = (*f).operand;
+        (*f).operand = false;
+        (*f).unary = false;
+        (*f).boundary = false;
+        (*f).binary = binary;
+        (*f).line_break = false;
+        match t.token {
+            Token::OCurly => {
+                (*f).depth += 1;
+                (*f).boundary = true;
+                (*f).line_break = (*f).parens == 0;
+            }
+            Token::CCurly => {
+                end_statement(f, next);
+                (*f).boundary = true;
+                (*f).line_break = (*f).parens == 0 && next != Some(Token::Else);
+            }
+            Token::SemiColon => {
+                if (*f).parens == 0 {
+                    (*f).ternaries = 0;
+                    end_statement(f, next);
+                    (*f).line_break = true;
+                }
+                (*f).boundary = true;
+            }
+            Token::OParen | Token::OBracket => {
+                if let Some(kind) = (*f).header {
+                    (*f).header = None;
+                    (*f).header_kind = kind;
+                    (*f).header_parens = (*f).parens;
+                    (*f).in_header = true;
+                }
+                (*f).parens += 1;
+            }
+            Token::CParen | Token::CBracket => {
+                if (*f).parens > 0 {
+                    (*f).parens -= 1;
+                }
+                if (*f).in_header && t.token == Token::CParen && (*f).parens == (*f).header_parens {
+                    (*f).in_header = false;
+                    (*f).body_pending = Some((*f).header_kind);
+                    (*f).boundary = true;
+                } else {
+                    (*f).operand = true;
+                }
+            }
+            Token::If | Token::While => {
+                (*f).header = Some(if t.token == Token::If { Body_Kind::If } else { Body_Kind::While });
+            }
+            Token::Else => {
+                (*f).body_pending = Some(Body_Kind::Else);
+                (*f).boundary = true;
+            }
+            Token::Question => {
+                (*f).ternaries += 1;
+            }
+            Token::Colon => {
+                if binary {
+                    (*f).ternaries -= 1;
+                } else {
+                    (*f).boundary = true;
+                }
+            }
+            Token::ID | Token::IntLit | Token::CharLit | Token::String => {
+                (*f).operand = true;
+            }
+            Token::PlusPlus | Token::MinusMinus => {
+                (*f).operand = was_operand;
+                (*f).unary = !was_operand;
+            }
+            Token::Not => {
+                (*f).unary = true;
+            }
+            Token::Mul | Token::And | Token::Minus | Token::Plus => {
+                (*f).unary = !binary;
+            }
+            _ => {}
+        }
+    }
+
+    if (*f).output.count > 0 {
+        da_append(&mut (*f).output, '\n' as c_char);
+    }
+}
+
+pub unsafe fn usage() {
+    fprintf(stderr(), c!("B source formatter\n"));
+    fprintf(stderr(), c!("Usage: %s [OPTIONS] <inputs...>\n"), flag_program_name());
+    fprintf(stderr(), c!("OPTIONS:\n"));
+    flag_print_options(stderr());
+}
+
+pub unsafe fn main(argc: i32, argv: *mut*mut c_char) -> Option<()> {
+    let check      = flag_bool(c!("check"), false, c!("Do not print or rewrite anything, just fail if some of the inputs are not formatted. Useful for CI"));
+    let write      = flag_bool(c!("w"), false, c!("Rewrite the inputs in place instead of printing the formatted sources to stdout"));
+    let historical = flag_bool(c!("hist"), false, c!("Format the inputs in the historical mode. See the -hist flag of the compiler"));
+    let help       = flag_bool(c!("help"), false, c!("Print this help message"));
+
+    if !flag_parse(argc, argv) {
+        usage();
+        flag_print_error(stderr());
+        return None;
+    }
+
+    if *help {
+        usage();
+        return Some(());
+    }
+
+    if *check && *write {
+        usage();
+        log(Log_Level::ERROR, c!("-%s and -%s can't be used together"), flag_name(check), flag_name(write));
+        return None;
+    }
+
+    let mut inputs: Array<*const c_char> = zeroed();
+    let mut rest_argc = flag_rest_argc();
+    let mut rest_argv = flag_rest_argv();
+    while rest_argc > 0 {
+        da_append(&mut inputs, shift!(rest_argv, rest_argc));
+    }
+
+    if inputs.count == 0 {
+        usage();
+        log(Log_Level::ERROR, c!("no inputs provided"));
+        return None;
+    }
+
+    let mut f: Formatter = zeroed();
+    f.historical = *historical;
+    let mut unformatted = 0;
+    for i in 0..inputs.count {
+        let input_path = *inputs.items.add(i);
+        let mut input: String_Builder = zeroed();
+        read_entire_file(input_path, &mut input)?;
+
+        f.tokens.count = 0;
+        f.output.count = 0;
+        f.bodies.count = 0;
+        f.depth = 0;
+        f.parens = 0;
+        f.ternaries = 0;
+        f.header = None;
+        f.in_header = false;
+        f.body_pending = None;
+        f.boundary = true;
+        f.operand = false;
+        f.unary = false;
+        f.binary = false;
+        f.line_break = false;
+        tokenize(&mut f, input_path, input)?;
+        format_tokens(&mut f);
+
+        let changed = f.output.count != input.count || strncmp(f.output.items, input.items, input.count) != 0;
+        if *check {
+            if changed {
+                log(Log_Level::ERROR, c!("%s is not formatted"), input_path);
+                unformatted += 1;
+            }
+        } else if *write {
+            if changed {
+                write_entire_file(input_path, f.output.items as *const c_void, f.output.count)?;
+            }
+        } else {
+            fwrite(f.output.items as *const c_void, 1, f.output.count, stdout());
+        }
+    }
+
+    if unformatted > 0 {
+        log(Log_Level::ERROR, c!("%zu of %zu files are not formatted. Run `%s -%s` on them"), unformatted, inputs.count, flag_program_name(), flag_name(write));
+        return None;
+    }
+    Some(())
+}
@@ -0,0 +1,1095 @@
+//! # The B Language Server
+//!
+//! Speaks the Language Server Protocol (https://microsoft.github.io/language-server-protocol/) over
+//! stdin/stdout. Point your editor's LSP client at the `blsp` executable.
+//!
+//! The open documents are analysed by the very same front end the compiler uses (see compiler.rs):
+//! libb of the target selected by the -t flag followed by all the open documents are compiled into a
+//! single Compiler, the same way `b` compiles all of its inputs together. Supported features:
+//!
+//! - Diagnostics (errors and warnings) on open and on save
+//! - Go to definition
+//! - Hover that tells whether the name is an auto variable, an extrn, a function or a global
+//! - Document symbols
+//! - Completion of the names visible at the cursor and the keywords
+//!
+//! To find out which names are visible at some position in the document, the document is compiled
+//! again with the input cut off at that position. The compilation bails out at the unexpected end of
+//! the input leaving Compiler::vars exactly in the state it was at that position.
+//!
+//! The positions are reported in bytes rather than in UTF-16 code units the protocol expects by
+//! default, so the columns may be off on the lines with non-ASCII characters.
+#![no_main]
+#![no_std]
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(unused_macros)]
+
+#[macro_use]
+pub mod crust;
+#[macro_use]
+pub mod nob;
+pub mod flag;
+pub mod arena;
+pub mod glob;
+pub mod lexer;
+pub mod jim;
+pub mod jimp;
+pub mod ir;
+pub mod compiler;
+pub mod targets;
+pub mod codegen;
+pub mod dwarf;
+pub mod shlex;
+pub mod proc;
+pub mod params;
+
+use core::ffi::*;
+use core::mem::zeroed;
+use core::ptr;
+use crust::libc::*;
+use nob::*;
+use flag::*;
+use jim::*;
+use jimp::*;
+use targets::*;
+use arena::Arena;
+use lexer::{Loc, KEYWORDS};
+use compiler::*;
+
+#[derive(Clone, Copy)]
+pub struct Document {
+    pub uri: *mut c_char,
+    pub path: *mut c_char,
+    pub text: String_Builder,
+}
+
+#[derive(Clone, Copy)]
+pub struct Server {
+    /// The documents currently open in the editor
+    pub documents: Array<Document>,
+    /// The sources of libb that are compiled before the documents
+    pub libb: Array<Document>,
+    /// Where `__include__` looks for the files (see Compiler::include_path)
+    pub include_path: Array<*const c_char>,
+    /// The target `__if_target__` is matched against. NULL with -nostdlib, then only `__else__` branches are taken.
+    pub target: *const c_char,
+    pub historical: bool,
+    pub warnings: [WarningState; WARNING_COUNT],
+    pub shutdown: bool,
+    pub jim: Jim,
+}
+
+#[derive(Clone, Copy)]
+pub enum RequestId {
+    Number(c_longlong),
+    String(*const c_char),
+}
+
+#[derive(Clone, Copy)]
+pub struct Message {
+    pub method: *const c_char,
+    /// Notifications don't have an id
+    pub id: Option<RequestId>,
+    /// Where the params of the message start within the message so they can be parsed after we figured out
+    /// the method. The members of the message may come in any order.
+    pub params: *const c_char,
+}
+
+/// All the params of the requests we support. Which of them are actually provided depends on the method.
+#[derive(Clone, Copy)]
+pub struct Params {
+    pub uri: *const c_char,
+    /// Allocated with strdup(). The documents may be bigger than the temporary storage.
+    pub text: *mut c_char,
+    pub line: usize,
+    pub character: usize,
+}
+
+pub const LSP_ERROR_METHOD_NOT_FOUND: c_longlong = -32601;
+
+pub const LSP_SEVERITY_ERROR:       c_longlong = 1;
+pub const LSP_SEVERITY_WARNING:     c_longlong = 2;
+pub const LSP_SEVERITY_INFORMATION: c_longlong = 3;
+
+pub const LSP_SYMBOL_KIND_FUNCTION: c_longlong = 12;
+pub const LSP_SYMBOL_KIND_VARIABLE: c_longlong = 13;
+
+pub const LSP_COMPLETION_KIND_FUNCTION: c_longlong = 3;
+pub const LSP_COMPLETION_KIND_VARIABLE: c_longlong = 6;
+pub const LSP_COMPLETION_KIND_KEYWORD:  c_longlong = 14;
+
+/// Reads the next message from stdin. Returns None at the end of the input.
+pub unsafe fn read_message(sb: *mut String_Builder) -> Option<()> {
+    let mut content_length: Option<usize> = None;
+    let mut header: String_Builder = zeroed();
+    loop {
+        header.count = 0;
+        loop {
+            let x = getchar();
+            if x < 0 { return None; }
+            if x == '\n' as c_int { break; }
+            if x != '\r' as c_int { da_append(&mut header, x as c_char); }
+        }
+        if header.count == 0 { break; }
+        da_append(&mut header, 0);
+        let prefix = c!("Content-Length:");
+        if strncmp(header.items, prefix, strlen(prefix)) == 0 {
+            content_length = Some(strtoull(header.items.add(strlen(prefix)), ptr::null_mut(), 10) as usize);
+        }
+    }
+    free(header.items);
+
+    let Some(content_length) = content_length else {
+        log(Log_Level::ERROR, c!("Message without Content-Length header"));
+        return None;
+    };
+
+    (*sb).count = 0;
+    for _ in 0..content_length {
+        let x = getchar();
+        if x < 0 { return None; }
+        da_append(sb, x as c_char);
+    }
+    // jimp expects the input to be NULL-terminated
+    da_append(sb, 0);
+    (*sb).count -= 1;
+    Some(())
+}
+
+pub unsafe fn send_message(jim: *mut Jim) {
+    printf(c!("Content-Length: %zu\r\n\r\n"), (*jim).sink_count);
+    fwrite((*jim).sink as *const c_void, 1, (*jim).sink_count, stdout());
+    fflush(stdout());
+}
+
+/// Begins the response to the request. Follow it up with the value of the result and response_end().
+pub unsafe fn response_begin(jim: *mut Jim, id: RequestId) {
+    jim_begin(jim);
+    jim_object_begin(jim);
+    jim_member_key(jim, c!("jsonrpc"));
+    jim_string(jim, c!("2.0"));
+    jim_member_key(jim, c!("id"));
+    match id {
+        RequestId::Number(id) => jim_integer(jim, id),
+        RequestId::String(id) => jim_string(jim, id),
+    }
+    jim_member_key(jim, c!("result"));
+}
+
+pub unsafe fn response_end(jim: *mut Jim) {
+    jim_object_end(jim);
+    send_message(jim);
+}
+
+/// Begins the notification. Follow it up with the value of the params and notification_end().
+pub unsafe fn notification_begin(jim: *mut Jim, method: *const c_char) {
+    jim_begin(jim);
+    jim_object_begin(jim);
+    jim_member_key(jim, c!("jsonrpc"));
+    jim_string(jim, c!("2.0"));
+    jim_member_key(jim, c!("method"));
+    jim_string(jim, method);
+    jim_member_key(jim, c!("params"));
+}
+
+pub unsafe fn notification_end(jim: *mut Jim) {
+    jim_object_end(jim);
+    send_message(jim);
+}
+
+pub unsafe fn send_error(jim: *mut Jim, id: RequestId, code: c_longlong, message: *const c_char) {
+    jim_begin(jim);
+    jim_object_begin(jim);
+    jim_member_key(jim, c!("jsonrpc"));
+    jim_string(jim, c!("2.0"));
+    jim_member_key(jim, c!("id"));
+    match id {
+        RequestId::Number(id) => jim_integer(jim, id),
+        RequestId::String(id) => jim_string(jim, id),
+    }
+    jim_member_key(jim, c!("error"));
+    jim_object_begin(jim);
+        jim_member_key(jim, c!("code"));
+        jim_integer(jim, code);
+        jim_member_key(jim, c!("message"));
+        jim_string(jim, message);
+    jim_object_end(jim);
+    jim_object_end(jim);
+    send_message(jim);
+}
+
+pub unsafe fn jimp_skip_value(jimp: *mut Jimp) -> Option<()> {
+    if jimp_is_object_ahead(jimp) {
+        jimp_object_begin(jimp)?;
+        while jimp_object_member(jimp) {
+            jimp_skip_value(jimp)?;
+        }
+        jimp_object_end(jimp)
+    } else if jimp_is_array_ahead(jimp) {
+        jimp_array_begin(jimp)?;
+        while jimp_array_item(jimp) {
+            jimp_skip_value(jimp)?;
+        }
+        jimp_array_end(jimp)
+    } else if jimp_is_string_ahead(jimp) {
+        jimp_string(jimp)
+    } else if jimp_is_number_ahead(jimp) {
+        jimp_number(jimp)
+    } else if jimp_is_bool_ahead(jimp) {
+        jimp_boolean(jimp)
+    } else {
+        jimp_null(jimp)
+    }
+}
+
+pub unsafe fn parse_message(jimp: *mut Jimp) -> Option<Message> {
+    let mut message = Message {
+        method: ptr::null(),
+        id: None,
+        params: ptr::null(),
+    };
+    jimp_object_begin(jimp)?;
+    while jimp_object_member(jimp) {
+        if strcmp((*jimp).string, c!("method")) == 0 {
+            jimp_string(jimp)?;
+            message.method = temp_strdup((*jimp).string);
+        } else if strcmp((*jimp).string, c!("id")) == 0 {
+            if jimp_is_number_ahead(jimp) {
+                jimp_number(jimp)?;
+                message.id = Some(RequestId::Number((*jimp).number as c_longlong));
+            } else {
+                jimp_string(jimp)?;
+                message.id = Some(RequestId::String(temp_strdup((*jimp).string)));
+            }
+        } else if strcmp((*jimp).string, c!("params")) == 0 {
+            message.params = (*jimp).point;
+            jimp_skip_value(jimp)?;
+        } else {
+            jimp_skip_value(jimp)?;
+        }
+    }
+    jimp_object_end(jimp)?;
+
+    if message.method.is_null() {
+        // Responses to the requests from the server. We never send any.
+        message.method = c!("");
+    }
+    Some(message)
+}
+
+pub unsafe fn parse_position(jimp: *mut Jimp, params: *mut Params) -> Option<()> {
+    jimp_object_begin(jimp)?;
+    while jimp_object_member(jimp) {
+        if strcmp((*jimp).string, c!("line")) == 0 {
+            jimp_number(jimp)?;
+            (*params).line = (*jimp).number as usize;
+        } else if strcmp((*jimp).string, c!("character")) == 0 {
+            jimp_number(jimp)?;
+            (*params).character = (*jimp).number as usize;
+        } else {
+            jimp_skip_value(jimp)?;
+        }
+    }
+    jimp_object_end(jimp)
+}
+
+/// Takes the text of the document if there is one. Replaces the text that was taken before.
+pub unsafe fn parse_text(jimp: *mut Jimp, params: *mut Params) -> Option<()> {
+    jimp_string(jimp)?;
+    free((*params).text);
+    (*params).text = strdup((*jimp).string);
+    Some(())
+}
+
+pub unsafe fn parse_params(jimp: *mut Jimp, params: *mut Params) -> Option<()> {
+    jimp_object_begin(jimp)?;
+    while jimp_object_member(jimp) {
+        if strcmp((*jimp).string, c!("textDocument")) == 0 {
+            jimp_object_begin(jimp)?;
+            while jimp_object_member(jimp) {
+                if strcmp((*jimp).string, c!("uri")) == 0 {
+                    jimp_string(jimp)?;
+                    (*params).uri = temp_strdup((*jimp).string);
+                } else if strcmp((*jimp).string, c!("text")) == 0 {
+                    parse_text(jimp, params)?;
+                } else {
+                    jimp_skip_value(jimp)?;
+                }
+            }
+            jimp_object_end(jimp)?;
+        } else if strcmp((*jimp).string, c!("position")) == 0 {
+            parse_position(jimp, params)?;
+        } else if strcmp((*jimp).string, c!("text")) == 0 {
+            parse_text(jimp, params)?;
+        } else if strcmp((*jimp).string, c!("contentChanges")) == 0 {
+            // We only ask for the full document sync, so the last change contains the whole document
+            jimp_array_begin(jimp)?;
+            while jimp_array_item(jimp) {
+                jimp_object_begin(jimp)?;
+                while jimp_object_member(jimp) {
+                    if strcmp((*jimp).string, c!("text")) == 0 {
+                        parse_text(jimp, params)?;
+                    } else {
+                        jimp_skip_value(jimp)?;
+                    }
+                }
+                jimp_object_end(jimp)?;
+            }
+            jimp_array_end(jimp)?;
+        } else {
+            jimp_skip_value(jimp)?;
+        }
+    }
+    jimp_object_end(jimp)
+}
+
+pub unsafe fn hex_digit_value(x: c_char) -> Option<u8> {
+    let x = x as u8;
+    if x.is_ascii_digit() {
+        Some(x - b'0')
+    } else if x.is_ascii_hexdigit() {
+        Some(x.to_ascii_lowercase() - b'a' + 10)
+    } else {
+        None
+    }
+}
+
+/// Converts `file://` URI into a path. Allocated with malloc().
+pub unsafe fn uri_to_path(uri: *const c_char) -> *mut c_char {
+    let scheme = c!("file://");
+    let mut p = uri;
+    if strncmp(p, scheme, strlen(scheme)) == 0 {
+        p = p.add(strlen(scheme));
+    }
+    let mut path: String_Builder = zeroed();
+    while *p != 0 {
+        if *p == '%' as c_char {
+            if let (Some(hi), Some(lo)) = (hex_digit_value(*p.add(1)), hex_digit_value(*p.add(2))) {
+                da_append(&mut path, (hi*16 + lo) as c_char);
+                p = p.add(3);
+                continue;
+            }
+        }
+        da_append(&mut path, *p);
+        p = p.add(1);
+    }
+    da_append(&mut path, 0);
+    path.items
+}
+
+/// Converts the path into `file://` URI. Allocated in the temporary storage.
+// TODO: Windows paths like C:\foo\bar.b are not converted into proper URIs
+pub unsafe fn temp_path_to_uri(path: *const c_char) -> *const c_char {
+    let mut uri: String_Builder = zeroed();
+    sb_appendf(&mut uri, c!("file://"));
+    if *path != '/' as c_char {
+        let mut cwd = [0 as c_char; 4096];
+        if !getcwd(cwd.as_mut_ptr(), cwd.len()).is_null() {
+            sb_appendf(&mut uri, c!("%s/"), cwd.as_ptr());
+        }
+    }
+    let mut p = path;
+    while *p != 0 {
+        let x = *p as u8;
+        if x.is_ascii_alphanumeric() || x == b'/' || x == b'-' || x == b'.' || x == b'_' || x == b'~' {
+            da_append(&mut uri, *p);
+        } else {
+            sb_appendf(&mut uri, c!("%%%02X"), x as c_uint);
+        }
+        p = p.add(1);
+    }
+    da_append(&mut uri, 0);
+    let result = temp_strdup(uri.items);
+    free(uri.items);
+    result
+}
+
+pub unsafe fn find_document(s: *const Server, uri: *const c_char) -> Option<usize> {
+    if uri.is_null() { return None; }
+    for i in 0..(*s).documents.count {
+        if strcmp((*(*s).documents.items.add(i)).uri, uri) == 0 {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// URI of the source that the location points to
+pub unsafe fn temp_loc_uri(s: *const Server, loc: Loc) -> *const c_char {
+    for i in 0..(*s).documents.count {
+        let doc = *(*s).documents.items.add(i);
+        if strcmp(doc.path, loc.input_path) == 0 {
+            return doc.uri;
+        }
+    }
+    temp_path_to_uri(loc.input_path)
+}
+
+/// Byte offset of the LSP position within the text. Clamped to the end of the line.
+pub unsafe fn offset_of_position(text: String_Builder, line: usize, character: usize) -> usize {
+    let mut i = 0;
+    let mut current_line = 0;
+    while i < text.count && current_line < line {
+        if *text.items.add(i) == '\n' as c_char {
+            current_line += 1;
+        }
+        i += 1;
+    }
+    let mut current_character = 0;
+    while i < text.count && current_character < character && *text.items.add(i) != '\n' as c_char {
+        i += 1;
+        current_character += 1;
+    }
+    i
+}
+
+/// Bounds of the identifier under the offset
+pub unsafe fn identifier_at(text: String_Builder, offset: usize) -> Option<(usize, usize)> {
+    let mut start = offset;
+    while start > 0 && lexer::is_identifier(*text.items.add(start - 1)) {
+        start -= 1;
+    }
+    let mut end = offset;
+    while end < text.count && lexer::is_identifier(*text.items.add(end)) {
+        end += 1;
+    }
+    if start == end || !lexer::is_identifier_start(*text.items.add(start)) {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// Compiles the source and recovers the Compiler if the compilation bails out, so the rest of the sources can still
+/// be analysed.
+pub unsafe fn compile_source(s: *const Server, c: *mut Compiler, doc: Document) {
+    // Already compiled as an `__include__` of another source
+    if file_was_compiled(c, normalize_path(doc.path, &mut (*c).arena)) {
+        return;
+    }
+    lexer::diag_register_source(doc.path, doc.text.items, doc.text.items.add(doc.text.count));
+    let mut l = lexer::new(doc.path, doc.text.items, doc.text.items.add(doc.text.count), (*s).historical);
+    if compile_program(&mut l, c).is_none() {
+        (*c).vars.count = 1; // Leave only the global scope
+        (*c).include_stack.count = 0;
+        reset_func_state(c);
+    }
+}
+
+/// Compiles libb and all the open documents into `c`. If `cut` is provided, the document with that index is compiled
+/// last and only up to the offset, so `(*c).vars` contains the scopes visible at that offset.
+// TODO: the arrays allocated by the Compiler are never freed, only its arena is reused
+pub unsafe fn analyse(s: *const Server, c: *mut Compiler, cut: Option<(usize, usize)>) {
+    let mut arena = (*c).arena;
+    arena::reset(&mut arena);
+    *c = zeroed();
+    (*c).arena = arena;
+    (*c).historical = (*s).historical;
+    (*c).warnings = (*s).warnings;
+    (*c).include_path = (*s).include_path;
+    (*c).target = (*s).target;
+    lexer::diag_reset();
+
+    scope_push(&mut (*c).vars); // begin global scope
+    (*c).compiling_libb = true;
+    for i in 0..(*s).libb.count {
+        compile_source(s, c, *(*s).libb.items.add(i));
+    }
+    (*c).compiling_libb = false;
+    for i in 0..(*s).documents.count {
+        if let Some((index, _)) = cut {
+            if index == i { continue; }
+        }
+        compile_source(s, c, *(*s).documents.items.add(i));
+    }
+
+    if let Some((index, offset)) = cut {
+        let doc = *(*s).documents.items.add(index);
+        let mut l = lexer::new(doc.path, doc.text.items, doc.text.items.add(offset), (*s).historical);
+        let _ = compile_program(&mut l, c);
+    } else {
+        let _ = resolve_used_funcs(c);
+    }
+}
+
+pub unsafe fn find_func(program: *const ir::Program, name: *const c_char) -> Option<*const ir::Func> {
+    for i in 0..(*program).funcs.count {
+        let func = (*program).funcs.items.add(i);
+        if strcmp((*func).name, name) == 0 {
+            return Some(func);
+        }
+    }
+    None
+}
+
+pub unsafe fn find_asm_func(program: *const ir::Program, name: *const c_char) -> Option<*const ir::AsmFunc> {
+    for i in 0..(*program).asm_funcs.count {
+        let func = (*program).asm_funcs.items.add(i);
+        if strcmp((*func).name, name) == 0 {
+            return Some(func);
+        }
+    }
+    None
+}
+
+pub unsafe fn find_global(program: *const ir::Program, name: *const c_char) -> Option<*const ir::Global> {
+    for i in 0..(*program).globals.count {
+        let global = (*program).globals.items.add(i);
+        if strcmp((*global).name, name) == 0 {
+            return Some(global);
+        }
+    }
+    None
+}
+
+/// What kind of thing the name refers to. `full` must be analysed without cutting anything.
+pub unsafe fn var_kind(full: *const Compiler, var: Var) -> *const c_char {
+    if var.extrn { return c!("extrn"); }
+    match var.storage {
+        Storage::Auto {..} => c!("auto"),
+        Storage::External {name} => {
+            if find_func(&(*full).program, name).is_some() || find_asm_func(&(*full).program, name).is_some() {
+                c!("function")
+            } else {
+                c!("global")
+            }
+        }
+    }
+}
+
+pub unsafe fn completion_kind(full: *const Compiler, var: Var) -> c_longlong {
+    if let Storage::External {name} = var.storage {
+        if find_func(&(*full).program, name).is_some() || find_asm_func(&(*full).program, name).is_some() {
+            return LSP_COMPLETION_KIND_FUNCTION;
+        }
+    }
+    LSP_COMPLETION_KIND_VARIABLE
+}
+
+/// Finds the declaration of the identifier between `start` and `end` in the document as it's seen at that position.
+/// Names of the functions and globals may be declared anywhere, so they are looked up in the global scope of the
+/// `full` analysis. `full` and `cut` are filled up with the analyses of the open documents.
+pub unsafe fn resolve_name(s: *const Server, index: usize, start: usize, end: usize, full: *mut Compiler, cut: *mut Compiler) -> Option<Var> {
+    let doc = *(*s).documents.items.add(index);
+    let name = temp_sv_to_cstr(sv_from_parts(doc.text.items.add(start), end - start));
+
+    analyse(s, full, None);
+    analyse(s, cut, Some((index, end)));
+
+    let var = find_var_deep(&(*cut).vars, name);
+    if !var.is_null() {
+        if let Storage::Auto {..} = (*var).storage {
+            return Some(*var);
+        }
+        if (*var).extrn {
+            return Some(*var);
+        }
+    }
+    if (*full).vars.count > 0 {
+        let global = find_var_near((*full).vars.items, name);
+        if !global.is_null() {
+            return Some(*global);
+        }
+    }
+    if var.is_null() { None } else { Some(*var) }
+}
+
+pub unsafe fn write_position(jim: *mut Jim, line: c_int, character: c_int) {
+    jim_object_begin(jim);
+        jim_member_key(jim, c!("line"));
+        jim_integer(jim, line as c_longlong);
+        jim_member_key(jim, c!("character"));
+        jim_integer(jim, character as c_longlong);
+    jim_object_end(jim);
+}
+
+pub unsafe fn write_range(jim: *mut Jim, loc: Loc, length: usize) {
+    jim_object_begin(jim);
+        jim_member_key(jim, c!("start"));
+        write_position(jim, loc.line_number - 1, loc.line_offset - 1);
+        jim_member_key(jim, c!("end"));
+        write_position(jim, loc.line_number - 1, loc.line_offset - 1 + length as c_int);
+    jim_object_end(jim);
+}
+
+pub unsafe fn write_location(s: *const Server, jim: *mut Jim, loc: Loc, length: usize) {
+    jim_object_begin(jim);
+        jim_member_key(jim, c!("uri"));
+        jim_string(jim, temp_loc_uri(s, loc));
+        jim_member_key(jim, c!("range"));
+        write_range(jim, loc, length);
+    jim_object_end(jim);
+}
+
+pub unsafe fn publish_diagnostics(s: *mut Server, index: usize, diagnostics: bool) {
+    let doc = *(*s).documents.items.add(index);
+    let jim = &mut (*s).jim;
+    notification_begin(jim, c!("textDocument/publishDiagnostics"));
+    jim_object_begin(jim);
+        jim_member_key(jim, c!("uri"));
+        jim_string(jim, doc.uri);
+        jim_member_key(jim, c!("diagnostics"));
+        jim_array_begin(jim);
+        for i in 0..if diagnostics { lexer::diagnostics.count } else { 0 } {
+            let d = *lexer::diagnostics.items.add(i);
+            if strcmp(d.loc.input_path, doc.path) != 0 { continue; }
+            jim_object_begin(jim);
+                jim_member_key(jim, c!("range"));
+                write_range(jim, d.loc, d.length);
+                jim_member_key(jim, c!("severity"));
+                jim_integer(jim, if strcmp(d.severity, c!("error")) == 0 {
+                    LSP_SEVERITY_ERROR
+                } else if strcmp(d.severity, c!("warning")) == 0 {
+                    LSP_SEVERITY_WARNING
+                } else {
+                    LSP_SEVERITY_INFORMATION
+                });
+                jim_member_key(jim, c!("source"));
+                jim_string(jim, c!("b"));
+                jim_member_key(jim, c!("message"));
+                jim_string(jim, d.message);
+                jim_member_key(jim, c!("relatedInformation"));
+                jim_array_begin(jim);
+                for j in 0..d.notes.count {
+                    let note = *d.notes.items.add(j);
+                    jim_object_begin(jim);
+                        jim_member_key(jim, c!("location"));
+                        write_location(s, jim, note.loc, note.length);
+                        jim_member_key(jim, c!("message"));
+                        jim_string(jim, note.message);
+                    jim_object_end(jim);
+                }
+                jim_array_end(jim);
+            jim_object_end(jim);
+        }
+        jim_array_end(jim);
+    jim_object_end(jim);
+    notification_end(jim);
+}
+
+/// Since all the documents are compiled together, changes in one of them may affect the diagnostics of the others
+pub unsafe fn publish_all_diagnostics(s: *mut Server) {
+    let mut c: Compiler = zeroed();
+    analyse(s, &mut c, None);
+    for i in 0..(*s).documents.count {
+        publish_diagnostics(s, i, true);
+    }
+    arena::free(&mut c.arena);
+}
+
+pub unsafe fn handle_initialize(s: *mut Server, id: RequestId) {
+    let jim = &mut (*s).jim;
+    response_begin(jim, id);
+    jim_object_begin(jim);
+        jim_member_key(jim, c!("capabilities"));
+        jim_object_begin(jim);
+            jim_member_key(jim, c!("textDocumentSync"));
+            jim_object_begin(jim);
+                jim_member_key(jim, c!("openClose"));
+                jim_bool(jim, 1);
+                jim_member_key(jim, c!("change"));
+                jim_integer(jim, 1); // Full
+                jim_member_key(jim, c!("save"));
+                jim_object_begin(jim);
+                    jim_member_key(jim, c!("includeText"));
+                    jim_bool(jim, 0);
+                jim_object_end(jim);
+            jim_object_end(jim);
+            jim_member_key(jim, c!("definitionProvider"));
+            jim_bool(jim, 1);
+            jim_member_key(jim, c!("hoverProvider"));
+            jim_bool(jim, 1);
+            jim_member_key(jim, c!("documentSymbolProvider"));
+            jim_bool(jim, 1);
+            jim_member_key(jim, c!("completionProvider"));
+            jim_object_begin(jim);
+            jim_object_end(jim);
+        jim_object_end(jim);
+        jim_member_key(jim, c!("serverInfo"));
+        jim_object_begin(jim);
+            jim_member_key(jim, c!("name"));
+            jim_string(jim, c!("blsp"));
+        jim_object_end(jim);
+    jim_object_end(jim);
+    response_end(jim);
+}
+
+pub unsafe fn handle_did_open(s: *mut Server, params: Params) {
+    if params.uri.is_null() || params.text.is_null() { return; }
+    let index = if let Some(index) = find_document(s, params.uri) {
+        index
+    } else {
+        da_append(&mut (*s).documents, Document {
+            uri: strdup(params.uri),
+            path: uri_to_path(params.uri),
+            text: zeroed(),
+        });
+        (*s).documents.count - 1
+    };
+    let doc = (*s).documents.items.add(index);
+    (*doc).text.count = 0;
+    da_append_many(&mut (*doc).text, core::slice::from_raw_parts(params.text, strlen(params.text)));
+    publish_all_diagnostics(s);
+}
+
+pub unsafe fn handle_did_change(s: *mut Server, params: Params) {
+    let Some(index) = find_document(s, params.uri) else { return };
+    if params.text.is_null() { return; }
+    let doc = (*s).documents.items.add(index);
+    (*doc).text.count = 0;
+    da_append_many(&mut (*doc).text, core::slice::from_raw_parts(params.text, strlen(params.text)));
+}
+
+pub unsafe fn handle_did_save(s: *mut Server, params: Params) {
+    handle_did_change(s, params);
+    if find_document(s, params.uri).is_some() {
+        publish_all_diagnostics(s);
+    }
+}
+
+pub unsafe fn handle_did_close(s: *mut Server, params: Params) {
+    let Some(index) = find_document(s, params.uri) else { return };
+    publish_diagnostics(s, index, false);
+    let doc = *(*s).documents.items.add(index);
+    free(doc.uri);
+    free(doc.path);
+    free(doc.text.items);
+    *(*s).documents.items.add(index) = *(*s).documents.items.add((*s).documents.count - 1);
+    (*s).documents.count -= 1;
+}
+
+pub unsafe fn handle_definition(s: *mut Server, id: RequestId, params: Params) {
+    let mut full: Compiler = zeroed();
+    let mut cut: Compiler = zeroed();
+    let mut var: Option<Var> = None;
+    if let Some(index) = find_document(s, params.uri) {
+        let text = (*(*s).documents.items.add(index)).text;
+        if let Some((start, end)) = identifier_at(text, offset_of_position(text, params.line, params.character)) {
+            var = resolve_name(s, index, start, end, &mut full, &mut cut);
+        }
+    }
+
+    let jim = &mut (*s).jim;
+    response_begin(jim, id);
+    if let Some(var) = var {
+        write_location(s, jim, var.loc, strlen(var.name));
+    } else {
+        jim_null(jim);
+    }
+    response_end(jim);
+    arena::free(&mut full.arena);
+    arena::free(&mut cut.arena);
+}
+
+pub unsafe fn handle_hover(s: *mut Server, id: RequestId, params: Params) {
+    let mut full: Compiler = zeroed();
+    let mut cut: Compiler = zeroed();
+    let mut hover: *const c_char = ptr::null();
+    if let Some(index) = find_document(s, params.uri) {
+        let text = (*(*s).documents.items.add(index)).text;
+        if let Some((start, end)) = identifier_at(text, offset_of_position(text, params.line, params.character)) {
+            if let Some(var) = resolve_name(s, index, start, end, &mut full, &mut cut) {
+                let mut sb: String_Builder = zeroed();
+                let kind = var_kind(&full, var);
+                sb_appendf(&mut sb, c!("```b\n"));
+                if let (Storage::External {name}, false) = (var.storage, var.extrn) {
+                    if let Some(func) = find_func(&full.program, name) {
+                        sb_appendf(&mut sb, c!("%s("), name);
+                        // The parameters are declared right at the beginning of the function
+                        for i in 0..(*func).params_count.min((*func).scope_events.count) {
+                            if let ir::ScopeEvent::Declare {name, ..} = *(*func).scope_events.items.add(i) {
+                                if i > 0 { sb_appendf(&mut sb, c!(", ")); }
+                                sb_appendf(&mut sb, c!("%s"), name);
+                            }
+                        }
+                        sb_appendf(&mut sb, c!(")"));
+                    } else if let Some(global) = find_global(&full.program, name) {
+                        if (*global).is_vec {
+                            sb_appendf(&mut sb, c!("%s[%zu]"), name, (*global).minimum_size.max((*global).values.count));
+                        } else {
+                            sb_appendf(&mut sb, c!("%s"), name);
+                        }
+                    } else {
+                        sb_appendf(&mut sb, c!("%s"), name);
+                    }
+                } else {
+                    sb_appendf(&mut sb, c!("%s %s"), kind, var.name);
+                }
+                sb_appendf(&mut sb, c!("\n```\n**%s** declared at %s:%d:%d"), kind, var.loc.input_path, var.loc.line_number, var.loc.line_offset);
+                da_append(&mut sb, 0);
+                hover = temp_strdup(sb.items);
+                free(sb.items);
+            }
+        }
+    }
+
+    let jim = &mut (*s).jim;
+    response_begin(jim, id);
+    if hover.is_null() {
+        jim_null(jim);
+    } else {
+        jim_object_begin(jim);
+            jim_member_key(jim, c!("contents"));
+            jim_object_begin(jim);
+                jim_member_key(jim, c!("kind"));
+                jim_string(jim, c!("markdown"));
+                jim_member_key(jim, c!("value"));
+                jim_string(jim, hover);
+            jim_object_end(jim);
+        jim_object_end(jim);
+    }
+    response_end(jim);
+    arena::free(&mut full.arena);
+    arena::free(&mut cut.arena);
+}
+
+pub unsafe fn write_symbol(s: *const Server, jim: *mut Jim, name: *const c_char, kind: c_longlong, loc: Loc) {
+    jim_object_begin(jim);
+        jim_member_key(jim, c!("name"));
+        jim_string(jim, name);
+        jim_member_key(jim, c!("kind"));
+        jim_integer(jim, kind);
+        jim_member_key(jim, c!("location"));
+        write_location(s, jim, loc, strlen(name));
+    jim_object_end(jim);
+}
+
+pub unsafe fn handle_document_symbol(s: *mut Server, id: RequestId, params: Params) {
+    let mut c: Compiler = zeroed();
+    let index = find_document(s, params.uri);
+    if index.is_some() {
+        analyse(s, &mut c, None);
+    }
+
+    let jim = &mut (*s).jim;
+    response_begin(jim, id);
+    jim_array_begin(jim);
+    if let Some(index) = index {
+        let path = (*(*s).documents.items.add(index)).path;
+        let program = &c.program;
+        for i in 0..program.funcs.count {
+            let func = *program.funcs.items.add(i);
+            if strcmp(func.name_loc.input_path, path) != 0 { continue; }
+            write_symbol(s, jim, func.name, LSP_SYMBOL_KIND_FUNCTION, func.name_loc);
+        }
+        for i in 0..program.asm_funcs.count {
+            let func = *program.asm_funcs.items.add(i);
+            if strcmp(func.name_loc.input_path, path) != 0 { continue; }
+            write_symbol(s, jim, func.name, LSP_SYMBOL_KIND_FUNCTION, func.name_loc);
+        }
+        for i in 0..program.globals.count {
+            let global = *program.globals.items.add(i);
+            if strcmp(global.name_loc.input_path, path) != 0 { continue; }
+            write_symbol(s, jim, global.name, LSP_SYMBOL_KIND_VARIABLE, global.name_loc);
+        }
+    }
+    jim_array_end(jim);
+    response_end(jim);
+    arena::free(&mut c.arena);
+}
+
+pub unsafe fn write_completion_item(jim: *mut Jim, label: *const c_char, kind: c_longlong, detail: *const c_char) {
+    jim_object_begin(jim);
+        jim_member_key(jim, c!("label"));
+        jim_string(jim, label);
+        jim_member_key(jim, c!("kind"));
+        jim_integer(jim, kind);
+        jim_member_key(jim, c!("detail"));
+        jim_string(jim, detail);
+    jim_object_end(jim);
+}
+
+pub unsafe fn handle_completion(s: *mut Server, id: RequestId, params: Params) {
+    let mut full: Compiler = zeroed();
+    let mut cut: Compiler = zeroed();
+    // The innermost scopes go first, so the shadowed names are skipped
+    let mut vars: Array<Var> = zeroed();
+    if let Some(index) = find_document(s, params.uri) {
+        let text = (*(*s).documents.items.add(index)).text;
+        analyse(s, &mut full, None);
+        analyse(s, &mut cut, Some((index, offset_of_position(text, params.line, params.character))));
+        let mut i = cut.vars.count;
+        while i > 0 {
+            i -= 1;
+            da_append_many(&mut vars, da_slice(*cut.vars.items.add(i)));
+        }
+        if full.vars.count > 0 {
+            da_append_many(&mut vars, da_slice(*full.vars.items));
+        }
+    }
+
+    let jim = &mut (*s).jim;
+    response_begin(jim, id);
+    jim_array_begin(jim);
+    'vars: for i in 0..vars.count {
+        let var = *vars.items.add(i);
+        for j in 0..i {
+            if strcmp((*vars.items.add(j)).name, var.name) == 0 { continue 'vars; }
+        }
+        write_completion_item(jim, var.name, completion_kind(&full, var), var_kind(&full, var));
+    }
+    for i in 0..KEYWORDS.len() {
+        write_completion_item(jim, (*KEYWORDS)[i].0, LSP_COMPLETION_KIND_KEYWORD, c!("keyword"));
+    }
+    jim_array_end(jim);
+    response_end(jim);
+    free(vars.items);
+    arena::free(&mut full.arena);
+    arena::free(&mut cut.arena);
+}
+
+/// Returns false when it's time to exit
+pub unsafe fn handle_message(s: *mut Server, jimp: *mut Jimp) -> Option<bool> {
+    let message = parse_message(jimp)?;
+
+    let mut params: Params = zeroed();
+    if !message.params.is_null() {
+        (*jimp).point = message.params;
+        parse_params(jimp, &mut params)?;
+    }
+
+    let method = message.method;
+    if let Some(id) = message.id {
+        if strcmp(method, c!("initialize")) == 0 {
+            handle_initialize(s, id);
+        } else if strcmp(method, c!("shutdown")) == 0 {
+            (*s).shutdown = true;
+            let jim = &mut (*s).jim;
+            response_begin(jim, id);
+            jim_null(jim);
+            response_end(jim);
+        } else if strcmp(method, c!("textDocument/definition")) == 0 {
+            handle_definition(s, id, params);
+        } else if strcmp(method, c!("textDocument/hover")) == 0 {
+            handle_hover(s, id, params);
+        } else if strcmp(method, c!("textDocument/documentSymbol")) == 0 {
+            handle_document_symbol(s, id, params);
+        } else if strcmp(method, c!("textDocument/completion")) == 0 {
+            handle_completion(s, id, params);
+        } else if *method != 0 {
+            send_error(&mut (*s).jim, id, LSP_ERROR_METHOD_NOT_FOUND, temp_sprintf(c!("Unsupported method %s"), method));
+        }
+    } else if strcmp(method, c!("textDocument/didOpen")) == 0 {
+        handle_did_open(s, params);
+    } else if strcmp(method, c!("textDocument/didChange")) == 0 {
+        handle_did_change(s, params);
+    } else if strcmp(method, c!("textDocument/didSave")) == 0 {
+        handle_did_save(s, params);
+    } else if strcmp(method, c!("textDocument/didClose")) == 0 {
+        handle_did_close(s, params);
+    } else if strcmp(method, c!("exit")) == 0 {
+        free(params.text);
+        return Some(false);
+    }
+    // All the other notifications are ignored
+
+    free(params.text);
+    Some(true)
+}
+
+pub unsafe fn usage() {
+    fprintf(stderr(), c!("B Language Server. Talks LSP over stdin/stdout.\n"));
+    fprintf(stderr(), c!("Usage: %s [OPTIONS]\n"), flag_program_name());
+    fprintf(stderr(), c!("OPTIONS:\n"));
+    flag_print_options(stderr());
+}
+
+pub unsafe fn main(argc: i32, argv: *mut*mut c_char) -> Option<()> {
+    let targets = codegen::load_targets()?;
+    let default_target_name = if let Some(default_target) = default_target(da_slice(targets)) {
+        default_target.name()
+    } else {
+        ptr::null()
+    };
+
+    let target_name = flag_str(c!("t"), default_target_name, c!("Target which libb is analysed along with the documents"));
+    let nostdlib    = flag_bool(c!("nostdlib"), false, c!("Do not analyse libb along with the documents"));
+    let libb_dirs   = flag_list(c!("libb-dir"), c!("Add a directory to look for libb in. See the -libb-dir flag of the compiler"));
+    let historical  = flag_bool(c!("hist"), false, c!("Analyse the documents in the historical mode. See the -hist flag of the compiler"));
+    let warnings    = flag_list(c!("W"), c!("Configure the warnings the same way as the -W flag of the compiler"));
+    let help        = flag_bool(c!("help"), false, c!("Print this help message"));
+
+    if !flag_parse(argc, argv) {
+        usage();
+        flag_print_error(stderr());
+        return None;
+    }
+
+    if *help {
+        usage();
+        return Some(());
+    }
+
+    if flag_rest_argc() > 0 {
+        usage();
+        log(Log_Level::ERROR, c!("Unexpected argument `%s`"), *flag_rest_argv());
+        return None;
+    }
+
+    let mut s: Server = zeroed();
+    s.historical = *historical;
+
+    let mut c: Compiler = zeroed();
+    set_default_warnings(&mut c);
+    for i in 0..(*warnings).count {
+        let warning = *(*warnings).items.add(i);
+        if !configure_warning(&mut c, warning) {
+            usage();
+            print_available_warnings();
+            log(Log_Level::ERROR, c!("Unknown value `%s` for -%s flag"), warning, flag_name(warnings));
+            return None;
+        }
+    }
+    s.warnings = c.warnings;
+
+    let mut a: Arena = zeroed();
+    let executable_directory = arena::strdup(&mut a, dirname(flag_program_name()));
+    s.include_path = libb_search_path(da_slice(*libb_dirs), executable_directory, &mut a);
+
+    if !*nostdlib {
+        if (*target_name).is_null() || Target::by_name(da_slice(targets), *target_name).is_none() {
+            usage();
+            log(Log_Level::ERROR, c!("Unknown target. Provide a valid one with the -%s flag or use -%s"), flag_name(target_name), flag_name(nostdlib));
+            return None;
+        }
+
+        s.target = *target_name;
+
+        let mut libb_paths: Array<*const c_char> = zeroed();
+        let search_path = s.include_path;
+        add_libb_files(da_slice(search_path), *target_name, &mut libb_paths, &mut a)?;
+        for i in 0..libb_paths.count {
+            let path = *libb_paths.items.add(i);
+            let mut text: String_Builder = zeroed();
+            read_entire_file(path, &mut text)?;
+            da_append(&mut s.libb, Document {
+                uri: ptr::null_mut(),
+                path: strdup(path),
+                text,
+            });
+        }
+    }
+
+    // Diagnostics are collected so they can be published to the client
+    lexer::diag_set_format(c!("json"));
+
+    let mut input: String_Builder = zeroed();
+    let mut jimp: Jimp = zeroed();
+    loop {
+        if read_message(&mut input).is_none() {
+            log(Log_Level::ERROR, c!("Unexpected end of the input"));
+            return None;
+        }
+
+        let checkpoint = temp_save();
+        jimp_begin(&mut jimp, c!("<message>"), input.items, input.count);
+        let proceed = handle_message(&mut s, &mut jimp);
+        temp_rewind(checkpoint);
+
+        match proceed {
+            Some(true) => {}
+            Some(false) => return if s.shutdown { Some(()) } else { None },
+            None => log(Log_Level::ERROR, c!("Could not parse the message")),
+        }
+    }
+}
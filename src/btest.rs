@@ -18,8 +18,11 @@ pub mod ir;
 pub mod arena;
 pub mod lexer;
 pub mod codegen;
+pub mod dwarf;
 pub mod shlex;
 pub mod params;
+pub mod time;
+pub mod proc;
 
 use core::ffi::*;
 use core::cmp;
@@ -33,6 +36,9 @@ use glob::*;
 use jim::*;
 use jimp::*;
 use crust::compar_cstr;
+use time::Instant;
+use proc::*;
+use shlex::*;
 
 const GARBAGE_FOLDER: *const c_char = c!("./build/tests/");
 
@@ -65,12 +71,16 @@ impl TestState {
 
 #[derive(Copy, Clone)]
 pub enum Outcome {
-    /// The test didn't even manage to build
-    BuildFail,
-    /// The test built, but crashed at runtime
+    /// The test didn't even manage to build. The diagnostics are extracted with extract_diagnostics().
+    BuildFail{diagnostics: *const c_char},
+    /// The test built and was not run, because it was expected to fail building
+    Built,
+    /// The test built, but could not be run
     RunFail,
-    /// The test built, printed something and exited normally
-    RunSuccess{stdout: *const c_char},
+    /// The test built, ran and exited on its own. A crash is reported as a non-zero exit code.
+    Finished{stdout: *const c_char, stderr: *const c_char, exit_code: c_int},
+    /// The test was killed because it took too long to build or to run
+    Timeout,
 }
 
 enum_with_order! {
@@ -79,8 +89,14 @@ enum_with_order! {
         OK,
         NeverRecorded,
         StdoutMismatch,
+        StderrMismatch,
+        ExitCodeMismatch,
+        DiagnosticsMismatch,
+        InputsMismatch,
+        UnexpectedlyCompiled,
         BuildFail,
         RunFail,
+        Timeout,
         Disabled,
     }
 }
@@ -88,34 +104,52 @@ enum_with_order! {
 impl ReportStatus {
     fn letter(self) -> *const c_char {
         match self {
-            ReportStatus::OK             => c!("K"),
-            ReportStatus::NeverRecorded  => c!("K"),
-            ReportStatus::StdoutMismatch => c!("K"),
-            ReportStatus::BuildFail      => c!("B"),
-            ReportStatus::RunFail        => c!("R"),
-            ReportStatus::Disabled       => c!("-"),
+            ReportStatus::OK                   => c!("K"),
+            ReportStatus::NeverRecorded        => c!("K"),
+            ReportStatus::StdoutMismatch       => c!("K"),
+            ReportStatus::StderrMismatch       => c!("E"),
+            ReportStatus::ExitCodeMismatch     => c!("X"),
+            ReportStatus::DiagnosticsMismatch  => c!("D"),
+            ReportStatus::InputsMismatch       => c!("I"),
+            ReportStatus::UnexpectedlyCompiled => c!("C"),
+            ReportStatus::BuildFail            => c!("B"),
+            ReportStatus::RunFail              => c!("R"),
+            ReportStatus::Timeout              => c!("T"),
+            ReportStatus::Disabled             => c!("-"),
         }
     }
 
     fn color(self) -> *const c_char {
         match self {
-            ReportStatus::OK             => GREEN,
-            ReportStatus::NeverRecorded  => BLUE,
-            ReportStatus::StdoutMismatch => RED,
-            ReportStatus::BuildFail      => RED,
-            ReportStatus::RunFail        => RED,
-            ReportStatus::Disabled       => GREY,
+            ReportStatus::OK                   => GREEN,
+            ReportStatus::NeverRecorded        => BLUE,
+            ReportStatus::StdoutMismatch       => RED,
+            ReportStatus::StderrMismatch       => RED,
+            ReportStatus::ExitCodeMismatch     => RED,
+            ReportStatus::DiagnosticsMismatch  => RED,
+            ReportStatus::InputsMismatch       => RED,
+            ReportStatus::UnexpectedlyCompiled => RED,
+            ReportStatus::BuildFail            => RED,
+            ReportStatus::RunFail              => RED,
+            ReportStatus::Timeout              => YELLOW,
+            ReportStatus::Disabled             => GREY,
         }
     }
 
     fn description(self) -> *const c_char {
         match self {
-            ReportStatus::OK             => c!("passed"),
-            ReportStatus::NeverRecorded  => c!("stdout is not recorded"),
-            ReportStatus::StdoutMismatch => c!("unexpected stdout"),
-            ReportStatus::BuildFail      => c!("build fail"),
-            ReportStatus::RunFail        => c!("runtime error"),
-            ReportStatus::Disabled       => c!("disabled"),
+            ReportStatus::OK                   => c!("passed"),
+            ReportStatus::NeverRecorded        => c!("outcome is not recorded"),
+            ReportStatus::StdoutMismatch       => c!("unexpected stdout"),
+            ReportStatus::StderrMismatch       => c!("unexpected stderr"),
+            ReportStatus::ExitCodeMismatch     => c!("unexpected exit code"),
+            ReportStatus::DiagnosticsMismatch  => c!("unexpected diagnostics"),
+            ReportStatus::InputsMismatch       => c!("inputs changed since the record"),
+            ReportStatus::UnexpectedlyCompiled => c!("unexpectedly compiled"),
+            ReportStatus::BuildFail            => c!("build fail"),
+            ReportStatus::RunFail              => c!("could not run"),
+            ReportStatus::Timeout              => c!("timeout"),
+            ReportStatus::Disabled             => c!("disabled"),
         }
     }
 }
@@ -126,16 +160,84 @@ pub struct Report {
     pub statuses: Array<ReportStatus>,
 }
 
-pub unsafe fn execute_test(
-    // Inputs
-    test_folder: *const c_char, name: *const c_char, target: Target, quiet: bool,
-    // Outputs
-    cmd: *mut Cmd, sb: *mut String_Builder,
-) -> Option<Outcome> {
-    // TODO: add timeouts for running and building in case they go into infinite loop or something
-    let input_path = temp_sprintf(c!("%s/%s.b"), test_folder, name);
-    let program_path = temp_sprintf(c!("%s/%s.%s%s"), GARBAGE_FOLDER, name, target.api.name(), target.file_ext());
-    let stdout_path = temp_sprintf(c!("%s/%s.%s.stdout.txt"), GARBAGE_FOLDER, name, target.api.name());
+/// How the tests are executed. Provided via the command line flags.
+#[derive(Copy, Clone)]
+pub struct RunOptions {
+    pub jobs: usize,
+    pub quiet: bool,
+    /// The default timeouts in seconds. Can be overridden by the rows of the Test Table. 0 means no timeout.
+    pub build_timeout: usize,
+    pub run_timeout: usize,
+    /// The codegen parameters passed to every build with `-C`, like `assembler=builtin`
+    pub codegen_args: Array<*const c_char>,
+}
+
+/// A test case to execute on a target. Its outcome goes to the cell of the Test Matrix slice.
+#[derive(Copy, Clone)]
+pub struct Job {
+    pub case_name: *const c_char,
+    pub target: Target,
+    pub cell: usize,
+    /// In seconds. 0 means no timeout.
+    pub build_timeout: usize,
+    pub run_timeout: usize,
+    /// The test is only built, the program is not run
+    pub expect_build_fail: bool,
+    pub inputs: Inputs,
+    pub codegen_args: Array<*const c_char>,
+}
+
+/// The inputs of the test case from the files next to it. See load_inputs().
+#[derive(Copy, Clone)]
+pub struct Inputs {
+    pub stdin_path: Option<*const c_char>,
+    pub stdin: Option<*const c_char>,
+    pub run_args: Option<*const c_char>,
+    pub build_flags: Option<*const c_char>,
+}
+
+#[derive(Copy, Clone)]
+pub enum Stage {
+    Build,
+    Run,
+}
+
+#[derive(Copy, Clone)]
+pub struct Running {
+    pub job: usize,
+    pub stage: Stage,
+    pub proc: Proc,
+    pub started: Instant,
+}
+
+pub unsafe fn program_path(job: Job) -> *const c_char {
+    temp_sprintf(c!("%s/%s.%s%s"), GARBAGE_FOLDER, job.case_name, job.target.api.name(), job.target.file_ext())
+}
+
+pub unsafe fn stdout_path(job: Job) -> *const c_char {
+    temp_sprintf(c!("%s/%s.%s.stdout.txt"), GARBAGE_FOLDER, job.case_name, job.target.api.name())
+}
+
+pub unsafe fn stderr_path(job: Job) -> *const c_char {
+    temp_sprintf(c!("%s/%s.%s.stderr.txt"), GARBAGE_FOLDER, job.case_name, job.target.api.name())
+}
+
+pub unsafe fn build_log_path(job: Job) -> *const c_char {
+    temp_sprintf(c!("%s/%s.%s.build.txt"), GARBAGE_FOLDER, job.case_name, job.target.api.name())
+}
+
+/// Appends the arguments written in the shell syntax
+pub unsafe fn cmd_append_shlex(cmd: *mut Cmd, args: *const c_char) {
+    let mut s: Shlex = zeroed();
+    shlex_init(&mut s, args, args.add(strlen(args)));
+    while !shlex_next(&mut s).is_null() {
+        da_append(cmd, temp_strdup(s.string));
+    }
+    shlex_free(&mut s);
+}
+
+pub unsafe fn start_build(test_folder: *const c_char, job: Job, quiet: bool, cmd: *mut Cmd) -> Proc {
+    let input_path = temp_sprintf(c!("%s/%s.b"), test_folder, job.case_name);
     cmd_append! {
         cmd,
         if cfg!(target_os = "windows") {
@@ -144,16 +246,104 @@ pub unsafe fn execute_test(
             c!("./build/b")
         },
         input_path,
-        c!("-t"), target.api.name(),
-        c!("-o"), program_path,
+        c!("-t"), job.target.api.name(),
+        c!("-o"), program_path(job),
     }
     if quiet {
         cmd_append! { cmd, c!("-q") }
     }
-    if !cmd_run_sync_and_reset(cmd) {
-        return Some(Outcome::BuildFail);
+    for i in 0..job.codegen_args.count {
+        cmd_append! { cmd, c!("-C"), *job.codegen_args.items.add(i) }
+    }
+    if let Some(build_flags) = job.inputs.build_flags {
+        cmd_append_shlex(cmd, build_flags);
+    }
+    let mut fderr = fd_open_for_write(build_log_path(job));
+    let mut redirect: Cmd_Redirect = zeroed();
+    redirect.fderr = &mut fderr;
+    proc_start_and_reset(cmd, redirect)
+}
+
+/// Returns the diagnostics of the build
+pub unsafe fn finish_build(test_folder: *const c_char, job: Job, sb: *mut String_Builder) -> Option<*const c_char> {
+    (*sb).count = 0;
+    read_entire_file(build_log_path(job), sb)?;
+    da_append(sb, 0);
+    fprintf(stderr(), c!("%s"), (*sb).items); // Forward the output of the compiler, it would go to stderr anyway
+    let input_path = temp_sprintf(c!("%s/%s.b"), test_folder, job.case_name);
+    Some(extract_diagnostics(input_path, (*sb).items))
+}
+
+unsafe fn skip_digits(mut s: *const c_char) -> *const c_char {
+    while isdigit(*s as c_int) != 0 {
+        s = s.add(1);
+    }
+    s
+}
+
+/// `  12 | source code` or `     |     ^~~~`
+unsafe fn is_source_snippet(mut line: *const c_char) -> bool {
+    while *line == ' ' as c_char { line = line.add(1); }
+    line = skip_digits(line);
+    while *line == ' ' as c_char { line = line.add(1); }
+    *line == '|' as c_char
+}
+
+/// `path:line:col: message`
+unsafe fn is_located(line: *const c_char, end: *const c_char) -> bool {
+    let mut p = line;
+    while p < end {
+        if *p == ':' as c_char {
+            let col = skip_digits(p.add(1));
+            if col > p.add(1) && *col == ':' as c_char {
+                let after = skip_digits(col.add(1));
+                if after > col.add(1) && *after == ':' as c_char {
+                    return true
+                }
+            }
+        }
+        p = p.add(1);
+    }
+    false
+}
+
+/// Whether the compiler rejected the test case itself rather than failed to build it for some other reason
+pub unsafe fn has_errors(diagnostics: *const c_char) -> bool {
+    !strstr(diagnostics, c!(": ERROR: ")).is_null()
+}
+
+/// Picks the diagnostics about the test case out of the output of the compiler, turning them into `line:col: message`
+/// lines. The source code snippets, the logs and the diagnostics about other files (libb for instance) are dropped,
+/// since they depend on the target.
+pub unsafe fn extract_diagnostics(input_path: *const c_char, output: *const c_char) -> *const c_char {
+    let mut diagnostics: String_Builder = zeroed();
+    let input_path_len = strlen(input_path);
+    let mut line = output;
+    while *line != 0 {
+        let mut end = strchr(line, '\n' as c_int) as *const c_char;
+        if end.is_null() {
+            end = line.add(strlen(line));
+        }
+        let mut line_end = end;
+        if line_end > line && *line_end.sub(1) == '\r' as c_char {
+            line_end = line_end.sub(1);
+        }
+
+        if strncmp(line, input_path, input_path_len) == 0 && *line.add(input_path_len) == ':' as c_char {
+            let start = line.add(input_path_len + 1);
+            sb_appendf(&mut diagnostics, c!("%.*s\n"), line_end.offset_from(start) as c_int, start);
+        } else if line_end > line && *line != '[' as c_char && !is_source_snippet(line) && !is_located(line, line_end) {
+            sb_appendf(&mut diagnostics, c!("%.*s\n"), line_end.offset_from(line) as c_int, line);
+        }
+
+        line = if *end == 0 { end } else { end.add(1) };
     }
+    da_append(&mut diagnostics, 0);
+    diagnostics.items // TODO: memory leak
+}
 
+pub unsafe fn start_run(test_folder: *const c_char, job: Job, cmd: *mut Cmd) -> Proc {
+    let input_path = temp_sprintf(c!("%s/%s.b"), test_folder, job.case_name);
     cmd_append! {
         cmd,
         if cfg!(target_os = "windows") {
@@ -162,33 +352,245 @@ pub unsafe fn execute_test(
             c!("./build/b")
         },
         input_path,
-        c!("-t"), target.api.name(),
-        c!("-o"), program_path,
+        c!("-t"), job.target.api.name(),
+        c!("-o"), program_path(job),
         c!("-q"),
         c!("-nobuild"),
         c!("-run"),
     }
-    // Hack for Uxn
-    if strcmp(target.api.name(), c!("uxn")) == 0 {
-        cmd_append!(cmd, c!("-C"), c!("runner=uxncli"));
+    // The default Uxn runner is the GUI emulator, which is not suitable for testing
+    if strcmp(job.target.api.name(), c!("uxn")) == 0 {
+        cmd_append!(cmd, c!("-C"), c!("runner=builtin"));
     }
-    let mut fdout = fd_open_for_write(stdout_path);
+    if let Some(run_args) = job.inputs.run_args {
+        cmd_append!(cmd, c!("--"));
+        cmd_append_shlex(cmd, run_args);
+    }
+    let mut fdout = fd_open_for_write(stdout_path(job));
+    let mut fderr = fd_open_for_write(stderr_path(job));
     let mut redirect: Cmd_Redirect = zeroed();
     redirect.fdout = &mut fdout;
-    let run_ok = cmd_run_sync_redirect_and_reset(cmd, redirect);
+    redirect.fderr = &mut fderr;
+    let mut fdin;
+    if let Some(stdin_path) = job.inputs.stdin_path {
+        fdin = fd_open_for_read(stdin_path);
+        redirect.fdin = &mut fdin;
+    }
+    proc_start_and_reset(cmd, redirect)
+}
 
+pub unsafe fn finish_run(job: Job, exit_code: Option<c_int>, quiet: bool, sb: *mut String_Builder) -> Option<Outcome> {
     (*sb).count = 0;
-    read_entire_file(stdout_path, sb)?; // Should always succeed, but may fail if stdout_path is a directory for instance.
-    da_append(sb, 0);                   // NULL-terminating the stdout
+    read_entire_file(stdout_path(job), sb)?; // Should always succeed, but may fail if stdout_path is a directory for instance.
+    da_append(sb, 0);                        // NULL-terminating the stdout
+    let stdout = strdup((*sb).items);        // TODO: memory leak
+
+    (*sb).count = 0;
+    read_entire_file(stderr_path(job), sb)?;
+    da_append(sb, 0);
+    let stderr_ = strdup((*sb).items);       // TODO: memory leak
+
     if !quiet {
-        printf(c!("%s"), (*sb).items);      // Forward stdout for diagnostic purposes
+        // Forward the output for diagnostic purposes
+        printf(c!("%s"), stdout);
+        fprintf(stderr(), c!("%s"), stderr_);
     }
 
-    if !run_ok {
-        Some(Outcome::RunFail)
-    } else {
-        Some(Outcome::RunSuccess{stdout: strdup((*sb).items)}) // TODO: memory leak
+    match exit_code {
+        Some(exit_code) => Some(Outcome::Finished{stdout, stderr: stderr_, exit_code}),
+        None => Some(Outcome::RunFail),
+    }
+}
+
+/// How much time the process has left before it's killed. u64::MAX if it's not limited.
+pub unsafe fn time_left_ms(r: Running, job: Job) -> u64 {
+    let timeout = match r.stage {
+        Stage::Build => job.build_timeout,
+        Stage::Run   => job.run_timeout,
+    };
+    if timeout == 0 { return u64::MAX; }
+    (timeout as u64*1000).saturating_sub(r.started.elapsed().as_millis() as u64)
+}
+
+/// Executes the jobs running up to `max_jobs` processes at a time. Every job has its own program and output
+/// paths, so they don't step on each other. The outcome of each job is put into its cell of `outcomes`,
+/// so the outcomes don't depend on the order the jobs finish in. The processes that take longer than
+/// the timeouts of their jobs are killed.
+pub unsafe fn execute_jobs(
+    // Inputs
+    test_folder: *const c_char, jobs: *const [Job], max_jobs: usize, quiet: bool,
+    // Outputs
+    cmd: *mut Cmd, sb: *mut String_Builder, outcomes: *mut Array<Option<Outcome>>,
+) -> Option<()> {
+    let mut running: Array<Running> = zeroed();
+    let mut next = 0;
+    while next < jobs.len() || running.count > 0 {
+        while running.count < max_jobs && next < jobs.len() {
+            let proc = start_build(test_folder, (*jobs)[next], quiet, cmd);
+            da_append(&mut running, Running {job: next, stage: Stage::Build, proc, started: Instant::now()});
+            next += 1;
+        }
+
+        let mut procs: Array<Proc> = zeroed();
+        let mut timeout_ms = u64::MAX;
+        for i in 0..running.count {
+            let r = *running.items.add(i);
+            da_append(&mut procs, r.proc);
+            timeout_ms = cmp::min(timeout_ms, time_left_ms(r, (*jobs)[r.job]));
+        }
+        let status = proc_wait_any(da_slice(procs), timeout_ms);
+        free(procs.items);
+
+        let Some((index, status)) = status else {
+            // Some of the processes ran out of time
+            let mut i = 0;
+            while i < running.count {
+                let r = *running.items.add(i);
+                if time_left_ms(r, (*jobs)[r.job]) > 0 {
+                    i += 1;
+                    continue;
+                }
+                let job = (*jobs)[r.job];
+                let (timeout, stage_name) = match r.stage {
+                    Stage::Build => (job.build_timeout, c!("building")),
+                    Stage::Run   => (job.run_timeout, c!("running")),
+                };
+                proc_kill(r.proc);
+                log(Log_Level::ERROR, c!("%s on %s timed out after %zu seconds of %s"), job.case_name, job.target.api.name(), timeout, stage_name);
+                *(*outcomes).items.add(job.cell) = Some(Outcome::Timeout);
+                running.count -= 1;
+                *running.items.add(i) = *running.items.add(running.count);
+            }
+            continue;
+        };
+
+        let finished = *running.items.add(index);
+        running.count -= 1;
+        *running.items.add(index) = *running.items.add(running.count);
+
+        let job = (*jobs)[finished.job];
+        let exit_code = match status {
+            Proc_Status::Exited(exit_code) => Some(exit_code),
+            Proc_Status::WaitFailed | Proc_Status::TimedOut => None,
+        };
+        match finished.stage {
+            Stage::Build => {
+                let diagnostics = finish_build(test_folder, job, sb)?;
+                if exit_code != Some(0) {
+                    *(*outcomes).items.add(job.cell) = Some(Outcome::BuildFail{diagnostics});
+                } else if job.expect_build_fail {
+                    *(*outcomes).items.add(job.cell) = Some(Outcome::Built);
+                } else {
+                    let proc = start_run(test_folder, job, cmd);
+                    da_append(&mut running, Running {job: finished.job, stage: Stage::Run, proc, started: Instant::now()});
+                }
+            }
+            Stage::Run => *(*outcomes).items.add(job.cell) = Some(finish_run(job, exit_code, quiet, sb)?),
+        }
+    }
+    free(running.items);
+    Some(())
+}
+
+pub unsafe fn read_file_if_exists(path: *const c_char, sb: *mut String_Builder) -> Option<Option<*const c_char>> {
+    if !file_exists(path)? {
+        return Some(None);
+    }
+    (*sb).count = 0;
+    read_entire_file(path, sb)?;
+    da_append(sb, 0);
+    Some(Some(strdup((*sb).items))) // TODO: memory leak
+}
+
+/// Loads the inputs of the test case from the files next to it. All of them are optional:
+/// - `<case>.stdin` is piped into the program,
+/// - `<case>.args` holds the run arguments of the program in the shell syntax,
+/// - `<case>.flags` holds additional flags for the compiler in the shell syntax.
+/// The outcome depends on them, so they are recorded along with it. See inputs_mismatch().
+pub unsafe fn load_inputs(test_folder: *const c_char, case_name: *const c_char, sb: *mut String_Builder) -> Option<Inputs> {
+    let stdin_path = temp_sprintf(c!("%s/%s.stdin"), test_folder, case_name);
+    let stdin = read_file_if_exists(stdin_path, sb)?;
+    let stdin_path = if stdin.is_some() { Some(strdup(stdin_path) as *const c_char) } else { None }; // TODO: memory leak
+    let run_args = read_file_if_exists(temp_sprintf(c!("%s/%s.args"), test_folder, case_name), sb)?;
+    let build_flags = read_file_if_exists(temp_sprintf(c!("%s/%s.flags"), test_folder, case_name), sb)?;
+    Some(Inputs {stdin_path, stdin, run_args, build_flags})
+}
+
+/// Checks that the test case still has the inputs its outcome was recorded with. A missing input is the same as an
+/// empty one.
+pub unsafe fn inputs_mismatch(row: *const TestRow, inputs: Inputs, jim: *mut Jim) -> bool {
+    let fields = [
+        (c!("stdin"), (*row).stdin,       inputs.stdin),
+        (c!("args"),  (*row).run_args,    inputs.run_args),
+        (c!("flags"), (*row).build_flags, inputs.build_flags),
+    ];
+    for (name, recorded, actual) in fields {
+        let recorded = recorded.unwrap_or(c!(""));
+        let actual = actual.unwrap_or(c!(""));
+        if strcmp(recorded, actual) != 0 {
+            print_output_mismatch((*row).case_name, (*row).target, name, recorded, actual, jim);
+            fprintf(stderr(), c!("The inputs of the test case changed since its outcome was recorded. Please use -record flag to record what is expected with the new inputs\n"));
+            return true;
+        }
+    }
+    false
+}
+
+/// The marker that makes the test case a test of the diagnostics. The compiler is expected to reject such a case, so
+/// its diagnostics are recorded instead of running it. Without the marker a failed build is just a failed build.
+pub const EXPECT_BUILD_FAIL_MARKER: *const c_char = c!("// btest: expect-build-fail");
+
+pub unsafe fn expects_build_fail(test_folder: *const c_char, case_name: *const c_char, sb: *mut String_Builder) -> Option<bool> {
+    (*sb).count = 0;
+    read_entire_file(temp_sprintf(c!("%s/%s.b"), test_folder, case_name), sb)?;
+    da_append(sb, 0);
+    Some(!strstr((*sb).items, EXPECT_BUILD_FAIL_MARKER).is_null())
+}
+
+/// Executes the enabled and never recorded cells of the Test Matrix slice. Returns the outcomes of the cells row by
+/// row, the disabled ones have no outcome.
+pub unsafe fn execute_test_matrix(
+    // Inputs
+    test_folder: *const c_char, cases: *const [*const c_char], targets: *const [Target], tt: *mut TestTable, options: RunOptions,
+    // Outputs
+    cmd: *mut Cmd, sb: *mut String_Builder,
+) -> Option<Array<Option<Outcome>>> {
+    let mut jobs: Array<Job> = zeroed();
+    let mut outcomes: Array<Option<Outcome>> = zeroed();
+    for i in 0..cases.len() {
+        let case_name = (*cases)[i];
+        let inputs = load_inputs(test_folder, case_name, sb)?;
+        let expect_build_fail = expects_build_fail(test_folder, case_name, sb)?;
+        for j in 0..targets.len() {
+            let target = (*targets)[j];
+            let mut job = Job {
+                case_name,
+                target,
+                cell: outcomes.count,
+                build_timeout: options.build_timeout,
+                run_timeout: options.run_timeout,
+                expect_build_fail,
+                inputs,
+                codegen_args: options.codegen_args,
+            };
+            let enabled = match test_table_find_row(tt, case_name, target) {
+                Some(row) => {
+                    if let Some(build_timeout) = (*row).build_timeout { job.build_timeout = build_timeout; }
+                    if let Some(run_timeout) = (*row).run_timeout { job.run_timeout = run_timeout; }
+                    job.expect_build_fail = expect_build_fail || (*row).expected_diagnostics.is_some();
+                    matches!((*row).state, TestState::Enabled)
+                }
+                None => true,
+            };
+            if enabled {
+                da_append(&mut jobs, job);
+            }
+            da_append(&mut outcomes, None);
+        }
     }
+    execute_jobs(test_folder, da_slice(jobs), options.jobs, options.quiet, cmd, sb, &mut outcomes)?;
+    free(jobs.items);
+    Some(outcomes)
 }
 
 pub unsafe fn usage() {
@@ -205,7 +607,7 @@ pub struct ReportStats {
 
 const RESET:  *const c_char = c!("\x1b[0m");
 const GREEN:  *const c_char = c!("\x1b[32m");
-const _YELLOW: *const c_char = c!("\x1b[33m");
+const YELLOW: *const c_char = c!("\x1b[33m");
 const GREY:   *const c_char = c!("\x1b[90m");
 const RED:    *const c_char = c!("\x1b[31m");
 const BLUE:   *const c_char = c!("\x1b[94m");
@@ -255,16 +657,7 @@ pub unsafe fn print_bottom_labels(targets: *const [Target], stats_by_target: *co
 }
 
 pub unsafe fn matches_glob(pattern: *const c_char, text: *const c_char) -> Option<bool> {
-    let mark = temp_save();
-    let result = match glob_utf8(pattern, text) {
-        Glob_Result::MATCHED        => Ok(true),
-        Glob_Result::UNMATCHED      => Ok(false),
-        Glob_Result::OOM_ERROR      => Err(c!("out of memory")),
-        Glob_Result::ENCODING_ERROR => Err(c!("encoding error")),
-        Glob_Result::SYNTAX_ERROR   => Err(c!("syntax error")),
-    };
-    temp_rewind(mark);
-    match result {
+    match match_glob(pattern, text) {
         Ok(result) => Some(result),
         Err(error) => {
             fprintf(stderr(), c!("ERROR: while matching pattern `%s`: %s\n"), pattern, error);
@@ -275,16 +668,21 @@ pub unsafe fn matches_glob(pattern: *const c_char, text: *const c_char) -> Optio
 
 pub unsafe fn record_tests(
     // Inputs
-    test_folder: *const c_char, cases: *const [*const c_char], targets: *const [Target], tt: *mut TestTable, quiet: bool,
+    test_folder: *const c_char, cases: *const [*const c_char], targets: *const [Target], tt: *mut TestTable, options: RunOptions,
     // Outputs
     cmd: *mut Cmd, sb: *mut String_Builder,
     reports: *mut Array<Report>, stats_by_target: *mut Array<ReportStats>,
 ) -> Option<()> {
-    // TODO: Parallelize the test runner.
-    // Probably using `cmd_run_async_and_reset`.
-    // Also don't forget to add the `-j` flag.
+    let outcomes = execute_test_matrix(
+        // Inputs
+        test_folder, cases, targets, tt, options,
+        // Outputs
+        cmd, sb,
+    )?;
     for i in 0..cases.len() {
         let case_name = (*cases)[i];
+        let inputs = load_inputs(test_folder, case_name, sb)?;
+        let expect_build_fail = expects_build_fail(test_folder, case_name, sb)?;
         let mut report = Report {
             name: case_name,
             statuses: zeroed(),
@@ -295,17 +693,24 @@ pub unsafe fn record_tests(
             if let Some(test_row) = test_table_find_row(tt, case_name, target) {
                 match (*test_row).state {
                     TestState::Enabled => {
-                        let outcome = execute_test(
-                            // Inputs
-                            test_folder, case_name, target, quiet,
-                            // Outputs
-                            cmd, sb,
-                        )?;
-                        match outcome {
-                            Outcome::BuildFail => da_append(&mut report.statuses, ReportStatus::BuildFail),
-                            Outcome::RunFail   => da_append(&mut report.statuses, ReportStatus::RunFail),
-                            Outcome::RunSuccess{stdout} => {
+                        let outcome = (*outcomes.items.add(i*targets.len() + j)).expect("Enabled cells are always executed");
+                        match ((*test_row).expected_diagnostics, outcome) {
+                            (Some(_), Outcome::BuildFail{diagnostics}) => {
+                                (*test_row).expected_diagnostics = Some(diagnostics);
+                                test_row_set_inputs(test_row, inputs);
+                                da_append(&mut report.statuses, ReportStatus::OK);
+                            }
+                            (Some(_), Outcome::Timeout) => da_append(&mut report.statuses, ReportStatus::Timeout),
+                            (Some(_), _)                => da_append(&mut report.statuses, ReportStatus::UnexpectedlyCompiled),
+                            (None, Outcome::BuildFail{..}) => da_append(&mut report.statuses, ReportStatus::BuildFail),
+                            (None, Outcome::Built)         => da_append(&mut report.statuses, ReportStatus::UnexpectedlyCompiled),
+                            (None, Outcome::RunFail)       => da_append(&mut report.statuses, ReportStatus::RunFail),
+                            (None, Outcome::Timeout)       => da_append(&mut report.statuses, ReportStatus::Timeout),
+                            (None, Outcome::Finished{stdout, stderr, exit_code}) => {
                                 (*test_row).expected_stdout = stdout;
+                                (*test_row).expected_stderr = stderr;
+                                (*test_row).expected_exit_code = exit_code;
+                                test_row_set_inputs(test_row, inputs);
                                 da_append(&mut report.statuses, ReportStatus::OK);
                             }
                         }
@@ -313,40 +718,98 @@ pub unsafe fn record_tests(
                     TestState::Disabled => da_append(&mut report.statuses, ReportStatus::Disabled),
                 }
             } else {
-                let outcome = execute_test(
-                    // Inputs
-                    test_folder, case_name, target, quiet,
-                    // Outputs
-                    cmd, sb,
-                )?;
+                let outcome = (*outcomes.items.add(i*targets.len() + j)).expect("Never recorded cells are always executed");
                 match outcome {
-                    Outcome::BuildFail => {
+                    // The compiler rejected the test case itself as the marker says, so it's a test of the diagnostics
+                    Outcome::BuildFail{diagnostics} if expect_build_fail && has_errors(diagnostics) => {
                         da_append(tt, TestRow {
                             case_name,
                             target,
+                            stdin: inputs.stdin,
+                            run_args: inputs.run_args,
+                            build_flags: inputs.build_flags,
                             expected_stdout: c!(""),
+                            expected_stderr: c!(""),
+                            expected_exit_code: 0,
+                            expected_diagnostics: Some(diagnostics),
+                            state: TestState::Enabled,
+                            comment: c!(""),
+                            build_timeout: None,
+                            run_timeout: None,
+                        });
+                        da_append(&mut report.statuses, ReportStatus::OK)
+                    }
+                    Outcome::BuildFail{..} => {
+                        da_append(tt, TestRow {
+                            case_name,
+                            target,
+                            stdin: inputs.stdin,
+                            run_args: inputs.run_args,
+                            build_flags: inputs.build_flags,
+                            expected_stdout: c!(""),
+                            expected_stderr: c!(""),
+                            expected_exit_code: 0,
+                            expected_diagnostics: None,
                             state: TestState::Enabled,
                             comment: c!("Failed to build on record"),
+                            build_timeout: None,
+                            run_timeout: None,
                         });
                         da_append(&mut report.statuses, ReportStatus::BuildFail)
                     },
+                    // Nothing to record, the case is supposed to be rejected
+                    Outcome::Built => da_append(&mut report.statuses, ReportStatus::UnexpectedlyCompiled),
                     Outcome::RunFail => {
                         da_append(tt, TestRow {
                             case_name,
                             target,
+                            stdin: inputs.stdin,
+                            run_args: inputs.run_args,
+                            build_flags: inputs.build_flags,
                             expected_stdout: c!(""),
+                            expected_stderr: c!(""),
+                            expected_exit_code: 0,
+                            expected_diagnostics: None,
                             state: TestState::Enabled,
                             comment: c!("Failed to run on record"),
+                            build_timeout: None,
+                            run_timeout: None,
                         });
                         da_append(&mut report.statuses, ReportStatus::RunFail)
                     }
-                    Outcome::RunSuccess{stdout} => {
+                    Outcome::Timeout => {
                         da_append(tt, TestRow {
                             case_name,
                             target,
+                            stdin: inputs.stdin,
+                            run_args: inputs.run_args,
+                            build_flags: inputs.build_flags,
+                            expected_stdout: c!(""),
+                            expected_stderr: c!(""),
+                            expected_exit_code: 0,
+                            expected_diagnostics: None,
+                            state: TestState::Enabled,
+                            comment: c!("Timed out on record"),
+                            build_timeout: None,
+                            run_timeout: None,
+                        });
+                        da_append(&mut report.statuses, ReportStatus::Timeout)
+                    }
+                    Outcome::Finished{stdout, stderr, exit_code} => {
+                        da_append(tt, TestRow {
+                            case_name,
+                            target,
+                            stdin: inputs.stdin,
+                            run_args: inputs.run_args,
+                            build_flags: inputs.build_flags,
                             expected_stdout: stdout,
+                            expected_stderr: stderr,
+                            expected_exit_code: exit_code,
+                            expected_diagnostics: None,
                             state: TestState::Enabled,
                             comment: c!(""),
+                            build_timeout: None,
+                            run_timeout: None,
                         });
                         da_append(&mut report.statuses, ReportStatus::OK);
                     }
@@ -407,13 +870,32 @@ pub unsafe fn generate_report(reports: *const [Report], stats_by_target: *const
 pub struct TestRow {
     pub case_name: *const c_char,
     pub target: Target,
+    /// The inputs the outcome was recorded with (see load_inputs()). Not saved to the JSON file when missing
+    pub stdin: Option<*const c_char>,
+    pub run_args: Option<*const c_char>,
+    pub build_flags: Option<*const c_char>,
     pub expected_stdout: *const c_char,
+    /// Not saved to the JSON file when empty
+    pub expected_stderr: *const c_char,
+    /// Not saved to the JSON file when 0
+    pub expected_exit_code: c_int,
+    /// The test is expected to fail building with these diagnostics instead of running
+    pub expected_diagnostics: Option<*const c_char>,
     pub state: TestState,
     pub comment: *const c_char,
+    /// Override the timeouts provided via the flags. In seconds.
+    pub build_timeout: Option<usize>,
+    pub run_timeout: Option<usize>,
 }
 
 type TestTable = Array<TestRow>;
 
+pub unsafe fn test_row_set_inputs(row: *mut TestRow, inputs: Inputs) {
+    (*row).stdin = inputs.stdin;
+    (*row).run_args = inputs.run_args;
+    (*row).build_flags = inputs.build_flags;
+}
+
 // TODO: test_table_find_row is O(n) which usually causes no problems on small arrays, but TestTable by the nature of the data
 // it holds has a tendency to grow rather fast (the growth is O(Cases*Targets), basically every time we add a case it adds
 // Targets amount of rows). We should invest into improving the performance of this operation rather soon. HashMap<K, V> is
@@ -452,9 +934,17 @@ pub unsafe fn load_tt_from_json_file_if_exists(
 
             let mut case_name: *const c_char = ptr::null();
             let mut target: Option<Target> = None;
+            let mut stdin: Option<*const c_char> = None;
+            let mut run_args: Option<*const c_char> = None;
+            let mut build_flags: Option<*const c_char> = None;
             let mut expected_stdout: *const c_char = c!("");
+            let mut expected_stderr: *const c_char = c!("");
+            let mut expected_exit_code: c_int = 0;
+            let mut expected_diagnostics: Option<*const c_char> = None;
             let mut state = TestState::Enabled;
             let mut comment: *const c_char = c!("");
+            let mut build_timeout: Option<usize> = None;
+            let mut run_timeout: Option<usize> = None;
 
             jimp_object_begin(jimp)?;
             'row: while jimp_object_member(jimp) {
@@ -472,11 +962,41 @@ pub unsafe fn load_tt_from_json_file_if_exists(
                     }
                     continue 'row;
                 }
+                if strcmp((*jimp).string, c!("stdin")) == 0 {
+                    jimp_string(jimp)?;
+                    stdin = Some(strdup((*jimp).string)); // TODO: memory leak
+                    continue 'row;
+                }
+                if strcmp((*jimp).string, c!("args")) == 0 {
+                    jimp_string(jimp)?;
+                    run_args = Some(strdup((*jimp).string)); // TODO: memory leak
+                    continue 'row;
+                }
+                if strcmp((*jimp).string, c!("flags")) == 0 {
+                    jimp_string(jimp)?;
+                    build_flags = Some(strdup((*jimp).string)); // TODO: memory leak
+                    continue 'row;
+                }
                 if strcmp((*jimp).string, c!("expected_stdout")) == 0 {
                     jimp_string(jimp)?;
                     expected_stdout = strdup((*jimp).string); // TODO: memory leak
                     continue 'row;
                 }
+                if strcmp((*jimp).string, c!("expected_stderr")) == 0 {
+                    jimp_string(jimp)?;
+                    expected_stderr = strdup((*jimp).string); // TODO: memory leak
+                    continue 'row;
+                }
+                if strcmp((*jimp).string, c!("expected_exit_code")) == 0 {
+                    jimp_number(jimp)?;
+                    expected_exit_code = (*jimp).number as c_int;
+                    continue 'row;
+                }
+                if strcmp((*jimp).string, c!("expected_diagnostics")) == 0 {
+                    jimp_string(jimp)?;
+                    expected_diagnostics = Some(strdup((*jimp).string)); // TODO: memory leak
+                    continue 'row;
+                }
                 if strcmp((*jimp).string, c!("state")) == 0 {
                     jimp_string(jimp)?;
                     if let Some(parsed_state) = TestState::from_name((*jimp).string) {
@@ -491,6 +1011,16 @@ pub unsafe fn load_tt_from_json_file_if_exists(
                     comment = strdup((*jimp).string); // TODO: memory leak
                     continue 'row;
                 }
+                if strcmp((*jimp).string, c!("build_timeout")) == 0 {
+                    jimp_number(jimp)?;
+                    build_timeout = Some((*jimp).number as usize);
+                    continue 'row;
+                }
+                if strcmp((*jimp).string, c!("run_timeout")) == 0 {
+                    jimp_number(jimp)?;
+                    run_timeout = Some((*jimp).number as usize);
+                    continue 'row;
+                }
 
                 jimp_diagf(jimp, c!("ERROR: unknown test row field `%s`\n"), (*jimp).string);
                 return None;
@@ -532,9 +1062,17 @@ pub unsafe fn load_tt_from_json_file_if_exists(
             da_append(&mut tt, TestRow {
                 case_name,
                 target,
+                stdin,
+                run_args,
+                build_flags,
                 expected_stdout,
+                expected_stderr,
+                expected_exit_code,
+                expected_diagnostics,
                 state,
                 comment,
+                build_timeout,
+                run_timeout,
             });
         }
         jimp_array_end(jimp)?;
@@ -560,12 +1098,44 @@ pub unsafe fn save_tt_to_json_file(
         jim_string(jim, (*row).case_name);
         jim_member_key(jim, c!("target"));
         jim_string(jim, (*row).target.api.name());
+        if let Some(stdin) = (*row).stdin {
+            jim_member_key(jim, c!("stdin"));
+            jim_string(jim, stdin);
+        }
+        if let Some(run_args) = (*row).run_args {
+            jim_member_key(jim, c!("args"));
+            jim_string(jim, run_args);
+        }
+        if let Some(build_flags) = (*row).build_flags {
+            jim_member_key(jim, c!("flags"));
+            jim_string(jim, build_flags);
+        }
         jim_member_key(jim, c!("expected_stdout"));
         jim_string(jim, (*row).expected_stdout);
+        if *(*row).expected_stderr != 0 {
+            jim_member_key(jim, c!("expected_stderr"));
+            jim_string(jim, (*row).expected_stderr);
+        }
+        if (*row).expected_exit_code != 0 {
+            jim_member_key(jim, c!("expected_exit_code"));
+            jim_integer(jim, (*row).expected_exit_code as c_longlong);
+        }
+        if let Some(expected_diagnostics) = (*row).expected_diagnostics {
+            jim_member_key(jim, c!("expected_diagnostics"));
+            jim_string(jim, expected_diagnostics);
+        }
         jim_member_key(jim, c!("state"));
         jim_string(jim, (*row).state.name());
         jim_member_key(jim, c!("comment"));
         jim_string(jim, (*row).comment);
+        if let Some(build_timeout) = (*row).build_timeout {
+            jim_member_key(jim, c!("build_timeout"));
+            jim_integer(jim, build_timeout as c_longlong);
+        }
+        if let Some(run_timeout) = (*row).run_timeout {
+            jim_member_key(jim, c!("run_timeout"));
+            jim_integer(jim, run_timeout as c_longlong);
+        }
 
         jim_object_end(jim);
     }
@@ -574,19 +1144,36 @@ pub unsafe fn save_tt_to_json_file(
     write_entire_file(json_path, (*jim).sink as *const c_void, (*jim).sink_count)
 }
 
+pub unsafe fn print_output_mismatch(
+    case_name: *const c_char, target: Target, stream: *const c_char, expected: *const c_char, actual: *const c_char,
+    jim: *mut Jim,
+) {
+    fprintf(stderr(), c!("UNEXPECTED OUTCOME!!! %s on %s\n"), case_name, target.api.name());
+    jim_begin(jim);
+    jim_string(jim, expected);
+    fprintf(stderr(), c!("EXPECTED %s: %.*s\n"), stream, (*jim).sink_count, (*jim).sink);
+    jim_begin(jim);
+    jim_string(jim, actual);
+    fprintf(stderr(), c!("ACTUAL %s:   %.*s\n"), stream, (*jim).sink_count, (*jim).sink);
+}
+
 pub unsafe fn replay_tests(
     // TODO: The Inputs and the Outputs want to be their own entity. But what should they be called?
     // Inputs
-    test_folder: *const c_char, cases: *const [*const c_char], targets: *const [Target], mut tt: TestTable, quiet: bool,
+    test_folder: *const c_char, cases: *const [*const c_char], targets: *const [Target], mut tt: TestTable, options: RunOptions,
     // Outputs
     cmd: *mut Cmd, sb: *mut String_Builder, reports: *mut Array<Report>, stats_by_target: *mut Array<ReportStats>, jim: *mut Jim,
 ) -> Option<()> {
-
-    // TODO: Parallelize the test runner.
-    // Probably using `cmd_run_async_and_reset`.
-    // Also don't forget to add the `-j` flag.
+    let outcomes = execute_test_matrix(
+        // Inputs
+        test_folder, cases, targets, &mut tt, options,
+        // Outputs
+        cmd, sb,
+    )?;
     for i in 0..cases.len() {
         let case_name = (*cases)[i];
+        let inputs = load_inputs(test_folder, case_name, sb)?;
+        let expect_build_fail = expects_build_fail(test_folder, case_name, sb)?;
         let mut report = Report {
             name: case_name,
             statuses: zeroed(),
@@ -596,48 +1183,62 @@ pub unsafe fn replay_tests(
             let target = (*targets)[j];
             if let Some(row) = test_table_find_row(&mut tt, case_name, target) {
                 match (*row).state {
+                    TestState::Enabled if inputs_mismatch(row, inputs, jim) => da_append(&mut report.statuses, ReportStatus::InputsMismatch),
                     TestState::Enabled => {
-                        let outcome = execute_test(
-                            // Inputs
-                            test_folder, case_name, target, quiet,
-                            // Outputs
-                            cmd, sb,
-                        )?;
-                        match outcome {
-                            Outcome::RunSuccess{stdout} =>
-                                if strcmp((*row).expected_stdout, stdout) != 0 {
-                                    fprintf(stderr(), c!("UNEXPECTED OUTCOME!!!\n"));
-                                    jim_begin(jim);
-                                    jim_string(jim, (*row).expected_stdout);
-                                    fprintf(stderr(), c!("EXPECTED: %.*s\n"), (*jim).sink_count, (*jim).sink);
-                                    jim_begin(jim);
-                                    jim_string(jim, stdout);
-                                    fprintf(stderr(), c!("ACTUAL:   %.*s\n"), (*jim).sink_count, (*jim).sink);
+                        let outcome = (*outcomes.items.add(i*targets.len() + j)).expect("Enabled cells are always executed");
+                        match ((*row).expected_diagnostics, outcome) {
+                            (Some(expected_diagnostics), Outcome::BuildFail{diagnostics}) =>
+                                if strcmp(expected_diagnostics, diagnostics) != 0 {
+                                    print_output_mismatch(case_name, target, c!("diagnostics"), expected_diagnostics, diagnostics, jim);
+                                    da_append(&mut report.statuses, ReportStatus::DiagnosticsMismatch);
+                                } else {
+                                    da_append(&mut report.statuses, ReportStatus::OK);
+                                },
+                            (Some(_), Outcome::Timeout) => da_append(&mut report.statuses, ReportStatus::Timeout),
+                            (Some(_), _) => {
+                                fprintf(stderr(), c!("UNEXPECTED OUTCOME!!! %s on %s was expected to fail building, but it compiled\n"), case_name, target.api.name());
+                                da_append(&mut report.statuses, ReportStatus::UnexpectedlyCompiled);
+                            }
+                            (None, Outcome::Finished{stdout, stderr: stderr_, exit_code}) =>
+                                if (*row).expected_exit_code != exit_code {
+                                    fprintf(stderr(), c!("UNEXPECTED OUTCOME!!! %s on %s\n"), case_name, target.api.name());
+                                    fprintf(stderr(), c!("EXPECTED EXIT CODE: %d\n"), (*row).expected_exit_code);
+                                    fprintf(stderr(), c!("ACTUAL EXIT CODE:   %d\n"), exit_code);
+                                    da_append(&mut report.statuses, ReportStatus::ExitCodeMismatch);
+                                } else if strcmp((*row).expected_stdout, stdout) != 0 {
+                                    print_output_mismatch(case_name, target, c!("stdout"), (*row).expected_stdout, stdout, jim);
                                     da_append(&mut report.statuses, ReportStatus::StdoutMismatch);
+                                } else if strcmp((*row).expected_stderr, stderr_) != 0 {
+                                    print_output_mismatch(case_name, target, c!("stderr"), (*row).expected_stderr, stderr_, jim);
+                                    da_append(&mut report.statuses, ReportStatus::StderrMismatch);
                                 } else {
                                     da_append(&mut report.statuses, ReportStatus::OK);
                                 },
-                            Outcome::BuildFail => da_append(&mut report.statuses, ReportStatus::BuildFail),
-                            Outcome::RunFail   => da_append(&mut report.statuses, ReportStatus::RunFail),
+                            (None, Outcome::BuildFail{..}) => da_append(&mut report.statuses, ReportStatus::BuildFail),
+                            (None, Outcome::Built)         => da_append(&mut report.statuses, ReportStatus::UnexpectedlyCompiled),
+                            (None, Outcome::RunFail)       => da_append(&mut report.statuses, ReportStatus::RunFail),
+                            (None, Outcome::Timeout)       => da_append(&mut report.statuses, ReportStatus::Timeout),
                         }
                     }
                     TestState::Disabled => da_append(&mut report.statuses, ReportStatus::Disabled),
                 }
             } else {
-                let outcome = execute_test(
-                    // Inputs
-                    test_folder, case_name, target, quiet,
-                    // Outputs
-                    cmd, sb,
-                )?;
+                let outcome = (*outcomes.items.add(i*targets.len() + j)).expect("Never recorded cells are always executed");
 
                 match outcome {
-                    Outcome::RunSuccess{..} => {
+                    Outcome::Finished{..} => {
                         fprintf(stderr(), c!("UNEXPECTED OUTCOME!!! The outcome was never recorded. Please use -record flag to record what is expected for this test case at this target\n"));
                         da_append(&mut report.statuses, ReportStatus::NeverRecorded);
                     }
-                    Outcome::BuildFail => da_append(&mut report.statuses, ReportStatus::BuildFail),
+                    // Recording it makes it a test of the diagnostics, see record_tests()
+                    Outcome::BuildFail{diagnostics} if expect_build_fail && has_errors(diagnostics) => {
+                        fprintf(stderr(), c!("UNEXPECTED OUTCOME!!! The diagnostics were never recorded. Please use -record flag to record what is expected for this test case at this target\n"));
+                        da_append(&mut report.statuses, ReportStatus::NeverRecorded);
+                    }
+                    Outcome::BuildFail{..} => da_append(&mut report.statuses, ReportStatus::BuildFail),
+                    Outcome::Built     => da_append(&mut report.statuses, ReportStatus::UnexpectedlyCompiled),
                     Outcome::RunFail   => da_append(&mut report.statuses, ReportStatus::RunFail),
+                    Outcome::Timeout   => da_append(&mut report.statuses, ReportStatus::Timeout),
                 }
             }
         }
@@ -703,6 +1304,10 @@ pub unsafe fn main(argc: i32, argv: *mut*mut c_char) -> Option<()> {
 
     let test_folder          = flag_str(c!("dir"), c!("./tests/"), c!("Test folder"));
     let quiet                = flag_bool(c!("q"), false, c!("Makes the test runner yap less about what it's doing"));
+    let jobs                 = flag_size(c!("j"), cpu_count() as u64, c!("Amount of tests to build and run in parallel. Defaults to the amount of processors"));
+    let build_timeout        = flag_size(c!("build-timeout"), 60, c!("Seconds a test may take to build before it is killed. 0 means no timeout. Can be overridden by the `build_timeout` field of the row in tests.json"));
+    let run_timeout          = flag_size(c!("run-timeout"), 30, c!("Seconds a test may take to run before it is killed. 0 means no timeout. Can be overridden by the `run_timeout` field of the row in tests.json"));
+    let codegen_args         = flag_list(c!("C"), c!("Codegen parameter to pass to every build, like `-C assembler=builtin`. Can be specified several times"));
     let help                 = flag_bool(c!("help"), false, c!("Print this help message"));
 
     if !flag_parse(argc, argv) {
@@ -759,6 +1364,21 @@ pub unsafe fn main(argc: i32, argv: *mut*mut c_char) -> Option<()> {
         minimal_log_level = Log_Level::WARNING;
     }
 
+    // Otherwise Ctrl-C leaves the tests that are being built and run behind
+    proc_kill_all_on_signals();
+
+    if *jobs == 0 {
+        fprintf(stderr(), c!("ERROR: -%s must be at least 1\n"), flag_name(jobs));
+        return None;
+    }
+    let options = RunOptions {
+        jobs: *jobs,
+        quiet: *quiet,
+        build_timeout: *build_timeout,
+        run_timeout: *run_timeout,
+        codegen_args: *codegen_args,
+    };
+
     let mut sb: String_Builder = zeroed();
     let mut cmd: Cmd = zeroed();
     let mut jim: Jim = zeroed();
@@ -884,7 +1504,7 @@ pub unsafe fn main(argc: i32, argv: *mut*mut c_char) -> Option<()> {
             let mut tt = load_tt_from_json_file_if_exists(da_slice(all_targets), json_path, *test_folder, &mut sb, &mut jimp)?;
             record_tests(
                 // Inputs
-                *test_folder, da_slice(cases), da_slice(targets), &mut tt, *quiet,
+                *test_folder, da_slice(cases), da_slice(targets), &mut tt, options,
                 // Outputs
                 &mut cmd, &mut sb, &mut reports, &mut stats_by_target,
             )?;
@@ -894,7 +1514,7 @@ pub unsafe fn main(argc: i32, argv: *mut*mut c_char) -> Option<()> {
             let tt = load_tt_from_json_file_if_exists(da_slice(all_targets), json_path, *test_folder, &mut sb, &mut jimp)?;
             replay_tests(
                 // Inputs
-                *test_folder, da_slice(cases), da_slice(targets), tt, *quiet,
+                *test_folder, da_slice(cases), da_slice(targets), tt, options,
                 // Outputs
                 &mut cmd, &mut sb, &mut reports, &mut stats_by_target, &mut jim,
             );
@@ -931,13 +1551,21 @@ pub unsafe fn main(argc: i32, argv: *mut*mut c_char) -> Option<()> {
                         da_append(&mut tt, TestRow {
                             case_name,
                             target,
+                            stdin: None,
+                            run_args: None,
+                            build_flags: None,
                             expected_stdout: c!(""),
+                            expected_stderr: c!(""),
+                            expected_exit_code: 0,
+                            expected_diagnostics: None,
                             state: TestState::Disabled,
                             comment: if (*comment).is_null() {
                                 c!("")
                             } else {
                                 *comment
                             },
+                            build_timeout: None,
+                            run_timeout: None,
                         });
                     }
                 }
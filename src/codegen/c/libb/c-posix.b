@@ -0,0 +1,13 @@
+/* Standard Library for the c-posix target
+ *
+ * The program is linked with libc. sx64, char and lchar are generated by the codegen itself
+ * since they can't be implemented in B. See PRELUDE_FUNCS in mod.rs
+ */
+extrn sx64;
+extrn printf;
+extrn putchar;
+extrn getchar;
+extrn exit;
+extrn char;
+extrn lchar;
+__variadic__(printf, 1);
@@ -0,0 +1,535 @@
+//! Lowers the IR to C and builds it with the system C compiler.
+//!
+//! Every Func becomes a C function that keeps all of its auto vars in a single array of
+//! intptr_t-s laid out the same way the native codegens lay them out on the stack (see
+//! Arg::RefAutoVar), so pointer arithmetic over the auto vectors keeps working. Every value is an
+//! intptr_t, every symbol is just an address, and every call goes through a function pointer of the
+//! appropriate arity. The generated code is not pretty, but it's straightforward to map back to
+//! the IR dumped with the -ir flag.
+
+use core::ffi::*;
+use core::mem::zeroed;
+use crate::ir::*;
+use crate::nob::*;
+use crate::targets::TargetAPI;
+use crate::crust::libc::*;
+use crate::crust::assoc_lookup_cstr;
+use crate::shlex::*;
+use crate::diagf;
+use crate::arena;
+use crate::params::*;
+use crate::proc::*;
+
+/// Prefix of all the names the codegen makes up. B names that clash with the C keywords get it too.
+const PREFIX: *const c_char = c!("b__");
+
+/// B names that can't be used as C identifiers as is.
+const C_KEYWORDS: *const [*const c_char] = &[
+    c!("auto"), c!("break"), c!("case"), c!("char"), c!("const"), c!("continue"), c!("default"),
+    c!("do"), c!("double"), c!("else"), c!("enum"), c!("extern"), c!("float"), c!("for"),
+    c!("goto"), c!("if"), c!("inline"), c!("int"), c!("long"), c!("register"), c!("restrict"),
+    c!("return"), c!("short"), c!("signed"), c!("sizeof"), c!("static"), c!("struct"),
+    c!("switch"), c!("typedef"), c!("union"), c!("unsigned"), c!("void"), c!("volatile"),
+    c!("while"), c!("asm"), c!("bool"), c!("true"), c!("false"), c!("intptr_t"), c!("uintptr_t"),
+    // main() of B has a different signature than main() of C, so we rename it and generate
+    // a C main() that calls it. See generate_main().
+    c!("main"),
+];
+
+/// The functions of libb that can't be implemented in B on this target. They are generated by
+/// the codegen itself unless the program defines them. See libb/c-posix.b
+const PRELUDE_FUNCS: *const [(*const c_char, *const c_char)] = &[
+    (c!("sx64"),  c!("(intptr_t x) { return (int32_t)x; }")),
+    (c!("char"),  c!("(intptr_t string, intptr_t i) { return ((unsigned char*)string)[i]; }")),
+    (c!("lchar"), c!("(intptr_t string, intptr_t i, intptr_t c) { ((unsigned char*)string)[i] = (unsigned char)c; return c; }")),
+];
+
+pub unsafe fn is_c_keyword(name: *const c_char) -> bool {
+    for i in 0..C_KEYWORDS.len() {
+        if strcmp((*C_KEYWORDS)[i], name) == 0 {
+            return true;
+        }
+    }
+    false
+}
+
+pub unsafe fn generate_name(output: *mut String_Builder, name: *const c_char) {
+    if is_c_keyword(name) {
+        sb_appendf(output, c!("%s"), PREFIX);
+    }
+    sb_appendf(output, c!("%s"), name);
+}
+
+pub unsafe fn generate_auto_var(output: *mut String_Builder, index: usize, auto_vars_count: usize) {
+    sb_appendf(output, c!("%sautos[%zu]"), PREFIX, auto_vars_count - index);
+}
+
+pub unsafe fn generate_arg(output: *mut String_Builder, arg: Arg, auto_vars_count: usize) {
+    match arg {
+        Arg::Bogus => unreachable!("bogus-amogus"),
+        Arg::AutoVar(index) => generate_auto_var(output, index, auto_vars_count),
+        Arg::Deref(index) => {
+            sb_appendf(output, c!("*(intptr_t*)"));
+            generate_auto_var(output, index, auto_vars_count);
+        }
+        Arg::RefAutoVar(index) => {
+            sb_appendf(output, c!("(intptr_t)&"));
+            generate_auto_var(output, index, auto_vars_count);
+        }
+        Arg::RefExternal(name) => {
+            sb_appendf(output, c!("(intptr_t)"));
+            generate_name(output, name);
+        }
+        Arg::External(name) => {
+            sb_appendf(output, c!("*(intptr_t*)"));
+            generate_name(output, name);
+        }
+        Arg::Literal(value) => {
+            sb_appendf(output, c!("(intptr_t)0x%llXULL"), value);
+        }
+        Arg::DataOffset(offset) => {
+            sb_appendf(output, c!("(intptr_t)&%sdata[%zu]"), PREFIX, offset);
+        }
+    }
+}
+
+/// Generates the type of a pointer to a function that accepts `args_count` words
+pub unsafe fn generate_func_type(output: *mut String_Builder, args_count: usize, variadic: Option<Variadic>) {
+    sb_appendf(output, c!("intptr_t (*)("));
+    let fixed_args = if let Some(variadic) = variadic { variadic.fixed_args } else { args_count };
+    for i in 0..fixed_args {
+        if i > 0 { sb_appendf(output, c!(", ")); }
+        sb_appendf(output, c!("intptr_t"));
+    }
+    if variadic.is_some() {
+        if fixed_args > 0 { sb_appendf(output, c!(", ")); }
+        sb_appendf(output, c!("..."));
+    } else if fixed_args == 0 {
+        sb_appendf(output, c!("void"));
+    }
+    sb_appendf(output, c!(")"));
+}
+
+pub unsafe fn generate_op(output: *mut String_Builder, op: Op, auto_vars_count: usize, variadics: *const [(*const c_char, Variadic)]) {
+    match op {
+        Op::Bogus => unreachable!("bogus-amogus"),
+        Op::UnaryNot {result, arg} => {
+            sb_appendf(output, c!("    "));
+            generate_auto_var(output, result, auto_vars_count);
+            sb_appendf(output, c!(" = !"));
+            generate_arg(output, arg, auto_vars_count);
+            sb_appendf(output, c!(";\n"));
+        }
+        Op::Negate {result, arg} => {
+            sb_appendf(output, c!("    "));
+            generate_auto_var(output, result, auto_vars_count);
+            sb_appendf(output, c!(" = (intptr_t)-(uintptr_t)"));
+            generate_arg(output, arg, auto_vars_count);
+            sb_appendf(output, c!(";\n"));
+        }
+        Op::Asm {..} => unreachable!("inline assembly must be rejected by check_program()"),
+        Op::Binop {binop, index, lhs, rhs} => {
+            sb_appendf(output, c!("    "));
+            generate_auto_var(output, index, auto_vars_count);
+            // The arithmetic is done on unsigned words so the overflows wrap around the same way
+            // they do on the native targets instead of being Undefined Behavior.
+            let (prefix, op, infix) = match binop {
+                Binop::Plus         => (c!("(intptr_t)((uintptr_t)"), c!(" + "),  c!("(uintptr_t)")),
+                Binop::Minus        => (c!("(intptr_t)((uintptr_t)"), c!(" - "),  c!("(uintptr_t)")),
+                Binop::Mult         => (c!("(intptr_t)((uintptr_t)"), c!(" * "),  c!("(uintptr_t)")),
+                Binop::BitShl       => (c!("(intptr_t)((uintptr_t)"), c!(" << "), c!("")),
+                Binop::BitShr       => (c!("(intptr_t)((uintptr_t)"), c!(" >> "), c!("")),
+                Binop::Div          => (c!("("), c!(" / "),  c!("")),
+                Binop::Mod          => (c!("("), c!(" % "),  c!("")),
+                Binop::BitOr        => (c!("("), c!(" | "),  c!("")),
+                Binop::BitAnd       => (c!("("), c!(" & "),  c!("")),
+                Binop::Equal        => (c!("("), c!(" == "), c!("")),
+                Binop::NotEqual     => (c!("("), c!(" != "), c!("")),
+                Binop::Less         => (c!("("), c!(" < "),  c!("")),
+                Binop::LessEqual    => (c!("("), c!(" <= "), c!("")),
+                Binop::Greater      => (c!("("), c!(" > "),  c!("")),
+                Binop::GreaterEqual => (c!("("), c!(" >= "), c!("")),
+            };
+            sb_appendf(output, c!(" = %s"), prefix);
+            generate_arg(output, lhs, auto_vars_count);
+            sb_appendf(output, c!("%s%s"), op, infix);
+            generate_arg(output, rhs, auto_vars_count);
+            sb_appendf(output, c!(");\n"));
+        }
+        Op::Index {result, arg, offset} => {
+            sb_appendf(output, c!("    "));
+            generate_auto_var(output, result, auto_vars_count);
+            sb_appendf(output, c!(" = (intptr_t)((uintptr_t)"));
+            generate_arg(output, arg, auto_vars_count);
+            sb_appendf(output, c!(" + (uintptr_t)"));
+            generate_arg(output, offset, auto_vars_count);
+            sb_appendf(output, c!("*sizeof(intptr_t));\n"));
+        }
+        Op::AutoAssign {index, arg} => {
+            sb_appendf(output, c!("    "));
+            generate_auto_var(output, index, auto_vars_count);
+            sb_appendf(output, c!(" = "));
+            generate_arg(output, arg, auto_vars_count);
+            sb_appendf(output, c!(";\n"));
+        }
+        Op::ExternalAssign {name, arg} => {
+            sb_appendf(output, c!("    *(intptr_t*)"));
+            generate_name(output, name);
+            sb_appendf(output, c!(" = "));
+            generate_arg(output, arg, auto_vars_count);
+            sb_appendf(output, c!(";\n"));
+        }
+        Op::Store {index, arg} => {
+            sb_appendf(output, c!("    *(intptr_t*)"));
+            generate_auto_var(output, index, auto_vars_count);
+            sb_appendf(output, c!(" = "));
+            generate_arg(output, arg, auto_vars_count);
+            sb_appendf(output, c!(";\n"));
+        }
+        Op::Funcall {result, fun, args} => {
+            sb_appendf(output, c!("    "));
+            generate_auto_var(output, result, auto_vars_count);
+            sb_appendf(output, c!(" = (("));
+            let mut variadic = None;
+            match fun {
+                Arg::External(name) | Arg::RefExternal(name) => {
+                    for i in 0..variadics.len() {
+                        if strcmp((*variadics)[i].0, name) == 0 {
+                            variadic = Some((*variadics)[i].1);
+                            break;
+                        }
+                    }
+                    generate_func_type(output, args.count, variadic);
+                    sb_appendf(output, c!(")"));
+                    generate_name(output, name);
+                }
+                fun => {
+                    generate_func_type(output, args.count, variadic);
+                    sb_appendf(output, c!(")"));
+                    generate_arg(output, fun, auto_vars_count);
+                }
+            }
+            sb_appendf(output, c!(")("));
+            for i in 0..args.count {
+                if i > 0 { sb_appendf(output, c!(", ")); }
+                generate_arg(output, *args.items.add(i), auto_vars_count);
+            }
+            sb_appendf(output, c!(");\n"));
+        }
+        Op::Label {label} => {
+            sb_appendf(output, c!("%slabel_%zu:;\n"), PREFIX, label);
+        }
+        Op::JmpLabel {label} => {
+            sb_appendf(output, c!("    goto %slabel_%zu;\n"), PREFIX, label);
+        }
+        Op::JmpIfNotLabel {label, arg} => {
+            sb_appendf(output, c!("    if (!"));
+            generate_arg(output, arg, auto_vars_count);
+            sb_appendf(output, c!(") goto %slabel_%zu;\n"), PREFIX, label);
+        }
+        Op::Return {arg} => {
+            sb_appendf(output, c!("    return "));
+            if let Some(arg) = arg {
+                generate_arg(output, arg, auto_vars_count);
+            } else {
+                sb_appendf(output, c!("0"));
+            }
+            sb_appendf(output, c!(";\n"));
+        }
+    }
+}
+
+pub unsafe fn generate_func_signature(output: *mut String_Builder, func: Func) {
+    sb_appendf(output, c!("intptr_t "));
+    generate_name(output, func.name);
+    sb_appendf(output, c!("("));
+    for i in 0..func.params_count {
+        if i > 0 { sb_appendf(output, c!(", ")); }
+        sb_appendf(output, c!("intptr_t %sparam_%zu"), PREFIX, i + 1);
+    }
+    if func.params_count == 0 {
+        sb_appendf(output, c!("void"));
+    }
+    sb_appendf(output, c!(")"));
+}
+
+pub unsafe fn generate_func(output: *mut String_Builder, func: Func, variadics: *const [(*const c_char, Variadic)], debug: bool) {
+    generate_func_signature(output, func);
+    sb_appendf(output, c!(" {\n"));
+    sb_appendf(output, c!("    intptr_t %sautos[%zu];\n"), PREFIX, func.auto_vars_count + 1);
+    for i in 0..func.params_count {
+        sb_appendf(output, c!("    "));
+        generate_auto_var(output, i + 1, func.auto_vars_count);
+        sb_appendf(output, c!(" = %sparam_%zu;\n"), PREFIX, i + 1);
+    }
+    for i in 0..func.body.count {
+        let op = *func.body.items.add(i);
+        if debug {
+            sb_appendf(output, c!("#line %d \"%s\"\n"), op.loc.line_number, op.loc.input_path);
+        }
+        generate_op(output, op.opcode, func.auto_vars_count, variadics);
+    }
+    sb_appendf(output, c!("    return 0;\n"));
+    sb_appendf(output, c!("}\n"));
+}
+
+pub unsafe fn is_defined(p: *const Program, name: *const c_char) -> bool {
+    for i in 0..(*p).funcs.count {
+        if strcmp((*(*p).funcs.items.add(i)).name, name) == 0 { return true; }
+    }
+    for i in 0..(*p).globals.count {
+        if strcmp((*(*p).globals.items.add(i)).name, name) == 0 { return true; }
+    }
+    false
+}
+
+pub unsafe fn global_words_count(global: Global) -> usize {
+    let mut count = global.values.count;
+    if count < global.minimum_size {
+        count = global.minimum_size;
+    }
+    if global.is_vec {
+        count += 1;
+    }
+    if count == 0 { 1 } else { count }
+}
+
+pub unsafe fn generate_global(output: *mut String_Builder, global: Global) {
+    sb_appendf(output, c!("intptr_t "));
+    generate_name(output, global.name);
+    sb_appendf(output, c!("[%zu] = {"), global_words_count(global));
+    if global.is_vec {
+        // The value of a vector is the address of its first element that goes right after it
+        sb_appendf(output, c!("(intptr_t)&"));
+        generate_name(output, global.name);
+        sb_appendf(output, c!("[1]"));
+        if global.values.count > 0 {
+            sb_appendf(output, c!(", "));
+        }
+    }
+    for i in 0..global.values.count {
+        if i > 0 { sb_appendf(output, c!(", ")); }
+        match *global.values.items.add(i) {
+            ImmediateValue::Literal(value) => {
+                sb_appendf(output, c!("(intptr_t)0x%llXULL"), value);
+            }
+            ImmediateValue::Name(name) => {
+                sb_appendf(output, c!("(intptr_t)"));
+                generate_name(output, name);
+            }
+            ImmediateValue::DataOffset(offset) => {
+                sb_appendf(output, c!("(intptr_t)&%sdata[%zu]"), PREFIX, offset);
+            }
+        }
+    }
+    if global.values.count == 0 && !global.is_vec {
+        sb_appendf(output, c!("0"));
+    }
+    sb_appendf(output, c!("};\n"));
+}
+
+pub unsafe fn generate_main(output: *mut String_Builder) {
+    sb_appendf(output, c!("int main(int argc, char **argv) {\n"));
+    sb_appendf(output, c!("    return (int)((intptr_t (*)(intptr_t, intptr_t))%smain)((intptr_t)argc, (intptr_t)argv);\n"), PREFIX);
+    sb_appendf(output, c!("}\n"));
+}
+
+pub unsafe fn generate_c_program(output: *mut String_Builder, p: *const Program, debug: bool) {
+    sb_appendf(output, c!("// Generated by the B compiler. Do not edit.\n"));
+    sb_appendf(output, c!("#include <stdint.h>\n"));
+    sb_appendf(output, c!("\n"));
+
+    // The symbols that are not defined by the program are declared as byte arrays. We never use
+    // their types anyway, just their addresses.
+    for i in 0..(*p).extrns.count {
+        let name = *(*p).extrns.items.add(i);
+        if is_defined(p, name) { continue; }
+        if assoc_lookup_cstr(PRELUDE_FUNCS, name).is_some() { continue; }
+        sb_appendf(output, c!("extern char "));
+        generate_name(output, name);
+        sb_appendf(output, c!("[];\n"));
+    }
+    for i in 0..(*p).funcs.count {
+        generate_func_signature(output, *(*p).funcs.items.add(i));
+        sb_appendf(output, c!(";\n"));
+    }
+    for i in 0..(*p).globals.count {
+        let global = *(*p).globals.items.add(i);
+        sb_appendf(output, c!("extern intptr_t "));
+        generate_name(output, global.name);
+        sb_appendf(output, c!("[%zu];\n"), global_words_count(global));
+    }
+    sb_appendf(output, c!("\n"));
+
+    for i in 0..PRELUDE_FUNCS.len() {
+        let (name, definition) = (*PRELUDE_FUNCS)[i];
+        if is_defined(p, name) { continue; }
+        sb_appendf(output, c!("intptr_t "));
+        generate_name(output, name);
+        sb_appendf(output, c!("%s\n"), definition);
+    }
+    sb_appendf(output, c!("\n"));
+
+    sb_appendf(output, c!("static unsigned char %sdata[%zu] = {"), PREFIX, (*p).data.count + 1);
+    for i in 0..(*p).data.count {
+        if i%16 == 0 { sb_appendf(output, c!("\n   ")); }
+        sb_appendf(output, c!(" 0x%02X,"), *(*p).data.items.add(i) as c_uint);
+    }
+    // An extra byte so the array is never empty
+    sb_appendf(output, c!("\n    0x00\n};\n"));
+    for i in 0..(*p).globals.count {
+        generate_global(output, *(*p).globals.items.add(i));
+    }
+    sb_appendf(output, c!("\n"));
+
+    for i in 0..(*p).funcs.count {
+        generate_func(output, *(*p).funcs.items.add(i), da_slice((*p).variadics), debug);
+        sb_appendf(output, c!("\n"));
+    }
+
+    for i in 0..(*p).funcs.count {
+        if strcmp((*(*p).funcs.items.add(i)).name, c!("main")) == 0 {
+            generate_main(output);
+            break;
+        }
+    }
+}
+
+pub unsafe fn check_program(p: *const Program) -> Option<()> {
+    let mut ok = true;
+    for i in 0..(*p).funcs.count {
+        let func = *(*p).funcs.items.add(i);
+        for j in 0..func.body.count {
+            let op = *func.body.items.add(j);
+            if let Op::Asm {..} = op.opcode {
+                diagf!(op.loc, c!("ERROR: inline assembly is not supported by the c-posix target\n"));
+                ok = false;
+            }
+        }
+    }
+    for i in 0..(*p).asm_funcs.count {
+        let asm_func = *(*p).asm_funcs.items.add(i);
+        diagf!(asm_func.name_loc, c!("ERROR: assembly function `%s` is not supported by the c-posix target\n"), asm_func.name);
+        ok = false;
+    }
+    if ok { Some(()) } else { None }
+}
+
+pub unsafe fn usage(params: *const [Param]) {
+    fprintf(stderr(), c!("C codegen for the B compiler\n"));
+    fprintf(stderr(), c!("OPTIONS:\n"));
+    print_params_help(params);
+}
+
+struct C_Posix {
+    cc: *const c_char,
+    link_args: *const c_char,
+    output: String_Builder,
+    cmd: Cmd,
+}
+
+pub unsafe fn get_apis(targets: *mut Array<TargetAPI>) {
+    da_append(targets, TargetAPI::V1 {
+        name: c!("c-posix"),
+        file_ext: c!(""),
+        new,
+        build: generate_program,
+        run: run_program,
+    });
+}
+
+pub unsafe fn new(a: *mut arena::Arena, args: *const [*const c_char]) -> Option<*mut c_void> {
+    let gen = arena::alloc_type::<C_Posix>(a);
+    memset(gen as _ , 0, size_of::<C_Posix>());
+
+    let mut help = false;
+    let params = &[
+        Param {
+            name:        c!("help"),
+            description: c!("Print this help message"),
+            value:       ParamValue::Flag { var: &mut help },
+        },
+        Param {
+            name:        c!("cc"),
+            description: c!("C compiler to build the generated code with"),
+            value:       ParamValue::String { var: &mut (*gen).cc, default: c!("cc") },
+        },
+        Param {
+            name:        c!("link-args"),
+            description: c!("Additional linker arguments"),
+            value:       ParamValue::String { var: &mut (*gen).link_args, default: c!("") },
+        },
+    ];
+
+    if let Err(message) = parse_args(params, args) {
+        usage(params);
+        log(Log_Level::ERROR, c!("%s"), message);
+        return None;
+    }
+
+    if help {
+        usage(params);
+        return None;
+    }
+
+    Some(gen as *mut c_void)
+}
+
+pub unsafe fn generate_program(
+    gen: *mut c_void, program: *const Program, program_path: *const c_char, garbage_base: *const c_char,
+    nostdlib: bool, debug: bool,
+) -> Option<()> {
+    let gen = gen as *mut C_Posix;
+    let output = &mut (*gen).output;
+    let cmd = &mut (*gen).cmd;
+
+    check_program(program)?;
+    generate_c_program(output, program, debug);
+
+    let output_c_path = temp_sprintf(c!("%s.c"), garbage_base);
+    write_entire_file(output_c_path, (*output).items as *const c_void, (*output).count)?;
+    log(Log_Level::INFO, c!("generated %s"), output_c_path);
+
+    // The generated code casts between the integers, data pointers and function pointers a lot
+    // and declares the libc functions as arrays of bytes, so the warnings are just noise.
+    cmd_append! {
+        cmd,
+        (*gen).cc, c!("-w"), c!("-fno-builtin"), c!("-fPIC"), c!("-o"), program_path, output_c_path,
+    }
+    if debug {
+        cmd_append!(cmd, c!("-g"));
+    }
+    if nostdlib {
+        cmd_append!(cmd, c!("-nostdlib"));
+    }
+    let mut s: Shlex = zeroed();
+    let link_args = (*gen).link_args;
+    shlex_init(&mut s, link_args, link_args.add(strlen(link_args)));
+    while !shlex_next(&mut s).is_null() {
+        da_append(cmd, temp_strdup(s.string));
+    }
+    shlex_free(&mut s);
+    if !cmd_run_sync_and_reset(cmd) { return None; }
+
+    Some(())
+}
+
+pub unsafe fn run_program(
+    gen: *mut c_void, program_path: *const c_char, run_args: *const [*const c_char],
+) -> Option<()> {
+    let gen = gen as *mut C_Posix;
+    let cmd = &mut (*gen).cmd;
+
+    // if the user does `b program.b -run` the compiler tries to run `program` which is not possible on POSIX. It has to be `./program`.
+    let run_path: *const c_char;
+    if (strchr(program_path, '/' as c_int)).is_null() {
+        run_path = temp_sprintf(c!("./%s"), program_path);
+    } else {
+        run_path = program_path;
+    }
+
+    cmd_append! {cmd, run_path}
+    da_append_many(cmd, run_args);
+    cmd_run_sync_forwarding_exit_code_and_reset(cmd)?;
+    Some(())
+}
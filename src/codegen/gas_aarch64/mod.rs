@@ -10,6 +10,8 @@ use crate::targets::{Os, TargetAPI};
 use crate::shlex::*;
 use crate::arena;
 use crate::params::*;
+use crate::proc::*;
+use crate::dwarf::{self, Dwarf_Target};
 
 pub unsafe fn align_bytes(bytes: usize, alignment: usize) -> usize {
     let rem = bytes%alignment;
@@ -127,7 +129,17 @@ pub unsafe fn load_arg_to_reg(arg: Arg, reg: *const c_char, output: *mut String_
     };
 }
 
-pub unsafe fn generate_function(name: *const c_char, _name_loc: Loc, params_count: usize, auto_vars_count: usize, os: Os, variadics: *const [(*const c_char, Variadic)], body: *const [OpWithLocation], output: *mut String_Builder) {
+pub unsafe fn generate_scope_labels(name: *const c_char, scope_events: *const [ScopeEvent], begin: usize, end: usize, output: *mut String_Builder) {
+    for i in begin..end {
+        match (*scope_events)[i] {
+            ScopeEvent::Declare    {  ..   } => {}
+            ScopeEvent::BlockBegin { index } => { sb_appendf(output, c!(".L%s_block_start_%zu:\n"), name, index); }
+            ScopeEvent::BlockEnd   { index } => { sb_appendf(output, c!(".L%s_block_end_%zu:\n"), name, index); }
+        }
+    }
+}
+
+pub unsafe fn generate_function(name: *const c_char, name_loc: Loc, func_index: usize, params_count: usize, auto_vars_count: usize, os: Os, variadics: *const [(*const c_char, Variadic)], body: *const [OpWithLocation], scope_events: *const [ScopeEvent], debug: bool, output: *mut String_Builder) {
     let stack_size = align_bytes(auto_vars_count*8, 16);
     match os {
         Os::Linux => {
@@ -144,9 +156,23 @@ pub unsafe fn generate_function(name: *const c_char, _name_loc: Loc, params_coun
             todo!("AArch64 is not supported on windows\n")
         }
     }
+
+    if debug {
+        sb_appendf(output, c!("    .file %lld \"%s\"\n"), func_index, name_loc.input_path);
+        sb_appendf(output, c!("    .loc %lld %lld\n"), func_index, name_loc.line_number);
+        sb_appendf(output, c!("    .cfi_startproc\n"));
+    }
     //sb_appendf(output, c!("    stp x29, x30, [sp, -%zu]!\n"), stack_size);
     sb_appendf(output, c!("    stp x29, x30, [sp, -2*8]!\n"));
+    if debug {
+        sb_appendf(output, c!("    .cfi_def_cfa_offset 16\n"));
+        sb_appendf(output, c!("    .cfi_offset x29, -16\n"));
+        sb_appendf(output, c!("    .cfi_offset x30, -8\n"));
+    }
     sb_appendf(output, c!("    mov x29, sp\n"), name);
+    if debug {
+        sb_appendf(output, c!("    .cfi_def_cfa x29, 16\n"));
+    }
     sb_appendf(output, c!("    sub sp, sp, %zu\n"), stack_size);
     assert!(auto_vars_count >= params_count);
 
@@ -163,8 +189,16 @@ pub unsafe fn generate_function(name: *const c_char, _name_loc: Loc, params_coun
         sb_appendf(output, c!("    str %s, [x29, -%zu]\n"), reg, below_index*8);
     }
 
+    let mut proccessed_scope_events = 0;
     for i in 0..body.len() {
         let op = (*body)[i];
+
+        if debug {
+            sb_appendf(output, c!("    .loc %lld %lld\n"), func_index, op.loc.line_number);
+            generate_scope_labels(name, scope_events, proccessed_scope_events, op.scope_events_count, output);
+            proccessed_scope_events = op.scope_events_count;
+        }
+
         match op.opcode {
             Op::Bogus => unreachable!("bogus-amogus"),
             Op::Return {arg} => {
@@ -393,12 +427,19 @@ pub unsafe fn generate_function(name: *const c_char, _name_loc: Loc, params_coun
     sb_appendf(output, c!("    add sp, sp, %zu\n"), stack_size);
     sb_appendf(output, c!("    ldp x29, x30, [sp], 2*8\n"));
     sb_appendf(output, c!("    ret\n"));
+
+    if debug {
+        sb_appendf(output, c!("    .cfi_endproc\n"));
+        sb_appendf(output, c!(".L%s_end:\n"), name);
+        generate_scope_labels(name, scope_events, proccessed_scope_events, scope_events.len(), output);
+    }
 }
 
-pub unsafe fn generate_funcs(output: *mut String_Builder, funcs: *const [Func], variadics: *const [(*const c_char, Variadic)], os: Os) {
+pub unsafe fn generate_funcs(output: *mut String_Builder, funcs: *const [Func], variadics: *const [(*const c_char, Variadic)], debug: bool, os: Os) {
     sb_appendf(output, c!(".text\n"));
     for i in 0..funcs.len() {
-        generate_function((*funcs)[i].name, (*funcs)[i].name_loc, (*funcs)[i].params_count, (*funcs)[i].auto_vars_count, os, variadics, da_slice((*funcs)[i].body), output);
+        let func = (*funcs)[i];
+        generate_function(func.name, func.name_loc, i, func.params_count, func.auto_vars_count, os, variadics, da_slice(func.body), da_slice(func.scope_events), debug, output);
     }
 }
 
@@ -492,6 +533,20 @@ pub unsafe fn generate_asm_funcs(output: *mut String_Builder, asm_funcs: *const
     }
 }
 
+// CFA is x29 + 16 since the frame record (x29, x30) sits right above the autos
+unsafe fn auto_var_cfa_offset(index: usize) -> i64 {
+    -(index as i64 + 2)*8
+}
+
+// Only ELF is supported, Mach-O keeps DWARF in differently named sections
+pub unsafe fn generate_debuginfo(output: *mut String_Builder, funcs: Array<Func>, globals: Array<Global>) {
+    dwarf::generate_debuginfo(output, funcs, globals, Dwarf_Target {
+        symbol_prefix: c!(""),
+        local_prefix: c!(".L"),
+        auto_var_offset: auto_var_cfa_offset,
+    });
+}
+
 pub unsafe fn usage(params: *const [Param]) {
     fprintf(stderr(), c!("gas_aarch64 codegen for the B compiler\n"));
     fprintf(stderr(), c!("OPTIONS:\n"));
@@ -505,7 +560,7 @@ struct Gas_AArch64 {
 }
 
 pub unsafe fn get_apis(targets: *mut Array<TargetAPI>) {
-    da_append(targets, TargetAPI::V1 {
+    da_append(targets, TargetAPI::V2 {
         name: c!("gas-aarch64-linux"),
         file_ext: c!(""),
         new,
@@ -515,9 +570,15 @@ pub unsafe fn get_apis(targets: *mut Array<TargetAPI>) {
         run: |gen, program_path, run_args| {
             run_program(gen, program_path, run_args, Os::Linux)
         },
+        build_object: |gen, program, object_path, garbage_base, debug| {
+            generate_object(gen, program, object_path, garbage_base, Os::Linux, debug)
+        },
+        link: |gen, object_paths, program_path, nostdlib| {
+            link_objects(gen as *mut Gas_AArch64, object_paths, program_path, Os::Linux, nostdlib)
+        },
     });
 
-    da_append(targets, TargetAPI::V1 {
+    da_append(targets, TargetAPI::V2 {
         name: c!("gas-aarch64-darwin"),
         file_ext: c!(""),
         new,
@@ -527,6 +588,12 @@ pub unsafe fn get_apis(targets: *mut Array<TargetAPI>) {
         run: |gen, program_path, run_args| {
             run_program(gen, program_path, run_args, Os::Darwin)
         },
+        build_object: |gen, program, object_path, garbage_base, debug| {
+            generate_object(gen, program, object_path, garbage_base, Os::Darwin, debug)
+        },
+        link: |gen, object_paths, program_path, nostdlib| {
+            link_objects(gen as *mut Gas_AArch64, object_paths, program_path, Os::Darwin, nostdlib)
+        },
     });
 }
 
@@ -562,17 +629,21 @@ pub unsafe fn new(a: *mut arena::Arena, args: *const [*const c_char]) -> Option<
     Some(gen as *mut c_void)
 }
 
-pub unsafe fn generate_program(
-    gen: *mut c_void, program: *const Program, program_path: *const c_char, garbage_base: *const c_char, os: Os,
-    nostdlib: bool, debug: bool,
-) -> Option<()> {
-    let gen = gen as *mut Gas_AArch64;
+// Generates the assembly of the program into Gas_AArch64::output and saves it next to the other garbage.
+// Returns the path to the saved assembly.
+unsafe fn generate_asm(gen: *mut Gas_AArch64, program: *const Program, garbage_base: *const c_char, os: Os, debug: bool) -> Option<*const c_char> {
     let output = &mut (*gen).output;
-    let cmd = &mut (*gen).cmd;
+    output.count = 0;
 
-    if debug { todo!("Debug information for aarch64") }
+    if debug {
+        if os != Os::Linux {
+            log(Log_Level::ERROR, c!("Debug information is only supported for gas-aarch64-linux for now"));
+            return None;
+        }
+        generate_debuginfo(output, (*program).funcs, (*program).globals);
+    }
 
-    generate_funcs(output, da_slice((*program).funcs), da_slice((*program).variadics), os);
+    generate_funcs(output, da_slice((*program).funcs), da_slice((*program).variadics), debug, os);
     generate_asm_funcs(output, da_slice((*program).asm_funcs), os);
     generate_globals(output, da_slice((*program). globals), os);
     generate_data_section(output, da_slice((*program).data));
@@ -580,24 +651,59 @@ pub unsafe fn generate_program(
     let output_asm_path = temp_sprintf(c!("%s.s"), garbage_base);
     write_entire_file(output_asm_path, (*output).items as *const c_void, (*output).count)?;
     log(Log_Level::INFO, c!("generated %s"), output_asm_path);
+    Some(output_asm_path)
+}
 
+// The assembler and the compiler driver used as the linker
+unsafe fn toolchain(os: Os) -> Option<(*const c_char, *const c_char)> {
     match os {
         Os::Linux => {
-            let (gas, cc) = if cfg!(target_arch = "aarch64") && (cfg!(target_os = "linux") || cfg!(target_os = "android")) {
-                (c!("as"), c!("cc"))
+            if cfg!(target_arch = "aarch64") && (cfg!(target_os = "linux") || cfg!(target_os = "android")) {
+                Some((c!("as"), c!("cc")))
             } else {
                 // TODO: document somewhere the additional packages you may require to cross compile gas-aarch64-linux
                 //   The packages include qemu-user and some variant of the aarch64 gcc compiler (different distros call it differently)
-                (c!("aarch64-linux-gnu-as"), c!("aarch64-linux-gnu-gcc"))
-            };
+                Some((c!("aarch64-linux-gnu-as"), c!("aarch64-linux-gnu-gcc")))
+            }
+        }
+        Os::Darwin => {
+            if !(cfg!(target_os = "macos")) {
+                log(Log_Level::ERROR, c!("Cross-compilation of darwin is not supported"));
+                return None;
+            }
+            Some((c!("as"), c!("cc")))
+        }
+        Os::Windows => todo!(),
+    }
+}
 
-            let output_obj_path = temp_sprintf(c!("%s.o"), garbage_base);
+unsafe fn assemble_object(gen: *mut Gas_AArch64, asm_path: *const c_char, object_path: *const c_char, os: Os) -> Option<()> {
+    let cmd = &mut (*gen).cmd;
+    let (gas, _) = toolchain(os)?;
+    match os {
+        Os::Linux => {
+            cmd_append! {
+                cmd,
+                gas, c!("-o"), object_path, asm_path,
+            }
+        }
+        Os::Darwin => {
             cmd_append! {
                 cmd,
-                gas, c!("-o"), output_obj_path, output_asm_path,
+                gas, c!("-arch"), c!("arm64"), c!("-o"), object_path, asm_path,
             }
-            if !cmd_run_sync_and_reset(cmd) { return None; }
+        }
+        Os::Windows => todo!(),
+    }
+    if !cmd_run_sync_and_reset(cmd) { return None; }
+    Some(())
+}
 
+unsafe fn link_objects(gen: *mut Gas_AArch64, object_paths: *const [*const c_char], program_path: *const c_char, os: Os, nostdlib: bool) -> Option<()> {
+    let cmd = &mut (*gen).cmd;
+    let (_, cc) = toolchain(os)?;
+    match os {
+        Os::Linux => {
             cmd_append! {
                 cmd,
                 cc, if cfg!(target_os = "android") {
@@ -605,59 +711,52 @@ pub unsafe fn generate_program(
                 } else {
                     c!("-no-pie")
                 },
-                c!("-o"), program_path, output_obj_path,
+                c!("-o"), program_path,
             }
-            if nostdlib {
-                cmd_append!(cmd, c!("-nostdlib"));
-            }
-            let mut s: Shlex = zeroed();
-            let link_args = (*gen).link_args;
-            shlex_init(&mut s, link_args, link_args.add(strlen(link_args)));
-            while !shlex_next(&mut s).is_null() {
-                da_append(cmd, temp_strdup(s.string));
-            }
-            shlex_free(&mut s);
-            if !cmd_run_sync_and_reset(cmd) { return None; }
         }
         Os::Darwin => {
-            let (gas, cc) = (c!("as"), c!("cc"));
-
-            if !(cfg!(target_os = "macos")) {
-                log(Log_Level::ERROR, c!("Cross-compilation of darwin is not supported"));
-                return None;
-            }
-
-            let output_obj_path = temp_sprintf(c!("%s.o"), garbage_base);
             cmd_append! {
                 cmd,
-                gas, c!("-arch"), c!("arm64"), c!("-o"), output_obj_path, output_asm_path,
+                cc, c!("-arch"), c!("arm64"), c!("-o"), program_path,
             }
-            if !cmd_run_sync_and_reset(cmd) { return None; }
-            cmd_append! {
-                cmd,
-                cc, c!("-arch"), c!("arm64"), c!("-o"), program_path, output_obj_path,
-            }
-            if nostdlib {
-                cmd_append! {
-                    cmd,
-                    c!("-nostdlib"),
-                }
-            }
-            let mut s: Shlex = zeroed();
-            let link_args = (*gen).link_args;
-            shlex_init(&mut s, link_args, link_args.add(strlen(link_args)));
-            while !shlex_next(&mut s).is_null() {
-                da_append(cmd, temp_strdup(s.string));
-            }
-            shlex_free(&mut s);
-            if !cmd_run_sync_and_reset(cmd) { return None; }
         }
         Os::Windows => todo!(),
     }
-
+    da_append_many(cmd, object_paths);
+    if nostdlib {
+        cmd_append!(cmd, c!("-nostdlib"));
+    }
+    let mut s: Shlex = zeroed();
+    let link_args = (*gen).link_args;
+    shlex_init(&mut s, link_args, link_args.add(strlen(link_args)));
+    while !shlex_next(&mut s).is_null() {
+        da_append(cmd, temp_strdup(s.string));
+    }
+    shlex_free(&mut s);
+    if !cmd_run_sync_and_reset(cmd) { return None; }
     Some(())
 }
 
+pub unsafe fn generate_program(
+    gen: *mut c_void, program: *const Program, program_path: *const c_char, garbage_base: *const c_char, os: Os,
+    nostdlib: bool, debug: bool,
+) -> Option<()> {
+    let gen = gen as *mut Gas_AArch64;
+    let output_asm_path = generate_asm(gen, program, garbage_base, os, debug)?;
+    let output_obj_path = temp_sprintf(c!("%s.o"), garbage_base);
+    assemble_object(gen, output_asm_path, output_obj_path, os)?;
+    link_objects(gen, &[output_obj_path as *const c_char], program_path, os, nostdlib)
+}
+
+pub unsafe fn generate_object(
+    gen: *mut c_void, program: *const Program, object_path: *const c_char, garbage_base: *const c_char, os: Os,
+    debug: bool,
+) -> Option<()> {
+    let gen = gen as *mut Gas_AArch64;
+    let output_asm_path = generate_asm(gen, program, garbage_base, os, debug)?;
+    assemble_object(gen, output_asm_path, object_path, os)
+}
+
 pub unsafe fn run_program(
     gen: *mut c_void, program_path: *const c_char, run_args: *const [*const c_char], os: Os,
 ) -> Option<()> {
@@ -687,7 +786,7 @@ pub unsafe fn run_program(
             }
 
             da_append_many(cmd, run_args);
-            if !cmd_run_sync_and_reset(cmd) { return None; }
+            cmd_run_sync_forwarding_exit_code_and_reset(cmd)?;
         }
         Os::Darwin => {
             if !cfg!(target_arch = "aarch64") {
@@ -709,7 +808,7 @@ pub unsafe fn run_program(
             }
 
             da_append_many(cmd, run_args);
-            if !cmd_run_sync_and_reset(cmd) { return None; }
+            cmd_run_sync_forwarding_exit_code_and_reset(cmd)?;
         }
         Os::Windows => todo!(),
     }
@@ -0,0 +1,1022 @@
+// In-process assembler for the subset of the GNU assembler (AT&T syntax) that the
+// gas-x86_64-linux codegen and its libb produce. Anything outside of that subset (usually
+// hand written __asm__ functions) is reported as an error. Use `-C assembler=gas` for those.
+use core::ffi::*;
+use core::ptr;
+use core::mem::zeroed;
+use crate::nob::*;
+use crate::crust::libc::*;
+use crate::crust::assoc_lookup_cstr;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Section_Kind {
+    Text,
+    Data,
+}
+
+pub const SECTIONS_COUNT: usize = 2;
+pub const SECTION_KINDS: [Section_Kind; SECTIONS_COUNT] = [Section_Kind::Text, Section_Kind::Data];
+
+impl Section_Kind {
+    pub fn name(self) -> *const c_char {
+        match self {
+            Section_Kind::Text => c!(".text"),
+            Section_Kind::Data => c!(".data"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Symbol {
+    pub name: *const c_char, // null for the anonymous symbols created by `.`
+    pub defined: bool,
+    pub global: bool,
+    pub section: Section_Kind,
+    pub offset: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Reloc_Kind {
+    Abs64, // R_X86_64_64
+    Pc32,  // R_X86_64_PC32
+    Plt32, // R_X86_64_PLT32
+}
+
+#[derive(Clone, Copy)]
+pub struct Reloc {
+    pub offset: u64,
+    pub kind: Reloc_Kind,
+    pub symbol: usize,
+    pub addend: i64,
+}
+
+#[derive(Clone, Copy)]
+pub struct Section {
+    pub bytes: Array<u8>,
+    pub relocs: Array<Reloc>,
+    pub alignment: u64,
+}
+
+pub struct Assembler {
+    pub sections: [Section; SECTIONS_COUNT],
+    pub current: Section_Kind,
+    pub symbols: Array<Symbol>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Expr {
+    pub symbol: Option<usize>,
+    pub addend: i64,
+}
+
+#[derive(Clone, Copy)]
+pub enum Operand {
+    Reg   {reg: u8, size: u8},
+    Imm   (i64),
+    Mem   {base: Option<u8>, index: Option<u8>, scale: u8, rip: bool, disp: Expr},
+    Label (Expr),
+}
+
+const REGISTERS: *const [(*const c_char, (u8, u8))] = &[
+    (c!("rax"), (0, 8)), (c!("rcx"), (1, 8)), (c!("rdx"), (2, 8)), (c!("rbx"), (3, 8)),
+    (c!("rsp"), (4, 8)), (c!("rbp"), (5, 8)), (c!("rsi"), (6, 8)), (c!("rdi"), (7, 8)),
+    (c!("r8"),  (8, 8)), (c!("r9"),  (9, 8)), (c!("r10"), (10, 8)), (c!("r11"), (11, 8)),
+    (c!("r12"), (12, 8)), (c!("r13"), (13, 8)), (c!("r14"), (14, 8)), (c!("r15"), (15, 8)),
+    (c!("eax"), (0, 4)), (c!("ecx"), (1, 4)), (c!("edx"), (2, 4)), (c!("ebx"), (3, 4)),
+    (c!("esp"), (4, 4)), (c!("ebp"), (5, 4)), (c!("esi"), (6, 4)), (c!("edi"), (7, 4)),
+    (c!("r8d"), (8, 4)), (c!("r9d"), (9, 4)), (c!("r10d"), (10, 4)), (c!("r11d"), (11, 4)),
+    (c!("r12d"), (12, 4)), (c!("r13d"), (13, 4)), (c!("r14d"), (14, 4)), (c!("r15d"), (15, 4)),
+    (c!("al"),  (0, 1)), (c!("cl"),  (1, 1)), (c!("dl"),  (2, 1)), (c!("bl"),  (3, 1)),
+    (c!("spl"), (4, 1)), (c!("bpl"), (5, 1)), (c!("sil"), (6, 1)), (c!("dil"), (7, 1)),
+    (c!("r8b"), (8, 1)), (c!("r9b"), (9, 1)), (c!("r10b"), (10, 1)), (c!("r11b"), (11, 1)),
+    (c!("r12b"), (12, 1)), (c!("r13b"), (13, 1)), (c!("r14b"), (14, 1)), (c!("r15b"), (15, 1)),
+];
+
+const CONDITION_CODES: *const [(*const c_char, u8)] = &[
+    (c!("o"), 0x0),  (c!("no"), 0x1),
+    (c!("b"), 0x2),  (c!("c"), 0x2),   (c!("nae"), 0x2),
+    (c!("ae"), 0x3), (c!("nb"), 0x3),  (c!("nc"), 0x3),
+    (c!("e"), 0x4),  (c!("z"), 0x4),
+    (c!("ne"), 0x5), (c!("nz"), 0x5),
+    (c!("be"), 0x6), (c!("na"), 0x6),
+    (c!("a"), 0x7),  (c!("nbe"), 0x7),
+    (c!("s"), 0x8),  (c!("ns"), 0x9),
+    (c!("p"), 0xA),  (c!("pe"), 0xA),
+    (c!("np"), 0xB), (c!("po"), 0xB),
+    (c!("l"), 0xC),  (c!("nge"), 0xC),
+    (c!("ge"), 0xD), (c!("nl"), 0xD),
+    (c!("le"), 0xE), (c!("ng"), 0xE),
+    (c!("g"), 0xF),  (c!("nle"), 0xF),
+];
+
+const FIXED_INSTRUCTIONS: *const [(*const c_char, *const [u8])] = &[
+    (c!("ret"),     &[0xC3]),
+    (c!("retq"),    &[0xC3]),
+    (c!("leave"),   &[0xC9]),
+    (c!("leaveq"),  &[0xC9]),
+    (c!("cqto"),    &[0x48, 0x99]),
+    (c!("cqo"),     &[0x48, 0x99]),
+    (c!("cltq"),    &[0x48, 0x98]),
+    (c!("cdqe"),    &[0x48, 0x98]),
+    (c!("cltd"),    &[0x99]),
+    (c!("cdq"),     &[0x99]),
+    (c!("syscall"), &[0x0F, 0x05]),
+    (c!("nop"),     &[0x90]),
+    (c!("hlt"),     &[0xF4]),
+    (c!("int3"),    &[0xCC]),
+    (c!("ud2"),     &[0x0F, 0x0B]),
+];
+
+// (mnemonic, REX.W, opcode)
+const EXTEND_INSTRUCTIONS: *const [(*const c_char, (bool, *const [u8]))] = &[
+    (c!("movslq"), (true,  &[0x63])),
+    (c!("movsxd"), (true,  &[0x63])),
+    (c!("movsbq"), (true,  &[0x0F, 0xBE])),
+    (c!("movsbl"), (false, &[0x0F, 0xBE])),
+    (c!("movzbq"), (true,  &[0x0F, 0xB6])),
+    (c!("movzbl"), (false, &[0x0F, 0xB6])),
+];
+
+// /digit of the `op r/m, r` family, which also happens to be the opcode divided by 8
+const ALU_INSTRUCTIONS: *const [(*const c_char, u8)] = &[
+    (c!("add"), 0), (c!("or"),  1), (c!("adc"), 2), (c!("sbb"), 3),
+    (c!("and"), 4), (c!("sub"), 5), (c!("xor"), 6), (c!("cmp"), 7),
+];
+
+const UNARY_INSTRUCTIONS: *const [(*const c_char, u8)] = &[
+    (c!("not"), 2), (c!("neg"), 3), (c!("mul"), 4), (c!("div"), 6), (c!("idiv"), 7),
+];
+
+const INC_DEC_INSTRUCTIONS: *const [(*const c_char, u8)] = &[
+    (c!("inc"), 0), (c!("dec"), 1),
+];
+
+const SHIFT_INSTRUCTIONS: *const [(*const c_char, u8)] = &[
+    (c!("rol"), 0), (c!("ror"), 1), (c!("shl"), 4), (c!("sal"), 4), (c!("shr"), 5), (c!("sar"), 7),
+];
+
+// Mnemonics that accept the b/l/q operand size suffix
+const OTHER_SIZED_INSTRUCTIONS: *const [*const c_char] = &[
+    c!("mov"), c!("movabs"), c!("lea"), c!("test"), c!("imul"), c!("push"), c!("pop"),
+];
+
+pub unsafe fn section(a: *mut Assembler) -> *mut Section {
+    &mut (*a).sections[(*a).current as usize]
+}
+
+unsafe fn here(a: *mut Assembler) -> u64 {
+    (*section(a)).bytes.count as u64
+}
+
+unsafe fn emit_u8(a: *mut Assembler, byte: u8) {
+    da_append(&mut (*section(a)).bytes, byte);
+}
+
+unsafe fn emit_bytes(a: *mut Assembler, bytes: *const [u8]) {
+    da_append_many(&mut (*section(a)).bytes, bytes);
+}
+
+unsafe fn emit_i32(a: *mut Assembler, x: i32) {
+    emit_bytes(a, &x.to_le_bytes());
+}
+
+unsafe fn emit_u64(a: *mut Assembler, x: u64) {
+    emit_bytes(a, &x.to_le_bytes());
+}
+
+unsafe fn emit_reloc(a: *mut Assembler, kind: Reloc_Kind, symbol: usize, addend: i64) {
+    da_append(&mut (*section(a)).relocs, Reloc {
+        offset: here(a),
+        kind,
+        symbol,
+        addend,
+    });
+}
+
+pub unsafe fn find_symbol(a: *mut Assembler, name: *const c_char) -> Option<usize> {
+    for i in 0..(*a).symbols.count {
+        let symbol = *(*a).symbols.items.add(i);
+        if !symbol.name.is_null() && strcmp(symbol.name, name) == 0 {
+            return Some(i);
+        }
+    }
+    None
+}
+
+unsafe fn symbol(a: *mut Assembler, name: *const c_char) -> usize {
+    if let Some(index) = find_symbol(a, name) {
+        return index;
+    }
+    da_append(&mut (*a).symbols, Symbol {
+        name: strdup(name),
+        defined: false,
+        global: false,
+        section: Section_Kind::Text,
+        offset: 0,
+    });
+    (*a).symbols.count - 1
+}
+
+unsafe fn location_symbol(a: *mut Assembler) -> usize {
+    da_append(&mut (*a).symbols, Symbol {
+        name: ptr::null(),
+        defined: true,
+        global: false,
+        section: (*a).current,
+        offset: here(a),
+    });
+    (*a).symbols.count - 1
+}
+
+unsafe fn define_symbol(a: *mut Assembler, name: *const c_char) -> Option<()> {
+    let index = symbol(a, name);
+    let symbol = (*a).symbols.items.add(index);
+    if (*symbol).defined {
+        log(Log_Level::ERROR, c!("symbol `%s` is already defined"), name);
+        return None;
+    }
+    (*symbol).defined = true;
+    (*symbol).section = (*a).current;
+    (*symbol).offset = here(a);
+    Some(())
+}
+
+fn fits_i8(x: i64) -> bool {
+    -0x80 <= x && x <= 0x7F
+}
+
+fn fits_i32(x: i64) -> bool {
+    -0x8000_0000 <= x && x <= 0x7FFF_FFFF
+}
+
+unsafe fn skip_spaces(s: *mut *const c_char) {
+    while isspace(**s as c_int) != 0 {
+        *s = (*s).add(1);
+    }
+}
+
+unsafe fn is_symbol_char(c: c_char) -> bool {
+    isalnum(c as c_int) != 0 || c == '_' as c_char || c == '.' as c_char
+}
+
+unsafe fn parse_name(s: *mut *const c_char) -> Option<*const c_char> {
+    let start = *s;
+    while is_symbol_char(**s) {
+        *s = (*s).add(1);
+    }
+    if *s == start {
+        return None;
+    }
+    Some(temp_sprintf(c!("%.*s"), (*s).offset_from(start) as c_int, start))
+}
+
+unsafe fn parse_expr(a: *mut Assembler, s: *mut *const c_char) -> Option<Expr> {
+    let mut expr = Expr { symbol: None, addend: 0 };
+    let mut negative = false;
+    loop {
+        skip_spaces(s);
+        if **s == '-' as c_char {
+            negative = !negative;
+            *s = (*s).add(1);
+            skip_spaces(s);
+        }
+        if isdigit(**s as c_int) != 0 {
+            let mut end: *mut c_char = ptr::null_mut();
+            let value = strtoull(*s, &mut end, 0) as i64;
+            *s = end;
+            expr.addend = if negative {
+                expr.addend.wrapping_sub(value)
+            } else {
+                expr.addend.wrapping_add(value)
+            };
+        } else {
+            let name = parse_name(s)?;
+            if expr.symbol.is_some() || negative {
+                log(Log_Level::ERROR, c!("only expressions of the form `symbol+constant` are supported"));
+                return None;
+            }
+            expr.symbol = if strcmp(name, c!(".")) == 0 {
+                Some(location_symbol(a))
+            } else {
+                Some(symbol(a, name))
+            };
+        }
+        skip_spaces(s);
+        match **s as u8 {
+            b'+' => negative = false,
+            b'-' => negative = true,
+            _    => return Some(expr),
+        }
+        *s = (*s).add(1);
+    }
+}
+
+unsafe fn parse_register(s: *mut *const c_char) -> Option<(u8, u8)> {
+    if **s != '%' as c_char {
+        return None;
+    }
+    *s = (*s).add(1);
+    let name = parse_name(s)?;
+    if let Some(reg) = assoc_lookup_cstr(REGISTERS, name) {
+        Some(*reg)
+    } else {
+        log(Log_Level::ERROR, c!("unknown register `%%%s`"), name);
+        None
+    }
+}
+
+unsafe fn parse_address_register(s: *mut *const c_char) -> Option<u8> {
+    let (reg, size) = parse_register(s)?;
+    if size != 8 {
+        log(Log_Level::ERROR, c!("only 64-bit registers can be used in addresses"));
+        return None;
+    }
+    Some(reg)
+}
+
+unsafe fn parse_operand(a: *mut Assembler, s: *mut *const c_char) -> Option<Operand> {
+    skip_spaces(s);
+    if **s == '%' as c_char {
+        let (reg, size) = parse_register(s)?;
+        return Some(Operand::Reg {reg, size});
+    }
+    if **s == '$' as c_char {
+        *s = (*s).add(1);
+        let expr = parse_expr(a, s)?;
+        if expr.symbol.is_some() {
+            log(Log_Level::ERROR, c!("symbolic immediates are not supported"));
+            return None;
+        }
+        return Some(Operand::Imm(expr.addend));
+    }
+
+    let mut disp = Expr { symbol: None, addend: 0 };
+    if **s != '(' as c_char {
+        disp = parse_expr(a, s)?;
+    }
+    if **s != '(' as c_char {
+        return Some(Operand::Label(disp));
+    }
+    *s = (*s).add(1);
+    skip_spaces(s);
+
+    let mut base = None;
+    let mut index = None;
+    let mut scale = 1;
+    let mut rip = false;
+    if **s == '%' as c_char {
+        if strncmp(*s, c!("%rip"), 4) == 0 && !is_symbol_char(*(*s).add(4)) {
+            *s = (*s).add(4);
+            rip = true;
+        } else {
+            base = Some(parse_address_register(s)?);
+        }
+    }
+    skip_spaces(s);
+    if **s == ',' as c_char {
+        *s = (*s).add(1);
+        skip_spaces(s);
+        let reg = parse_address_register(s)?;
+        if reg == 4 || rip {
+            log(Log_Level::ERROR, c!("invalid index register"));
+            return None;
+        }
+        index = Some(reg);
+        skip_spaces(s);
+        if **s == ',' as c_char {
+            *s = (*s).add(1);
+            skip_spaces(s);
+            let mut end: *mut c_char = ptr::null_mut();
+            scale = strtoull(*s, &mut end, 10) as u8;
+            *s = end;
+            if !matches!(scale, 1 | 2 | 4 | 8) {
+                log(Log_Level::ERROR, c!("scale factor must be 1, 2, 4 or 8"));
+                return None;
+            }
+            skip_spaces(s);
+        }
+    }
+    if **s != ')' as c_char {
+        return None;
+    }
+    *s = (*s).add(1);
+    Some(Operand::Mem {base, index, scale, rip, disp})
+}
+
+fn needs_rex8(op: Operand) -> bool {
+    // spl, bpl, sil and dil are only accessible with a REX prefix (otherwise they mean ah, ch, dh, bh)
+    matches!(op, Operand::Reg {reg: 4..=7, size: 1})
+}
+
+unsafe fn emit_rel32(a: *mut Assembler, target: Expr, kind: Reloc_Kind) -> Option<()> {
+    let Some(symbol) = target.symbol else {
+        log(Log_Level::ERROR, c!("jumps to absolute addresses are not supported"));
+        return None;
+    };
+    emit_reloc(a, kind, symbol, target.addend - 4);
+    emit_i32(a, 0);
+    Some(())
+}
+
+// `reg` is the value of the ModRM.reg field: either a register or an opcode extension.
+// `imm_size` is the amount of immediate bytes the caller emits right after, which the RIP-relative
+// displacement has to account for.
+unsafe fn emit_modrm(a: *mut Assembler, w: bool, rex8: bool, opcode: *const [u8], reg: u8, rm: Operand, imm_size: i64) -> Option<()> {
+    let mut rex: u8 = 0;
+    if w        { rex |= 0x8; }
+    if reg >= 8 { rex |= 0x4; }
+    match rm {
+        Operand::Reg {reg: r, ..} => if r >= 8 { rex |= 0x1; },
+        Operand::Mem {base, index, ..} => {
+            if let Some(i) = index { if i >= 8 { rex |= 0x2; } }
+            if let Some(b) = base  { if b >= 8 { rex |= 0x1; } }
+        }
+        Operand::Imm(..) | Operand::Label(..) => return None,
+    }
+    if rex != 0 || rex8 {
+        emit_u8(a, 0x40 | rex);
+    }
+    emit_bytes(a, opcode);
+
+    let reg = (reg & 7) << 3;
+    match rm {
+        Operand::Reg {reg: r, ..} => emit_u8(a, 0xC0 | reg | (r & 7)),
+        Operand::Mem {base, index, scale, rip, disp} => {
+            let scale_bits = match scale {
+                1 => 0,
+                2 => 1,
+                4 => 2,
+                _ => 3,
+            } << 6;
+            let index_bits = (index.unwrap_or(4) & 7) << 3;
+            if rip {
+                emit_u8(a, reg | 5);
+                if let Some(symbol) = disp.symbol {
+                    emit_reloc(a, Reloc_Kind::Pc32, symbol, disp.addend - 4 - imm_size);
+                    emit_i32(a, 0);
+                } else {
+                    if !fits_i32(disp.addend) { return None; }
+                    emit_i32(a, disp.addend as i32);
+                }
+                return Some(());
+            }
+            if disp.symbol.is_some() {
+                log(Log_Level::ERROR, c!("symbolic displacements are only supported with %%rip"));
+                return None;
+            }
+            if !fits_i32(disp.addend) { return None; }
+            let Some(b) = base else {
+                emit_u8(a, reg | 4);
+                emit_u8(a, scale_bits | index_bits | 5);
+                emit_i32(a, disp.addend as i32);
+                return Some(());
+            };
+            let mode = if disp.addend == 0 && b & 7 != 5 {
+                0x00
+            } else if fits_i8(disp.addend) {
+                0x40
+            } else {
+                0x80
+            };
+            if index.is_some() || b & 7 == 4 {
+                emit_u8(a, mode | reg | 4);
+                emit_u8(a, scale_bits | index_bits | (b & 7));
+            } else {
+                emit_u8(a, mode | reg | (b & 7));
+            }
+            match mode {
+                0x40 => emit_u8(a, disp.addend as i8 as u8),
+                0x80 => emit_i32(a, disp.addend as i32),
+                _    => {}
+            }
+        }
+        Operand::Imm(..) | Operand::Label(..) => unreachable!(),
+    }
+    Some(())
+}
+
+// Instructions with the register encoded in the low bits of the opcode (push, pop, mov imm)
+unsafe fn emit_opreg(a: *mut Assembler, w: bool, rex8: bool, opcode: u8, reg: u8) {
+    let mut rex: u8 = 0;
+    if w        { rex |= 0x8; }
+    if reg >= 8 { rex |= 0x1; }
+    if rex != 0 || rex8 {
+        emit_u8(a, 0x40 | rex);
+    }
+    emit_u8(a, opcode + (reg & 7));
+}
+
+unsafe fn emit_imm(a: *mut Assembler, imm: i64, size: i64) -> Option<()> {
+    match size {
+        1 => {
+            if !(-0x80 <= imm && imm <= 0xFF) { return None; }
+            emit_u8(a, imm as u8);
+        }
+        _ => {
+            if !fits_i32(imm) && !(size == 4 && 0 <= imm && imm <= 0xFFFF_FFFF) { return None; }
+            emit_i32(a, imm as i32);
+        }
+    }
+    Some(())
+}
+
+fn is_rm(op: Operand) -> bool {
+    matches!(op, Operand::Reg {..} | Operand::Mem {..})
+}
+
+unsafe fn encode_alu(a: *mut Assembler, ext: u8, size: u8, src: Operand, dst: Operand) -> Option<()> {
+    let w = size == 8;
+    let rex8 = needs_rex8(src) || needs_rex8(dst);
+    let byte = size == 1;
+    match (src, dst) {
+        (Operand::Imm(imm), dst) if is_rm(dst) => {
+            if byte {
+                emit_modrm(a, w, rex8, &[0x80], ext, dst, 1)?;
+                emit_imm(a, imm, 1)
+            } else if fits_i8(imm) {
+                emit_modrm(a, w, rex8, &[0x83], ext, dst, 1)?;
+                emit_imm(a, imm, 1)
+            } else {
+                emit_modrm(a, w, rex8, &[0x81], ext, dst, 4)?;
+                emit_imm(a, imm, size as i64)
+            }
+        }
+        (Operand::Reg {reg, ..}, dst) if is_rm(dst) => {
+            emit_modrm(a, w, rex8, &[ext*8 + if byte {0x00} else {0x01}], reg, dst, 0)
+        }
+        (src @ Operand::Mem {..}, Operand::Reg {reg, ..}) => {
+            emit_modrm(a, w, rex8, &[ext*8 + if byte {0x02} else {0x03}], reg, src, 0)
+        }
+        _ => None,
+    }
+}
+
+unsafe fn encode_mov(a: *mut Assembler, size: u8, src: Operand, dst: Operand) -> Option<()> {
+    let w = size == 8;
+    let rex8 = needs_rex8(src) || needs_rex8(dst);
+    let byte = size == 1;
+    match (src, dst) {
+        (Operand::Reg {reg, ..}, dst) if is_rm(dst) => {
+            emit_modrm(a, w, rex8, &[if byte {0x88} else {0x89}], reg, dst, 0)
+        }
+        (src @ Operand::Mem {..}, Operand::Reg {reg, ..}) => {
+            emit_modrm(a, w, rex8, &[if byte {0x8A} else {0x8B}], reg, src, 0)
+        }
+        (Operand::Imm(imm), Operand::Reg {reg, ..}) => match size {
+            8 if !fits_i32(imm) => {
+                emit_opreg(a, true, false, 0xB8, reg);
+                emit_u64(a, imm as u64);
+                Some(())
+            }
+            8 => {
+                emit_modrm(a, true, false, &[0xC7], 0, dst, 4)?;
+                emit_imm(a, imm, 4)
+            }
+            4 => {
+                emit_opreg(a, false, false, 0xB8, reg);
+                emit_imm(a, imm, 4)
+            }
+            _ => {
+                emit_opreg(a, false, rex8, 0xB0, reg);
+                emit_imm(a, imm, 1)
+            }
+        }
+        (Operand::Imm(imm), dst @ Operand::Mem {..}) => {
+            let imm_size = if byte {1} else {4};
+            emit_modrm(a, w, false, &[if byte {0xC6} else {0xC7}], 0, dst, imm_size)?;
+            emit_imm(a, imm, imm_size)
+        }
+        _ => None,
+    }
+}
+
+unsafe fn encode_test(a: *mut Assembler, size: u8, src: Operand, dst: Operand) -> Option<()> {
+    let w = size == 8;
+    let rex8 = needs_rex8(src) || needs_rex8(dst);
+    let byte = size == 1;
+    match (src, dst) {
+        (Operand::Imm(imm), dst) if is_rm(dst) => {
+            let imm_size = if byte {1} else {4};
+            emit_modrm(a, w, rex8, &[if byte {0xF6} else {0xF7}], 0, dst, imm_size)?;
+            emit_imm(a, imm, imm_size)
+        }
+        (Operand::Reg {reg, ..}, rm) | (rm @ Operand::Mem {..}, Operand::Reg {reg, ..}) if is_rm(rm) => {
+            emit_modrm(a, w, rex8, &[if byte {0x84} else {0x85}], reg, rm, 0)
+        }
+        _ => None,
+    }
+}
+
+unsafe fn encode_shift(a: *mut Assembler, ext: u8, size: u8, ops: *const [Operand]) -> Option<()> {
+    let w = size == 8;
+    let byte = size == 1;
+    match *ops {
+        [dst] | [Operand::Imm(1), dst] if is_rm(dst) => {
+            emit_modrm(a, w, needs_rex8(dst), &[if byte {0xD0} else {0xD1}], ext, dst, 0)
+        }
+        [Operand::Imm(imm), dst] if is_rm(dst) => {
+            emit_modrm(a, w, needs_rex8(dst), &[if byte {0xC0} else {0xC1}], ext, dst, 1)?;
+            emit_imm(a, imm, 1)
+        }
+        [Operand::Reg {reg: 1, size: 1}, dst] if is_rm(dst) => {
+            emit_modrm(a, w, needs_rex8(dst), &[if byte {0xD2} else {0xD3}], ext, dst, 0)
+        }
+        _ => None,
+    }
+}
+
+unsafe fn is_sized_mnemonic(name: *const c_char) -> bool {
+    if assoc_lookup_cstr(ALU_INSTRUCTIONS, name).is_some()
+        || assoc_lookup_cstr(UNARY_INSTRUCTIONS, name).is_some()
+        || assoc_lookup_cstr(INC_DEC_INSTRUCTIONS, name).is_some()
+        || assoc_lookup_cstr(SHIFT_INSTRUCTIONS, name).is_some()
+    {
+        return true;
+    }
+    for i in 0..OTHER_SIZED_INSTRUCTIONS.len() {
+        if strcmp((*OTHER_SIZED_INSTRUCTIONS)[i], name) == 0 {
+            return true;
+        }
+    }
+    false
+}
+
+unsafe fn split_size_suffix(mnemonic: *const c_char) -> Option<(*const c_char, u8)> {
+    if is_sized_mnemonic(mnemonic) {
+        return Some((mnemonic, 0));
+    }
+    let n = strlen(mnemonic);
+    if n < 2 {
+        return None;
+    }
+    let size = match *mnemonic.add(n - 1) as u8 {
+        b'b' => 1,
+        b'l' => 4,
+        b'q' => 8,
+        _    => return None,
+    };
+    let base = temp_sprintf(c!("%.*s"), (n - 1) as c_int, mnemonic);
+    if is_sized_mnemonic(base) {
+        Some((base, size))
+    } else {
+        None
+    }
+}
+
+unsafe fn encode_instruction(a: *mut Assembler, mnemonic: *const c_char, ops: *const [Operand], indirect: bool) -> Option<()> {
+    if let Some(bytes) = assoc_lookup_cstr(FIXED_INSTRUCTIONS, mnemonic) {
+        if ops.len() != 0 || indirect { return None; }
+        emit_bytes(a, *bytes);
+        return Some(());
+    }
+
+    if strcmp(mnemonic, c!("call")) == 0 || strcmp(mnemonic, c!("callq")) == 0 ||
+       strcmp(mnemonic, c!("jmp")) == 0  || strcmp(mnemonic, c!("jmpq")) == 0 {
+        let call = *mnemonic == 'c' as c_char;
+        return match *ops {
+            [Operand::Label(target)] if !indirect => {
+                emit_u8(a, if call {0xE8} else {0xE9});
+                emit_rel32(a, target, if call {Reloc_Kind::Plt32} else {Reloc_Kind::Pc32})
+            }
+            [target @ (Operand::Reg {size: 8, ..} | Operand::Mem {..})] if indirect => {
+                emit_modrm(a, false, false, &[0xFF], if call {2} else {4}, target, 0)
+            }
+            _ => None,
+        }
+    }
+    if indirect {
+        return None;
+    }
+
+    if *mnemonic == 'j' as c_char {
+        let cc = *assoc_lookup_cstr(CONDITION_CODES, mnemonic.add(1))?;
+        let [Operand::Label(target)] = *ops else { return None; };
+        emit_bytes(a, &[0x0F, 0x80 + cc]);
+        return emit_rel32(a, target, Reloc_Kind::Pc32);
+    }
+
+    if strncmp(mnemonic, c!("set"), 3) == 0 {
+        if let Some(cc) = assoc_lookup_cstr(CONDITION_CODES, mnemonic.add(3)) {
+            return match *ops {
+                [dst @ (Operand::Reg {size: 1, ..} | Operand::Mem {..})] => {
+                    emit_modrm(a, false, needs_rex8(dst), &[0x0F, 0x90 + *cc], 0, dst, 0)
+                }
+                _ => None,
+            }
+        }
+    }
+
+    if let Some(extend) = assoc_lookup_cstr(EXTEND_INSTRUCTIONS, mnemonic) {
+        let (w, opcode) = *extend;
+        return match *ops {
+            [src, Operand::Reg {reg, size}] if is_rm(src) && size == if w {8} else {4} => {
+                emit_modrm(a, w, needs_rex8(src), opcode, reg, src, 0)
+            }
+            _ => None,
+        }
+    }
+
+    let (base, mut size) = split_size_suffix(mnemonic)?;
+    if size == 0 {
+        for i in (0..ops.len()).rev() {
+            if let Operand::Reg {size: reg_size, ..} = (*ops)[i] {
+                size = reg_size;
+                break;
+            }
+        }
+        if size == 0 {
+            log(Log_Level::ERROR, c!("ambiguous operand size for `%s`"), mnemonic);
+            return None;
+        }
+    }
+    let is_shift = assoc_lookup_cstr(SHIFT_INSTRUCTIONS, base).is_some();
+    for i in 0..ops.len() {
+        if let Operand::Reg {size: reg_size, ..} = (*ops)[i] {
+            // The count of shift instructions is always %cl
+            if reg_size != size && !(is_shift && i + 1 < ops.len()) {
+                log(Log_Level::ERROR, c!("operand size mismatch for `%s`"), mnemonic);
+                return None;
+            }
+        }
+    }
+    let w = size == 8;
+
+    if let Some(ext) = assoc_lookup_cstr(ALU_INSTRUCTIONS, base) {
+        let [src, dst] = *ops else { return None; };
+        return encode_alu(a, *ext, size, src, dst);
+    }
+    if let Some(ext) = assoc_lookup_cstr(SHIFT_INSTRUCTIONS, base) {
+        return encode_shift(a, *ext, size, ops);
+    }
+    if let Some(ext) = assoc_lookup_cstr(UNARY_INSTRUCTIONS, base) {
+        let [dst] = *ops else { return None; };
+        if !is_rm(dst) { return None; }
+        return emit_modrm(a, w, needs_rex8(dst), &[if size == 1 {0xF6} else {0xF7}], *ext, dst, 0);
+    }
+    if let Some(ext) = assoc_lookup_cstr(INC_DEC_INSTRUCTIONS, base) {
+        let [dst] = *ops else { return None; };
+        if !is_rm(dst) { return None; }
+        return emit_modrm(a, w, needs_rex8(dst), &[if size == 1 {0xFE} else {0xFF}], *ext, dst, 0);
+    }
+
+    match *ops {
+        [src, dst] if strcmp(base, c!("mov")) == 0 => encode_mov(a, size, src, dst),
+        [src, dst] if strcmp(base, c!("test")) == 0 => encode_test(a, size, src, dst),
+        [Operand::Imm(imm), Operand::Reg {reg, size: 8}] if strcmp(base, c!("movabs")) == 0 => {
+            emit_opreg(a, true, false, 0xB8, reg);
+            emit_u64(a, imm as u64);
+            Some(())
+        }
+        [src @ Operand::Mem {..}, Operand::Reg {reg, ..}] if strcmp(base, c!("lea")) == 0 && size != 1 => {
+            emit_modrm(a, w, false, &[0x8D], reg, src, 0)
+        }
+        [dst] if strcmp(base, c!("imul")) == 0 && is_rm(dst) => {
+            emit_modrm(a, w, needs_rex8(dst), &[if size == 1 {0xF6} else {0xF7}], 5, dst, 0)
+        }
+        [src, Operand::Reg {reg, ..}] if strcmp(base, c!("imul")) == 0 && is_rm(src) && size != 1 => {
+            emit_modrm(a, w, false, &[0x0F, 0xAF], reg, src, 0)
+        }
+        [Operand::Reg {reg, size: 8}] if strcmp(base, c!("push")) == 0 => {
+            emit_opreg(a, false, false, 0x50, reg);
+            Some(())
+        }
+        [Operand::Imm(imm)] if strcmp(base, c!("push")) == 0 && size == 8 => {
+            if fits_i8(imm) {
+                emit_u8(a, 0x6A);
+                emit_imm(a, imm, 1)
+            } else {
+                emit_u8(a, 0x68);
+                emit_imm(a, imm, 4)
+            }
+        }
+        [Operand::Reg {reg, size: 8}] if strcmp(base, c!("pop")) == 0 => {
+            emit_opreg(a, false, false, 0x58, reg);
+            Some(())
+        }
+        _ => None,
+    }
+}
+
+unsafe fn align_section(a: *mut Assembler, alignment: u64, fill: u8) {
+    if alignment > (*section(a)).alignment {
+        (*section(a)).alignment = alignment;
+    }
+    while here(a) % alignment != 0 {
+        emit_u8(a, fill);
+    }
+}
+
+unsafe fn assemble_directive(a: *mut Assembler, directive: *const c_char, mut s: *const c_char) -> Option<()> {
+    skip_spaces(&mut s);
+    match () {
+        _ if strcmp(directive, c!(".text")) == 0 => (*a).current = Section_Kind::Text,
+        _ if strcmp(directive, c!(".data")) == 0 => (*a).current = Section_Kind::Data,
+        _ if strcmp(directive, c!(".section")) == 0 => {
+            let name = parse_name(&mut s)?;
+            if strcmp(name, c!(".text")) == 0 {
+                (*a).current = Section_Kind::Text;
+            } else if strcmp(name, c!(".data")) == 0 {
+                (*a).current = Section_Kind::Data;
+            } else {
+                log(Log_Level::ERROR, c!("unsupported section `%s`"), name);
+                return None;
+            }
+        }
+        _ if strcmp(directive, c!(".global")) == 0 || strcmp(directive, c!(".globl")) == 0 => {
+            loop {
+                let name = parse_name(&mut s)?;
+                let index = symbol(a, name);
+                (*(*a).symbols.items.add(index)).global = true;
+                skip_spaces(&mut s);
+                if *s != ',' as c_char { break; }
+                s = s.add(1);
+                skip_spaces(&mut s);
+            }
+        }
+        _ if strcmp(directive, c!(".p2align")) == 0 || strcmp(directive, c!(".balign")) == 0 || strcmp(directive, c!(".align")) == 0 => {
+            let amount = parse_expr(a, &mut s)?;
+            let mut fill = if (*a).current == Section_Kind::Text {0x90} else {0x00};
+            if *s == ',' as c_char {
+                s = s.add(1);
+                fill = parse_expr(a, &mut s)?.addend as u8;
+            }
+            if amount.symbol.is_some() || amount.addend < 0 || amount.addend >= 16 && *directive.add(1) == 'p' as c_char {
+                return None;
+            }
+            let alignment = if *directive.add(1) == 'p' as c_char {
+                1 << amount.addend
+            } else {
+                amount.addend as u64
+            };
+            if alignment == 0 || alignment & (alignment - 1) != 0 {
+                log(Log_Level::ERROR, c!("alignment must be a power of two"));
+                return None;
+            }
+            align_section(a, alignment, fill);
+        }
+        _ if strcmp(directive, c!(".space")) == 0 || strcmp(directive, c!(".skip")) == 0 || strcmp(directive, c!(".zero")) == 0 => {
+            let amount = parse_expr(a, &mut s)?;
+            let mut fill = 0;
+            if *s == ',' as c_char {
+                s = s.add(1);
+                fill = parse_expr(a, &mut s)?.addend as u8;
+            }
+            if amount.symbol.is_some() || amount.addend < 0 {
+                return None;
+            }
+            for _ in 0..amount.addend {
+                emit_u8(a, fill);
+            }
+        }
+        _ if strcmp(directive, c!(".quad")) == 0 || strcmp(directive, c!(".long")) == 0 || strcmp(directive, c!(".byte")) == 0 => {
+            let size = match *directive.add(1) as u8 {
+                b'q' => 8,
+                b'l' => 4,
+                _    => 1,
+            };
+            loop {
+                let expr = parse_expr(a, &mut s)?;
+                match (expr.symbol, size) {
+                    (Some(symbol), 8) => {
+                        emit_reloc(a, Reloc_Kind::Abs64, symbol, expr.addend);
+                        emit_u64(a, 0);
+                    }
+                    (Some(_), _) => {
+                        log(Log_Level::ERROR, c!("symbols are only supported in .quad"));
+                        return None;
+                    }
+                    (None, 8) => emit_u64(a, expr.addend as u64),
+                    (None, _) => emit_bytes(a, &(expr.addend as u64).to_le_bytes()[0..size]),
+                }
+                if *s != ',' as c_char { break; }
+                s = s.add(1);
+            }
+        }
+        _ => {
+            log(Log_Level::ERROR, c!("unsupported directive `%s`"), directive);
+            return None;
+        }
+    }
+    skip_spaces(&mut s);
+    if *s != 0 {
+        return None;
+    }
+    Some(())
+}
+
+unsafe fn assemble_statement(a: *mut Assembler, mut s: *const c_char) -> Option<()> {
+    loop {
+        skip_spaces(&mut s);
+        if *s == 0 {
+            return Some(());
+        }
+        let name = parse_name(&mut s)?;
+        if *s == ':' as c_char {
+            s = s.add(1);
+            define_symbol(a, name)?;
+            continue;
+        }
+        if *name == '.' as c_char {
+            return assemble_directive(a, name, s);
+        }
+
+        skip_spaces(&mut s);
+        let mut indirect = false;
+        if *s == '*' as c_char {
+            indirect = true;
+            s = s.add(1);
+        }
+        let mut ops = [Operand::Imm(0); 3];
+        let mut ops_count = 0;
+        skip_spaces(&mut s);
+        if *s != 0 {
+            loop {
+                if ops_count >= ops.len() {
+                    return None;
+                }
+                ops[ops_count] = parse_operand(a, &mut s)?;
+                ops_count += 1;
+                skip_spaces(&mut s);
+                if *s == 0 { break; }
+                if *s != ',' as c_char { return None; }
+                s = s.add(1);
+            }
+        }
+        return encode_instruction(a, name, &ops[..ops_count], indirect);
+    }
+}
+
+// Patches the PC-relative references that do not cross sections, so only the relocations
+// that need a linker are left
+unsafe fn resolve_local_relocs(a: *mut Assembler) {
+    for i in 0..SECTIONS_COUNT {
+        let section = &mut (*a).sections[i];
+        let mut count = 0;
+        for j in 0..section.relocs.count {
+            let reloc = *section.relocs.items.add(j);
+            let symbol = *(*a).symbols.items.add(reloc.symbol);
+            if reloc.kind != Reloc_Kind::Abs64 && symbol.defined && symbol.section as usize == i {
+                let value = (symbol.offset as i64 + reloc.addend - reloc.offset as i64) as i32;
+                ptr::copy_nonoverlapping(value.to_le_bytes().as_ptr(), section.bytes.items.add(reloc.offset as usize), 4);
+            } else {
+                *section.relocs.items.add(count) = reloc;
+                count += 1;
+            }
+        }
+        section.relocs.count = count;
+    }
+}
+
+pub unsafe fn assemble(a: *mut Assembler, source: *const [c_char], source_path: *const c_char) -> Option<()> {
+    let mut line_start = 0;
+    let mut line_number = 1;
+    while line_start < source.len() {
+        let mut line_end = line_start;
+        while line_end < source.len() && (*source)[line_end] != '\n' as c_char {
+            line_end += 1;
+        }
+
+        let checkpoint = temp_save();
+        let line = temp_sprintf(c!("%.*s"), (line_end - line_start) as c_int, (source as *const c_char).add(line_start));
+        let comment = strchr(line, '#' as c_int);
+        if !comment.is_null() {
+            *(comment as *mut c_char) = 0;
+        }
+        let mut stmt = line as *const c_char;
+        loop {
+            let separator = strchr(stmt, ';' as c_int);
+            if !separator.is_null() {
+                *(separator as *mut c_char) = 0;
+            }
+            if assemble_statement(a, stmt).is_none() {
+                log(Log_Level::ERROR, c!("%s:%zu: could not assemble `%s`"), source_path, line_number, stmt);
+                return None;
+            }
+            if separator.is_null() { break; }
+            stmt = separator.add(1);
+        }
+        temp_rewind(checkpoint);
+
+        line_start = line_end + 1;
+        line_number += 1;
+    }
+
+    let mut result = Some(());
+    for i in 0..(*a).symbols.count {
+        let symbol = *(*a).symbols.items.add(i);
+        if !symbol.defined && strncmp(symbol.name, c!(".L"), 2) == 0 {
+            log(Log_Level::ERROR, c!("%s: undefined local label `%s`"), source_path, symbol.name);
+            result = None;
+        }
+    }
+    resolve_local_relocs(a);
+    result
+}
+
+pub unsafe fn new_assembler() -> Assembler {
+    let mut a: Assembler = zeroed();
+    for i in 0..SECTIONS_COUNT {
+        a.sections[i].alignment = 1;
+    }
+    a
+}
@@ -0,0 +1,427 @@
+// ELF64 writer for the output of the builtin assembler. It can produce either a relocatable
+// object to be linked by the system linker, or a static executable when nothing has to be
+// linked in (-nostdlib).
+//
+// https://refspecs.linuxfoundation.org/elf/gabi4+/contents.html
+// https://refspecs.linuxfoundation.org/elf/x86_64-abi-0.99.pdf
+use core::ffi::*;
+use core::ptr;
+use core::mem::zeroed;
+use crate::nob::*;
+use crate::crust::libc::*;
+use super::assembler::*;
+
+const ELF_HEADER_SIZE:     u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+const SECTION_HEADER_SIZE: u64 = 64;
+const SYMBOL_SIZE:         u64 = 24;
+const RELA_SIZE:           u64 = 24;
+
+const ET_REL:  u16 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB:   u32 = 2;
+const SHT_STRTAB:   u32 = 3;
+const SHT_RELA:     u32 = 4;
+
+const SHF_WRITE:     u64 = 0x1;
+const SHF_ALLOC:     u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL:   u8 = 0;
+const STB_GLOBAL:  u8 = 1;
+const STT_NOTYPE:  u8 = 0;
+const STT_OBJECT:  u8 = 1;
+const STT_FUNC:    u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const R_X86_64_64:    u32 = 1;
+const R_X86_64_PC32:  u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+
+const PT_LOAD:      u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474E551;
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+const PF_R: u32 = 0x4;
+
+// The traditional base address of non-PIE executables on x86_64 Linux
+const EXECUTABLE_BASE: u64 = 0x400000;
+const PAGE_SIZE:       u64 = 0x1000;
+
+#[derive(Clone, Copy)]
+struct Section_Header {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    alignment: u64,
+    entry_size: u64,
+}
+
+unsafe fn write_u8(out: *mut Array<u8>, x: u8) {
+    da_append(out, x);
+}
+
+unsafe fn write_u16(out: *mut Array<u8>, x: u16) {
+    da_append_many(out, &x.to_le_bytes());
+}
+
+unsafe fn write_u32(out: *mut Array<u8>, x: u32) {
+    da_append_many(out, &x.to_le_bytes());
+}
+
+unsafe fn write_u64(out: *mut Array<u8>, x: u64) {
+    da_append_many(out, &x.to_le_bytes());
+}
+
+unsafe fn write_padding(out: *mut Array<u8>, alignment: u64) {
+    while (*out).count as u64 % alignment != 0 {
+        da_append(out, 0);
+    }
+}
+
+unsafe fn write_string(strtab: *mut Array<u8>, s: *const c_char) -> u32 {
+    let offset = (*strtab).count as u32;
+    da_append_many(strtab, core::slice::from_raw_parts(s as *const u8, strlen(s) + 1));
+    offset
+}
+
+unsafe fn write_elf_header(out: *mut Array<u8>, kind: u16, entry: u64, phnum: u16, shoff: u64, shnum: u16, shstrndx: u16) {
+    da_append_many(out, &[0x7F, b'E', b'L', b'F', 2 /* 64 bit */, 1 /* little endian */, 1 /* version */, 0 /* System V */]);
+    write_u64(out, 0);
+    write_u16(out, kind);
+    write_u16(out, EM_X86_64);
+    write_u32(out, 1);
+    write_u64(out, entry);
+    write_u64(out, if phnum > 0 {ELF_HEADER_SIZE} else {0});
+    write_u64(out, shoff);
+    write_u32(out, 0);
+    write_u16(out, ELF_HEADER_SIZE as u16);
+    write_u16(out, if phnum > 0 {PROGRAM_HEADER_SIZE as u16} else {0});
+    write_u16(out, phnum);
+    write_u16(out, SECTION_HEADER_SIZE as u16);
+    write_u16(out, shnum);
+    write_u16(out, shstrndx);
+}
+
+unsafe fn write_section_header(out: *mut Array<u8>, header: Section_Header) {
+    write_u32(out, header.name);
+    write_u32(out, header.kind);
+    write_u64(out, header.flags);
+    write_u64(out, header.addr);
+    write_u64(out, header.offset);
+    write_u64(out, header.size);
+    write_u32(out, header.link);
+    write_u32(out, header.info);
+    write_u64(out, header.alignment);
+    write_u64(out, header.entry_size);
+}
+
+unsafe fn write_symbol(out: *mut Array<u8>, name: u32, bind: u8, kind: u8, shndx: u16, value: u64) {
+    write_u32(out, name);
+    write_u8(out, (bind << 4) | kind);
+    write_u8(out, 0);
+    write_u16(out, shndx);
+    write_u64(out, value);
+    write_u64(out, 0);
+}
+
+unsafe fn write_program_header(out: *mut Array<u8>, kind: u32, flags: u32, offset: u64, vaddr: u64, size: u64) {
+    write_u32(out, kind);
+    write_u32(out, flags);
+    write_u64(out, offset);
+    write_u64(out, vaddr);
+    write_u64(out, vaddr);
+    write_u64(out, size);
+    write_u64(out, size);
+    write_u64(out, if kind == PT_LOAD {PAGE_SIZE} else {0x10});
+}
+
+// Layout of the object file:
+// [0] NULL, [1] .text, [2] .data, [3] .rela.text, [4] .rela.data, [5] .symtab, [6] .strtab,
+// [7] .shstrtab, [8] .note.GNU-stack
+const TEXT_SHNDX:     u16 = 1;
+const SYMTAB_SHNDX:   u32 = 5;
+const STRTAB_SHNDX:   u32 = 6;
+const SHSTRTAB_SHNDX: u16 = 7;
+const SHNUM:          u16 = 9;
+
+pub unsafe fn write_object(a: *mut Assembler, output_path: *const c_char) -> Option<()> {
+    let mut strtab: Array<u8> = zeroed();
+    let mut symtab: Array<u8> = zeroed();
+    let mut symbol_indices: Array<u32> = zeroed();
+    write_u8(&mut strtab, 0);
+
+    write_symbol(&mut symtab, 0, 0, 0, 0, 0);
+    for i in 0..SECTIONS_COUNT {
+        write_symbol(&mut symtab, 0, STB_LOCAL, STT_SECTION, TEXT_SHNDX + i as u16, 0);
+    }
+    let mut symbols_count = 1 + SECTIONS_COUNT as u32;
+    for i in 0..(*a).symbols.count {
+        da_append(&mut symbol_indices, 0);
+        let symbol = *(*a).symbols.items.add(i);
+        // Local labels are referenced through their section symbols, same as what GNU as does
+        if symbol.global || !symbol.defined || symbol.name.is_null() || strncmp(symbol.name, c!(".L"), 2) == 0 {
+            continue;
+        }
+        let name = write_string(&mut strtab, symbol.name);
+        write_symbol(&mut symtab, name, STB_LOCAL, STT_NOTYPE, TEXT_SHNDX + symbol.section as u16, symbol.offset);
+        *symbol_indices.items.add(i) = symbols_count;
+        symbols_count += 1;
+    }
+    let first_global = symbols_count;
+    for i in 0..(*a).symbols.count {
+        let symbol = *(*a).symbols.items.add(i);
+        if symbol.name.is_null() || (symbol.defined && !symbol.global) {
+            continue;
+        }
+        let name = write_string(&mut strtab, symbol.name);
+        if symbol.defined {
+            let kind = match symbol.section {
+                Section_Kind::Text => STT_FUNC,
+                Section_Kind::Data => STT_OBJECT,
+            };
+            write_symbol(&mut symtab, name, STB_GLOBAL, kind, TEXT_SHNDX + symbol.section as u16, symbol.offset);
+        } else {
+            write_symbol(&mut symtab, name, STB_GLOBAL, STT_NOTYPE, 0, 0);
+        }
+        *symbol_indices.items.add(i) = symbols_count;
+        symbols_count += 1;
+    }
+
+    let mut relas: [Array<u8>; SECTIONS_COUNT] = zeroed();
+    for i in 0..SECTIONS_COUNT {
+        let section = (*a).sections[i];
+        for j in 0..section.relocs.count {
+            let reloc = *section.relocs.items.add(j);
+            let symbol = *(*a).symbols.items.add(reloc.symbol);
+            let mut index = *symbol_indices.items.add(reloc.symbol);
+            let mut addend = reloc.addend;
+            if index == 0 {
+                index = 1 + symbol.section as u32;
+                addend += symbol.offset as i64;
+            }
+            let kind = match reloc.kind {
+                Reloc_Kind::Abs64 => R_X86_64_64,
+                Reloc_Kind::Pc32  => R_X86_64_PC32,
+                Reloc_Kind::Plt32 => R_X86_64_PLT32,
+            };
+            write_u64(&mut relas[i], reloc.offset);
+            write_u64(&mut relas[i], ((index as u64) << 32) | kind as u64);
+            write_u64(&mut relas[i], addend as u64);
+        }
+    }
+
+    let mut shstrtab: Array<u8> = zeroed();
+    write_u8(&mut shstrtab, 0);
+    let mut headers: [Section_Header; SHNUM as usize] = zeroed();
+    let mut out: Array<u8> = zeroed();
+    for _ in 0..ELF_HEADER_SIZE {
+        write_u8(&mut out, 0);
+    }
+
+    for i in 0..SECTIONS_COUNT {
+        let section = (*a).sections[i];
+        write_padding(&mut out, section.alignment);
+        headers[TEXT_SHNDX as usize + i] = Section_Header {
+            name: write_string(&mut shstrtab, SECTION_KINDS[i].name()),
+            kind: SHT_PROGBITS,
+            flags: match SECTION_KINDS[i] {
+                Section_Kind::Text => SHF_ALLOC | SHF_EXECINSTR,
+                Section_Kind::Data => SHF_ALLOC | SHF_WRITE,
+            },
+            addr: 0,
+            offset: out.count as u64,
+            size: section.bytes.count as u64,
+            link: 0,
+            info: 0,
+            alignment: section.alignment,
+            entry_size: 0,
+        };
+        da_append_many(&mut out, da_slice(section.bytes));
+    }
+    for i in 0..SECTIONS_COUNT {
+        write_padding(&mut out, 8);
+        headers[TEXT_SHNDX as usize + SECTIONS_COUNT + i] = Section_Header {
+            name: write_string(&mut shstrtab, temp_sprintf(c!(".rela%s"), SECTION_KINDS[i].name())),
+            kind: SHT_RELA,
+            flags: SHF_INFO_LINK,
+            addr: 0,
+            offset: out.count as u64,
+            size: relas[i].count as u64,
+            link: SYMTAB_SHNDX,
+            info: TEXT_SHNDX as u32 + i as u32,
+            alignment: 8,
+            entry_size: RELA_SIZE,
+        };
+        da_append_many(&mut out, da_slice(relas[i]));
+    }
+    write_padding(&mut out, 8);
+    headers[SYMTAB_SHNDX as usize] = Section_Header {
+        name: write_string(&mut shstrtab, c!(".symtab")),
+        kind: SHT_SYMTAB,
+        flags: 0,
+        addr: 0,
+        offset: out.count as u64,
+        size: symtab.count as u64,
+        link: STRTAB_SHNDX,
+        info: first_global,
+        alignment: 8,
+        entry_size: SYMBOL_SIZE,
+    };
+    da_append_many(&mut out, da_slice(symtab));
+    headers[STRTAB_SHNDX as usize] = Section_Header {
+        name: write_string(&mut shstrtab, c!(".strtab")),
+        kind: SHT_STRTAB,
+        flags: 0,
+        addr: 0,
+        offset: out.count as u64,
+        size: strtab.count as u64,
+        link: 0,
+        info: 0,
+        alignment: 1,
+        entry_size: 0,
+    };
+    da_append_many(&mut out, da_slice(strtab));
+    headers[SHNUM as usize - 1] = Section_Header {
+        name: write_string(&mut shstrtab, c!(".note.GNU-stack")),
+        kind: SHT_PROGBITS,
+        flags: 0,
+        addr: 0,
+        offset: out.count as u64,
+        size: 0,
+        link: 0,
+        info: 0,
+        alignment: 1,
+        entry_size: 0,
+    };
+    headers[SHSTRTAB_SHNDX as usize] = Section_Header {
+        name: write_string(&mut shstrtab, c!(".shstrtab")),
+        kind: SHT_STRTAB,
+        flags: 0,
+        addr: 0,
+        offset: out.count as u64,
+        size: 0,
+        link: 0,
+        info: 0,
+        alignment: 1,
+        entry_size: 0,
+    };
+    headers[SHSTRTAB_SHNDX as usize].size = shstrtab.count as u64;
+    da_append_many(&mut out, da_slice(shstrtab));
+
+    write_padding(&mut out, 8);
+    let shoff = out.count as u64;
+    for i in 0..headers.len() {
+        write_section_header(&mut out, headers[i]);
+    }
+
+    let mut header: Array<u8> = zeroed();
+    write_elf_header(&mut header, ET_REL, 0, 0, shoff, SHNUM, SHSTRTAB_SHNDX);
+    ptr::copy_nonoverlapping(header.items, out.items, header.count);
+
+    let result = write_entire_file(output_path, out.items as *const c_void, out.count);
+    free(out.items);
+    free(header.items);
+    free(shstrtab.items);
+    free(strtab.items);
+    free(symtab.items);
+    free(symbol_indices.items);
+    for i in 0..SECTIONS_COUNT {
+        free(relas[i].items);
+    }
+    result
+}
+
+// Links the assembled program on its own into a static executable with the entry point at `_start`.
+// There is no dynamic linker or libc involved, so the program must provide everything it uses.
+pub unsafe fn write_executable(a: *mut Assembler, output_path: *const c_char) -> Option<()> {
+    let text = &mut (*a).sections[Section_Kind::Text as usize];
+    let data = &mut (*a).sections[Section_Kind::Data as usize];
+
+    let has_data = data.bytes.count > 0;
+    let phnum: u64 = if has_data {3} else {2};
+    let text_offset = align_up(ELF_HEADER_SIZE + phnum*PROGRAM_HEADER_SIZE, text.alignment.max(16));
+    let text_end = text_offset + text.bytes.count as u64;
+    let data_offset = align_up(text_end, data.alignment.max(8));
+    // Data gets its own page right after the text, at the same offset within a page as in the file
+    let data_addr = align_up(EXECUTABLE_BASE + text_end, PAGE_SIZE) + data_offset % PAGE_SIZE;
+    let section_addrs = [EXECUTABLE_BASE + text_offset, data_addr];
+
+    let mut result = Some(());
+    for i in 0..SECTIONS_COUNT {
+        let section = &mut (*a).sections[i];
+        for j in 0..section.relocs.count {
+            let reloc = *section.relocs.items.add(j);
+            let symbol = *(*a).symbols.items.add(reloc.symbol);
+            if !symbol.defined {
+                log(Log_Level::ERROR, c!("undefined reference to `%s`"), symbol.name);
+                result = None;
+                continue;
+            }
+            let s = section_addrs[symbol.section as usize] + symbol.offset;
+            let p = section_addrs[i] + reloc.offset;
+            let target = section.bytes.items.add(reloc.offset as usize);
+            match reloc.kind {
+                Reloc_Kind::Abs64 => {
+                    let value = (s as i64 + reloc.addend) as u64;
+                    ptr::copy_nonoverlapping(value.to_le_bytes().as_ptr(), target, 8);
+                }
+                Reloc_Kind::Pc32 | Reloc_Kind::Plt32 => {
+                    let value = s as i64 + reloc.addend - p as i64;
+                    ptr::copy_nonoverlapping((value as i32).to_le_bytes().as_ptr(), target, 4);
+                }
+            }
+        }
+    }
+    result?;
+
+    let Some(entry) = find_symbol(a, c!("_start")) else {
+        log(Log_Level::ERROR, c!("undefined entry point `_start`"));
+        return None;
+    };
+    let entry = *(*a).symbols.items.add(entry);
+    if !entry.defined || entry.section != Section_Kind::Text {
+        log(Log_Level::ERROR, c!("undefined entry point `_start`"));
+        return None;
+    }
+
+    let mut out: Array<u8> = zeroed();
+    write_elf_header(&mut out, ET_EXEC, section_addrs[0] + entry.offset, phnum as u16, 0, 0, 0);
+    write_program_header(&mut out, PT_LOAD, PF_R | PF_X, 0, EXECUTABLE_BASE, text_end);
+    if has_data {
+        write_program_header(&mut out, PT_LOAD, PF_R | PF_W, data_offset, data_addr, data.bytes.count as u64);
+    }
+    write_program_header(&mut out, PT_GNU_STACK, PF_R | PF_W, 0, 0, 0);
+    while (out.count as u64) < text_offset {
+        write_u8(&mut out, 0);
+    }
+    da_append_many(&mut out, da_slice(text.bytes));
+    if has_data {
+        while (out.count as u64) < data_offset {
+            write_u8(&mut out, 0);
+        }
+        da_append_many(&mut out, da_slice(data.bytes));
+    }
+
+    let result = write_entire_file(output_path, out.items as *const c_void, out.count);
+    free(out.items);
+    result?;
+    if chmod(output_path, 0o755) != 0 {
+        log(Log_Level::ERROR, c!("could not make %s executable"), output_path);
+        return None;
+    }
+    Some(())
+}
+
+fn align_up(x: u64, alignment: u64) -> u64 {
+    (x + alignment - 1) / alignment * alignment
+}
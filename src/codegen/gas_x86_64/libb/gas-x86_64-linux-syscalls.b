@@ -0,0 +1,163 @@
+/* Freestanding libb for x86_64 Linux that talks to the kernel directly instead of going through libc.
+   It is meant for static executables produced by the builtin assembler and has to be included explicitly:
+
+     b -t gas-x86_64-linux -nostdlib -C assembler=builtin program.b ./build/libb/gas-x86_64-linux-syscalls.b
+*/
+
+_start __asm__(
+    "movq (%rsp), %rdi",   // argc
+    "leaq 8(%rsp), %rsi",  // argv
+    "andq $-16, %rsp",
+    "call main",
+    "movq %rax, %rdi",
+    "movq $231, %rax",     // exit_group
+    "syscall"
+);
+
+/* syscall(number, a, b, c) */
+syscall __asm__(
+    "movq %rdi, %rax",
+    "movq %rsi, %rdi",
+    "movq %rdx, %rsi",
+    "movq %rcx, %rdx",
+    "syscall",
+    "ret"
+);
+
+sx64  __asm__("movslq %edi, %rax", "ret");
+char  __asm__("xorq %rax, %rax", "movb (%rdi, %rsi), %al", "ret");
+lchar __asm__("movb %dl, (%rdi, %rsi)", "ret");
+
+exit(code) {
+    syscall(231, code, 0, 0);
+}
+
+abort() {
+    exit(134);
+}
+
+putchar(c) {
+    syscall(1, 1, &c, 1); /* write */
+    return (c);
+}
+
+getchar() {
+    auto c;
+    c = 0;
+    if (syscall(0, 0, &c, 1) <= 0) return (-1); /* read */
+    return (c);
+}
+
+__printn(n, b, sign, digits) {
+    auto q, r;
+
+    if (sign & n < 0) {
+        putchar('-');
+        n = -n;
+    }
+
+    /* The division is signed, so halve the number first to get a positive one */
+    q = ((n >> 1) / b) << 1;
+    r = n - q*b;
+    if (r >= b) {
+        q++;
+        r -= b;
+    }
+
+    if (q) __printn(q, b, 0, digits); /* recursive */
+    putchar(char(digits, r));
+}
+
+printn(n, b, sign) {
+    __printn(n, b, sign, "0123456789abcdef");
+}
+
+printf(str, x1, x2, x3, x4, x5, x6, x7, x8, x9, x10, x11, x12, x13, x14, x15) {
+    auto i, j, arg, c;
+    i = 0;
+
+    arg = &x1;
+
+    while (c = char(str, i++)) {
+        if (c != '%') {
+            putchar(c);
+        } else {
+            c = char(str, i++);
+            while (c == 'l' | c == 'z') { /* %ld, %lld, %zu, etc */
+                c = char(str, i++);
+            }
+            if (c == 0) {
+                return;
+            } else if (c == '%') {
+                putchar('%');
+                arg += 8; /* word size */
+            } else if (c == 'd') {
+                printn(*arg, 10, 1);
+            } else if (c == 'u') {
+                printn(*arg, 10, 0);
+            } else if (c == 'x') {
+                printn(*arg, 16, 0);
+            } else if (c == 'X') {
+                __printn(*arg, 16, 0, "0123456789ABCDEF");
+            } else if (c == 'o') {
+                printn(*arg, 8, 0);
+            } else if (c == 'p') {
+                putchar('0');
+                putchar('x');
+                printn(*arg, 16, 0);
+            } else if (c == 'c') {
+                putchar(*arg);
+            } else if (c == 's') {
+                j = 0;
+                while (c = char(*arg, j++)) {
+                    putchar(c);
+                }
+            } else {
+                putchar('%');
+                putchar(c);
+                arg += 8; /* word size */
+            }
+            arg -= 8; /* word size */
+        }
+    }
+}
+
+strlen(s) {
+    auto n;
+    n = 0;
+    while (char(s, n)) n++;
+    return (n);
+}
+
+toupper(c) {
+    if ('a' <= c & c <= 'z') return (c - 'a' + 'A');
+    return (c);
+}
+
+/* memory related functions */
+__heap_end 0;
+malloc(size) {
+    auto ptr;
+    if (__heap_end == 0) __heap_end = syscall(12, 0, 0, 0); /* brk */
+    ptr = __heap_end;
+    size = (size + 15)/16*16;
+    __heap_end = syscall(12, ptr + size, 0, 0);
+    if (__heap_end != ptr + size) {
+        __heap_end = ptr;
+        return (0);
+    }
+    return (ptr);
+}
+/* TODO: free someting? */
+free(ptr) {
+    ptr; /* the allocations are never given back yet */
+}
+
+memset(addr, val, size) {
+    auto i;
+    i = 0;
+    while (i < size) {
+        lchar(addr, i, val);
+        i += 1;
+    }
+}
@@ -9,6 +9,11 @@ use crate::lexer::Loc;
 use crate::shlex::*;
 use crate::arena;
 use crate::params::*;
+use crate::proc::*;
+use crate::dwarf::{self, Dwarf_Target};
+
+pub mod assembler;
+pub mod elf;
 
 pub unsafe fn align_bytes(bytes: usize, alignment: usize) -> usize {
     let rem = bytes%alignment;
@@ -419,225 +424,69 @@ pub unsafe fn generate_data_section(output: *mut String_Builder, data: *const [u
     }
 }
 
-mod dwarf {
-    // arbitrary constants
-    pub const TEMPLATE_compilation_unit : u64 = 1;
-    pub const TEMPLATE_function         : u64 = 2;
-    pub const TEMPLATE_variable         : u64 = 3;
-    pub const TEMPLATE_block            : u64 = 4;
-    pub const TEMPLATE_type             : u64 = 5;
-
-    // other constants
-    pub const version                   : u64 = 5;
-    pub const addr_size                 : u64 = 8;
-    pub const default_type_size         : u64 = 8;
-
-    // taken from dwarf.h, DW_ prefix stripped
-    pub const CHILDREN_no               : u64 = 0;
-    pub const CHILDREN_yes              : u64 = 1;
-
-    pub const AT_location               : u64 = 0x02;
-    pub const AT_name                   : u64 = 0x03;
-    pub const AT_byte_size              : u64 = 0x0b;
-    pub const AT_stmt_list              : u64 = 0x10;
-    pub const AT_low_pc                 : u64 = 0x11;
-    pub const AT_high_pc                : u64 = 0x12;
-    pub const AT_encoding               : u64 = 0x3e;
-    pub const AT_frame_base             : u64 = 0x40;
-    pub const AT_type                   : u64 = 0x49;
-
-    pub const DW_ATE_signed             : u64 = 0x05;
-
-    pub const FORM_addr                 : u64 = 0x01;
-    pub const FORM_string               : u64 = 0x08;
-    pub const FORM_data1                : u64 = 0x0b;
-    pub const FORM_ref4                 : u64 = 0x13;
-    pub const FORM_sec_offset           : u64 = 0x17;
-    pub const FORM_exprloc              : u64 = 0x18;
-
-    pub const OP_addr                   : u64 = 0x03;
-    pub const OP_fbreg                  : u64 = 0x91;
-    pub const OP_call_frame_cfa         : u64 = 0x9c;
-
-    pub const UT_compile                : u64 = 0x01;
-    pub const TAG_lexical_block         : u64 = 0x0b;
-    pub const TAG_compile_unit          : u64 = 0x11;
-    pub const TAG_base_type             : u64 = 0x24;
-    pub const TAG_subprogram            : u64 = 0x2e;
-    pub const TAG_variable              : u64 = 0x34;
+// The autos are below the saved %rbp and the return address, so the CFA is %rbp + 16
+unsafe fn auto_var_cfa_offset(index: usize) -> i64 {
+    -(index as i64 + 2)*8
 }
 
 // TODO: all of this probably doesn't work on gas-x86_64-darwin
 pub unsafe fn generate_debuginfo(output: *mut String_Builder, funcs: Array<Func>, globals: Array<Global>, os: Os) {
-    sb_appendf(output, c!(".section .debug_abbrev\n"));
-
-        sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_compilation_unit);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TAG_compile_unit);
-            sb_appendf(output, c!(".byte %lld\n"),    dwarf::CHILDREN_yes);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_stmt_list);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_sec_offset);
-        sb_appendf(output, c!(".byte 0\n"));
-        sb_appendf(output, c!(".byte 0\n"));
-
-        sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_function);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TAG_subprogram);
-            sb_appendf(output, c!(".byte %lld\n"), dwarf::CHILDREN_yes);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_name);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_string);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_low_pc);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_addr);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_high_pc);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_addr);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_frame_base);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_exprloc);
-        sb_appendf(output, c!(".byte 0\n"));
-        sb_appendf(output, c!(".byte 0\n"));
-
-        sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_variable);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TAG_variable);
-            sb_appendf(output, c!(".byte	%lld\n"), dwarf::CHILDREN_no);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_name);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_string);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_type);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_ref4);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_location);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_exprloc);
-        sb_appendf(output, c!(".byte	0\n"));
-        sb_appendf(output, c!(".byte	0\n"));
-
-        sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_block);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TAG_lexical_block);
-            sb_appendf(output, c!(".byte %lld\n"), dwarf::CHILDREN_yes);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_low_pc);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_addr);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_high_pc);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_addr);
-        sb_appendf(output, c!(".byte 0\n"));
-        sb_appendf(output, c!(".byte 0\n"));
-
-        sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_type);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TAG_base_type);
-            sb_appendf(output, c!(".byte %lld\n"), dwarf::CHILDREN_no);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_byte_size);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_data1);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_encoding);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_data1);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::AT_name);
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::FORM_string);
-        sb_appendf(output, c!(".byte 0\n"));
-        sb_appendf(output, c!(".byte 0\n"));
-
-    sb_appendf(output, c!(".byte 0\n"));
-
-
-    sb_appendf(output, c!(".section .debug_info\n"));
-
-        sb_appendf(output, c!(".long .debug_info_end - .debug_info_start\n"));
-        sb_appendf(output, c!(".debug_info_start: \n"));
-        sb_appendf(output, c!(".value %lld\n"), dwarf::version);
-        sb_appendf(output, c!(".byte %lld\n"), dwarf::UT_compile);
-        sb_appendf(output, c!(".byte %lld\n"), dwarf::addr_size);
-        sb_appendf(output, c!(".long .debug_abbrev\n"));
-
-        sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_compilation_unit);
-
-            sb_appendf(output, c!(".long .debug_line\n"));
-            generate_funcs_debuginfo(output, funcs, os);
-            generate_globals_debuginfo(output, globals, os);
-
-            sb_appendf(output, c!("debug_info_word_type_offset = .-.debug_info\n"));
-            sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_type);
-            sb_appendf(output, c!(".byte %lld\n"), dwarf::default_type_size);
-            sb_appendf(output, c!(".byte %lld\n"), dwarf::DW_ATE_signed);
-            sb_appendf(output, c!(".string \"word\"\n"));
-
-        sb_appendf(output, c!(".byte 0\n"));
-
-    sb_appendf(output, c!(".debug_info_end: \n"));
+    let target = match os {
+        Os::Linux | Os::Windows => Dwarf_Target { symbol_prefix: c!(""),  local_prefix: c!(".L"), auto_var_offset: auto_var_cfa_offset },
+        Os::Darwin              => Dwarf_Target { symbol_prefix: c!("_"), local_prefix: c!("L"),  auto_var_offset: auto_var_cfa_offset },
+    };
+    dwarf::generate_debuginfo(output, funcs, globals, target);
 }
 
-pub unsafe fn generate_globals_debuginfo(output: *mut String_Builder, globals: Array<Global>, os: Os) {
-    for i in 0..globals.count {
-        let global = *globals.items.add(i);
-        sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_variable);
-        sb_appendf(output, c!(".string \"%s\"\n"), global.name);
-        sb_appendf(output, c!(".long debug_info_word_type_offset\n"));
-        sb_appendf(output, c!(".uleb128 0x9\n")); // .byte (1) + .quad (8) = 9
-        sb_appendf(output, c!(".byte %lld\n"), dwarf::OP_addr);
-        match os {
-            Os::Linux | Os::Windows => sb_appendf(output, c!(".quad %s\n"),  global.name),
-            Os::Darwin              => sb_appendf(output, c!(".quad _%s\n"), global.name)
-        };
-    }
+pub unsafe fn usage(params: *const [Param]) {
+    fprintf(stderr(), c!("gas_x86_64 codegen for the B compiler\n"));
+    fprintf(stderr(), c!("OPTIONS:\n"));
+    print_params_help(params);
 }
 
-pub unsafe fn sleb128_length(mut n: i64) -> u64 {
-    if n == 0 { return 1 }
-
-    let mut len = 0;
-    n <<= 1;
-    while n != 0 && n != -1 {
-        n >>= 7;
-        len += 1;
+enum_with_order! {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Assembler_Kind in ASSEMBLER_KIND_ORDER {
+        Gas,
+        Builtin,
     }
-    len
 }
 
-pub unsafe fn generate_funcs_debuginfo(output: *mut String_Builder, funcs: Array<Func>, os: Os) {
-    for i in 0..funcs.count {
-        let func = *funcs.items.add(i);
-
-        sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_function);
-        sb_appendf(output, c!(".string \"%s\"\n"), func.name);
-        sb_appendf(output, c!(".quad %s\n"), func.name);
-        match os {
-            Os::Linux | Os::Windows => sb_appendf(output, c!(".quad .L%s_end\n"), func.name),
-            Os::Darwin              => sb_appendf(output, c!(".quad  L%s_end\n"), func.name),
-        };
-        sb_appendf(output, c!(".uleb128 0x1\n")); // .byte (1) = 1
-        sb_appendf(output, c!(".byte %lld\n"), dwarf::OP_call_frame_cfa);
-
-        for j in 0..func.scope_events.count {
-            match *func.scope_events.items.add(j) {
-                ScopeEvent::Declare { name, index } => {
-                    sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_variable);
-                    sb_appendf(output, c!(".string \"%s\"\n"), name);
-                    sb_appendf(output, c!(".long debug_info_word_type_offset\n"));
-
-                    let offset = -(index as i64 + 2)*8;
-                    sb_appendf(output, c!(".uleb128 %lld\n"), sleb128_length(offset)+1);
-                    sb_appendf(output, c!(".byte %lld\n"), dwarf::OP_fbreg);
-                    sb_appendf(output, c!(".sleb128 %lld\n"), offset);
-                }
-                ScopeEvent::BlockBegin { index } => {
-                    sb_appendf(output, c!(".uleb128 %lld\n"), dwarf::TEMPLATE_block);
-                    sb_appendf(output, c!(".quad .L%s_block_start_%zu\n"), func.name, index);
-                    sb_appendf(output, c!(".quad .L%s_block_end_%zu\n"), func.name, index);
-                }
-                ScopeEvent::BlockEnd { .. } => {
-                    sb_appendf(output, c!(".byte 0\n"));
-                }
-            }
+impl Assembler_Kind {
+    fn name(self) -> *const c_char {
+        match self {
+            Assembler_Kind::Gas     => c!("gas"),
+            Assembler_Kind::Builtin => c!("builtin"),
         }
+    }
 
-        sb_appendf(output, c!(".byte 0\n"));
+    fn description(self) -> *const c_char {
+        match self {
+            Assembler_Kind::Gas     => c!("GNU assembler followed by the system linker"),
+            Assembler_Kind::Builtin => c!("Builtin assembler writing ELF directly (linux only)"),
+        }
     }
-}
 
-pub unsafe fn usage(params: *const [Param]) {
-    fprintf(stderr(), c!("gas_x86_64 codegen for the B compiler\n"));
-    fprintf(stderr(), c!("OPTIONS:\n"));
-    print_params_help(params);
+    unsafe fn from_name(name: *const c_char) -> Option<Self> {
+        for i in 0..ASSEMBLER_KIND_ORDER.len() {
+            let kind = (*ASSEMBLER_KIND_ORDER)[i];
+            if strcmp(kind.name(), name) == 0 {
+                return Some(kind);
+            }
+        }
+        None
+    }
 }
 
 struct Gas_x86_64 {
     link_args: *const c_char,
+    assembler: Assembler_Kind,
     output: String_Builder,
     cmd: Cmd,
 }
 
 pub unsafe fn get_apis(targets: *mut Array<TargetAPI>) {
-    da_append(targets, TargetAPI::V1 {
+    da_append(targets, TargetAPI::V2 {
         name: c!("gas-x86_64-linux"),
         file_ext: c!(""),
         new,
@@ -647,9 +496,15 @@ pub unsafe fn get_apis(targets: *mut Array<TargetAPI>) {
         run: |gen, program_path, run_args| {
             run_program(gen, program_path, run_args, Os::Linux)
         },
+        build_object: |gen, program, object_path, garbage_base, debug| {
+            generate_object(gen, program, object_path, garbage_base, Os::Linux, debug)
+        },
+        link: |gen, object_paths, program_path, nostdlib| {
+            link_objects(gen as *mut Gas_x86_64, object_paths, program_path, Os::Linux, nostdlib)
+        },
     });
 
-    da_append(targets, TargetAPI::V1 {
+    da_append(targets, TargetAPI::V2 {
         name: c!("gas-x86_64-windows"),
         file_ext: c!(".exe"),
         new,
@@ -659,9 +514,15 @@ pub unsafe fn get_apis(targets: *mut Array<TargetAPI>) {
         run: |gen, program_path, run_args| {
             run_program(gen, program_path, run_args, Os::Windows)
         },
+        build_object: |gen, program, object_path, garbage_base, debug| {
+            generate_object(gen, program, object_path, garbage_base, Os::Windows, debug)
+        },
+        link: |gen, object_paths, program_path, nostdlib| {
+            link_objects(gen as *mut Gas_x86_64, object_paths, program_path, Os::Windows, nostdlib)
+        },
     });
 
-    da_append(targets, TargetAPI::V1 {
+    da_append(targets, TargetAPI::V2 {
         name: c!("gas-x86_64-darwin"),
         file_ext: c!(""),
         new,
@@ -671,6 +532,12 @@ pub unsafe fn get_apis(targets: *mut Array<TargetAPI>) {
         run: |gen, program_path, run_args| {
             run_program(gen, program_path, run_args, Os::Darwin)
         },
+        build_object: |gen, program, object_path, garbage_base, debug| {
+            generate_object(gen, program, object_path, garbage_base, Os::Darwin, debug)
+        },
+        link: |gen, object_paths, program_path, nostdlib| {
+            link_objects(gen as *mut Gas_x86_64, object_paths, program_path, Os::Darwin, nostdlib)
+        },
     });
 }
 
@@ -679,6 +546,7 @@ pub unsafe fn new(a: *mut arena::Arena, args: *const [*const c_char]) -> Option<
     memset(gen as _ , 0, size_of::<Gas_x86_64>());
 
     let mut help = false;
+    let mut assembler_name = zeroed();
     let params = &[
         Param {
             name:        c!("help"),
@@ -690,6 +558,11 @@ pub unsafe fn new(a: *mut arena::Arena, args: *const [*const c_char]) -> Option<
             description: c!("Additional linker arguments"),
             value:       ParamValue::String { var: &mut (*gen).link_args, default: c!("") },
         },
+        Param {
+            name:        c!("assembler"),
+            description: c!("What assembler to use for producing the executable"),
+            value:       ParamValue::String { var: &mut assembler_name, default: Assembler_Kind::Gas.name() },
+        },
     ];
 
     if let Err(message) = parse_args(params, args) {
@@ -703,16 +576,38 @@ pub unsafe fn new(a: *mut arena::Arena, args: *const [*const c_char]) -> Option<
         return None;
     }
 
+    if let Some(assembler) = Assembler_Kind::from_name(assembler_name) {
+        (*gen).assembler = assembler;
+    } else {
+        usage(params);
+        log(Log_Level::ERROR, c!("Invalid assembler name `%s`!"), assembler_name);
+        log(Log_Level::ERROR, c!("Valid names:"));
+        for i in 0..ASSEMBLER_KIND_ORDER.len() {
+            let assembler = (*ASSEMBLER_KIND_ORDER)[i];
+            log(Log_Level::ERROR, c!("    %s - %s"), assembler.name(), assembler.description());
+        }
+        return None;
+    }
+
     Some(gen as *mut c_void)
 }
 
-pub unsafe fn generate_program(
-    gen: *mut c_void, program: *const Program, program_path: *const c_char, garbage_base: *const c_char, os: Os,
-    nostdlib: bool, debug: bool,
-) -> Option<()> {
-    let gen = gen as *mut Gas_x86_64;
+// Generates the assembly of the program into Gas_x86_64::output and saves it next to the other garbage.
+// Returns the path to the saved assembly.
+unsafe fn generate_asm(gen: *mut Gas_x86_64, program: *const Program, garbage_base: *const c_char, os: Os, debug: bool) -> Option<*const c_char> {
     let output = &mut (*gen).output;
-    let cmd = &mut (*gen).cmd;
+    output.count = 0;
+
+    if (*gen).assembler == Assembler_Kind::Builtin {
+        if os != Os::Linux {
+            log(Log_Level::ERROR, c!("The builtin assembler only supports Linux for now"));
+            return None;
+        }
+        if debug {
+            log(Log_Level::ERROR, c!("The builtin assembler does not support debug information yet. Use -%s assembler=gas"), PARAM_FLAG_NAME);
+            return None;
+        }
+    }
 
     if debug { generate_debuginfo(output, (*program).funcs, (*program).globals, os); }
 
@@ -732,7 +627,12 @@ pub unsafe fn generate_program(
     let output_asm_path = temp_sprintf(c!("%s.s"), garbage_base);
     write_entire_file(output_asm_path, (*output).items as *const c_void, (*output).count)?;
     log(Log_Level::INFO, c!("generated %s"), output_asm_path);
+    Some(output_asm_path)
+}
 
+// Turns the assembly generated by generate_asm() into an object
+unsafe fn assemble_object(gen: *mut Gas_x86_64, asm_path: *const c_char, object_path: *const c_char, os: Os) -> Option<()> {
+    let cmd = &mut (*gen).cmd;
     match os {
         Os::Darwin => {
             if !(cfg!(target_os = "macos")) {
@@ -740,31 +640,57 @@ pub unsafe fn generate_program(
                 log(Log_Level::ERROR, c!("Cross-compilation of darwin is not supported"));
                 return None;
             }
-
-            let (gas, cc) = (c!("as"), c!("cc"));
-
-            let output_obj_path = temp_sprintf(c!("%s.o"), program_path);
             cmd_append! {
                 cmd,
-                gas, c!("-arch"), c!("x86_64"), c!("-o"), output_obj_path, output_asm_path,
+                c!("as"), c!("-arch"), c!("x86_64"), c!("-o"), object_path, asm_path,
             }
             if !cmd_run_sync_and_reset(cmd) { return None; }
+        }
+        Os::Linux => match (*gen).assembler {
+            Assembler_Kind::Gas => {
+                if !(cfg!(target_arch = "x86_64") && cfg!(target_os = "linux")) {
+                    // TODO: think how to approach cross-compilation
+                    log(Log_Level::ERROR, c!("Cross-compilation of x86_64 linux is not supported for now"));
+                    return None;
+                }
 
+                cmd_append! {
+                    cmd,
+                    c!("as"), asm_path, c!("-o"), object_path,
+                }
+                if !cmd_run_sync_and_reset(cmd) { return None; }
+            }
+            Assembler_Kind::Builtin => {
+                let mut a = assembler::new_assembler();
+                assembler::assemble(&mut a, da_slice((*gen).output), asm_path)?;
+                elf::write_object(&mut a, object_path)?;
+                log(Log_Level::INFO, c!("generated %s"), object_path);
+            }
+        }
+        Os::Windows => {
             cmd_append! {
                 cmd,
-                cc, c!("-arch"), c!("x86_64"), c!("-o"), program_path, output_obj_path,
+                c!("as"), asm_path, c!("-o"), object_path,
             }
-            if nostdlib {
-                cmd_append!(cmd, c!("-nostdlib"));
+            if !cmd_run_sync_and_reset(cmd) { return None; }
+        }
+    }
+    Some(())
+}
+
+unsafe fn link_objects(gen: *mut Gas_x86_64, object_paths: *const [*const c_char], program_path: *const c_char, os: Os, nostdlib: bool) -> Option<()> {
+    let cmd = &mut (*gen).cmd;
+    match os {
+        Os::Darwin => {
+            if !(cfg!(target_os = "macos")) {
+                // TODO: think how to approach cross-compilation
+                log(Log_Level::ERROR, c!("Cross-compilation of darwin is not supported"));
+                return None;
             }
-            let mut s: Shlex = zeroed();
-            let link_args = (*gen).link_args;
-            shlex_init(&mut s, link_args, link_args.add(strlen(link_args)));
-            while !shlex_next(&mut s).is_null() {
-                da_append(cmd, temp_strdup(s.string));
+            cmd_append! {
+                cmd,
+                c!("cc"), c!("-arch"), c!("x86_64"), c!("-o"), program_path,
             }
-            shlex_free(&mut s);
-            if !cmd_run_sync_and_reset(cmd) { return None; }
         }
         Os::Linux => {
             if !(cfg!(target_arch = "x86_64") && cfg!(target_os = "linux")) {
@@ -772,57 +698,67 @@ pub unsafe fn generate_program(
                 log(Log_Level::ERROR, c!("Cross-compilation of x86_64 linux is not supported for now"));
                 return None;
             }
-
-            let output_obj_path = temp_sprintf(c!("%s.o"), garbage_base);
             cmd_append! {
                 cmd,
-                c!("as"), output_asm_path, c!("-o"), output_obj_path,
-            }
-            if !cmd_run_sync_and_reset(cmd) { return None; }
-
-            cmd_append! {
-                cmd,
-                c!("cc"), c!("-no-pie"), c!("-o"), program_path, output_obj_path,
-            }
-            if nostdlib {
-                cmd_append!(cmd, c!("-nostdlib"));
-            }
-            let mut s: Shlex = zeroed();
-            let link_args = (*gen).link_args;
-            shlex_init(&mut s, link_args, link_args.add(strlen(link_args)));
-            while !shlex_next(&mut s).is_null() {
-                da_append(cmd, temp_strdup(s.string));
+                c!("cc"), c!("-no-pie"), c!("-o"), program_path,
             }
-            shlex_free(&mut s);
-            if !cmd_run_sync_and_reset(cmd) { return None; }
         }
         Os::Windows => {
-            let output_obj_path = temp_sprintf(c!("%s.o"), garbage_base);
             cmd_append! {
                 cmd,
-                c!("as"), output_asm_path, c!("-o"), output_obj_path,
+                c!("x86_64-w64-mingw32-gcc"), c!("-no-pie"), c!("-o"), program_path,
             }
-            if !cmd_run_sync_and_reset(cmd) { return None; }
+        }
+    }
+    da_append_many(cmd, object_paths);
+    if nostdlib {
+        cmd_append!(cmd, c!("-nostdlib"));
+    }
+    let mut s: Shlex = zeroed();
+    let link_args = (*gen).link_args;
+    shlex_init(&mut s, link_args, link_args.add(strlen(link_args)));
+    while !shlex_next(&mut s).is_null() {
+        da_append(cmd, temp_strdup(s.string));
+    }
+    shlex_free(&mut s);
+    if !cmd_run_sync_and_reset(cmd) { return None; }
+    Some(())
+}
 
-            cmd_append! {
-                cmd,
-                c!("x86_64-w64-mingw32-gcc"), c!("-no-pie"), c!("-o"), program_path, output_obj_path,
-            }
-            if nostdlib {
-                cmd_append!(cmd, c!("-nostdlib"));
-            }
-            let mut s: Shlex = zeroed();
-            let link_args = (*gen).link_args;
-            shlex_init(&mut s, link_args, link_args.add(strlen(link_args)));
-            while !shlex_next(&mut s).is_null() {
-                da_append(cmd, temp_strdup(s.string));
-            }
-            shlex_free(&mut s);
-            if !cmd_run_sync_and_reset(cmd) { return None; }
+pub unsafe fn generate_program(
+    gen: *mut c_void, program: *const Program, program_path: *const c_char, garbage_base: *const c_char, os: Os,
+    nostdlib: bool, debug: bool,
+) -> Option<()> {
+    let gen = gen as *mut Gas_x86_64;
+    let output_asm_path = generate_asm(gen, program, garbage_base, os, debug)?;
+
+    if (*gen).assembler == Assembler_Kind::Builtin && nostdlib {
+        // Nothing to link with, so we can produce the final executable ourselves
+        if *(*gen).link_args != 0 {
+            log(Log_Level::WARNING, c!("link-args are ignored when the builtin assembler produces a static executable"));
         }
+        let mut a = assembler::new_assembler();
+        assembler::assemble(&mut a, da_slice((*gen).output), output_asm_path)?;
+        elf::write_executable(&mut a, program_path)?;
+        log(Log_Level::INFO, c!("generated %s"), program_path);
+        return Some(());
     }
 
-    Some(())
+    let output_obj_path = match os {
+        Os::Darwin => temp_sprintf(c!("%s.o"), program_path),
+        Os::Linux | Os::Windows => temp_sprintf(c!("%s.o"), garbage_base),
+    };
+    assemble_object(gen, output_asm_path, output_obj_path, os)?;
+    link_objects(gen, &[output_obj_path as *const c_char], program_path, os, nostdlib)
+}
+
+pub unsafe fn generate_object(
+    gen: *mut c_void, program: *const Program, object_path: *const c_char, garbage_base: *const c_char, os: Os,
+    debug: bool,
+) -> Option<()> {
+    let gen = gen as *mut Gas_x86_64;
+    let output_asm_path = generate_asm(gen, program, garbage_base, os, debug)?;
+    assemble_object(gen, output_asm_path, object_path, os)
 }
 
 pub unsafe fn run_program(
@@ -842,7 +778,7 @@ pub unsafe fn run_program(
 
             cmd_append! {cmd, run_path}
             da_append_many(cmd, run_args);
-            if !cmd_run_sync_and_reset(cmd) { return None; }
+            cmd_run_sync_forwarding_exit_code_and_reset(cmd)?;
         }
         Os::Windows => {
             // TODO: document that you may need wine as a system package to cross-run gas-x86_64-windows
@@ -855,7 +791,7 @@ pub unsafe fn run_program(
 
             cmd_append! {cmd, program_path}
             da_append_many(cmd, run_args);
-            if !cmd_run_sync_and_reset(cmd) { return None; }
+            cmd_run_sync_forwarding_exit_code_and_reset(cmd)?;
         }
         Os::Darwin => {
             if !cfg!(target_os = "macos") {
@@ -873,7 +809,7 @@ pub unsafe fn run_program(
 
             cmd_append! {cmd, run_path}
             da_append_many(cmd, run_args);
-            if !cmd_run_sync_and_reset(cmd) { return None; }
+            cmd_run_sync_forwarding_exit_code_and_reset(cmd)?;
         }
     }
     Some(())
@@ -0,0 +1,22 @@
+/* Standard Library for the ir-interp target
+ *
+ * These functions are implemented natively by the interpreter itself. See Native in mod.rs
+ */
+extrn putchar;
+extrn getchar;
+extrn printf;
+extrn exit;
+extrn char;
+extrn lchar;
+
+strlen(s) {
+    auto n;
+    n = 0;
+    while (char(s, n)) n++;
+    return (n);
+}
+
+toupper(c) {
+    if ('a' <= c & c <= 'z') return (c - 'a' + 'A');
+    return (c);
+}
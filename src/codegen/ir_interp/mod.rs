@@ -0,0 +1,911 @@
+//! Interpreter of the IR. Runs B programs without any assembler, linker or emulator.
+//!
+//! The build step serializes the Program into a binary image and the run step loads the image
+//! back and executes it. All the memory the program touches (autovars, globals, the data section,
+//! malloc-ed blocks) is the native memory of the interpreter, so a B pointer is just a native
+//! pointer and the word size is 8 bytes.
+
+use core::ffi::*;
+use core::mem::zeroed;
+use core::ptr;
+use crate::ir::*;
+use crate::nob::*;
+use crate::targets::TargetAPI;
+use crate::crust::libc::*;
+use crate::lexer::Loc;
+use crate::diagf;
+use crate::arena;
+use crate::params::*;
+
+const IMAGE_MAGIC: *const c_char = c!("B-IR-IMAGE-1");
+const STACK_WORDS: usize = 1024*1024;
+const MAX_PRINTF_ARGS: usize = 16;
+
+enum_with_order! {
+    #[derive(Clone, Copy)]
+    enum Native in NATIVE_ORDER {
+        Putchar,
+        Getchar,
+        Printf,
+        Exit,
+        Abort,
+        Malloc,
+        Free,
+        Char,
+        Lchar,
+    }
+}
+
+impl Native {
+    unsafe fn name(self) -> *const c_char {
+        match self {
+            Native::Putchar => c!("putchar"),
+            Native::Getchar => c!("getchar"),
+            Native::Printf  => c!("printf"),
+            Native::Exit    => c!("exit"),
+            Native::Abort   => c!("abort"),
+            Native::Malloc  => c!("malloc"),
+            Native::Free    => c!("free"),
+            Native::Char    => c!("char"),
+            Native::Lchar   => c!("lchar"),
+        }
+    }
+
+    unsafe fn from_name(name: *const c_char) -> Option<Self> {
+        for i in 0..NATIVE_ORDER.len() {
+            let native = (*NATIVE_ORDER)[i];
+            if strcmp(native.name(), name) == 0 {
+                return Some(native);
+            }
+        }
+        None
+    }
+}
+
+const BINOP_ORDER: *const [Binop] = &[
+    Binop::Plus,
+    Binop::Minus,
+    Binop::Mult,
+    Binop::Div,
+    Binop::Mod,
+    Binop::Equal,
+    Binop::NotEqual,
+    Binop::Less,
+    Binop::LessEqual,
+    Binop::Greater,
+    Binop::GreaterEqual,
+    Binop::BitOr,
+    Binop::BitAnd,
+    Binop::BitShl,
+    Binop::BitShr,
+];
+
+pub unsafe fn write_u64(output: *mut String_Builder, value: u64) {
+    for i in 0..8 {
+        da_append(output, (value >> (i*8)) as u8 as c_char);
+    }
+}
+
+pub unsafe fn write_str(output: *mut String_Builder, s: *const c_char) {
+    let n = strlen(s);
+    write_u64(output, n as u64);
+    da_append_many(output, core::slice::from_raw_parts(s, n));
+}
+
+pub unsafe fn write_loc(output: *mut String_Builder, loc: Loc) {
+    write_str(output, loc.input_path);
+    write_u64(output, loc.line_number as u64);
+    write_u64(output, loc.line_offset as u64);
+}
+
+pub unsafe fn write_arg(output: *mut String_Builder, arg: Arg) {
+    match arg {
+        Arg::Bogus              => unreachable!("bogus-amogus"),
+        Arg::AutoVar(index)     => { write_u64(output, 1); write_u64(output, index as u64); }
+        Arg::Deref(index)       => { write_u64(output, 2); write_u64(output, index as u64); }
+        Arg::RefAutoVar(index)  => { write_u64(output, 3); write_u64(output, index as u64); }
+        Arg::RefExternal(name)  => { write_u64(output, 4); write_str(output, name); }
+        Arg::External(name)     => { write_u64(output, 5); write_str(output, name); }
+        Arg::Literal(value)     => { write_u64(output, 6); write_u64(output, value); }
+        Arg::DataOffset(offset) => { write_u64(output, 7); write_u64(output, offset as u64); }
+    }
+}
+
+pub unsafe fn write_op(output: *mut String_Builder, op: OpWithLocation) {
+    write_loc(output, op.loc);
+    match op.opcode {
+        Op::Bogus => unreachable!("bogus-amogus"),
+        Op::Asm {..} => unreachable!("inline assembly must be rejected before serialization"),
+        Op::UnaryNot {result, arg} => {
+            write_u64(output, 1);
+            write_u64(output, result as u64);
+            write_arg(output, arg);
+        }
+        Op::Negate {result, arg} => {
+            write_u64(output, 2);
+            write_u64(output, result as u64);
+            write_arg(output, arg);
+        }
+        Op::Binop {binop, index, lhs, rhs} => {
+            write_u64(output, 3);
+            write_u64(output, binop as u64);
+            write_u64(output, index as u64);
+            write_arg(output, lhs);
+            write_arg(output, rhs);
+        }
+        Op::Index {result, arg, offset} => {
+            write_u64(output, 4);
+            write_u64(output, result as u64);
+            write_arg(output, arg);
+            write_arg(output, offset);
+        }
+        Op::AutoAssign {index, arg} => {
+            write_u64(output, 5);
+            write_u64(output, index as u64);
+            write_arg(output, arg);
+        }
+        Op::ExternalAssign {name, arg} => {
+            write_u64(output, 6);
+            write_str(output, name);
+            write_arg(output, arg);
+        }
+        Op::Store {index, arg} => {
+            write_u64(output, 7);
+            write_u64(output, index as u64);
+            write_arg(output, arg);
+        }
+        Op::Funcall {result, fun, args} => {
+            write_u64(output, 8);
+            write_u64(output, result as u64);
+            write_arg(output, fun);
+            write_u64(output, args.count as u64);
+            for i in 0..args.count {
+                write_arg(output, *args.items.add(i));
+            }
+        }
+        Op::Label {label} => {
+            write_u64(output, 9);
+            write_u64(output, label as u64);
+        }
+        Op::JmpLabel {label} => {
+            write_u64(output, 10);
+            write_u64(output, label as u64);
+        }
+        Op::JmpIfNotLabel {label, arg} => {
+            write_u64(output, 11);
+            write_u64(output, label as u64);
+            write_arg(output, arg);
+        }
+        Op::Return {arg} => {
+            write_u64(output, 12);
+            if let Some(arg) = arg {
+                write_u64(output, 1);
+                write_arg(output, arg);
+            } else {
+                write_u64(output, 0);
+            }
+        }
+    }
+}
+
+pub unsafe fn write_image(output: *mut String_Builder, p: *const Program) {
+    write_str(output, IMAGE_MAGIC);
+
+    write_u64(output, (*p).funcs.count as u64);
+    for i in 0..(*p).funcs.count {
+        let func = *(*p).funcs.items.add(i);
+        write_str(output, func.name);
+        write_loc(output, func.name_loc);
+        write_u64(output, func.params_count as u64);
+        write_u64(output, func.auto_vars_count as u64);
+        write_u64(output, func.body.count as u64);
+        for j in 0..func.body.count {
+            write_op(output, *func.body.items.add(j));
+        }
+    }
+
+    write_u64(output, (*p).globals.count as u64);
+    for i in 0..(*p).globals.count {
+        let global = *(*p).globals.items.add(i);
+        write_str(output, global.name);
+        write_loc(output, global.name_loc);
+        write_u64(output, global.is_vec as u64);
+        write_u64(output, global.minimum_size as u64);
+        write_u64(output, global.values.count as u64);
+        for j in 0..global.values.count {
+            match *global.values.items.add(j) {
+                ImmediateValue::Name(name)         => { write_u64(output, 1); write_str(output, name); }
+                ImmediateValue::Literal(lit)       => { write_u64(output, 2); write_u64(output, lit); }
+                ImmediateValue::DataOffset(offset) => { write_u64(output, 3); write_u64(output, offset as u64); }
+            }
+        }
+    }
+
+    write_u64(output, (*p).data.count as u64);
+    for i in 0..(*p).data.count {
+        da_append(output, *(*p).data.items.add(i) as c_char);
+    }
+}
+
+pub struct Image_Reader {
+    pub bytes: *const c_char,
+    pub count: usize,
+    pub pos: usize,
+    pub arena: *mut arena::Arena,
+}
+
+pub unsafe fn read_u64(r: *mut Image_Reader) -> Option<u64> {
+    if (*r).pos + 8 > (*r).count { return None; }
+    let mut value = 0;
+    for i in 0..8 {
+        value |= (*(*r).bytes.add((*r).pos + i) as u8 as u64) << (i*8);
+    }
+    (*r).pos += 8;
+    Some(value)
+}
+
+pub unsafe fn read_usize(r: *mut Image_Reader) -> Option<usize> {
+    Some(read_u64(r)? as usize)
+}
+
+pub unsafe fn read_str(r: *mut Image_Reader) -> Option<*const c_char> {
+    let n = read_usize(r)?;
+    if (*r).pos + n > (*r).count { return None; }
+    let s = arena::alloc((*r).arena, n + 1) as *mut c_char;
+    for i in 0..n {
+        *s.add(i) = *(*r).bytes.add((*r).pos + i);
+    }
+    *s.add(n) = 0;
+    (*r).pos += n;
+    Some(s)
+}
+
+pub unsafe fn read_loc(r: *mut Image_Reader) -> Option<Loc> {
+    Some(Loc {
+        input_path: read_str(r)?,
+        line_number: read_u64(r)? as c_int,
+        line_offset: read_u64(r)? as c_int,
+    })
+}
+
+pub unsafe fn read_arg(r: *mut Image_Reader) -> Option<Arg> {
+    match read_u64(r)? {
+        1 => Some(Arg::AutoVar(read_usize(r)?)),
+        2 => Some(Arg::Deref(read_usize(r)?)),
+        3 => Some(Arg::RefAutoVar(read_usize(r)?)),
+        4 => Some(Arg::RefExternal(read_str(r)?)),
+        5 => Some(Arg::External(read_str(r)?)),
+        6 => Some(Arg::Literal(read_u64(r)?)),
+        7 => Some(Arg::DataOffset(read_usize(r)?)),
+        _ => None,
+    }
+}
+
+pub unsafe fn read_op(r: *mut Image_Reader) -> Option<OpWithLocation> {
+    let loc = read_loc(r)?;
+    let opcode = match read_u64(r)? {
+        1 => Op::UnaryNot {result: read_usize(r)?, arg: read_arg(r)?},
+        2 => Op::Negate {result: read_usize(r)?, arg: read_arg(r)?},
+        3 => {
+            let binop = read_usize(r)?;
+            if binop >= BINOP_ORDER.len() { return None; }
+            Op::Binop {binop: (*BINOP_ORDER)[binop], index: read_usize(r)?, lhs: read_arg(r)?, rhs: read_arg(r)?}
+        }
+        4 => Op::Index {result: read_usize(r)?, arg: read_arg(r)?, offset: read_arg(r)?},
+        5 => Op::AutoAssign {index: read_usize(r)?, arg: read_arg(r)?},
+        6 => Op::ExternalAssign {name: read_str(r)?, arg: read_arg(r)?},
+        7 => Op::Store {index: read_usize(r)?, arg: read_arg(r)?},
+        8 => {
+            let result = read_usize(r)?;
+            let fun = read_arg(r)?;
+            let mut args: Array<Arg> = zeroed();
+            let count = read_usize(r)?;
+            for _ in 0..count {
+                da_append(&mut args, read_arg(r)?);
+            }
+            Op::Funcall {result, fun, args}
+        }
+        9  => Op::Label {label: read_usize(r)?},
+        10 => Op::JmpLabel {label: read_usize(r)?},
+        11 => Op::JmpIfNotLabel {label: read_usize(r)?, arg: read_arg(r)?},
+        12 => match read_u64(r)? {
+            0 => Op::Return {arg: None},
+            1 => Op::Return {arg: Some(read_arg(r)?)},
+            _ => return None,
+        }
+        _ => return None,
+    };
+    Some(OpWithLocation {opcode, loc, scope_events_count: 0})
+}
+
+pub unsafe fn read_image(r: *mut Image_Reader, p: *mut Program) -> Option<()> {
+    if strcmp(read_str(r)?, IMAGE_MAGIC) != 0 { return None; }
+
+    let funcs_count = read_usize(r)?;
+    for _ in 0..funcs_count {
+        let mut func: Func = zeroed();
+        func.name = read_str(r)?;
+        func.name_loc = read_loc(r)?;
+        func.params_count = read_usize(r)?;
+        func.auto_vars_count = read_usize(r)?;
+        let body_count = read_usize(r)?;
+        for _ in 0..body_count {
+            da_append(&mut func.body, read_op(r)?);
+        }
+        da_append(&mut (*p).funcs, func);
+    }
+
+    let globals_count = read_usize(r)?;
+    for _ in 0..globals_count {
+        let mut global: Global = zeroed();
+        global.name = read_str(r)?;
+        global.name_loc = read_loc(r)?;
+        global.is_vec = read_u64(r)? != 0;
+        global.minimum_size = read_usize(r)?;
+        let values_count = read_usize(r)?;
+        for _ in 0..values_count {
+            let value = match read_u64(r)? {
+                1 => ImmediateValue::Name(read_str(r)?),
+                2 => ImmediateValue::Literal(read_u64(r)?),
+                3 => ImmediateValue::DataOffset(read_usize(r)?),
+                _ => return None,
+            };
+            da_append(&mut global.values, value);
+        }
+        da_append(&mut (*p).globals, global);
+    }
+
+    let data_count = read_usize(r)?;
+    if (*r).pos + data_count > (*r).count { return None; }
+    for i in 0..data_count {
+        da_append(&mut (*p).data, *(*r).bytes.add((*r).pos + i) as u8);
+    }
+    (*r).pos += data_count;
+
+    Some(())
+}
+
+#[derive(Clone, Copy)]
+pub enum Symbol_Kind {
+    Func(usize),
+    Native(Native),
+    Global(*mut u64),
+}
+
+#[derive(Clone, Copy)]
+pub struct Symbol {
+    pub name: *const c_char,
+    pub kind: Symbol_Kind,
+}
+
+#[derive(Clone, Copy)]
+pub struct Frame {
+    pub func: usize,
+    pub ip: usize,
+    /// Index of the first word of the frame in Machine::stack
+    pub bp: usize,
+    /// Autovar of the caller that receives the return value
+    pub result: usize,
+}
+
+pub struct Machine {
+    pub program: *const Program,
+    /// Sorted by name for binary search
+    pub symbols: Array<Symbol>,
+    /// Per function mapping from label index to the op index
+    pub labels: Array<Array<usize>>,
+    /// Functions don't have any machine code, but they still need distinct addresses so they can
+    /// be stored in variables and called indirectly. The functions and the natives are assigned
+    /// consecutive bytes of this allocation in that order.
+    pub code: *mut u8,
+    pub data: *mut u8,
+    pub stack: *mut u64,
+    pub frames: Array<Frame>,
+    pub args: Array<u64>,
+}
+
+pub unsafe extern "C" fn compar_symbol(a: *const c_void, b: *const c_void) -> c_int {
+    strcmp((*(a as *const Symbol)).name, (*(b as *const Symbol)).name)
+}
+
+pub unsafe fn find_symbol(m: *const Machine, name: *const c_char) -> Option<Symbol> {
+    let mut lo = 0;
+    let mut hi = (*m).symbols.count;
+    while lo < hi {
+        let mid = lo + (hi - lo)/2;
+        let symbol = *(*m).symbols.items.add(mid);
+        let cmp = strcmp(name, symbol.name);
+        if cmp == 0 {
+            return Some(symbol);
+        } else if cmp < 0 {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    None
+}
+
+pub unsafe fn symbol_value(m: *const Machine, symbol: Symbol) -> u64 {
+    match symbol.kind {
+        Symbol_Kind::Func(index)    => (*m).code.add(index) as u64,
+        Symbol_Kind::Native(native) => (*m).code.add((*(*m).program).funcs.count + native as usize) as u64,
+        Symbol_Kind::Global(addr)   => addr as u64,
+    }
+}
+
+pub unsafe fn symbol_from_address(m: *const Machine, addr: u64) -> Option<Symbol_Kind> {
+    let offset = addr.wrapping_sub((*m).code as u64) as usize;
+    let funcs_count = (*(*m).program).funcs.count;
+    if offset < funcs_count {
+        Some(Symbol_Kind::Func(offset))
+    } else if offset < funcs_count + NATIVE_ORDER.len() {
+        Some(Symbol_Kind::Native((*NATIVE_ORDER)[offset - funcs_count]))
+    } else {
+        None
+    }
+}
+
+pub unsafe fn auto_var(m: *const Machine, frame: Frame, index: usize, loc: Loc) -> Option<*mut u64> {
+    let func = *(*(*m).program).funcs.items.add(frame.func);
+    if index < 1 || index > func.auto_vars_count {
+        diagf!(loc, c!("RUNTIME ERROR: auto var %zu is out of range, `%s` has %zu auto vars\n"), index, func.name, func.auto_vars_count);
+        return None;
+    }
+    // The autovars are layed out in memory from right to left. See TODO(2025-06-05 17:45:36)
+    Some((*m).stack.add(frame.bp + func.auto_vars_count - index))
+}
+
+/// Where the execution continues after jumping to `label` of the function of `frame`
+pub unsafe fn label_target(m: *const Machine, frame: Frame, label: usize, loc: Loc) -> Option<usize> {
+    let labels = *(*m).labels.items.add(frame.func);
+    if label >= labels.count || *labels.items.add(label) == usize::MAX {
+        diagf!(loc, c!("RUNTIME ERROR: jump to the undefined label %zu\n"), label);
+        return None;
+    }
+    Some(*labels.items.add(label))
+}
+
+pub unsafe fn load_arg(m: *const Machine, frame: Frame, arg: Arg, loc: Loc) -> Option<u64> {
+    match arg {
+        Arg::Bogus              => unreachable!("bogus-amogus"),
+        Arg::AutoVar(index)     => Some(*auto_var(m, frame, index, loc)?),
+        Arg::Deref(index)       => Some(ptr::read_unaligned(*auto_var(m, frame, index, loc)? as *const u64)),
+        Arg::RefAutoVar(index)  => Some(auto_var(m, frame, index, loc)? as u64),
+        Arg::Literal(value)     => Some(value),
+        Arg::DataOffset(offset) => Some((*m).data.add(offset) as u64),
+        Arg::RefExternal(name)  => {
+            let Some(symbol) = find_symbol(m, name) else {
+                diagf!(loc, c!("RUNTIME ERROR: unknown name `%s`\n"), name);
+                return None;
+            };
+            Some(symbol_value(m, symbol))
+        }
+        Arg::External(name) => {
+            let Some(symbol) = find_symbol(m, name) else {
+                diagf!(loc, c!("RUNTIME ERROR: unknown name `%s`\n"), name);
+                return None;
+            };
+            match symbol.kind {
+                Symbol_Kind::Global(addr) => Some(*addr),
+                _ => Some(symbol_value(m, symbol)),
+            }
+        }
+    }
+}
+
+pub unsafe fn call_native(native: Native, args: *const [u64]) -> u64 {
+    let arg = |i: usize| if i < args.len() { (*args)[i] } else { 0 };
+    match native {
+        Native::Putchar => putchar(arg(0) as c_int) as i64 as u64,
+        Native::Getchar => getchar() as i64 as u64,
+        Native::Printf  => {
+            printf(
+                arg(0) as *const c_char,
+                arg(1), arg(2), arg(3), arg(4), arg(5), arg(6), arg(7), arg(8),
+                arg(9), arg(10), arg(11), arg(12), arg(13), arg(14), arg(15),
+            ) as i64 as u64
+        }
+        Native::Exit    => exit(arg(0) as c_int),
+        Native::Abort   => abort(),
+        Native::Malloc  => malloc(arg(0) as usize) as u64,
+        Native::Free    => {
+            free(arg(0) as *mut c_void);
+            0
+        }
+        Native::Char    => *(arg(0) as *const u8).add(arg(1) as usize) as u64,
+        Native::Lchar   => {
+            *(arg(0) as *mut u8).add(arg(1) as usize) = arg(2) as u8;
+            arg(2)
+        }
+    }
+}
+
+pub unsafe fn push_frame(m: *mut Machine, func: usize, result: usize, loc: Loc) -> Option<()> {
+    let bp = if let Some(caller) = da_last(&(*m).frames) {
+        (*caller).bp + (*(*(*m).program).funcs.items.add((*caller).func)).auto_vars_count
+    } else {
+        0
+    };
+    let callee = *(*(*m).program).funcs.items.add(func);
+    if bp + callee.auto_vars_count > STACK_WORDS {
+        diagf!(loc, c!("RUNTIME ERROR: stack overflow while calling `%s`\n"), callee.name);
+        return None;
+    }
+    memset((*m).stack.add(bp) as *mut c_void, 0, callee.auto_vars_count*8);
+    let frame = Frame {func, ip: 0, bp, result};
+    for i in 0..callee.params_count.min((*m).args.count) {
+        *auto_var(m, frame, i + 1, loc)? = *(*m).args.items.add(i);
+    }
+    da_append(&mut (*m).frames, frame);
+    Some(())
+}
+
+pub unsafe fn execute(m: *mut Machine, main: usize, main_loc: Loc) -> Option<u64> {
+    push_frame(m, main, 0, main_loc)?;
+    loop {
+        let frame = *da_last(&(*m).frames).unwrap();
+        let func = *(*(*m).program).funcs.items.add(frame.func);
+
+        if frame.ip >= func.body.count {
+            // Falling off the end of the function returns 0 just like the native codegens do
+            (*m).frames.count -= 1;
+            let Some(caller) = da_last(&(*m).frames) else { return Some(0); };
+            *auto_var(m, *caller, frame.result, func.name_loc)? = 0;
+            continue;
+        }
+
+        let op = *func.body.items.add(frame.ip);
+        (*da_last_mut(&mut (*m).frames).unwrap()).ip += 1;
+        match op.opcode {
+            Op::Bogus => unreachable!("bogus-amogus"),
+            Op::Asm {..} => unreachable!("inline assembly must be rejected during the build"),
+            Op::UnaryNot {result, arg} => {
+                let value = load_arg(m, frame, arg, op.loc)?;
+                *auto_var(m, frame, result, op.loc)? = (value == 0) as u64;
+            }
+            Op::Negate {result, arg} => {
+                let value = load_arg(m, frame, arg, op.loc)?;
+                *auto_var(m, frame, result, op.loc)? = value.wrapping_neg();
+            }
+            Op::Binop {binop, index, lhs, rhs} => {
+                let lhs = load_arg(m, frame, lhs, op.loc)?;
+                let rhs = load_arg(m, frame, rhs, op.loc)?;
+                let value = match binop {
+                    Binop::Plus         => lhs.wrapping_add(rhs),
+                    Binop::Minus        => lhs.wrapping_sub(rhs),
+                    Binop::Mult         => lhs.wrapping_mul(rhs),
+                    Binop::Div | Binop::Mod => {
+                        if rhs == 0 {
+                            diagf!(op.loc, c!("RUNTIME ERROR: division by zero\n"));
+                            return None;
+                        }
+                        if binop == Binop::Div {
+                            (lhs as i64).wrapping_div(rhs as i64) as u64
+                        } else {
+                            (lhs as i64).wrapping_rem(rhs as i64) as u64
+                        }
+                    }
+                    Binop::Equal        => (lhs == rhs) as u64,
+                    Binop::NotEqual     => (lhs != rhs) as u64,
+                    Binop::Less         => ((lhs as i64) <  (rhs as i64)) as u64,
+                    Binop::LessEqual    => ((lhs as i64) <= (rhs as i64)) as u64,
+                    Binop::Greater      => ((lhs as i64) >  (rhs as i64)) as u64,
+                    Binop::GreaterEqual => ((lhs as i64) >= (rhs as i64)) as u64,
+                    Binop::BitOr        => lhs | rhs,
+                    Binop::BitAnd       => lhs & rhs,
+                    Binop::BitShl       => lhs.wrapping_shl(rhs as u32),
+                    Binop::BitShr       => lhs.wrapping_shr(rhs as u32),
+                };
+                *auto_var(m, frame, index, op.loc)? = value;
+            }
+            Op::Index {result, arg, offset} => {
+                let base = load_arg(m, frame, arg, op.loc)?;
+                let offset = load_arg(m, frame, offset, op.loc)?;
+                *auto_var(m, frame, result, op.loc)? = base.wrapping_add(offset.wrapping_mul(8));
+            }
+            Op::AutoAssign {index, arg} => {
+                *auto_var(m, frame, index, op.loc)? = load_arg(m, frame, arg, op.loc)?;
+            }
+            Op::ExternalAssign {name, arg} => {
+                let value = load_arg(m, frame, arg, op.loc)?;
+                match find_symbol(m, name) {
+                    Some(Symbol {kind: Symbol_Kind::Global(addr), ..}) => *addr = value,
+                    _ => {
+                        diagf!(op.loc, c!("RUNTIME ERROR: `%s` is not a global variable\n"), name);
+                        return None;
+                    }
+                }
+            }
+            Op::Store {index, arg} => {
+                let value = load_arg(m, frame, arg, op.loc)?;
+                ptr::write_unaligned(*auto_var(m, frame, index, op.loc)? as *mut u64, value);
+            }
+            Op::Funcall {result, fun, args} => {
+                let kind = match fun {
+                    Arg::External(name) | Arg::RefExternal(name) => {
+                        let Some(symbol) = find_symbol(m, name) else {
+                            diagf!(op.loc, c!("RUNTIME ERROR: unknown function `%s`\n"), name);
+                            return None;
+                        };
+                        symbol.kind
+                    }
+                    fun => {
+                        let addr = load_arg(m, frame, fun, op.loc)?;
+                        let Some(kind) = symbol_from_address(m, addr) else {
+                            diagf!(op.loc, c!("RUNTIME ERROR: calling 0x%llX which is not an address of a function\n"), addr);
+                            return None;
+                        };
+                        kind
+                    }
+                };
+
+                (*m).args.count = 0;
+                for i in 0..args.count {
+                    let value = load_arg(m, frame, *args.items.add(i), op.loc)?;
+                    da_append(&mut (*m).args, value);
+                }
+
+                match kind {
+                    Symbol_Kind::Func(index) => {
+                        // The result is only stored when the callee returns, so check it upfront
+                        auto_var(m, frame, result, op.loc)?;
+                        push_frame(m, index, result, op.loc)?;
+                    }
+                    Symbol_Kind::Native(native) => {
+                        if matches!(native, Native::Printf) && (*m).args.count > MAX_PRINTF_ARGS {
+                            diagf!(op.loc, c!("RUNTIME ERROR: printf accepts at most %zu arguments on this target\n"), MAX_PRINTF_ARGS);
+                            return None;
+                        }
+                        *auto_var(m, frame, result, op.loc)? = call_native(native, da_slice((*m).args));
+                    }
+                    Symbol_Kind::Global(_) => {
+                        diagf!(op.loc, c!("RUNTIME ERROR: calling a global variable\n"));
+                        return None;
+                    }
+                }
+            }
+            Op::Label {..} => {}
+            Op::JmpLabel {label} => {
+                (*da_last_mut(&mut (*m).frames).unwrap()).ip = label_target(m, frame, label, op.loc)?;
+            }
+            Op::JmpIfNotLabel {label, arg} => {
+                if load_arg(m, frame, arg, op.loc)? == 0 {
+                    (*da_last_mut(&mut (*m).frames).unwrap()).ip = label_target(m, frame, label, op.loc)?;
+                }
+            }
+            Op::Return {arg} => {
+                let value = if let Some(arg) = arg {
+                    load_arg(m, frame, arg, op.loc)?
+                } else {
+                    0
+                };
+                (*m).frames.count -= 1;
+                let Some(caller) = da_last(&(*m).frames) else { return Some(value); };
+                *auto_var(m, *caller, frame.result, op.loc)? = value;
+            }
+        }
+    }
+}
+
+pub unsafe fn init_machine(m: *mut Machine, p: *const Program) {
+    (*m).program = p;
+
+    for i in 0..NATIVE_ORDER.len() {
+        let native = (*NATIVE_ORDER)[i];
+        da_append(&mut (*m).symbols, Symbol {name: native.name(), kind: Symbol_Kind::Native(native)});
+    }
+    for i in 0..(*p).funcs.count {
+        let func = *(*p).funcs.items.add(i);
+        da_append(&mut (*m).symbols, Symbol {name: func.name, kind: Symbol_Kind::Func(i)});
+    }
+    for i in 0..(*p).globals.count {
+        let global = *(*p).globals.items.add(i);
+        let mut words = global.values.count.max(global.minimum_size).max(1);
+        if global.is_vec { words += 1; }
+        let addr = malloc(words*8) as *mut u64;
+        memset(addr as *mut c_void, 0, words*8);
+        da_append(&mut (*m).symbols, Symbol {name: global.name, kind: Symbol_Kind::Global(addr)});
+    }
+    // The definitions of the program must shadow the natives with the same name. qsort is not
+    // stable, so when collapsing a group of equal names we keep whichever is not a native.
+    qsort((*m).symbols.items as *mut c_void, (*m).symbols.count, size_of::<Symbol>(), compar_symbol);
+    let mut count = 0;
+    for i in 0..(*m).symbols.count {
+        let symbol = *(*m).symbols.items.add(i);
+        if count > 0 && strcmp((*(*m).symbols.items.add(count - 1)).name, symbol.name) == 0 {
+            if matches!((*(*m).symbols.items.add(count - 1)).kind, Symbol_Kind::Native(_)) {
+                *(*m).symbols.items.add(count - 1) = symbol;
+            }
+            continue;
+        }
+        *(*m).symbols.items.add(count) = symbol;
+        count += 1;
+    }
+    (*m).symbols.count = count;
+
+    (*m).code = malloc((*p).funcs.count + NATIVE_ORDER.len()) as *mut u8;
+    (*m).data = malloc((*p).data.count + 8) as *mut u8;
+    memset((*m).data as *mut c_void, 0, (*p).data.count + 8);
+    for i in 0..(*p).data.count {
+        *(*m).data.add(i) = *(*p).data.items.add(i);
+    }
+    (*m).stack = malloc(STACK_WORDS*8) as *mut u64;
+
+    for i in 0..(*p).globals.count {
+        let global = *(*p).globals.items.add(i);
+        let Some(Symbol {kind: Symbol_Kind::Global(mut addr), ..}) = find_symbol(m, global.name) else {
+            unreachable!("globals are registered above");
+        };
+        if global.is_vec {
+            *addr = addr.add(1) as u64;
+            addr = addr.add(1);
+        }
+        for j in 0..global.values.count {
+            *addr.add(j) = match *global.values.items.add(j) {
+                ImmediateValue::Literal(lit)       => lit,
+                ImmediateValue::DataOffset(offset) => (*m).data.add(offset) as u64,
+                ImmediateValue::Name(name)         => symbol_value(m, find_symbol(m, name).expect("names of the globals are checked during the build")),
+            };
+        }
+    }
+
+    for i in 0..(*p).funcs.count {
+        let func = *(*p).funcs.items.add(i);
+        let mut labels: Array<usize> = zeroed();
+        for j in 0..func.body.count {
+            if let Op::Label {label} = (*func.body.items.add(j)).opcode {
+                while labels.count <= label {
+                    da_append(&mut labels, usize::MAX);
+                }
+                *labels.items.add(label) = j;
+            }
+        }
+        da_append(&mut (*m).labels, labels);
+    }
+}
+
+pub unsafe fn check_program(p: *const Program) -> Option<()> {
+    let mut ok = true;
+    for i in 0..(*p).funcs.count {
+        let func = *(*p).funcs.items.add(i);
+        for j in 0..func.body.count {
+            let op = *func.body.items.add(j);
+            if let Op::Asm {..} = op.opcode {
+                diagf!(op.loc, c!("ERROR: inline assembly is not supported by the ir-interp target\n"));
+                ok = false;
+            }
+        }
+    }
+    for i in 0..(*p).asm_funcs.count {
+        let asm_func = *(*p).asm_funcs.items.add(i);
+        diagf!(asm_func.name_loc, c!("ERROR: assembly function `%s` is not supported by the ir-interp target\n"), asm_func.name);
+        ok = false;
+    }
+    'extrns: for i in 0..(*p).extrns.count {
+        let name = *(*p).extrns.items.add(i);
+        if Native::from_name(name).is_some() { continue 'extrns; }
+        for j in 0..(*p).funcs.count {
+            if strcmp((*(*p).funcs.items.add(j)).name, name) == 0 { continue 'extrns; }
+        }
+        for j in 0..(*p).globals.count {
+            if strcmp((*(*p).globals.items.add(j)).name, name) == 0 { continue 'extrns; }
+        }
+        log(Log_Level::ERROR, c!("ir-interp: Unknown extrn: `%s`, can not link"), name);
+        ok = false;
+    }
+    if ok { Some(()) } else { None }
+}
+
+pub unsafe fn usage(params: *const [Param]) {
+    fprintf(stderr(), c!("IR interpreter for the B compiler\n"));
+    fprintf(stderr(), c!("OPTIONS:\n"));
+    print_params_help(params);
+}
+
+struct Ir_Interp {
+    output: String_Builder,
+    arena: arena::Arena,
+}
+
+pub unsafe fn get_apis(targets: *mut Array<TargetAPI>) {
+    da_append(targets, TargetAPI::V1 {
+        name: c!("ir-interp"),
+        file_ext: c!(".birx"),
+        new,
+        build: generate_program,
+        run: run_program,
+    });
+}
+
+pub unsafe fn new(a: *mut arena::Arena, args: *const [*const c_char]) -> Option<*mut c_void> {
+    let gen = arena::alloc_type::<Ir_Interp>(a);
+    memset(gen as _ , 0, size_of::<Ir_Interp>());
+
+    let mut help = false;
+    let params = &[
+        Param {
+            name:        c!("help"),
+            description: c!("Print this help message"),
+            value:       ParamValue::Flag { var: &mut help },
+        },
+    ];
+
+    if let Err(message) = parse_args(params, args) {
+        usage(params);
+        log(Log_Level::ERROR, c!("%s"), message);
+        return None;
+    }
+
+    if help {
+        usage(params);
+        return None;
+    }
+
+    Some(gen as *mut c_void)
+}
+
+pub unsafe fn generate_program(
+    gen: *mut c_void, program: *const Program, program_path: *const c_char, _garbage_base: *const c_char,
+    _nostdlib: bool, _debug: bool,
+) -> Option<()> {
+    let gen = gen as *mut Ir_Interp;
+    let output = &mut (*gen).output;
+
+    check_program(program)?;
+    write_image(output, program);
+
+    write_entire_file(program_path, (*output).items as *const c_void, (*output).count)?;
+    log(Log_Level::INFO, c!("generated %s"), program_path);
+    Some(())
+}
+
+pub unsafe fn run_program(
+    gen: *mut c_void, program_path: *const c_char, run_args: *const [*const c_char],
+) -> Option<()> {
+    let gen = gen as *mut Ir_Interp;
+    let image = &mut (*gen).output;
+
+    image.count = 0;
+    read_entire_file(program_path, image)?;
+
+    let mut r = Image_Reader {
+        bytes: image.items,
+        count: image.count,
+        pos: 0,
+        arena: &mut (*gen).arena,
+    };
+    let mut program: Program = zeroed();
+    if read_image(&mut r, &mut program).is_none() {
+        log(Log_Level::ERROR, c!("%s is not a valid ir-interp image"), program_path);
+        return None;
+    }
+
+    let mut m: Machine = zeroed();
+    init_machine(&mut m, &program);
+
+    let main = 'search: {
+        for i in 0..program.funcs.count {
+            if strcmp((*program.funcs.items.add(i)).name, c!("main")) == 0 {
+                break 'search i;
+            }
+        }
+        log(Log_Level::ERROR, c!("%s does not define function `main`"), program_path);
+        return None;
+    };
+
+    let argv = malloc((run_args.len() + 2)*8) as *mut u64;
+    *argv = program_path as u64;
+    for i in 0..run_args.len() {
+        *argv.add(i + 1) = (*run_args)[i] as u64;
+    }
+    *argv.add(run_args.len() + 1) = ptr::null::<c_char>() as u64;
+    da_append(&mut m.args, (run_args.len() + 1) as u64);
+    da_append(&mut m.args, argv as u64);
+
+    let code = execute(&mut m, main, (*program.funcs.items.add(main)).name_loc)?;
+    if code != 0 {
+        exit(code as c_int);
+    }
+    Some(())
+}
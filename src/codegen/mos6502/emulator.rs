@@ -0,0 +1,415 @@
+// Builtin 6502 emulator used by `-C runner=builtin`.
+//
+// It mimics the environment of posix6502 (https://github.com/bext-lang/posix6502) that libb/6502-posix.b
+// is written against:
+// - The rom is loaded at the load offset and the execution starts from its first byte.
+// - Calling $FFEF outputs the byte in A to stdout and returns to the caller.
+// - Jumping to $0000 exits the program with the exit code in A. This is also where the
+//   `JMP ($FFFC)` at the end of the entry point ends up, since the reset vector is zero.
+// - The command line arguments are placed right after the rom. The entry point is started as if
+//   it was called with argc in Y:A and argv on the stack, so `main(argc, argv)` receives them.
+use core::ffi::*;
+use core::mem::transmute;
+use crate::nob::*;
+use crate::crust::libc::*;
+use super::{Instr, AddrMode, OPCODES, INVL};
+use super::Instr::*;
+use super::AddrMode::*;
+
+pub const PUTCHAR_TRAP: u16 = 0xFFEF;
+pub const EXIT_TRAP:    u16 = 0x0000;
+pub const MEMORY_SIZE:  usize = 0x10000;
+
+const FLAG_C: u8 = 0x01;
+const FLAG_Z: u8 = 0x02;
+const FLAG_I: u8 = 0x04;
+const FLAG_D: u8 = 0x08;
+const FLAG_B: u8 = 0x10;
+const FLAG_U: u8 = 0x20;
+const FLAG_V: u8 = 0x40;
+const FLAG_N: u8 = 0x80;
+
+pub struct Cpu {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub status: u8,
+    pub memory: *mut u8,
+    // Reverse of OPCODES: for every opcode byte the instruction and its addressing mode
+    pub decode: [Option<(Instr, AddrMode)>; 256],
+}
+
+unsafe fn read(cpu: *mut Cpu, addr: u16) -> u8 {
+    *(*cpu).memory.add(addr as usize)
+}
+
+unsafe fn write(cpu: *mut Cpu, addr: u16, value: u8) {
+    *(*cpu).memory.add(addr as usize) = value;
+}
+
+unsafe fn read16(cpu: *mut Cpu, addr: u16) -> u16 {
+    read(cpu, addr) as u16 | (read(cpu, addr.wrapping_add(1)) as u16) << 8
+}
+
+// Reads a pointer from the zero page, wrapping around within it
+unsafe fn read16_zp(cpu: *mut Cpu, addr: u8) -> u16 {
+    read(cpu, addr as u16) as u16 | (read(cpu, addr.wrapping_add(1) as u16) as u16) << 8
+}
+
+unsafe fn fetch(cpu: *mut Cpu) -> u8 {
+    let byte = read(cpu, (*cpu).pc);
+    (*cpu).pc = (*cpu).pc.wrapping_add(1);
+    byte
+}
+
+unsafe fn fetch16(cpu: *mut Cpu) -> u16 {
+    let lo = fetch(cpu) as u16;
+    let hi = fetch(cpu) as u16;
+    lo | hi << 8
+}
+
+unsafe fn push(cpu: *mut Cpu, value: u8) {
+    write(cpu, 0x0100 | (*cpu).sp as u16, value);
+    (*cpu).sp = (*cpu).sp.wrapping_sub(1);
+}
+
+unsafe fn pull(cpu: *mut Cpu) -> u8 {
+    (*cpu).sp = (*cpu).sp.wrapping_add(1);
+    read(cpu, 0x0100 | (*cpu).sp as u16)
+}
+
+unsafe fn push16(cpu: *mut Cpu, value: u16) {
+    push(cpu, (value >> 8) as u8);
+    push(cpu, value as u8);
+}
+
+unsafe fn pull16(cpu: *mut Cpu) -> u16 {
+    let lo = pull(cpu) as u16;
+    let hi = pull(cpu) as u16;
+    lo | hi << 8
+}
+
+unsafe fn set_flag(cpu: *mut Cpu, flag: u8, value: bool) {
+    if value {
+        (*cpu).status |= flag;
+    } else {
+        (*cpu).status &= !flag;
+    }
+}
+
+unsafe fn get_flag(cpu: *mut Cpu, flag: u8) -> bool {
+    (*cpu).status & flag != 0
+}
+
+unsafe fn set_nz(cpu: *mut Cpu, value: u8) -> u8 {
+    set_flag(cpu, FLAG_Z, value == 0);
+    set_flag(cpu, FLAG_N, value & 0x80 != 0);
+    value
+}
+
+// Effective address of the operand for the addressing modes that refer to memory
+unsafe fn operand_address(cpu: *mut Cpu, mode: AddrMode) -> u16 {
+    match mode {
+        IMM   => {
+            let addr = (*cpu).pc;
+            (*cpu).pc = (*cpu).pc.wrapping_add(1);
+            addr
+        }
+        ZP    => fetch(cpu) as u16,
+        ZP_X  => fetch(cpu).wrapping_add((*cpu).x) as u16,
+        ZP_Y  => fetch(cpu).wrapping_add((*cpu).y) as u16,
+        ABS   => fetch16(cpu),
+        ABS_X => fetch16(cpu).wrapping_add((*cpu).x as u16),
+        ABS_Y => fetch16(cpu).wrapping_add((*cpu).y as u16),
+        IND_X => {
+            let zp = fetch(cpu).wrapping_add((*cpu).x);
+            read16_zp(cpu, zp)
+        }
+        IND_Y => {
+            let zp = fetch(cpu);
+            read16_zp(cpu, zp).wrapping_add((*cpu).y as u16)
+        }
+        IND   => {
+            // The original 6502 does not carry into the high byte when the pointer is at the end of a page
+            let ptr = fetch16(cpu);
+            let hi = (ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF);
+            read(cpu, ptr) as u16 | (read(cpu, hi) as u16) << 8
+        }
+        ACC | REL | IMPL | AddrMode::COUNT => unreachable!(),
+    }
+}
+
+// Read-modify-write instructions operate either on the accumulator or on memory
+unsafe fn modify(cpu: *mut Cpu, mode: AddrMode, f: unsafe fn(*mut Cpu, u8) -> u8) {
+    if mode == ACC {
+        (*cpu).a = f(cpu, (*cpu).a);
+    } else {
+        let addr = operand_address(cpu, mode);
+        let value = f(cpu, read(cpu, addr));
+        write(cpu, addr, value);
+    }
+}
+
+unsafe fn asl(cpu: *mut Cpu, value: u8) -> u8 {
+    set_flag(cpu, FLAG_C, value & 0x80 != 0);
+    set_nz(cpu, value << 1)
+}
+
+unsafe fn lsr(cpu: *mut Cpu, value: u8) -> u8 {
+    set_flag(cpu, FLAG_C, value & 0x01 != 0);
+    set_nz(cpu, value >> 1)
+}
+
+unsafe fn rol(cpu: *mut Cpu, value: u8) -> u8 {
+    let carry = get_flag(cpu, FLAG_C) as u8;
+    set_flag(cpu, FLAG_C, value & 0x80 != 0);
+    set_nz(cpu, value << 1 | carry)
+}
+
+unsafe fn ror(cpu: *mut Cpu, value: u8) -> u8 {
+    let carry = get_flag(cpu, FLAG_C) as u8;
+    set_flag(cpu, FLAG_C, value & 0x01 != 0);
+    set_nz(cpu, value >> 1 | carry << 7)
+}
+
+unsafe fn inc(cpu: *mut Cpu, value: u8) -> u8 {
+    set_nz(cpu, value.wrapping_add(1))
+}
+
+unsafe fn dec(cpu: *mut Cpu, value: u8) -> u8 {
+    set_nz(cpu, value.wrapping_sub(1))
+}
+
+unsafe fn adc(cpu: *mut Cpu, value: u8) {
+    let a = (*cpu).a;
+    let carry = get_flag(cpu, FLAG_C) as u16;
+    let sum = a as u16 + value as u16 + carry;
+    set_flag(cpu, FLAG_V, (!(a ^ value) & (a ^ sum as u8)) & 0x80 != 0);
+    if get_flag(cpu, FLAG_D) {
+        let mut lo = (a & 0x0F) as u16 + (value & 0x0F) as u16 + carry;
+        if lo > 0x09 { lo += 0x06; }
+        let mut result = (a & 0xF0) as u16 + (value & 0xF0) as u16 + if lo > 0x0F {0x10} else {0} + (lo & 0x0F);
+        if result > 0x9F { result += 0x60; }
+        set_flag(cpu, FLAG_C, result > 0xFF);
+        (*cpu).a = set_nz(cpu, result as u8);
+    } else {
+        set_flag(cpu, FLAG_C, sum > 0xFF);
+        (*cpu).a = set_nz(cpu, sum as u8);
+    }
+}
+
+unsafe fn sbc(cpu: *mut Cpu, value: u8) {
+    if !get_flag(cpu, FLAG_D) {
+        adc(cpu, !value);
+        return;
+    }
+    let a = (*cpu).a;
+    let borrow = !get_flag(cpu, FLAG_C) as i16;
+    let diff = a as i16 - value as i16 - borrow;
+    set_flag(cpu, FLAG_V, ((a ^ value) & (a ^ diff as u8)) & 0x80 != 0);
+    let mut lo = (a & 0x0F) as i16 - (value & 0x0F) as i16 - borrow;
+    let mut hi = (a >> 4) as i16 - (value >> 4) as i16;
+    if lo < 0 {
+        lo += 10;
+        hi -= 1;
+    }
+    if hi < 0 {
+        hi += 10;
+    }
+    set_flag(cpu, FLAG_C, diff >= 0);
+    (*cpu).a = set_nz(cpu, ((hi << 4) | (lo & 0x0F)) as u8);
+}
+
+unsafe fn compare(cpu: *mut Cpu, register: u8, value: u8) {
+    set_flag(cpu, FLAG_C, register >= value);
+    set_nz(cpu, register.wrapping_sub(value));
+}
+
+unsafe fn branch(cpu: *mut Cpu, condition: bool) {
+    let offset = fetch(cpu) as i8;
+    if condition {
+        (*cpu).pc = (*cpu).pc.wrapping_add(offset as u16);
+    }
+}
+
+pub unsafe fn init_cpu(cpu: *mut Cpu, rom: *const [u8], load_offset: u16) -> Option<()> {
+    if load_offset as usize + rom.len() > MEMORY_SIZE {
+        log(Log_Level::ERROR, c!("6502: rom of size 0x%zx does not fit into memory at load offset $%04X"), rom.len(), load_offset as c_uint);
+        return None;
+    }
+    (*cpu).memory = malloc(MEMORY_SIZE) as *mut u8;
+    memset((*cpu).memory as *mut c_void, 0, MEMORY_SIZE);
+    for i in 0..rom.len() {
+        *(*cpu).memory.add(load_offset as usize + i) = (*rom)[i];
+    }
+
+    (*cpu).decode = [None; 256];
+    for i in 0..Instr::COUNT as u8 {
+        for j in 0..AddrMode::COUNT as u8 {
+            let opcode = OPCODES[i as usize][j as usize];
+            if opcode != INVL {
+                (*cpu).decode[opcode as usize] = Some((transmute::<u8, Instr>(i), transmute::<u8, AddrMode>(j)));
+            }
+        }
+    }
+
+    (*cpu).a = 0;
+    (*cpu).x = 0;
+    (*cpu).y = 0;
+    (*cpu).sp = 0xFF;
+    (*cpu).status = FLAG_U | FLAG_I;
+    (*cpu).pc = load_offset;
+    Some(())
+}
+
+// Executes one instruction. Returns the exit code once the program exits.
+pub unsafe fn step(cpu: *mut Cpu) -> Result<Option<u8>, ()> {
+    match (*cpu).pc {
+        PUTCHAR_TRAP => {
+            putchar((*cpu).a as c_int);
+            (*cpu).pc = pull16(cpu).wrapping_add(1);
+            return Ok(None);
+        }
+        EXIT_TRAP => return Ok(Some((*cpu).a)),
+        _ => {}
+    }
+
+    let pc = (*cpu).pc;
+    let opcode = fetch(cpu);
+    let Some((instr, mode)) = (*cpu).decode[opcode as usize] else {
+        log(Log_Level::ERROR, c!("6502: illegal opcode $%02X at $%04X"), opcode as c_uint, pc as c_uint);
+        return Err(());
+    };
+
+    match instr {
+        ADC => { let value = read(cpu, operand_address(cpu, mode)); adc(cpu, value); }
+        SBC => { let value = read(cpu, operand_address(cpu, mode)); sbc(cpu, value); }
+        AND => { let value = read(cpu, operand_address(cpu, mode)); (*cpu).a = set_nz(cpu, (*cpu).a & value); }
+        ORA => { let value = read(cpu, operand_address(cpu, mode)); (*cpu).a = set_nz(cpu, (*cpu).a | value); }
+        EOR => { let value = read(cpu, operand_address(cpu, mode)); (*cpu).a = set_nz(cpu, (*cpu).a ^ value); }
+        CMP => { let value = read(cpu, operand_address(cpu, mode)); compare(cpu, (*cpu).a, value); }
+        CPX => { let value = read(cpu, operand_address(cpu, mode)); compare(cpu, (*cpu).x, value); }
+        CPY => { let value = read(cpu, operand_address(cpu, mode)); compare(cpu, (*cpu).y, value); }
+        BIT => {
+            let value = read(cpu, operand_address(cpu, mode));
+            set_flag(cpu, FLAG_Z, (*cpu).a & value == 0);
+            set_flag(cpu, FLAG_N, value & 0x80 != 0);
+            set_flag(cpu, FLAG_V, value & 0x40 != 0);
+        }
+
+        ASL => modify(cpu, mode, asl),
+        LSR => modify(cpu, mode, lsr),
+        ROL => modify(cpu, mode, rol),
+        ROR => modify(cpu, mode, ror),
+        INC => modify(cpu, mode, inc),
+        DEC => modify(cpu, mode, dec),
+        INX => (*cpu).x = inc(cpu, (*cpu).x),
+        INY => (*cpu).y = inc(cpu, (*cpu).y),
+        DEX => (*cpu).x = dec(cpu, (*cpu).x),
+        DEY => (*cpu).y = dec(cpu, (*cpu).y),
+
+        BCC => branch(cpu, !get_flag(cpu, FLAG_C)),
+        BCS => branch(cpu,  get_flag(cpu, FLAG_C)),
+        BNE => branch(cpu, !get_flag(cpu, FLAG_Z)),
+        BEQ => branch(cpu,  get_flag(cpu, FLAG_Z)),
+        BPL => branch(cpu, !get_flag(cpu, FLAG_N)),
+        BMI => branch(cpu,  get_flag(cpu, FLAG_N)),
+        BVC => branch(cpu, !get_flag(cpu, FLAG_V)),
+        BVS => branch(cpu,  get_flag(cpu, FLAG_V)),
+
+        JMP => (*cpu).pc = operand_address(cpu, mode),
+        JSR => {
+            let addr = operand_address(cpu, mode);
+            push16(cpu, (*cpu).pc.wrapping_sub(1));
+            (*cpu).pc = addr;
+        }
+        RTS => (*cpu).pc = pull16(cpu).wrapping_add(1),
+        BRK => {
+            push16(cpu, (*cpu).pc.wrapping_add(1));
+            push(cpu, (*cpu).status | FLAG_B | FLAG_U);
+            set_flag(cpu, FLAG_I, true);
+            (*cpu).pc = read16(cpu, 0xFFFE);
+        }
+        RTI => {
+            (*cpu).status = (pull(cpu) & !FLAG_B) | FLAG_U;
+            (*cpu).pc = pull16(cpu);
+        }
+
+        LDA => { let value = read(cpu, operand_address(cpu, mode)); (*cpu).a = set_nz(cpu, value); }
+        LDX => { let value = read(cpu, operand_address(cpu, mode)); (*cpu).x = set_nz(cpu, value); }
+        LDY => { let value = read(cpu, operand_address(cpu, mode)); (*cpu).y = set_nz(cpu, value); }
+        STA => { let addr = operand_address(cpu, mode); write(cpu, addr, (*cpu).a); }
+        STX => { let addr = operand_address(cpu, mode); write(cpu, addr, (*cpu).x); }
+        STY => { let addr = operand_address(cpu, mode); write(cpu, addr, (*cpu).y); }
+
+        PHA => push(cpu, (*cpu).a),
+        PHP => push(cpu, (*cpu).status | FLAG_B | FLAG_U),
+        PLA => { let value = pull(cpu); (*cpu).a = set_nz(cpu, value); }
+        PLP => (*cpu).status = (pull(cpu) & !FLAG_B) | FLAG_U,
+
+        TAX => (*cpu).x = set_nz(cpu, (*cpu).a),
+        TAY => (*cpu).y = set_nz(cpu, (*cpu).a),
+        TXA => (*cpu).a = set_nz(cpu, (*cpu).x),
+        TYA => (*cpu).a = set_nz(cpu, (*cpu).y),
+        TSX => (*cpu).x = set_nz(cpu, (*cpu).sp),
+        TXS => (*cpu).sp = (*cpu).x,
+
+        CLC => set_flag(cpu, FLAG_C, false),
+        SEC => set_flag(cpu, FLAG_C, true),
+        CLD => set_flag(cpu, FLAG_D, false),
+        SED => set_flag(cpu, FLAG_D, true),
+        CLI => set_flag(cpu, FLAG_I, false),
+        SEI => set_flag(cpu, FLAG_I, true),
+        CLV => set_flag(cpu, FLAG_V, false),
+        NOP => {}
+
+        Instr::COUNT => unreachable!(),
+    }
+    Ok(None)
+}
+
+// Copies the arguments (including the program name) into the memory starting at `start` and passes
+// them to the entry point according to the calling convention of the codegen.
+pub unsafe fn init_args(cpu: *mut Cpu, start: usize, args: *const [*const c_char]) -> Option<()> {
+    let argv = start;
+    let mut end = argv + args.len()*2;
+    for i in 0..args.len() {
+        let arg = (*args)[i];
+        let size = strlen(arg) + 1;
+        if end + size > PUTCHAR_TRAP as usize {
+            log(Log_Level::ERROR, c!("6502: command line arguments do not fit into memory after the rom"));
+            return None;
+        }
+        for j in 0..size {
+            write(cpu, (end + j) as u16, *arg.add(j) as u8);
+        }
+        write(cpu, (argv + i*2) as u16, end as u8);
+        write(cpu, (argv + i*2 + 1) as u16, (end >> 8) as u8);
+        end += size;
+    }
+    (*cpu).a = args.len() as u8;
+    (*cpu).y = (args.len() >> 8) as u8;
+    push16(cpu, argv as u16);
+    Some(())
+}
+
+// Runs the rom until it exits and returns its exit code. `args` become the argv of the program.
+pub unsafe fn run(rom: *const [u8], load_offset: u16, args: *const [*const c_char]) -> Option<u8> {
+    let mut cpu: Cpu = core::mem::zeroed();
+    init_cpu(&mut cpu, rom, load_offset)?;
+    if init_args(&mut cpu, load_offset as usize + rom.len(), args).is_none() {
+        free(cpu.memory);
+        return None;
+    }
+    let result = loop {
+        match step(&mut cpu) {
+            Ok(None)       => {}
+            Ok(Some(code)) => break Some(code),
+            Err(())        => break None,
+        }
+    };
+    free(cpu.memory);
+    result
+}
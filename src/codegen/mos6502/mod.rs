@@ -17,6 +17,9 @@ use crate::lexer::{is_identifier_start, is_identifier};
 use crate::arena::{self, Arena};
 use crate::targets::TargetAPI;
 use crate::params::*;
+use crate::proc::*;
+
+pub mod emulator;
 
 // TODO: does this have to be a macro?
 macro_rules! instr_enum {
@@ -1550,8 +1553,43 @@ pub unsafe fn usage(params: *const [Param]) {
     print_params_help(params);
 }
 
+enum_with_order! {
+    #[derive(Clone, Copy)]
+    enum Mos6502_Runner in MOS6502_RUNNER_ORDER {
+        Builtin,
+        Posix6502,
+    }
+}
+
+impl Mos6502_Runner {
+    fn name(self) -> *const c_char {
+        match self {
+            Mos6502_Runner::Builtin   => c!("builtin"),
+            Mos6502_Runner::Posix6502 => c!("posix6502"),
+        }
+    }
+
+    fn description(self) -> *const c_char {
+        match self {
+            Mos6502_Runner::Builtin   => c!("Emulator built into the compiler"),
+            Mos6502_Runner::Posix6502 => c!("External emulator from https://github.com/bext-lang/posix6502"),
+        }
+    }
+
+    unsafe fn from_name(name: *const c_char) -> Option<Self> {
+        for i in 0..MOS6502_RUNNER_ORDER.len() {
+            let runner = (*MOS6502_RUNNER_ORDER)[i];
+            if strcmp(runner.name(), name) == 0 {
+                return Some(runner);
+            }
+        }
+        None
+    }
+}
+
 struct Mos6502 {
     load_offset: u64,
+    runner: Mos6502_Runner,
     out: String_Builder,
     cmd: Cmd,
 }
@@ -1571,6 +1609,7 @@ pub unsafe fn new(a: *mut arena::Arena, args: *const [*const c_char]) -> Option<
     memset(gen as _ , 0, size_of::<Mos6502>());
 
     let mut help = false;
+    let mut runner_name = zeroed();
     let params = &[
         Param {
             name:        c!("help"),
@@ -1582,6 +1621,11 @@ pub unsafe fn new(a: *mut arena::Arena, args: *const [*const c_char]) -> Option<
             description: c!("Offset at which the rom is expected to be loaded"),
             value:       ParamValue::Hex { var: &mut (*gen).load_offset, default: 0x8000 },
         },
+        Param {
+            name:        c!("runner"),
+            description: c!("What runner to use for the 6502 roms"),
+            value:       ParamValue::String { var: &mut runner_name, default: Mos6502_Runner::Builtin.name() },
+        },
     ];
 
     if let Err(message) = parse_args(params, args) {
@@ -1595,6 +1639,19 @@ pub unsafe fn new(a: *mut arena::Arena, args: *const [*const c_char]) -> Option<
         return None;
     }
 
+    if let Some(runner) = Mos6502_Runner::from_name(runner_name) {
+        (*gen).runner = runner;
+    } else {
+        usage(params);
+        log(Log_Level::ERROR, c!("Invalid 6502 runner name `%s`!"), runner_name);
+        log(Log_Level::ERROR, c!("Valid names:"));
+        for i in 0..MOS6502_RUNNER_ORDER.len() {
+            let runner = (*MOS6502_RUNNER_ORDER)[i];
+            log(Log_Level::ERROR, c!("    %s - %s"), runner.name(), runner.description());
+        }
+        return None;
+    }
+
     Some(gen as *mut c_void)
 }
 
@@ -1605,8 +1662,6 @@ pub unsafe fn generate_program(
     let gen = gen as *mut Mos6502;
     let out = &mut (*gen).out;
 
-    if debug { todo!("Debug information for 6502") }
-
     let mut asm: Assembler = zeroed();
     generate_entry(out, &mut asm);
     asm.code_start = (*gen).load_offset as u16;
@@ -1621,28 +1676,90 @@ pub unsafe fn generate_program(
 
     log(Log_Level::INFO, c!("Generated size: 0x%x"), (*out).count as c_uint);
     apply_relocations(out, data_start, &mut asm);
-    arena::reset(&mut asm.string_arena);
 
     write_entire_file(program_path, (*out).items as *const c_void, (*out).count)?;
     log(Log_Level::INFO, c!("generated %s"), program_path);
 
+    if debug {
+        // Has to be done before resetting the string arena, since it owns the names of inline assembly labels
+        generate_debug_info(program_path, data_start, &mut asm)?;
+    }
+    arena::reset(&mut asm.string_arena);
+
+    Some(())
+}
+
+// Label files for emulators and monitors, all addresses are final (the load offset is applied):
+// - `<program>.lbl` - VICE monitor commands (`al C:xxxx .name`) that can be loaded with `ll`
+// - `<program>.sym` - plain `xxxx name` listing
+pub unsafe fn generate_debug_info(program_path: *const c_char, data_start: u16, asm: *mut Assembler) -> Option<()> {
+    let mut vice: String_Builder = zeroed();
+    let mut listing: String_Builder = zeroed();
+
+    // Externals are added in the order they are emitted, so the data section goes right before the globals
+    let mut data_written = false;
+    for i in 0..(*asm).externals.count {
+        let ext = *(*asm).externals.items.add(i);
+        let addr = (*asm).code_start + ext.addr;
+        if !data_written && addr >= data_start {
+            write_debug_symbol(&mut vice, &mut listing, data_start, c!("__data_section"));
+            data_written = true;
+        }
+        write_debug_symbol(&mut vice, &mut listing, addr, ext.name);
+    }
+    if !data_written {
+        write_debug_symbol(&mut vice, &mut listing, data_start, c!("__data_section"));
+    }
+
+    let vice_path = temp_sprintf(c!("%s.lbl"), program_path);
+    let listing_path = temp_sprintf(c!("%s.sym"), program_path);
+    write_entire_file(vice_path, vice.items as *const c_void, vice.count)?;
+    log(Log_Level::INFO, c!("generated %s"), vice_path);
+    write_entire_file(listing_path, listing.items as *const c_void, listing.count)?;
+    log(Log_Level::INFO, c!("generated %s"), listing_path);
+
+    free(vice.items);
+    free(listing.items);
     Some(())
 }
 
+pub unsafe fn write_debug_symbol(vice: *mut String_Builder, listing: *mut String_Builder, addr: u16, name: *const c_char) {
+    sb_appendf(vice, c!("al C:%04x .%s\n"), addr as c_uint, name);
+    sb_appendf(listing, c!("%04x %s\n"), addr as c_uint, name);
+}
+
 pub unsafe fn run_program(
     gen: *mut c_void, program_path: *const c_char, run_args: *const [*const c_char],
 ) -> Option<()> {
     let gen = gen as *mut Mos6502;
-    let cmd = &mut (*gen).cmd;
-    cmd_append!{
-        cmd,
-        c!("posix6502"), c!("-load-offset"), temp_sprintf(c!("%u"), (*gen).load_offset as c_uint),
-        program_path
-    }
-    if run_args.len() > 0 {
-        cmd_append!(cmd, c!("--"));
-        da_append_many(cmd, run_args);
+    match (*gen).runner {
+        Mos6502_Runner::Builtin => {
+            let rom = &mut (*gen).out;
+            rom.count = 0;
+            read_entire_file(program_path, rom)?;
+            let mut args: Array<*const c_char> = zeroed();
+            da_append(&mut args, program_path);
+            da_append_many(&mut args, run_args);
+            let code = emulator::run(da_slice(*rom) as *const [u8], (*gen).load_offset as u16, da_slice(args));
+            free(args.items);
+            let code = code?;
+            if code != 0 {
+                exit(code as c_int);
+            }
+        }
+        Mos6502_Runner::Posix6502 => {
+            let cmd = &mut (*gen).cmd;
+            cmd_append!{
+                cmd,
+                c!("posix6502"), c!("-load-offset"), temp_sprintf(c!("%u"), (*gen).load_offset as c_uint),
+                program_path
+            }
+            if run_args.len() > 0 {
+                cmd_append!(cmd, c!("--"));
+                da_append_many(cmd, run_args);
+            }
+            cmd_run_sync_forwarding_exit_code_and_reset(cmd)?;
+        }
     }
-    if !cmd_run_sync_and_reset(cmd) { return None; }
     Some(())
 }
@@ -0,0 +1,347 @@
+// Builtin Uxn emulator used by `-C runner=builtin`.
+//
+// It implements the Uxn CPU (https://wiki.xxiivv.com/site/uxntal_reference.html) and the subset of the
+// Varvara devices that headless programs compiled with libb/uxn.b rely on, mimicking uxncli:
+// - System/state ($0f): writing a non-zero value halts the machine with the exit code in the lower 7 bits.
+// - System/debug ($0e): prints the contents of the stacks to stderr.
+// - Console/vector ($10), Console/read ($12) and Console/type ($17): the command line arguments and
+//   then stdin are fed to the console vector byte by byte.
+// - Console/write ($18) and Console/error ($19): output to stdout and stderr respectively.
+use core::ffi::*;
+use core::mem::zeroed;
+use crate::crust::libc::*;
+
+pub const PAGE_PROGRAM: u16   = 0x0100;
+pub const MEMORY_SIZE:  usize = 0x10000;
+
+const SYSTEM_WST:     u8 = 0x04;
+const SYSTEM_RST:     u8 = 0x05;
+const SYSTEM_DEBUG:   u8 = 0x0e;
+const SYSTEM_STATE:   u8 = 0x0f;
+const CONSOLE_VECTOR: u8 = 0x10;
+const CONSOLE_READ:   u8 = 0x12;
+const CONSOLE_TYPE:   u8 = 0x17;
+const CONSOLE_WRITE:  u8 = 0x18;
+const CONSOLE_ERROR:  u8 = 0x19;
+
+const CONSOLE_STD: u8 = 0x1;
+const CONSOLE_ARG: u8 = 0x2;
+const CONSOLE_EOA: u8 = 0x3;
+const CONSOLE_END: u8 = 0x4;
+
+pub struct Stack {
+    pub dat: [u8; 256],
+    pub ptr: u8,
+}
+
+pub struct Uxn {
+    pub memory: *mut u8,
+    pub wst: Stack,
+    pub rst: Stack,
+    pub dev: [u8; 256],
+}
+
+// State of the instruction that is currently being executed, derived from its mode bits
+struct Instr {
+    short: bool,
+    keep: bool,
+    // The stack the instruction operates on and the opposite one (for JSR and STH)
+    src: *mut Stack,
+    dst: *mut Stack,
+    // Pops go through this pointer so the keep mode can leave the stack intact
+    ptr: u8,
+}
+
+unsafe fn peek8(uxn: *mut Uxn, addr: u16) -> u8 {
+    *(*uxn).memory.add(addr as usize)
+}
+
+unsafe fn poke8(uxn: *mut Uxn, addr: u16, value: u8) {
+    *(*uxn).memory.add(addr as usize) = value;
+}
+
+unsafe fn peek16(uxn: *mut Uxn, addr: u16) -> u16 {
+    (peek8(uxn, addr) as u16) << 8 | peek8(uxn, addr.wrapping_add(1)) as u16
+}
+
+unsafe fn peek(uxn: *mut Uxn, instr: *const Instr, addr: u16) -> u16 {
+    if (*instr).short { peek16(uxn, addr) } else { peek8(uxn, addr) as u16 }
+}
+
+unsafe fn poke(uxn: *mut Uxn, instr: *const Instr, addr: u16, value: u16) {
+    if (*instr).short {
+        poke8(uxn, addr, (value >> 8) as u8);
+        poke8(uxn, addr.wrapping_add(1), value as u8);
+    } else {
+        poke8(uxn, addr, value as u8);
+    }
+}
+
+unsafe fn stack_push8(stack: *mut Stack, value: u8) {
+    (*stack).dat[(*stack).ptr as usize] = value;
+    (*stack).ptr = (*stack).ptr.wrapping_add(1);
+}
+
+unsafe fn stack_push16(stack: *mut Stack, value: u16) {
+    stack_push8(stack, (value >> 8) as u8);
+    stack_push8(stack, value as u8);
+}
+
+unsafe fn pop8(instr: *mut Instr) -> u8 {
+    (*instr).ptr = (*instr).ptr.wrapping_sub(1);
+    if !(*instr).keep {
+        (*(*instr).src).ptr = (*instr).ptr;
+    }
+    (*(*instr).src).dat[(*instr).ptr as usize]
+}
+
+unsafe fn pop16(instr: *mut Instr) -> u16 {
+    let lo = pop8(instr) as u16;
+    let hi = pop8(instr) as u16;
+    hi << 8 | lo
+}
+
+unsafe fn pop(instr: *mut Instr) -> u16 {
+    if (*instr).short { pop16(instr) } else { pop8(instr) as u16 }
+}
+
+unsafe fn push8(instr: *mut Instr, value: u8) {
+    stack_push8((*instr).src, value);
+}
+
+unsafe fn push(instr: *mut Instr, value: u16) {
+    if (*instr).short { stack_push16((*instr).src, value) } else { stack_push8((*instr).src, value as u8) }
+}
+
+unsafe fn jump(instr: *const Instr, pc: &mut u16, addr: u16) {
+    if (*instr).short {
+        *pc = addr;
+    } else {
+        *pc = pc.wrapping_add(addr as u8 as i8 as u16);
+    }
+}
+
+unsafe fn print_stack(name: *const c_char, stack: *const Stack) {
+    fprintf(stderr(), c!("%s"), name);
+    for i in 0..(*stack).ptr as usize {
+        fprintf(stderr(), c!(" %02x"), (*stack).dat[i] as c_uint);
+    }
+    fprintf(stderr(), c!("\n"));
+}
+
+unsafe fn write_byte(stream: *mut FILE, byte: u8) {
+    fwrite(&byte as *const u8 as *const c_void, 1, 1, stream);
+}
+
+unsafe fn dei(uxn: *mut Uxn, port: u8) -> u8 {
+    match port {
+        SYSTEM_WST => (*uxn).wst.ptr,
+        SYSTEM_RST => (*uxn).rst.ptr,
+        _          => (*uxn).dev[port as usize],
+    }
+}
+
+unsafe fn deo(uxn: *mut Uxn, port: u8, value: u8) {
+    (*uxn).dev[port as usize] = value;
+    match port {
+        SYSTEM_WST    => (*uxn).wst.ptr = value,
+        SYSTEM_RST    => (*uxn).rst.ptr = value,
+        SYSTEM_DEBUG  => if value != 0 {
+            print_stack(c!("WST"), &(*uxn).wst);
+            print_stack(c!("RST"), &(*uxn).rst);
+        }
+        CONSOLE_WRITE => write_byte(stdout(), value),
+        CONSOLE_ERROR => {
+            // Keep the order of the output when both streams go to the same place
+            fflush(stdout());
+            write_byte(stderr(), value);
+        }
+        _ => {}
+    }
+}
+
+unsafe fn halted(uxn: *mut Uxn) -> bool {
+    (*uxn).dev[SYSTEM_STATE as usize] != 0
+}
+
+// Evaluates the vector at `pc` until it hits BRK or the machine is halted
+pub unsafe fn eval(uxn: *mut Uxn, mut pc: u16) {
+    if pc == 0 { return; }
+    while !halted(uxn) {
+        let op = peek8(uxn, pc);
+        pc = pc.wrapping_add(1);
+
+        // Immediate instructions don't have the mode bits
+        match op {
+            0x00 => return, // BRK
+            0x20 => { // JCI
+                let cond = (*uxn).wst.ptr.wrapping_sub(1);
+                (*uxn).wst.ptr = cond;
+                if (*uxn).wst.dat[cond as usize] == 0 {
+                    pc = pc.wrapping_add(2);
+                } else {
+                    pc = pc.wrapping_add(peek16(uxn, pc)).wrapping_add(2);
+                }
+                continue;
+            }
+            0x40 => { // JMI
+                pc = pc.wrapping_add(peek16(uxn, pc)).wrapping_add(2);
+                continue;
+            }
+            0x60 => { // JSI
+                stack_push16(&mut (*uxn).rst, pc.wrapping_add(2));
+                pc = pc.wrapping_add(peek16(uxn, pc)).wrapping_add(2);
+                continue;
+            }
+            _ => {}
+        }
+
+        let returning = op & 0x40 != 0;
+        let mut instr = Instr {
+            short: op & 0x20 != 0,
+            keep:  op & 0x80 != 0,
+            src:   if returning { &mut (*uxn).rst } else { &mut (*uxn).wst },
+            dst:   if returning { &mut (*uxn).wst } else { &mut (*uxn).rst },
+            ptr:   0,
+        };
+        instr.ptr = (*instr.src).ptr;
+        let instr = &mut instr;
+
+        match op & 0x1f {
+            0x00 => { // LIT
+                let value = peek(uxn, instr, pc);
+                push(instr, value);
+                pc = pc.wrapping_add(if instr.short { 2 } else { 1 });
+            }
+            0x01 => { let a = pop(instr); push(instr, a.wrapping_add(1)); } // INC
+            0x02 => { pop(instr); } // POP
+            0x03 => { let b = pop(instr); pop(instr); push(instr, b); } // NIP
+            0x04 => { let b = pop(instr); let a = pop(instr); push(instr, b); push(instr, a); } // SWP
+            0x05 => { // ROT
+                let c = pop(instr); let b = pop(instr); let a = pop(instr);
+                push(instr, b); push(instr, c); push(instr, a);
+            }
+            0x06 => { let a = pop(instr); push(instr, a); push(instr, a); } // DUP
+            0x07 => { let b = pop(instr); let a = pop(instr); push(instr, a); push(instr, b); push(instr, a); } // OVR
+            0x08 => { let b = pop(instr); let a = pop(instr); push8(instr, (a == b) as u8); } // EQU
+            0x09 => { let b = pop(instr); let a = pop(instr); push8(instr, (a != b) as u8); } // NEQ
+            0x0a => { let b = pop(instr); let a = pop(instr); push8(instr, (a >  b) as u8); } // GTH
+            0x0b => { let b = pop(instr); let a = pop(instr); push8(instr, (a <  b) as u8); } // LTH
+            0x0c => { let addr = pop(instr); jump(instr, &mut pc, addr); } // JMP
+            0x0d => { // JCN
+                let addr = pop(instr);
+                let cond = pop8(instr);
+                if cond != 0 { jump(instr, &mut pc, addr); }
+            }
+            0x0e => { // JSR
+                let addr = pop(instr);
+                stack_push16(instr.dst, pc);
+                jump(instr, &mut pc, addr);
+            }
+            0x0f => { // STH
+                let a = pop(instr);
+                if instr.short { stack_push16(instr.dst, a) } else { stack_push8(instr.dst, a as u8) }
+            }
+            0x10 => { let addr = pop8(instr) as u16; let value = peek(uxn, instr, addr); push(instr, value); } // LDZ
+            0x11 => { let addr = pop8(instr) as u16; let value = pop(instr); poke(uxn, instr, addr, value); } // STZ
+            0x12 => { // LDR
+                let addr = pc.wrapping_add(pop8(instr) as i8 as u16);
+                let value = peek(uxn, instr, addr);
+                push(instr, value);
+            }
+            0x13 => { // STR
+                let addr = pc.wrapping_add(pop8(instr) as i8 as u16);
+                let value = pop(instr);
+                poke(uxn, instr, addr, value);
+            }
+            0x14 => { let addr = pop16(instr); let value = peek(uxn, instr, addr); push(instr, value); } // LDA
+            0x15 => { let addr = pop16(instr); let value = pop(instr); poke(uxn, instr, addr, value); } // STA
+            0x16 => { // DEI
+                let port = pop8(instr);
+                if instr.short {
+                    let value = (dei(uxn, port) as u16) << 8 | dei(uxn, port.wrapping_add(1)) as u16;
+                    push(instr, value);
+                } else {
+                    let value = dei(uxn, port);
+                    push8(instr, value);
+                }
+            }
+            0x17 => { // DEO
+                let port = pop8(instr);
+                let value = pop(instr);
+                if instr.short {
+                    deo(uxn, port, (value >> 8) as u8);
+                    deo(uxn, port.wrapping_add(1), value as u8);
+                } else {
+                    deo(uxn, port, value as u8);
+                }
+            }
+            0x18 => { let b = pop(instr); let a = pop(instr); push(instr, a.wrapping_add(b)); } // ADD
+            0x19 => { let b = pop(instr); let a = pop(instr); push(instr, a.wrapping_sub(b)); } // SUB
+            0x1a => { let b = pop(instr); let a = pop(instr); push(instr, a.wrapping_mul(b)); } // MUL
+            0x1b => { let b = pop(instr); let a = pop(instr); push(instr, if b == 0 { 0 } else { a/b }); } // DIV
+            0x1c => { let b = pop(instr); let a = pop(instr); push(instr, a & b); } // AND
+            0x1d => { let b = pop(instr); let a = pop(instr); push(instr, a | b); } // ORA
+            0x1e => { let b = pop(instr); let a = pop(instr); push(instr, a ^ b); } // EOR
+            0x1f => { // SFT
+                let shift = pop8(instr);
+                let a = pop(instr);
+                push(instr, (a >> (shift & 0x0f)) << (shift >> 4));
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+unsafe fn console_input(uxn: *mut Uxn, c: u8, type_: u8) {
+    (*uxn).dev[CONSOLE_READ as usize] = c;
+    (*uxn).dev[CONSOLE_TYPE as usize] = type_;
+    let vector = ((*uxn).dev[CONSOLE_VECTOR as usize] as u16) << 8 | (*uxn).dev[CONSOLE_VECTOR as usize + 1] as u16;
+    eval(uxn, vector);
+}
+
+unsafe fn console_vector(uxn: *mut Uxn) -> bool {
+    (*uxn).dev[CONSOLE_VECTOR as usize] != 0 || (*uxn).dev[CONSOLE_VECTOR as usize + 1] != 0
+}
+
+// Runs the rom the same way uxncli does and returns its exit code
+pub unsafe fn run(rom: *const [u8], args: *const [*const c_char]) -> Option<u8> {
+    if rom.len() > MEMORY_SIZE - PAGE_PROGRAM as usize {
+        fprintf(stderr(), c!("uxn: rom is too big: %zu bytes\n"), rom.len());
+        return None;
+    }
+
+    let mut uxn: Uxn = zeroed();
+    uxn.memory = malloc(MEMORY_SIZE) as *mut u8;
+    memset(uxn.memory as *mut c_void, 0, MEMORY_SIZE);
+    for i in 0..rom.len() {
+        *uxn.memory.add(PAGE_PROGRAM as usize + i) = (*rom)[i];
+    }
+
+    if args.len() > 0 {
+        uxn.dev[CONSOLE_TYPE as usize] = 1;
+    }
+    eval(&mut uxn, PAGE_PROGRAM);
+
+    for i in 0..args.len() {
+        let mut arg = (*args)[i];
+        while *arg != 0 {
+            console_input(&mut uxn, *arg as u8, CONSOLE_ARG);
+            arg = arg.add(1);
+        }
+        console_input(&mut uxn, b'\n', if i + 1 == args.len() { CONSOLE_END } else { CONSOLE_EOA });
+    }
+
+    // Only feed stdin if the program is actually listening to it
+    while !halted(&mut uxn) && console_vector(&mut uxn) {
+        let c = getchar();
+        if c < 0 {
+            console_input(&mut uxn, 0, CONSOLE_END);
+            break;
+        }
+        console_input(&mut uxn, c as u8, CONSOLE_STD);
+    }
+
+    free(uxn.memory);
+    Some(uxn.dev[SYSTEM_STATE as usize] & 0x7f)
+}
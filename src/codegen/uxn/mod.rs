@@ -1,5 +1,6 @@
 use core::ffi::*;
 use core::mem::zeroed;
+use core::slice;
 use crate::ir::*;
 use crate::nob::*;
 use crate::crust::libc::*;
@@ -11,6 +12,9 @@ use crate::lexer;
 use crate::lexer::{Token, loc};
 use crate::targets::TargetAPI;
 use crate::params::*;
+use crate::proc::*;
+
+pub mod emulator;
 
 // UXN memory map
 // 0x0000 - 0x00ff - zero page
@@ -129,6 +133,7 @@ enum_with_order! {
     enum Uxn_Runner in UXN_RUNNER_ORDER {
         Uxncli,
         Uxnemu,
+        Builtin,
     }
 }
 
@@ -137,6 +142,7 @@ impl Uxn_Runner {
         match self {
             Uxn_Runner::Uxncli => c!("uxncli"),
             Uxn_Runner::Uxnemu => c!("uxnemu"),
+            Uxn_Runner::Builtin => c!("builtin"),
         }
     }
 
@@ -144,6 +150,7 @@ impl Uxn_Runner {
         match self {
             Uxn_Runner::Uxncli => c!("CLI Emulator"),
             Uxn_Runner::Uxnemu => c!("GUI Emulator"),
+            Uxn_Runner::Builtin => c!("Builtin CLI Emulator (does not require uxn installed)"),
         }
     }
 
@@ -227,8 +234,6 @@ pub unsafe fn generate_program(
     let gen = gen as *mut Uxn;
     let output = &mut (*gen).output;
 
-    if debug { todo!("Debug information for uxn") }
-
     let mut assembler: Assembler = zeroed();
     assembler.data_section_label = create_label(&mut assembler);
     // set the top of the stack
@@ -264,17 +269,57 @@ pub unsafe fn generate_program(
     write_entire_file(program_path, (*output).items as *const c_void, (*output).count)?;
     log(Log_Level::INFO, c!("generated %s"), program_path);
 
+    if debug {
+        let sym_path = temp_sprintf(c!("%s.sym"), program_path);
+        let mut symbols: String_Builder = zeroed();
+        generate_symbols(&mut symbols, &mut assembler);
+        write_entire_file(sym_path, symbols.items as *const c_void, symbols.count)?;
+        free(symbols.items);
+        log(Log_Level::INFO, c!("generated %s"), sym_path);
+    }
+
     Some(())
 }
 
+// Symbol file in the uxnasm format: every symbol is a big endian absolute address
+// followed by the NULL-terminated name of the label.
+pub unsafe fn generate_symbols(output: *mut String_Builder, a: *mut Assembler) {
+    for i in 0..(*a).named_labels.count {
+        let named_label = *(*a).named_labels.items.add(i);
+        let addr = *(*a).resolved_addresses.items.add(named_label.label);
+        if addr == 0 { continue; } // Never linked
+        write_symbol(output, addr, named_label.name);
+    }
+    let data_addr = *(*a).resolved_addresses.items.add((*a).data_section_label);
+    write_symbol(output, data_addr, c!("__data_section"));
+}
+
+pub unsafe fn write_symbol(output: *mut String_Builder, addr: u16, name: *const c_char) {
+    write_short(output, addr + 0x100);
+    da_append_many(output, slice::from_raw_parts(name, strlen(name) + 1));
+}
+
 pub unsafe fn run_program(
     gen: *mut c_void, program_path: *const c_char, run_args: *const [*const c_char],
 ) -> Option<()> {
     let gen = gen as *mut Uxn;
-    let cmd = &mut (*gen).cmd;
-    cmd_append! {cmd, (*gen).runner.name(), program_path}
-    da_append_many(cmd, run_args);
-    if !cmd_run_sync_and_reset(cmd) { return None; }
+    match (*gen).runner {
+        Uxn_Runner::Builtin => {
+            let rom = &mut (*gen).output;
+            rom.count = 0;
+            read_entire_file(program_path, rom)?;
+            let code = emulator::run(da_slice(*rom) as *const [u8], run_args)?;
+            if code != 0 {
+                exit(code as c_int);
+            }
+        }
+        Uxn_Runner::Uxncli | Uxn_Runner::Uxnemu => {
+            let cmd = &mut (*gen).cmd;
+            cmd_append! {cmd, (*gen).runner.name(), program_path}
+            da_append_many(cmd, run_args);
+            cmd_run_sync_forwarding_exit_code_and_reset(cmd)?;
+        }
+    }
     Some(())
 }
 
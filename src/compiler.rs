@@ -0,0 +1,1686 @@
+//! The front end of the B compiler that turns the B sources into the IR (see ir.rs)
+//!
+//! Shared between the compiler itself (b.rs) and the language server (blsp.rs)
+
+use core::ffi::*;
+use core::mem::zeroed;
+use core::ptr;
+use core::slice;
+use crate::nob::*;
+use crate::crust::libc::*;
+use crate::crust::assoc_lookup_cstr;
+use crate::arena::{self, Arena};
+use crate::lexer::{self, Lexer, Loc, Token};
+use crate::ir::*;
+use crate::glob::match_glob;
+use crate::{diagf, missingf};
+
+pub unsafe fn expect_tokens(l: *mut Lexer, tokens: *const [Token]) -> Option<()> {
+    for i in 0..tokens.len() {
+        if (*tokens)[i] == (*l).token {
+            return Some(());
+        }
+    }
+
+    let mut sb: String_Builder = zeroed();
+    for i in 0..tokens.len() {
+        if i > 0 {
+            if i + 1 >= tokens.len() {
+                sb_appendf(&mut sb, c!(", or "));
+            } else {
+                sb_appendf(&mut sb, c!(", "));
+            }
+        }
+        sb_appendf(&mut sb, c!("%s"), lexer::display_token((*tokens)[i]));
+    }
+    da_append(&mut sb, 0);
+
+    diagf!((*l).loc, c!("ERROR: expected %s, but got %s\n"), sb.items, lexer::display_token((*l).token));
+
+    free(sb.items);
+    None
+}
+
+pub unsafe fn expect_token(l: *mut Lexer, token: Token) -> Option<()> {
+    expect_tokens(l, &[token])
+}
+
+pub unsafe fn get_and_expect_token(l: *mut Lexer, token: Token) -> Option<()> {
+    lexer::get_token(l)?;
+    expect_token(l, token)
+}
+
+pub unsafe fn get_and_expect_token_but_continue(l: *mut Lexer, c: *mut Compiler, token: Token) -> Option<()> {
+    let saved_point = (*l).parse_point;
+    lexer::get_token(l)?;
+    if expect_token(l, token).is_none() {
+        (*l).parse_point = saved_point;
+        bump_error_count(c)
+    } else {
+        Some(())
+    }
+}
+
+pub unsafe fn get_and_expect_tokens(l: *mut Lexer, clexes: *const [Token]) -> Option<()> {
+    lexer::get_token(l)?;
+    expect_tokens(l, clexes)
+}
+
+pub unsafe fn expect_token_id(l: *mut Lexer, id: *const c_char) -> Option<()> {
+    expect_token(l, Token::ID)?;
+    if strcmp((*l).string, id) != 0 {
+        diagf!((*l).loc, c!("ERROR: expected `%s`, but got `%s`\n"), id, (*l).string);
+        return None;
+    }
+    Some(())
+}
+
+pub unsafe fn get_and_expect_token_id(l: *mut Lexer, id: *const c_char) -> Option<()> {
+    lexer::get_token(l)?;
+    expect_token_id(l, id)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub enum Storage {
+    External {name: *const c_char},
+    Auto     {index: usize},
+}
+
+#[derive(Clone, Copy)]
+pub struct Var {
+    pub name: *const c_char,
+    pub loc: Loc,
+    pub storage: Storage,
+    /// The variable was introduced by an `extrn` statement rather than defined
+    pub extrn: bool,
+    /// The variable was referenced at least once after its declaration
+    pub used: bool,
+}
+
+pub unsafe fn scope_push(vars: *mut Array<Array<Var>>) {
+    if (*vars).count < (*vars).capacity {
+        // Reusing already allocated scopes
+        (*vars).count += 1;
+        (*da_last_mut(vars).expect("There should be always at least the global scope")).count = 0;
+    } else {
+        da_append(vars, zeroed());
+    }
+}
+
+pub unsafe fn scope_pop(vars: *mut Array<Array<Var>>) {
+    assert!((*vars).count > 0);
+    (*vars).count -= 1;
+}
+
+pub unsafe fn find_var_near(vars: *const Array<Var>, name: *const c_char) -> *const Var {
+    for i in 0..(*vars).count {
+        let var = (*vars).items.add(i);
+        if strcmp((*var).name, name) == 0 {
+            return var
+        }
+    }
+    ptr::null()
+}
+
+pub unsafe fn find_var_deep(vars: *const Array<Array<Var>>, name: *const c_char) -> *const Var {
+    find_var_in_scopes(vars, (*vars).count, name)
+}
+
+/// Same as find_var_deep() but only looks through the first `count` scopes
+pub unsafe fn find_var_in_scopes(vars: *const Array<Array<Var>>, count: usize, name: *const c_char) -> *const Var {
+    let mut i = count;
+    while i > 0 {
+        let var = find_var_near((*vars).items.add(i-1), name);
+        if !var.is_null() {
+            return var;
+        }
+        i -= 1;
+    }
+    ptr::null()
+}
+
+pub unsafe fn declare_var(c: *mut Compiler, name: *const c_char, loc: Loc, storage: Storage, extrn: bool) -> Option<()> {
+    let scope = da_last_mut(&mut (*c).vars).expect("There should be always at least the global scope");
+    let existing_var = find_var_near(scope, name);
+    if !existing_var.is_null() && (*c).vars.count == 1 && (extrn || (*existing_var).extrn) {
+        // A top-level `extrn` may refer to a name defined in the same program. That happens when
+        // the modules compiled separately with -c declare the names they import from each other.
+        if !extrn {
+            let existing_var = existing_var as *mut Var;
+            (*existing_var).loc = loc;
+            (*existing_var).extrn = false;
+        }
+        return Some(());
+    }
+    if !existing_var.is_null() {
+        diagf!(loc, c!("ERROR: redefinition of variable `%s`\n"), name);
+        diagf!((*existing_var).loc, c!("NOTE: the first declaration is located here\n"));
+        return bump_error_count(c);
+    }
+
+    // `extrn` is the way to bring the outer names into the scope, so it is not considered shadowing
+    if !extrn {
+        let shadowed_var = find_var_in_scopes(&(*c).vars, (*c).vars.count - 1, name);
+        if !shadowed_var.is_null() && report_warning(c, Warning::Shadow, loc, temp_sprintf(c!("declaration of `%s` shadows an outer declaration"), name))? {
+            diagf!((*shadowed_var).loc, c!("NOTE: the shadowed declaration is located here\n"));
+        }
+    }
+
+    if let Storage::Auto {index} = storage {
+        da_append(&mut (*c).func_scope_events, ScopeEvent::Declare {name, index});
+    }
+
+    da_append(scope, Var {name, loc, storage, extrn, used: false});
+    Some(())
+}
+
+/// Warns about the variables of the innermost scope that were never used. The first `params_count`
+/// variables of the scope are considered to be the parameters of the function. The parameters that
+/// come after the one whose address was taken are considered used, since they can be reached through
+/// that address.
+pub unsafe fn check_unused_vars(c: *mut Compiler, params_count: usize) -> Option<()> {
+    let scope = da_last_mut(&mut (*c).vars).expect("There should be always at least the global scope");
+    for i in 0..(*scope).count {
+        let var = *(*scope).items.add(i);
+        if var.used { continue }
+        if var.extrn {
+            report_warning(c, Warning::UnusedExtrn, var.loc, temp_sprintf(c!("extrn `%s` is declared but never used"), var.name))?;
+        } else if let Storage::Auto {..} = var.storage {
+            if i < params_count {
+                if let (Storage::Auto {index}, Some(lowest)) = (var.storage, (*c).func_lowest_ref_auto) {
+                    if index > lowest { continue }
+                }
+                report_warning(c, Warning::UnusedVariable, var.loc, temp_sprintf(c!("unused parameter `%s`"), var.name))?;
+            } else {
+                report_warning(c, Warning::UnusedVariable, var.loc, temp_sprintf(c!("unused variable `%s`"), var.name))?;
+            }
+        }
+    }
+    Some(())
+}
+
+#[derive(Clone, Copy)]
+pub struct GotoLabel {
+    name: *const c_char,
+    loc: Loc,
+    label: usize,
+    used: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct Goto {
+    name: *const c_char,
+    loc: Loc,
+    addr: usize,
+}
+
+pub unsafe fn find_goto_label(labels: *const Array<GotoLabel>, name: *const c_char) -> *const GotoLabel {
+    for i in 0..(*labels).count {
+        let label = (*labels).items.add(i);
+        if strcmp((*label).name, name) == 0 {
+            return label
+        }
+    }
+    ptr::null()
+}
+
+pub unsafe fn define_goto_label(c: *mut Compiler, name: *const c_char, loc: Loc, label: usize) -> Option<()> {
+    let existing_label = find_goto_label(&(*c).func_goto_labels, name);
+    if !existing_label.is_null() {
+        diagf!(loc, c!("ERROR: duplicate label `%s`\n"), name);
+        diagf!((*existing_label).loc, c!("NOTE: the first definition is located here\n"));
+        return bump_error_count(c);
+    }
+
+    da_append(&mut (*c).func_goto_labels, GotoLabel {name, loc, label, used: false});
+    Some(())
+}
+
+// The higher the index of the row in this table the higher the precedence of the Binop
+pub const PRECEDENCE: *const [*const [Binop]] = &[
+    &[Binop::BitOr],
+    &[Binop::BitAnd],
+    &[Binop::BitShl, Binop::BitShr],
+    &[Binop::Equal, Binop::NotEqual],
+    &[Binop::Less, Binop::Greater, Binop::GreaterEqual, Binop::LessEqual],
+    &[Binop::Plus, Binop::Minus],
+    &[Binop::Mult, Binop::Mod, Binop::Div],
+];
+
+impl Binop {
+    // The outer Option indicates success.
+    // The inner Option indicates whether the assign has binop associated with it.
+    // It's kinda confusing but I don't know how to make it "prettier"
+    pub fn from_assign_token(token: Token) -> Option<Option<Self>> {
+        match token {
+            Token::Eq      => Some(None),
+            Token::PlusEq  => Some(Some(Binop::Plus)),
+            Token::MinusEq => Some(Some(Binop::Minus)),
+            Token::MulEq   => Some(Some(Binop::Mult)),
+            Token::DivEq   => Some(Some(Binop::Div)),
+            Token::ModEq   => Some(Some(Binop::Mod)),
+            Token::ShlEq   => Some(Some(Binop::BitShl)),
+            Token::ShrEq   => Some(Some(Binop::BitShr)),
+            Token::OrEq    => Some(Some(Binop::BitOr)),
+            Token::AndEq   => Some(Some(Binop::BitAnd)),
+            _              => None,
+        }
+    }
+
+    pub fn from_token(token: Token) -> Option<Self> {
+        match token {
+            Token::Plus      => Some(Binop::Plus),
+            Token::Minus     => Some(Binop::Minus),
+            Token::Mul       => Some(Binop::Mult),
+            Token::Div       => Some(Binop::Div),
+            Token::Mod       => Some(Binop::Mod),
+            Token::EqEq      => Some(Binop::Equal),
+            Token::NotEq     => Some(Binop::NotEqual),
+            Token::Less      => Some(Binop::Less),
+            Token::LessEq    => Some(Binop::LessEqual),
+            Token::Greater   => Some(Binop::Greater),
+            Token::GreaterEq => Some(Binop::GreaterEqual),
+            Token::Or        => Some(Binop::BitOr),
+            Token::And       => Some(Binop::BitAnd),
+            Token::Shl       => Some(Binop::BitShl),
+            Token::Shr       => Some(Binop::BitShr),
+            _ => None,
+        }
+    }
+
+    pub const MAX_PRECEDENCE: usize = PRECEDENCE.len();
+    pub unsafe fn precedence(self) -> usize {
+        for precedence in 0..PRECEDENCE.len() {
+            for i in 0..(*PRECEDENCE)[precedence].len() {
+                if self == (*(*PRECEDENCE)[precedence])[i] {
+                    return precedence
+                }
+            }
+        }
+        unreachable!()
+    }
+}
+
+pub unsafe fn push_opcode(opcode: Op, loc: Loc, c: *mut Compiler) {
+    da_append(&mut (*c).func_body, OpWithLocation {opcode, loc, scope_events_count: (*c).func_scope_events.count });
+}
+
+/// Allocator of Auto Vars
+#[derive(Clone, Copy)]
+pub struct AutoVarsAtor {
+    /// How many autovars currently allocated
+    pub count: usize,
+    /// Maximum allocated autovars throughout the function body
+    pub max: usize,
+}
+
+pub unsafe fn allocate_label_index(c: *mut Compiler) -> usize {
+    let index = (*c).op_label_count;
+    (*c).op_label_count += 1;
+    index
+}
+
+pub unsafe fn allocate_auto_var(t: *mut AutoVarsAtor) -> usize {
+    (*t).count += 1;
+    if (*t).count > (*t).max {
+        (*t).max = (*t).count;
+    }
+    (*t).count
+}
+
+
+pub unsafe fn compile_string(string: *const c_char, c: *mut Compiler) -> usize {
+    let offset = (*c).program.data.count;
+    let string_len = strlen(string);
+    da_append_many(&mut (*c).program.data, slice::from_raw_parts(string as *const u8, string_len));
+    // TODO: Strings in B are not NULL-terminated.
+    // They are terminated with symbol '*e' ('*' is escape character akin to '\' in C) which according to the
+    // spec is called just "end-of-file" without any elaboration on what its value is. Maybe it had a specific
+    // value on PDP that was a common knowledge at the time? In any case that breaks compatibility with
+    // libc. While the language is still in development we gonna terminate it with 0. We will make it
+    // "spec complaint" later.
+    da_append(&mut (*c).program.data, 0); // NULL-terminator
+    offset
+}
+
+pub unsafe fn compile_primary_expression(l: *mut Lexer, c: *mut Compiler) -> Option<(Arg, bool)> {
+    lexer::get_token(l)?;
+    let arg = match (*l).token {
+        Token::OParen => {
+            let result = compile_expression(l, c)?;
+            get_and_expect_token_but_continue(l, c, Token::CParen)?;
+            Some(result)
+        }
+        Token::Not => {
+            let (arg, _) = compile_primary_expression(l, c)?;
+            let result = allocate_auto_var(&mut (*c).auto_vars_ator);
+            push_opcode(Op::UnaryNot{result, arg}, (*l).loc, c);
+            Some((Arg::AutoVar(result), false))
+        }
+        Token::Mul => {
+            let (arg, _) = compile_primary_expression(l, c)?;
+            let index = allocate_auto_var(&mut (*c).auto_vars_ator);
+            push_opcode(Op::AutoAssign {index, arg}, (*l).loc, c);
+            Some((Arg::Deref(index), true))
+        }
+        Token::Minus => {
+            let (arg, _) = compile_primary_expression(l, c)?;
+            if let Arg::Literal(v) = arg {
+                Some((Arg::Literal(!v + 1), false))
+            } else {
+                let index = allocate_auto_var(&mut (*c).auto_vars_ator);
+                push_opcode(Op::Negate {result: index, arg}, (*l).loc, c);
+                Some((Arg::AutoVar(index), false))
+            }
+        }
+        Token::And => {
+            let loc = (*l).loc;
+            let (arg, is_lvalue) = compile_primary_expression(l, c)?;
+
+            if !is_lvalue {
+                diagf!(loc, c!("ERROR: cannot take the address of an rvalue\n"));
+                return bump_error_count(c).map(|()| (Arg::Bogus, false));
+            }
+
+            match arg {
+                Arg::Deref(index)   =>  Some((Arg::AutoVar(index), false)), // "&*x is identically x"
+                Arg::External(name) =>  Some((Arg::RefExternal(name), false)),
+                Arg::AutoVar(index) =>  {
+                    if (*c).func_lowest_ref_auto.map_or(true, |lowest| index < lowest) {
+                        (*c).func_lowest_ref_auto = Some(index);
+                    }
+                    Some((Arg::RefAutoVar(index), false))
+                }
+                Arg::Bogus          =>  Some((Arg::Bogus, false)), // Reference of a bogus value is a bogus value
+                Arg::Literal(_) | Arg::DataOffset(_) | Arg::RefAutoVar(_) | Arg::RefExternal(_) => unreachable!(),
+            }
+        }
+        Token::PlusPlus => {
+            let loc = (*l).loc;
+            let (arg, is_lvalue) = compile_primary_expression(l, c)?;
+
+            if !is_lvalue {
+                diagf!(loc, c!("ERROR: cannot increment an rvalue\n"));
+                return bump_error_count(c).map(|()| (Arg::Bogus, false));
+            }
+
+            compile_binop(arg, Arg::Literal(1), Binop::Plus, loc, c);
+            Some((arg, false))
+        }
+        Token::MinusMinus => {
+            let loc = (*l).loc;
+            let (arg, is_lvalue) = compile_primary_expression(l, c)?;
+
+            if !is_lvalue {
+                diagf!(loc, c!("ERROR: cannot decrement an rvalue\n"));
+                return bump_error_count(c).map(|()| (Arg::Bogus, false));
+            }
+
+            compile_binop(arg, Arg::Literal(1), Binop::Minus, loc, c);
+            Some((arg, false))
+        }
+        Token::CharLit | Token::IntLit => Some((Arg::Literal((*l).int_number), false)),
+        Token::ID => {
+            let name = arena::strdup(&mut (*c).arena, (*l).string);
+
+            let var_def = find_var_deep(&mut (*c).vars, name) as *mut Var;
+            if var_def.is_null() {
+                da_append(&mut (*c).used_funcs, UsedFunc {name, loc: (*l).loc});
+                Some((Arg::External(name), true))
+            } else {
+                (*var_def).used = true;
+                match (*var_def).storage {
+                    Storage::Auto{index} => Some((Arg::AutoVar(index), true)),
+                    Storage::External{name} => Some((Arg::External(name), true)),
+                }
+            }
+        }
+        Token::String => {
+            let offset = compile_string((*l).string, c);
+            Some((Arg::DataOffset(offset), false))
+        }
+        _ => {
+            diagf!((*l).loc, c!("Expected start of a primary expression but got %s\n"), lexer::display_token((*l).token));
+            None
+        }
+    };
+
+    let (mut arg, mut is_lvalue) = arg?;
+
+    loop {
+        let saved_point = (*l).parse_point;
+        lexer::get_token(l)?;
+
+        (arg, is_lvalue) = match (*l).token {
+            Token::OParen => Some((compile_function_call(l, c, arg)?, false)),
+            Token::OBracket => {
+                let (offset, _) = compile_expression(l, c)?;
+                get_and_expect_token_but_continue(l, c, Token::CBracket)?;
+
+                let result = allocate_auto_var(&mut (*c).auto_vars_ator);
+                push_opcode(Op::Index {result, arg, offset}, (*l).loc, c);
+
+                Some((Arg::Deref(result), true))
+            }
+            Token::PlusPlus => {
+                let loc = (*l).loc;
+                if !is_lvalue {
+                    diagf!(loc, c!("ERROR: cannot increment an rvalue\n"));
+                    return bump_error_count(c).map(|()| (Arg::Bogus, false));
+                }
+
+                let pre = allocate_auto_var(&mut (*c).auto_vars_ator);
+                push_opcode(Op::AutoAssign {index: pre, arg}, loc, c);
+                compile_binop(arg, Arg::Literal(1), Binop::Plus, loc, c);
+
+                Some((Arg::AutoVar(pre), false))
+            }
+            Token::MinusMinus => {
+                let loc = (*l).loc;
+                if !is_lvalue {
+                    diagf!(loc, c!("ERROR: cannot decrement an rvalue\n"));
+                    return bump_error_count(c).map(|()| (Arg::Bogus, false));
+                }
+
+                let pre = allocate_auto_var(&mut (*c).auto_vars_ator);
+                push_opcode(Op::AutoAssign {index: pre, arg}, loc, c);
+                compile_binop(arg, Arg::Literal(1), Binop::Minus, loc, c);
+
+                Some((Arg::AutoVar(pre), false))
+            }
+            _ => {
+                (*l).parse_point = saved_point;
+                return Some((arg, is_lvalue));
+            }
+        }?;
+    }
+}
+
+// TODO: communicate to the caller of this function that it expects `lhs` to be an lvalue
+pub unsafe fn compile_binop(lhs: Arg, rhs: Arg, binop: Binop, loc: Loc, c: *mut Compiler) {
+    match lhs {
+        Arg::Deref(index) => {
+            let tmp = allocate_auto_var(&mut (*c).auto_vars_ator);
+            push_opcode(Op::Binop {binop, index: tmp, lhs, rhs}, loc, c);
+            push_opcode(Op::Store {index, arg: Arg::AutoVar(tmp)}, loc, c);
+        },
+        Arg::External(name) => {
+            let tmp = allocate_auto_var(&mut (*c).auto_vars_ator);
+            push_opcode(Op::Binop {binop, index: tmp, lhs, rhs}, loc, c);
+            push_opcode(Op::ExternalAssign {name, arg: Arg::AutoVar(tmp)}, loc, c)
+        }
+        Arg::AutoVar(index) => {
+            push_opcode(Op::Binop {binop, index, lhs, rhs}, loc, c)
+        }
+        Arg::Bogus => {
+            // Bogus value does not compile to anything
+        }
+        Arg::Literal(_) | Arg::DataOffset(_) | Arg::RefAutoVar(_) | Arg::RefExternal(_) => unreachable!(),
+    }
+}
+
+pub unsafe fn compile_binop_expression(l: *mut Lexer, c: *mut Compiler, precedence: usize) -> Option<(Arg, bool)> {
+    if precedence >= Binop::MAX_PRECEDENCE {
+        return compile_primary_expression(l, c);
+    }
+
+    let (mut lhs, mut lvalue) = compile_binop_expression(l, c, precedence + 1)?;
+
+    let mut saved_point = (*l).parse_point;
+    lexer::get_token(l)?;
+
+    if let Some(binop) = Binop::from_token((*l).token) {
+        if binop.precedence() == precedence {
+            while let Some(binop) = Binop::from_token((*l).token) {
+                if binop.precedence() != precedence { break; }
+
+                let (rhs, _) = compile_binop_expression(l, c, precedence + 1)?;
+
+                let index = allocate_auto_var(&mut (*c).auto_vars_ator);
+                push_opcode(Op::Binop {binop, index, lhs, rhs}, (*l).loc, c);
+                lhs = Arg::AutoVar(index);
+
+                lvalue = false;
+
+                saved_point = (*l).parse_point;
+                lexer::get_token(l)?;
+            }
+        }
+    }
+
+    (*l).parse_point = saved_point;
+    Some((lhs, lvalue))
+}
+
+pub unsafe fn compile_assign_expression(l: *mut Lexer, c: *mut Compiler) -> Option<(Arg, bool)> {
+    let (lhs, lvalue) = compile_binop_expression(l, c, 0)?;
+    compile_assign_expression_rest(l, c, lhs, lvalue)
+}
+
+/// Compiles the part of the assign expression after its already compiled left-hand side
+pub unsafe fn compile_assign_expression_rest(l: *mut Lexer, c: *mut Compiler, lhs: Arg, mut lvalue: bool) -> Option<(Arg, bool)> {
+    let mut saved_point = (*l).parse_point;
+    lexer::get_token(l)?;
+
+    while let Some(binop) = Binop::from_assign_token((*l).token) {
+        let binop_loc = (*l).loc;
+        let (rhs, _) = compile_assign_expression(l, c)?;
+
+        if !lvalue {
+            diagf!(binop_loc, c!("ERROR: cannot assign to rvalue\n"));
+            return bump_error_count(c).map(|()| (Arg::Bogus, false));
+        }
+
+        if let Some(binop) = binop {
+            compile_binop(lhs, rhs, binop, binop_loc, c);
+        } else {
+            match lhs {
+                Arg::Deref(index) => {
+                    push_opcode(Op::Store {index, arg: rhs}, binop_loc, c);
+                }
+                Arg::External(name) => {
+                    push_opcode(Op::ExternalAssign {name, arg: rhs}, binop_loc, c);
+                }
+                Arg::AutoVar(index) => {
+                    push_opcode(Op::AutoAssign {index, arg: rhs}, binop_loc, c);
+                }
+                Arg::Bogus => {
+                    // Bogus value does not compile to anything
+                }
+                Arg::Literal(_) | Arg::DataOffset(_) | Arg::RefAutoVar(_) | Arg::RefExternal(_) => unreachable!(),
+            }
+        }
+
+        lvalue = false;
+
+        saved_point = (*l).parse_point;
+        lexer::get_token(l)?;
+    }
+
+    if (*l).token == Token::Question {
+        let result = allocate_auto_var(&mut (*c).auto_vars_ator);
+
+        let else_label = allocate_label_index(c);
+        push_opcode(Op::JmpIfNotLabel{label: else_label, arg: lhs}, (*l).loc, c);
+
+        let (if_true, _) = compile_expression(l, c)?;
+        push_opcode(Op::AutoAssign {index: result, arg: if_true}, (*l).loc, c);
+        let out_label = allocate_label_index(c);
+        push_opcode(Op::JmpLabel{label: out_label}, (*l).loc, c);
+
+        get_and_expect_token_but_continue(l, c, Token::Colon)?;
+
+        push_opcode(Op::Label{label: else_label}, (*l).loc, c);
+        let (if_false, _) = compile_expression(l, c)?;
+        push_opcode(Op::AutoAssign {index: result, arg: if_false}, (*l).loc, c);
+        push_opcode(Op::Label{label: out_label}, (*l).loc, c);
+
+        Some((Arg::AutoVar(result), false))
+    } else {
+        (*l).parse_point = saved_point;
+        Some((lhs, lvalue))
+    }
+}
+
+pub unsafe fn compile_expression(l: *mut Lexer, c: *mut Compiler) -> Option<(Arg, bool)> {
+    compile_assign_expression(l, c)
+}
+
+/// Compiles the condition of `if` and `while` statements warning about `if (a = b)` which is
+/// usually a typo of `if (a == b)`. Wrap the assignment into extra parenthesis to silence it.
+pub unsafe fn compile_condition(l: *mut Lexer, c: *mut Compiler) -> Option<Arg> {
+    let (lhs, lvalue) = compile_binop_expression(l, c, 0)?;
+
+    let saved_point = (*l).parse_point;
+    lexer::get_token(l)?;
+    if (*l).token == Token::Eq {
+        report_warning(c, Warning::AssignInCondition, (*l).loc, c!("assignment used as a condition"))?;
+    }
+    (*l).parse_point = saved_point;
+
+    let (arg, _) = compile_assign_expression_rest(l, c, lhs, lvalue)?;
+    Some(arg)
+}
+
+/// Parses the `("pattern")` of `__if_target__` and matches the pattern against the name of the target
+pub unsafe fn compile_target_condition(l: *mut Lexer, c: *mut Compiler) -> Option<bool> {
+    get_and_expect_token(l, Token::OParen)?;
+    get_and_expect_token(l, Token::String)?;
+    let mut matched = false;
+    if !(*c).target.is_null() {
+        match match_glob((*l).string, (*c).target) {
+            Ok(result) => matched = result,
+            Err(error) => {
+                diagf!((*l).loc, c!("ERROR: invalid target pattern `%s`: %s\n"), (*l).string, error);
+                bump_error_count(c)?;
+            }
+        }
+    }
+    get_and_expect_token(l, Token::CParen)?;
+    Some(matched)
+}
+
+/// Skips the block of the branch of `__if_target__` that is not taken without compiling it
+pub unsafe fn skip_target_block(l: *mut Lexer) -> Option<()> {
+    get_and_expect_token(l, Token::OCurly)?;
+    let loc = (*l).loc;
+    let mut depth = 1;
+    while depth > 0 {
+        lexer::get_token(l)?;
+        match (*l).token {
+            Token::OCurly => depth += 1,
+            Token::CCurly => depth -= 1,
+            Token::EOF => {
+                diagf!(loc, c!("ERROR: the block is never closed\n"));
+                return None;
+            }
+            _ => {}
+        }
+    }
+    Some(())
+}
+
+/// Consumes `__else__` if it is the next token
+pub unsafe fn get_else_target(l: *mut Lexer) -> Option<bool> {
+    let saved_point = (*l).parse_point;
+    lexer::get_token(l)?;
+    if (*l).token == Token::ElseTarget {
+        return Some(true);
+    }
+    (*l).parse_point = saved_point;
+    Some(false)
+}
+
+pub unsafe fn compile_target_block(l: *mut Lexer, c: *mut Compiler) -> Option<()> {
+    let saved_point = (*l).parse_point;
+    get_and_expect_token(l, Token::OCurly)?;
+    (*l).parse_point = saved_point;
+    compile_statement(l, c)
+}
+
+pub unsafe fn compile_block(l: *mut Lexer, c: *mut Compiler) -> Option<()> {
+    let index = (*c).func_blocks_count;
+    (*c).func_blocks_count += 1;
+    da_append(&mut (*c).func_scope_events, ScopeEvent::BlockBegin {index});
+
+    let mut after_return = false;
+    loop {
+        let saved_point = (*l).parse_point;
+        lexer::get_token(l)?;
+        if (*l).token == Token::CCurly { break }
+        let token = (*l).token;
+        let loc = (*l).loc;
+
+        if after_return {
+            // Labels and cases can still be reached by jumping to them
+            let mut reachable = token == Token::Case;
+            if token == Token::ID {
+                lexer::get_token(l)?;
+                reachable = (*l).token == Token::Colon;
+            }
+            if !reachable {
+                report_warning(c, Warning::UnreachableCode, loc, c!("code after `return` is never executed"))?;
+            }
+        }
+        (*l).parse_point = saved_point;
+
+        compile_statement(l, c)?;
+        after_return = token == Token::Return;
+    }
+
+    da_append(&mut (*c).func_scope_events, ScopeEvent::BlockEnd {index});
+    Some(())
+}
+ unsafe fn compile_function_call(l: *mut Lexer, c: *mut Compiler, fun: Arg) -> Option<Arg> {
+    let mut args: Array<Arg> = zeroed();
+    let saved_point = (*l).parse_point;
+    lexer::get_token(l)?;
+    if (*l).token != Token::CParen {
+        (*l).parse_point = saved_point;
+        loop {
+            let (expr, _) = compile_expression(l, c)?;
+            da_append(&mut args, expr);
+            get_and_expect_tokens(l, &[Token::CParen, Token::Comma])?;
+            match (*l).token {
+                Token::CParen => break,
+                Token::Comma => continue,
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    let result = allocate_auto_var(&mut (*c).auto_vars_ator);
+    push_opcode(Op::Funcall {result, fun, args}, (*l).loc, c);
+    Some(Arg::AutoVar(result))
+}
+
+pub unsafe fn name_declare_if_not_exists(names: *mut Array<*const c_char>, name: *const c_char) {
+    for i in 0..(*names).count {
+        if strcmp(*(*names).items.add(i), name) == 0 {
+            return;
+        }
+    }
+    da_append(names, name)
+}
+
+pub unsafe fn compile_asm_stmts(l: *mut Lexer, c: *mut Compiler, stmts: *mut Array<AsmStmt>) -> Option<()> {
+    get_and_expect_token_but_continue(l, c, Token::OParen)?;
+    let saved_point = (*l).parse_point;
+    lexer::get_token(l)?;
+    if (*l).token != Token::CParen {
+        (*l).parse_point = saved_point;
+        loop {
+            get_and_expect_token(l, Token::String)?;
+            match (*l).token {
+                Token::String => {
+                    let line = arena::strdup(&mut (*c).arena, (*l).string);
+                    let loc = (*l).loc;
+                    da_append(stmts, AsmStmt { line, loc });
+                }
+                _ => unreachable!(),
+            }
+
+            get_and_expect_tokens(l, &[Token::Comma, Token::CParen])?;
+            match (*l).token {
+                Token::Comma  => {}
+                Token::CParen => break,
+                _             => unreachable!(),
+            }
+        }
+    }
+    get_and_expect_token_but_continue(l, c, Token::SemiColon)?;
+    Some(())
+}
+
+pub unsafe fn compile_statement(l: *mut Lexer, c: *mut Compiler) -> Option<()> {
+    let saved_point = (*l).parse_point;
+    lexer::get_token(l)?;
+
+    match (*l).token {
+        Token::SemiColon => {
+            Some(())
+        },
+        Token::OCurly => {
+            scope_push(&mut (*c).vars);
+            let saved_auto_vars_count = (*c).auto_vars_ator.count;
+            compile_block(l, c)?;
+            (*c).auto_vars_ator.count = saved_auto_vars_count;
+            check_unused_vars(c, 0)?;
+            scope_pop(&mut (*c).vars);
+            Some(())
+        }
+        Token::Extrn => {
+            while (*l).token != Token::SemiColon {
+                get_and_expect_token(l, Token::ID)?;
+                let name = arena::strdup(&mut (*c).arena, (*l).string);
+                name_declare_if_not_exists(&mut (*c).program.extrns, name);
+                declare_var(c, name, (*l).loc, Storage::External {name}, true)?;
+                get_and_expect_tokens(l, &[Token::SemiColon, Token::Comma])?;
+            }
+            compile_statement(l, c)
+        }
+        Token::Auto => {
+            while (*l).token != Token::SemiColon {
+                get_and_expect_token(l, Token::ID)?;
+                let name = arena::strdup(&mut (*c).arena, (*l).string);
+                let index = allocate_auto_var(&mut (*c).auto_vars_ator);
+                declare_var(c, name, (*l).loc, Storage::Auto {index}, false)?;
+                get_and_expect_tokens(l, &[Token::SemiColon, Token::Comma, Token::IntLit, Token::CharLit])?;
+                if (*l).token == Token::IntLit || (*l).token == Token::CharLit {
+                    let size = (*l).int_number as usize;
+                    if size == 0 {
+                        missingf!((*l).loc, c!("It's unclear how to compile automatic vector of size 0\n"));
+                    }
+                    for _ in 0..size {
+                        allocate_auto_var(&mut (*c).auto_vars_ator);
+                    }
+                    // TODO: Here we assume the stack grows down. Should we
+                    //   instead find a way for the target to decide that?
+                    //   See TODO(2025-06-05 17:45:36)
+                    let arg = Arg::RefAutoVar(index + size);
+                    push_opcode(Op::AutoAssign {index, arg}, (*l).loc, c);
+                    get_and_expect_tokens(l, &[Token::SemiColon, Token::Comma])?;
+                }
+            }
+            compile_statement(l, c)
+        }
+        Token::If => {
+            get_and_expect_token_but_continue(l, c, Token::OParen)?;
+                let saved_auto_vars_count = (*c).auto_vars_ator.count;
+                   let cond = compile_condition(l, c)?;
+                   let else_label = allocate_label_index(c);
+                   push_opcode(Op::JmpIfNotLabel{label: else_label, arg: cond}, (*l).loc, c);
+                (*c).auto_vars_ator.count = saved_auto_vars_count;
+            get_and_expect_token_but_continue(l, c, Token::CParen)?;
+
+            compile_statement(l, c)?;
+
+            let saved_point = (*l).parse_point;
+            lexer::get_token(l)?;
+            if (*l).token == Token::Else {
+                let out_label = allocate_label_index(c);
+                push_opcode(Op::JmpLabel{label: out_label}, (*l).loc, c);
+                push_opcode(Op::Label{label: else_label}, (*l).loc, c);
+                    compile_statement(l, c)?;
+                push_opcode(Op::Label{label: out_label}, (*l).loc, c);
+            } else {
+                (*l).parse_point = saved_point;
+                push_opcode(Op::Label{label: else_label}, (*l).loc, c);
+            }
+
+            Some(())
+        }
+        Token::While => {
+            let cond_label = allocate_label_index(c);
+            push_opcode(Op::Label {label: cond_label}, (*l).loc, c);
+
+            get_and_expect_token_but_continue(l, c, Token::OParen)?;
+                let saved_auto_vars_count = (*c).auto_vars_ator.count;
+                    let arg = compile_condition(l, c)?;
+                (*c).auto_vars_ator.count = saved_auto_vars_count;
+            get_and_expect_token_but_continue(l, c, Token::CParen)?;
+
+            let out_label = allocate_label_index(c);
+            push_opcode(Op::JmpIfNotLabel{label: out_label, arg}, (*l).loc, c);
+
+                compile_statement(l, c)?;
+
+            push_opcode(Op::JmpLabel{label: cond_label}, (*l).loc, c);
+            push_opcode(Op::Label {label: out_label}, (*l).loc, c);
+            Some(())
+        }
+        Token::Return => {
+            get_and_expect_tokens(l, &[Token::SemiColon, Token::OParen])?;
+            if (*l).token == Token::SemiColon {
+                push_opcode(Op::Return {arg: None}, (*l).loc, c);
+            } else if (*l).token == Token::OParen {
+                let (arg, _) = compile_expression(l, c)?;
+                get_and_expect_token_but_continue(l, c, Token::CParen)?;
+                get_and_expect_token_but_continue(l, c, Token::SemiColon)?;
+                push_opcode(Op::Return {arg: Some(arg)}, (*l).loc, c);
+            } else {
+                unreachable!();
+            }
+            Some(())
+        }
+        Token::Goto => {
+            get_and_expect_token(l, Token::ID)?;
+            let name = arena::strdup(&mut (*c).arena, (*l).string);
+            let loc = (*l).loc;
+            let addr = (*c).func_body.count;
+            da_append(&mut (*c).func_gotos, Goto {name, loc, addr});
+            get_and_expect_token_but_continue(l, c, Token::SemiColon)?;
+            push_opcode(Op::Bogus, (*l).loc, c);
+            Some(())
+        }
+        Token::IfTarget => {
+            if compile_target_condition(l, c)? {
+                compile_target_block(l, c)?;
+                if get_else_target(l)? {
+                    skip_target_block(l)?;
+                }
+            } else {
+                skip_target_block(l)?;
+                if get_else_target(l)? {
+                    compile_target_block(l, c)?;
+                }
+            }
+            Some(())
+        }
+        Token::ElseTarget => {
+            diagf!((*l).loc, c!("ERROR: `__else__` without `__if_target__`\n"));
+            bump_error_count(c)
+        }
+        Token::Asm => {
+            let loc = (*l).loc;
+            let mut stmts: Array<AsmStmt> = zeroed();
+            compile_asm_stmts(l, c, &mut stmts)?;
+            push_opcode(Op::Asm {stmts}, loc, c);
+            Some(())
+        }
+        Token::Case => {
+            let case_loc = (*l).loc;
+            lexer::get_token(l);
+            expect_tokens(l, &[Token::IntLit, Token::CharLit])?; // TODO: String ??!
+            let case_value = (*l).int_number;
+            get_and_expect_token_but_continue(l, c, Token::Colon)?;
+
+            if let Some(switch_frame) = da_last_mut(&mut (*c).switch_stack) {
+                let fallthrough_label = allocate_label_index(c);
+                push_opcode(Op::JmpLabel{label: fallthrough_label}, case_loc, c);
+
+                push_opcode(Op::Label{
+                    label: (*switch_frame).label
+                }, case_loc, c);
+
+                push_opcode(Op::Binop{
+                    binop: Binop::Equal,
+                    index: (*switch_frame).cond,
+                    lhs: (*switch_frame).value,
+                    rhs: Arg::Literal(case_value)
+                }, case_loc, c);
+
+                let next_case_label = allocate_label_index(c);
+                push_opcode(Op::JmpIfNotLabel {
+                    label: next_case_label,
+                    arg: Arg::AutoVar((*switch_frame).cond)
+                }, case_loc, c);
+                (*switch_frame).label = next_case_label;
+
+                push_opcode(Op::Label{label: fallthrough_label}, case_loc, c);
+
+                Some(())
+            } else {
+                diagf!(case_loc, c!("ERROR: case label outside of switch\n"));
+                bump_error_count(c)
+            }
+        }
+        Token::Switch => {
+            let saved_auto_vars_count = (*c).auto_vars_ator.count;
+
+            let switch_loc = (*l).loc;
+            let (value, _) = compile_expression(l, c)?;
+            let cond = allocate_auto_var(&mut (*c).auto_vars_ator);
+            let label = allocate_label_index(c);
+            da_append(&mut (*c).switch_stack, Switch {label, value, cond});
+            push_opcode(Op::JmpLabel {label}, switch_loc, c);
+
+            compile_statement(l, c)?;
+
+            let switch_frame = da_last_mut(&mut (*c).switch_stack).expect("Switch stack was modified by somebody else");
+            push_opcode(Op::Label{label: (*switch_frame).label}, (*l).loc, c);
+            (*c).switch_stack.count -= 1;
+
+            (*c).auto_vars_ator.count = saved_auto_vars_count;
+
+            Some(())
+        }
+        _ => {
+            if (*l).token == Token::ID {
+                let name = arena::strdup(&mut (*c).arena, (*l).string);
+                let name_loc = (*l).loc;
+                lexer::get_token(l)?;
+                if (*l).token == Token::Colon {
+                    let label = allocate_label_index(c);
+                    push_opcode(Op::Label{label}, name_loc, c);
+                    define_goto_label(c, name, name_loc, label)?;
+                    return Some(());
+                }
+            }
+            (*l).parse_point = saved_point;
+            let saved_auto_vars_count = (*c).auto_vars_ator.count;
+            compile_expression(l, c)?;
+            (*c).auto_vars_ator.count = saved_auto_vars_count;
+            get_and_expect_token_but_continue(l, c, Token::SemiColon)?;
+            Some(())
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Switch {
+    pub label: usize,
+    pub value: Arg,
+    pub cond: usize,
+}
+
+#[derive(Clone, Copy)]
+pub struct Compiler {
+    pub program: Program,
+    pub vars: Array<Array<Var>>,
+    pub auto_vars_ator: AutoVarsAtor,
+    pub func_body: Array<OpWithLocation>,
+    pub func_goto_labels: Array<GotoLabel>,
+    pub func_gotos: Array<Goto>,
+    pub func_scope_events: Array<ScopeEvent>,
+    pub func_blocks_count: usize,
+    pub used_funcs: Array<UsedFunc>,
+    pub op_label_count: usize,
+    pub switch_stack: Array<Switch>,
+    /// The lowest index of the auto var whose address was taken with `&` in the current function.
+    /// The parameters after it may be accessed through that address (see printf() in libb/).
+    pub func_lowest_ref_auto: Option<usize>,
+    /// Arena into which the Compiler allocates all the names and
+    /// objects that need to live for the duration of the
+    /// compilation. Even if some object/names don't need to live that
+    /// long (for example, function labels need to live only for the
+    /// duration of that function compilation), just letting them live
+    /// longer makes the memory management easier.
+    ///
+    /// Basically just dump everything into this arena and if you ever
+    /// need to reset the state of the Compiler, just reset all its
+    /// Dynamic Arrays and this Arena.
+    pub arena: Arena,
+    pub error_count: usize,
+    pub historical: bool,
+    pub warnings: [WarningState; WARNING_COUNT],
+    /// The warnings are not reported while libb is compiled. The user can't do anything about them anyway.
+    pub compiling_libb: bool,
+    /// Directories `__include__` looks through when the file is not found next to the including one.
+    /// Usually the libb search path (see libb_search_path()).
+    pub include_path: Array<*const c_char>,
+    /// Normalized paths of all the files compiled so far. Every file is compiled at most once.
+    pub compiled_files: Array<*const c_char>,
+    /// The files that are being compiled at the moment. The innermost one is the last.
+    pub include_stack: Array<Include>,
+    /// Name of the target the program is compiled for. `__if_target__` is matched against it.
+    pub target: *const c_char,
+}
+
+#[derive(Clone, Copy)]
+pub struct Include {
+    /// Normalized path of the file
+    pub path: *const c_char,
+    /// The `__include__` of the file that is being processed at the moment
+    pub loc: Loc,
+}
+
+#[derive(Clone, Copy)]
+pub struct UsedFunc {
+    name: *const c_char,
+    loc: Loc,
+}
+
+pub const MAX_ERROR_COUNT: usize = 100;
+/// The point of this function is to indicate that a compilation error happened, but continue the compilation anyway
+/// even if the state of the Compiler became bogus. This is needed to report as many compilation errors as possible.
+/// After calling this function always continue the compilation like nothing happened.
+pub unsafe fn bump_error_count(c: *mut Compiler) -> Option<()> {
+    (*c).error_count += 1;
+    if (*c).error_count >= MAX_ERROR_COUNT {
+        fprintf(stderr(), c!("TOO MANY ERRORS! Fix your program!\n"));
+        return None
+    }
+    Some(())
+}
+
+enum_with_order! {
+    #[derive(Clone, Copy)]
+    enum Warning in WARNING_ORDER {
+        UnusedVariable,
+        UnusedLabel,
+        UnusedExtrn,
+        UnreachableCode,
+        AssignInCondition,
+        Shadow,
+    }
+}
+
+pub const WARNING_COUNT: usize = WARNING_ORDER.len();
+
+impl Warning {
+    pub unsafe fn name(self) -> *const c_char {
+        match self {
+            Self::UnusedVariable    => c!("unused-variable"),
+            Self::UnusedLabel       => c!("unused-label"),
+            Self::UnusedExtrn       => c!("unused-extrn"),
+            Self::UnreachableCode   => c!("unreachable-code"),
+            Self::AssignInCondition => c!("assign-in-condition"),
+            Self::Shadow            => c!("shadow"),
+        }
+    }
+
+    pub unsafe fn description(self) -> *const c_char {
+        match self {
+            Self::UnusedVariable    => c!("`auto` variables and function parameters that are never used"),
+            Self::UnusedLabel       => c!("labels that are never jumped to with `goto`"),
+            Self::UnusedExtrn       => c!("`extrn` names that are declared but never used"),
+            Self::UnreachableCode   => c!("statements right after `return` that are never executed"),
+            Self::AssignInCondition => c!("assignment used as the condition of `if` or `while`"),
+            Self::Shadow            => c!("declarations that shadow a name from an outer scope"),
+        }
+    }
+
+    pub unsafe fn enabled_by_default(self) -> bool {
+        match self {
+            // `while (c = char(s, i))` is an idiom of B rather than a typo
+            Self::AssignInCondition | Self::Shadow => false,
+            _ => true,
+        }
+    }
+
+    pub unsafe fn from_name(name: *const c_char) -> Option<Self> {
+        for i in 0..WARNING_ORDER.len() {
+            let warning = (*WARNING_ORDER)[i];
+            if strcmp(warning.name(), name) == 0 {
+                return Some(warning)
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct WarningState {
+    pub enabled: bool,
+    /// Report the warning as an error
+    pub error: bool,
+}
+
+pub unsafe fn print_available_warnings() {
+    fprintf(stderr(), c!("Warnings:\n"));
+    for i in 0..WARNING_ORDER.len() {
+        let warning = (*WARNING_ORDER)[i];
+        fprintf(stderr(), c!("    %-20s - %s%s\n"), warning.name(), warning.description(), if warning.enabled_by_default() { c!("") } else { c!(" (disabled by default)") });
+    }
+}
+
+/// Applies a single value of the -W flag to the warnings configuration of the Compiler:
+/// `<name>`, `no-<name>`, `all`, `no-all`, `error`, `no-error`, `error=<name>` or `no-error=<name>`.
+pub unsafe fn configure_warning(c: *mut Compiler, mut arg: *const c_char) -> bool {
+    let mut enable = true;
+    if strncmp(arg, c!("no-"), 3) == 0 {
+        enable = false;
+        arg = arg.add(3);
+    }
+
+    if strcmp(arg, c!("all")) == 0 {
+        for i in 0..WARNING_COUNT {
+            (*c).warnings[i].enabled = enable;
+        }
+        return true;
+    }
+
+    if strcmp(arg, c!("error")) == 0 {
+        for i in 0..WARNING_COUNT {
+            (*c).warnings[i].error = enable;
+        }
+        return true;
+    }
+
+    if strncmp(arg, c!("error="), 6) == 0 {
+        let Some(warning) = Warning::from_name(arg.add(6)) else { return false };
+        (*c).warnings[warning as usize].error = enable;
+        // `-W error=<name>` also enables the warning like in GCC, but `-W no-error=<name>` leaves it as is
+        if enable {
+            (*c).warnings[warning as usize].enabled = true;
+        }
+        return true;
+    }
+
+    let Some(warning) = Warning::from_name(arg) else { return false };
+    (*c).warnings[warning as usize].enabled = enable;
+    true
+}
+
+/// Reports the warning if it's enabled and the code is not from libb. Returns whether it was reported. Warnings promoted to errors
+/// with `-W error` bump the error count, so the compilation fails at the end like with any other error.
+pub unsafe fn report_warning(c: *mut Compiler, warning: Warning, loc: Loc, message: *const c_char) -> Option<bool> {
+    let state = (*c).warnings[warning as usize];
+    if !state.enabled || (*c).compiling_libb {
+        return Some(false);
+    }
+    if state.error {
+        diagf!(loc, c!("ERROR: %s [-W error=%s]\n"), message, warning.name());
+        bump_error_count(c)?;
+    } else {
+        diagf!(loc, c!("WARNING: %s [-W %s]\n"), message, warning.name());
+    }
+    Some(true)
+}
+
+/// Prepares the Compiler for compiling the next function. The body of the previous function is expected
+/// to be moved somewhere else (like Program::funcs) by this point, or just thrown away.
+pub unsafe fn reset_func_state(c: *mut Compiler) {
+    (*c).func_body = zeroed();
+    (*c).func_goto_labels.count = 0;
+    (*c).func_gotos.count = 0;
+    (*c).func_scope_events = zeroed();
+    (*c).func_blocks_count = 0;
+    (*c).auto_vars_ator = zeroed();
+    (*c).op_label_count = 0;
+    (*c).switch_stack.count = 0;
+    (*c).func_lowest_ref_auto = None;
+}
+
+pub unsafe fn set_default_warnings(c: *mut Compiler) {
+    for i in 0..WARNING_COUNT {
+        (*c).warnings[i].enabled = (*WARNING_ORDER)[i].enabled_by_default();
+    }
+}
+
+/// Checks that all the names that were used before their declaration got eventually declared
+/// somewhere in the global scope. Must be called after all the files are compiled with
+/// compile_program() while the global scope is still there.
+pub unsafe fn resolve_used_funcs(c: *mut Compiler) -> Option<()> {
+    for i in 0..(*c).used_funcs.count {
+        let used_global = *(*c).used_funcs.items.add(i);
+
+        let var = find_var_deep(&mut (*c).vars, used_global.name) as *mut Var;
+        if var.is_null() {
+            diagf!(used_global.loc, c!("ERROR: could not find name `%s`\n"), used_global.name);
+            bump_error_count(c)?;
+        } else {
+            (*var).used = true;
+        }
+    }
+    Some(())
+}
+
+pub unsafe fn is_path_separator(x: c_char) -> bool {
+    x == '/' as c_char || (cfg!(target_os = "windows") && x == '\\' as c_char)
+}
+
+pub unsafe fn is_absolute_path(path: *const c_char) -> bool {
+    is_path_separator(*path) || (cfg!(target_os = "windows") && *path != 0 && *path.add(1) == ':' as c_char)
+}
+
+/// Drops the `.` components of the path and resolves the `..` ones without looking at the file
+/// system, so the same file is recognized when it's reached via different paths.
+pub unsafe fn normalize_path(path: *const c_char, a: *mut Arena) -> *const c_char {
+    let mut components: Array<(*const c_char, usize)> = zeroed();
+    let absolute = is_path_separator(*path);
+    let mut start = path;
+    while *start != 0 {
+        let mut end = start;
+        while *end != 0 && !is_path_separator(*end) {
+            end = end.add(1);
+        }
+        let component = (start, end.offset_from(start) as usize);
+        if component.1 == 0 || (component.1 == 1 && *start == '.' as c_char) {
+            // Empty components and `.` don't change the path
+        } else if component.1 == 2 && strncmp(start, c!(".."), 2) == 0 {
+            match da_last(&components) {
+                Some(last) if !((*last).1 == 2 && strncmp((*last).0, c!(".."), 2) == 0) => components.count -= 1,
+                // `..` of the root is the root itself
+                _ => if !absolute { da_append(&mut components, component) },
+            }
+        } else {
+            da_append(&mut components, component);
+        }
+        start = if *end == 0 { end } else { end.add(1) };
+    }
+
+    let mut sb: String_Builder = zeroed();
+    if absolute {
+        da_append(&mut sb, '/' as c_char);
+    }
+    for i in 0..components.count {
+        let (start, count) = *components.items.add(i);
+        if i > 0 {
+            da_append(&mut sb, '/' as c_char);
+        }
+        da_append_many(&mut sb, slice::from_raw_parts(start, count));
+    }
+    if sb.count == 0 {
+        da_append(&mut sb, '.' as c_char);
+    }
+    da_append(&mut sb, 0);
+    let result = arena::strdup(a, sb.items);
+    free(sb.items as *mut c_void);
+    free(components.items as *mut c_void);
+    result
+}
+
+pub unsafe fn file_was_compiled(c: *const Compiler, path: *const c_char) -> bool {
+    for i in 0..(*c).compiled_files.count {
+        if strcmp(*(*c).compiled_files.items.add(i), path) == 0 {
+            return true;
+        }
+    }
+    false
+}
+
+/// Looks for the file of `__include__` first in the directory of the including file and then in the
+/// directories of the include path. Returns the normalized path of the file.
+pub unsafe fn resolve_include(c: *mut Compiler, including_path: *const c_char, path: *const c_char) -> Option<*const c_char> {
+    if is_absolute_path(path) {
+        if file_exists(path) == Some(true) {
+            return Some(normalize_path(path, &mut (*c).arena));
+        }
+        return None;
+    }
+
+    let mut dir_end = including_path;
+    let mut p = including_path;
+    while *p != 0 {
+        if is_path_separator(*p) {
+            dir_end = p;
+        }
+        p = p.add(1);
+    }
+    let candidate = if dir_end == including_path && !is_path_separator(*including_path) {
+        path
+    } else {
+        arena::sprintf(&mut (*c).arena, c!("%.*s/%s"), dir_end.offset_from(including_path) as c_int, including_path, path) as *const c_char
+    };
+    if file_exists(candidate) == Some(true) {
+        return Some(normalize_path(candidate, &mut (*c).arena));
+    }
+
+    for i in 0..(*c).include_path.count {
+        let candidate = arena::sprintf(&mut (*c).arena, c!("%s/%s"), *(*c).include_path.items.add(i), path);
+        if file_exists(candidate) == Some(true) {
+            return Some(normalize_path(candidate, &mut (*c).arena));
+        }
+    }
+    None
+}
+
+/// Compiles the file of `__include__` into the same Compiler unless it was already compiled
+pub unsafe fn compile_include(c: *mut Compiler, path: *const c_char, loc: Loc, historical: bool) -> Option<()> {
+    for i in 0..(*c).include_stack.count {
+        let include = *(*c).include_stack.items.add(i);
+        if strcmp(include.path, path) == 0 {
+            diagf!(loc, c!("ERROR: include cycle: `%s` is already being compiled\n"), path);
+            for j in i..(*c).include_stack.count - 1 {
+                let include = *(*c).include_stack.items.add(j);
+                let next = *(*c).include_stack.items.add(j + 1);
+                diagf!(include.loc, c!("NOTE: `%s` includes `%s` here\n"), include.path, next.path);
+            }
+            return bump_error_count(c);
+        }
+    }
+    if file_was_compiled(c, path) {
+        return Some(());
+    }
+
+    // Not freed, diagnostics may refer to the file until the very end
+    let mut input: String_Builder = zeroed();
+    read_entire_file(path, &mut input)?;
+    lexer::diag_register_source(path, input.items, input.items.add(input.count));
+    let mut l = lexer::new(path, input.items, input.items.add(input.count), historical);
+    compile_program(&mut l, c)
+}
+
+pub unsafe fn compile_program(l: *mut Lexer, c: *mut Compiler) -> Option<()> {
+    let path = normalize_path((*l).input_path, &mut (*c).arena);
+    da_append(&mut (*c).compiled_files, path);
+    da_append(&mut (*c).include_stack, Include { path, loc: zeroed() });
+
+    // The blocks of `__if_target__` the definitions are inside of. Whether the block can be followed by `__else__`
+    // and where it starts.
+    let mut target_blocks: Array<(bool, Loc)> = zeroed();
+
+    'def: loop {
+        lexer::get_token(l)?;
+        match (*l).token {
+            Token::EOF => {
+                if let Some(block) = da_last(&target_blocks) {
+                    diagf!((*block).1, c!("ERROR: the block is never closed\n"));
+                    return None;
+                }
+                break 'def
+            }
+            Token::IfTarget => {
+                if compile_target_condition(l, c)? {
+                    get_and_expect_token(l, Token::OCurly)?;
+                    da_append(&mut target_blocks, (true, (*l).loc));
+                } else {
+                    skip_target_block(l)?;
+                    if get_else_target(l)? {
+                        get_and_expect_token(l, Token::OCurly)?;
+                        da_append(&mut target_blocks, (false, (*l).loc));
+                    }
+                }
+            }
+            Token::CCurly if target_blocks.count > 0 => {
+                target_blocks.count -= 1;
+                let (can_have_else, _) = *target_blocks.items.add(target_blocks.count);
+                if can_have_else && get_else_target(l)? {
+                    skip_target_block(l)?;
+                }
+            }
+            Token::ElseTarget => {
+                diagf!((*l).loc, c!("ERROR: `__else__` without `__if_target__`\n"));
+                bump_error_count(c)?;
+            }
+            Token::Include => {
+                let include_loc = (*l).loc;
+                get_and_expect_token_but_continue(l, c, Token::String)?;
+                let include_path = arena::strdup(&mut (*c).arena, (*l).string);
+                get_and_expect_token_but_continue(l, c, Token::SemiColon)?;
+                if let Some(resolved) = resolve_include(c, (*l).input_path, include_path) {
+                    (*da_last_mut(&mut (*c).include_stack).expect("The current file is always on the stack")).loc = include_loc;
+                    compile_include(c, resolved, include_loc, (*l).historical)?;
+                } else {
+                    diagf!(include_loc, c!("ERROR: could not find included file `%s` next to this file or in the libb search path\n"), include_path);
+                    bump_error_count(c)?;
+                }
+            }
+            Token::Variadic => {
+                get_and_expect_token_but_continue(l, c, Token::OParen)?;
+                get_and_expect_token_but_continue(l, c, Token::ID)?;
+                let func = arena::strdup(&mut (*c).arena, (*l).string);
+                let func_loc = (*l).loc;
+                if let Some(existing_variadic) = assoc_lookup_cstr(da_slice((*c).program.variadics), func) {
+                    // TODO: report all the duplicate variadics maybe?
+                    diagf!(func_loc, c!("ERROR: duplicate variadic declaration `%s`\n"), func);
+                    diagf!((*existing_variadic).loc, c!("NOTE: the first declaration is located here\n"));
+                    bump_error_count(c)?;
+                }
+                get_and_expect_token_but_continue(l, c, Token::Comma)?;
+                get_and_expect_token_but_continue(l, c, Token::IntLit)?;
+                if (*l).int_number == 0 {
+                    diagf!((*l).loc, c!("ERROR: variadic function `%s` cannot have 0 arguments\n"), func);
+                    bump_error_count(c)?;
+                }
+                da_append(&mut (*c).program.variadics, (func, Variadic {
+                    loc: func_loc,
+                    fixed_args: (*l).int_number as usize,
+                }));
+                get_and_expect_token_but_continue(l, c, Token::CParen)?;
+                get_and_expect_token_but_continue(l, c, Token::SemiColon)?;
+            }
+            Token::Extrn => {
+                while (*l).token != Token::SemiColon {
+                    get_and_expect_token(l, Token::ID)?;
+                    let name = arena::strdup(&mut (*c).arena, (*l).string);
+                    name_declare_if_not_exists(&mut (*c).program.extrns, name);
+                    declare_var(c, name, (*l).loc, Storage::External {name}, true)?;
+                    get_and_expect_tokens(l, &[Token::SemiColon, Token::Comma])?;
+                }
+            }
+            _ => {
+                expect_token(l, Token::ID)?;
+                let name = arena::strdup(&mut (*c).arena, (*l).string);
+                let name_loc = (*l).loc;
+                declare_var(c, name, name_loc, Storage::External{name}, false)?;
+
+                let saved_point = (*l).parse_point;
+                lexer::get_token(l)?;
+
+                match (*l).token {
+                    Token::OParen => { // Function definition
+                        scope_push(&mut (*c).vars); // begin function scope
+                        let mut params_count = 0;
+                        let saved_point = (*l).parse_point;
+                        lexer::get_token(l)?;
+                        if (*l).token != Token::CParen {
+                            (*l).parse_point = saved_point;
+                            'params: loop {
+                                get_and_expect_token(l, Token::ID)?;
+                                let name = arena::strdup(&mut (*c).arena, (*l).string);
+                                let name_loc = (*l).loc;
+                                let index = allocate_auto_var(&mut (*c).auto_vars_ator);
+                                declare_var(c, name, name_loc, Storage::Auto{index}, false)?;
+                                params_count += 1;
+                                get_and_expect_tokens(l, &[Token::CParen, Token::Comma])?;
+                                match (*l).token {
+                                    Token::CParen => break 'params,
+                                    Token::Comma => continue 'params,
+                                    _ => unreachable!(),
+                                }
+                            }
+                        }
+                        compile_statement(l, c)?;
+                        check_unused_vars(c, params_count)?;
+                        scope_pop(&mut (*c).vars); // end function scope
+
+                        for i in 0..(*c).func_gotos.count {
+                            let used_label = *(*c).func_gotos.items.add(i);
+                            let existing_label = find_goto_label(&(*c).func_goto_labels, used_label.name) as *mut GotoLabel;
+                            if existing_label.is_null() {
+                                diagf!(used_label.loc, c!("ERROR: label `%s` used but not defined\n"), used_label.name);
+                                bump_error_count(c)?;
+                                continue;
+                            }
+                            (*existing_label).used = true;
+                            (*(*c).func_body.items.add(used_label.addr)).opcode = Op::JmpLabel {label: (*existing_label).label};
+                        }
+
+                        for i in 0..(*c).func_goto_labels.count {
+                            let label = *(*c).func_goto_labels.items.add(i);
+                            if !label.used {
+                                report_warning(c, Warning::UnusedLabel, label.loc, temp_sprintf(c!("label `%s` defined but not used"), label.name))?;
+                            }
+                        }
+
+                        da_append(&mut (*c).program.funcs, Func {
+                            name,
+                            name_loc,
+                            body: (*c).func_body,
+                            scope_events: (*c).func_scope_events,
+                            params_count,
+                            auto_vars_count: (*c).auto_vars_ator.max,
+                        });
+                        reset_func_state(c);
+                    }
+                    Token::Asm => { // Assembly function definition
+                        let mut body: Array<AsmStmt> = zeroed();
+                        compile_asm_stmts(l, c, &mut body)?;
+                        da_append(&mut (*c).program.asm_funcs, AsmFunc {name, name_loc, body});
+                    }
+                    _ => { // Variable definition
+                        (*l).parse_point = saved_point;
+
+                        let mut global = Global {
+                            name,
+                            name_loc,
+                            values: zeroed(),
+                            is_vec: false,
+                            minimum_size: 0,
+                        };
+
+                        // TODO: This code is ugly
+                        // couldn't find a better way to write it while keeping accurate error messages
+                        get_and_expect_tokens(l, &[Token::Minus, Token::IntLit, Token::CharLit, Token::String, Token::ID, Token::SemiColon, Token::OBracket])?;
+
+                        if (*l).token == Token::OBracket {
+                            global.is_vec = true;
+                            get_and_expect_tokens(l, &[Token::IntLit, Token::CBracket])?;
+                            if (*l).token == Token::IntLit {
+                                global.minimum_size = (*l).int_number as usize;
+                                get_and_expect_token_but_continue(l, c, Token::CBracket)?;
+                            }
+                            get_and_expect_tokens(l, &[Token::Minus, Token::IntLit, Token::CharLit, Token::String, Token::ID, Token::SemiColon])?;
+                        }
+
+                        while (*l).token != Token::SemiColon {
+                            let value = match (*l).token {
+                                Token::Minus => {
+                                    get_and_expect_token(l, Token::IntLit)?;
+                                    ImmediateValue::Literal(!(*l).int_number + 1)
+                                }
+                                Token::IntLit | Token::CharLit => ImmediateValue::Literal((*l).int_number),
+                                Token::String => ImmediateValue::DataOffset(compile_string((*l).string, c)),
+                                Token::ID => {
+                                    let name = arena::strdup(&mut (*c).arena, (*l).string);
+                                    let scope = da_last_mut(&mut (*c).vars).expect("There should be always at least the global scope");
+                                    let var = find_var_near(scope, name) as *mut Var;
+                                    if var.is_null() {
+                                        diagf!((*l).loc, c!("ERROR: could not find name `%s`\n"), name);
+                                        bump_error_count(c)?;
+                                    } else {
+                                        (*var).used = true;
+                                    }
+                                    ImmediateValue::Name(name)
+                                }
+                                _ => unreachable!()
+                            };
+                            da_append(&mut global.values, value);
+
+                            get_and_expect_tokens(l, &[Token::SemiColon, Token::Comma])?;
+                            if (*l).token == Token::Comma {
+                                get_and_expect_tokens(l, &[Token::Minus, Token::IntLit, Token::CharLit, Token::String, Token::ID])?;
+                            } else {
+                                break;
+                            }
+                        }
+
+                        if !global.is_vec && global.values.count == 0 {
+                            da_append(&mut global.values, ImmediateValue::Literal(0));
+                        }
+                        da_append(&mut (*c).program.globals, global)
+                    }
+                }
+            }
+        }
+    }
+
+    free(target_blocks.items);
+    (*c).include_stack.count -= 1;
+    Some(())
+}
+
+/// Where libb is installed by `make install`. See PREFIX in the Makefile.
+pub const INSTALL_PREFIX: &str = match option_env!("B_PREFIX") {
+    Some(prefix) => prefix,
+    None => "/usr/local",
+};
+
+/// The environment variable with the additional directories to look for libb in
+pub const LIBB_PATH_ENV: *const c_char = c!("B_LIBB_PATH");
+pub const LIBB_PATH_SEPARATOR: c_char = if cfg!(target_os = "windows") { ';' } else { ':' } as c_char;
+
+/// The directories libb is looked for in, in the order of priority:
+/// 1. The directories provided by the user via the command line (`user_dirs`)
+/// 2. The directories from the B_LIBB_PATH environment variable
+/// 3. `libb/` in the current working directory
+/// 4. `libb/` next to the executable
+/// 5. `share/b/libb/` of the install prefix
+pub unsafe fn libb_search_path(user_dirs: *const [*const c_char], executable_directory: *const c_char, a: *mut Arena) -> Array<*const c_char> {
+    let mut dirs: Array<*const c_char> = zeroed();
+    da_append_many(&mut dirs, user_dirs);
+
+    let env = getenv(LIBB_PATH_ENV);
+    if !env.is_null() {
+        let mut start: *const c_char = env;
+        loop {
+            let mut end = start;
+            while *end != 0 && *end != LIBB_PATH_SEPARATOR {
+                end = end.add(1);
+            }
+            if end > start {
+                da_append(&mut dirs, arena::sprintf(a, c!("%.*s"), end.offset_from(start) as c_int, start) as *const c_char);
+            }
+            if *end == 0 { break; }
+            start = end.add(1);
+        }
+    }
+
+    da_append(&mut dirs, c!("./libb"));
+    da_append(&mut dirs, arena::sprintf(a, c!("%s/libb"), executable_directory) as *const c_char);
+    da_append(&mut dirs, arena::sprintf(a, c!("%.*s/share/b/libb"), INSTALL_PREFIX.len() as c_int, INSTALL_PREFIX.as_ptr()) as *const c_char);
+    dirs
+}
+
+/// Adds the libb files of the target to the inputs. They are taken from the first directory of the
+/// search path (see libb_search_path()) that has `<target>.b`, `all.b` is taken from the same directory.
+pub unsafe fn add_libb_files(search_path: *const [*const c_char], target: *const c_char, inputs: &mut Array<*const c_char>, a: *mut Arena) -> Option<()> {
+    for i in 0..search_path.len() {
+        let dir = (*search_path)[i];
+        let target_path = arena::sprintf(a, c!("%s/%s.b"), dir, target);
+        if file_exists(target_path)? {
+            log(Log_Level::INFO, c!("using libb of target %s: %s"), target, target_path);
+            include_path_if_exists(inputs, arena::sprintf(a, c!("%s/all.b"), dir));
+            da_append(inputs, target_path as *const c_char);
+            return Some(());
+        }
+    }
+
+    log(Log_Level::ERROR, c!("could not find libb of target %s. Looked for %s.b in:"), target, target);
+    for i in 0..search_path.len() {
+        log(Log_Level::ERROR, c!("    %s"), (*search_path)[i]);
+    }
+    log(Log_Level::ERROR, c!("Provide the directory with libb via -libb-dir or %s, or compile without libb with -nostdlib"), LIBB_PATH_ENV);
+    None
+}
+
+pub unsafe fn include_path_if_exists(input_paths: &mut Array<*const c_char>, path: *const c_char) -> Option<()> {
+    if file_exists(path)? {
+        da_append(input_paths, path);
+    }
+    Some(())
+}
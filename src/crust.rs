@@ -91,16 +91,25 @@ pub mod libc {
         pub fn stdout() -> *mut FILE;
         #[link_name = "get_stderr"]
         pub fn stderr() -> *mut FILE;
+        #[link_name = "get_cpu_count"]
+        pub fn cpu_count() -> c_int;
         pub fn fopen(pathname: *const c_char, mode: *const c_char) -> *mut FILE;
         pub fn fclose(stream: *mut FILE) -> c_int;
         pub fn strcmp(s1: *const c_char, s2: *const c_char) -> c_int;
+        pub fn strncmp(s1: *const c_char, s2: *const c_char, n: usize) -> c_int;
         pub fn strchr(s: *const c_char, c: c_int) -> *const c_char;
         pub fn strrchr(s: *const c_char, c: c_int) -> *const c_char;
+        pub fn strstr(haystack: *const c_char, needle: *const c_char) -> *const c_char;
         pub fn strlen(s: *const c_char) -> usize;
         pub fn strtoull(nptr: *const c_char, endptr: *mut*mut c_char, base: c_int) -> c_ulonglong;
         pub fn fwrite(ptr: *const c_void, size: usize, nmemb: usize, stream: *mut FILE) -> usize;
+        pub fn fflush(stream: *mut FILE) -> c_int;
 
         pub fn abort() -> !;
+        pub fn exit(status: c_int) -> !;
+        pub fn malloc(size: usize) -> *mut c_void;
+        pub fn putchar(c: c_int) -> c_int;
+        pub fn getchar() -> c_int;
         pub fn strdup(s: *const c_char) -> *mut c_char;
         pub fn strncpy(dst: *mut c_char, src: *const c_char, dsize: usize) -> *mut c_char;
         pub fn printf(fmt: *const c_char, ...) -> c_int;
@@ -115,6 +124,10 @@ pub mod libc {
         pub fn toupper(c: c_int) -> c_int;
         pub fn qsort(base: *mut c_void, nmemb: usize, size: usize, compar: unsafe extern "C" fn(*const c_void, *const c_void) -> c_int);
         pub fn dirname(path: *const c_char) -> *const c_char;
+        pub fn chmod(pathname: *const c_char, mode: c_uint) -> c_int;
+        pub fn isatty(fd: c_int) -> c_int;
+        pub fn getcwd(buf: *mut c_char, size: usize) -> *mut c_char;
+        pub fn getenv(name: *const c_char) -> *mut c_char;
     }
 
     // count is the amount of items, not bytes
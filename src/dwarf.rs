@@ -0,0 +1,190 @@
+// DWARF 5 debug information for the gas-* codegens (enabled with the -g flag). The sections are
+// emitted as GNU assembler directives, so only the symbol naming and the layout of the stack frame
+// are specific to the codegen. See Dwarf_Target.
+//
+// https://dwarfstd.org/doc/DWARF5.pdf
+
+use core::ffi::*;
+use crate::nob::*;
+use crate::ir::*;
+
+// arbitrary constants
+pub const TEMPLATE_compilation_unit : u64 = 1;
+pub const TEMPLATE_function         : u64 = 2;
+pub const TEMPLATE_variable         : u64 = 3;
+pub const TEMPLATE_block            : u64 = 4;
+pub const TEMPLATE_type             : u64 = 5;
+
+// other constants
+pub const version                   : u64 = 5;
+pub const addr_size                 : u64 = 8;
+pub const default_type_size         : u64 = 8;
+
+// taken from dwarf.h, DW_ prefix stripped
+pub const CHILDREN_no               : u64 = 0;
+pub const CHILDREN_yes              : u64 = 1;
+
+pub const AT_location               : u64 = 0x02;
+pub const AT_name                   : u64 = 0x03;
+pub const AT_byte_size              : u64 = 0x0b;
+pub const AT_stmt_list              : u64 = 0x10;
+pub const AT_low_pc                 : u64 = 0x11;
+pub const AT_high_pc                : u64 = 0x12;
+pub const AT_encoding               : u64 = 0x3e;
+pub const AT_frame_base             : u64 = 0x40;
+pub const AT_type                   : u64 = 0x49;
+
+pub const DW_ATE_signed             : u64 = 0x05;
+
+pub const FORM_addr                 : u64 = 0x01;
+pub const FORM_string               : u64 = 0x08;
+pub const FORM_data1                : u64 = 0x0b;
+pub const FORM_ref4                 : u64 = 0x13;
+pub const FORM_sec_offset           : u64 = 0x17;
+pub const FORM_exprloc              : u64 = 0x18;
+
+pub const OP_addr                   : u64 = 0x03;
+pub const OP_fbreg                  : u64 = 0x91;
+pub const OP_call_frame_cfa         : u64 = 0x9c;
+
+pub const UT_compile                : u64 = 0x01;
+pub const TAG_lexical_block         : u64 = 0x0b;
+pub const TAG_compile_unit          : u64 = 0x11;
+pub const TAG_base_type             : u64 = 0x24;
+pub const TAG_subprogram            : u64 = 0x2e;
+pub const TAG_variable              : u64 = 0x34;
+
+// What the debug information needs to know about the codegen. The codegen is expected to emit
+// `<local_prefix><func>_end` at the end of every function and `<local_prefix><func>_block_start_<index>`/
+// `<local_prefix><func>_block_end_<index>` around every block (see ScopeEvent).
+#[derive(Clone, Copy)]
+pub struct Dwarf_Target {
+    pub symbol_prefix: *const c_char, // Prefix of the functions and the globals, like `_` on Darwin
+    pub local_prefix: *const c_char,  // Prefix of the local labels, like `.L` on ELF
+    // Offset of the auto var from the Canonical Frame Address which is the frame base of the functions
+    pub auto_var_offset: unsafe fn(index: usize) -> i64,
+}
+
+pub unsafe fn generate_abbrev(output: *mut String_Builder, template: u64, tag: u64, children: u64, attributes: *const [(u64, u64)]) {
+    sb_appendf(output, c!(".uleb128 %lld\n"), template);
+        sb_appendf(output, c!(".uleb128 %lld\n"), tag);
+        sb_appendf(output, c!(".byte %lld\n"), children);
+        for i in 0..attributes.len() {
+            let (attribute, form) = (*attributes)[i];
+            sb_appendf(output, c!(".uleb128 %lld\n"), attribute);
+            sb_appendf(output, c!(".uleb128 %lld\n"), form);
+        }
+    sb_appendf(output, c!(".byte 0\n"));
+    sb_appendf(output, c!(".byte 0\n"));
+}
+
+pub unsafe fn generate_debuginfo(output: *mut String_Builder, funcs: Array<Func>, globals: Array<Global>, target: Dwarf_Target) {
+    sb_appendf(output, c!(".section .debug_abbrev\n"));
+        generate_abbrev(output, TEMPLATE_compilation_unit, TAG_compile_unit, CHILDREN_yes, &[
+            (AT_stmt_list,  FORM_sec_offset),
+        ]);
+        generate_abbrev(output, TEMPLATE_function, TAG_subprogram, CHILDREN_yes, &[
+            (AT_name,       FORM_string),
+            (AT_low_pc,     FORM_addr),
+            (AT_high_pc,    FORM_addr),
+            (AT_frame_base, FORM_exprloc),
+        ]);
+        generate_abbrev(output, TEMPLATE_variable, TAG_variable, CHILDREN_no, &[
+            (AT_name,       FORM_string),
+            (AT_type,       FORM_ref4),
+            (AT_location,   FORM_exprloc),
+        ]);
+        generate_abbrev(output, TEMPLATE_block, TAG_lexical_block, CHILDREN_yes, &[
+            (AT_low_pc,     FORM_addr),
+            (AT_high_pc,    FORM_addr),
+        ]);
+        generate_abbrev(output, TEMPLATE_type, TAG_base_type, CHILDREN_no, &[
+            (AT_byte_size,  FORM_data1),
+            (AT_encoding,   FORM_data1),
+            (AT_name,       FORM_string),
+        ]);
+    sb_appendf(output, c!(".byte 0\n"));
+
+    sb_appendf(output, c!(".section .debug_info\n"));
+        sb_appendf(output, c!(".long .debug_info_end - .debug_info_start\n"));
+        sb_appendf(output, c!(".debug_info_start:\n"));
+        sb_appendf(output, c!(".2byte %lld\n"), version);
+        sb_appendf(output, c!(".byte %lld\n"), UT_compile);
+        sb_appendf(output, c!(".byte %lld\n"), addr_size);
+        sb_appendf(output, c!(".long .debug_abbrev\n"));
+
+        sb_appendf(output, c!(".uleb128 %lld\n"), TEMPLATE_compilation_unit);
+            sb_appendf(output, c!(".long .debug_line\n"));
+            generate_funcs_debuginfo(output, funcs, target);
+            generate_globals_debuginfo(output, globals, target);
+
+            sb_appendf(output, c!("debug_info_word_type_offset = .-.debug_info\n"));
+            sb_appendf(output, c!(".uleb128 %lld\n"), TEMPLATE_type);
+            sb_appendf(output, c!(".byte %lld\n"), default_type_size);
+            sb_appendf(output, c!(".byte %lld\n"), DW_ATE_signed);
+            sb_appendf(output, c!(".string \"word\"\n"));
+        sb_appendf(output, c!(".byte 0\n"));
+    sb_appendf(output, c!(".debug_info_end:\n"));
+}
+
+pub unsafe fn generate_globals_debuginfo(output: *mut String_Builder, globals: Array<Global>, target: Dwarf_Target) {
+    for i in 0..globals.count {
+        let global = *globals.items.add(i);
+        sb_appendf(output, c!(".uleb128 %lld\n"), TEMPLATE_variable);
+        sb_appendf(output, c!(".string \"%s\"\n"), global.name);
+        sb_appendf(output, c!(".long debug_info_word_type_offset\n"));
+        sb_appendf(output, c!(".uleb128 0x9\n")); // .byte (1) + .quad (8) = 9
+        sb_appendf(output, c!(".byte %lld\n"), OP_addr);
+        sb_appendf(output, c!(".quad %s%s\n"), target.symbol_prefix, global.name);
+    }
+}
+
+pub unsafe fn sleb128_length(mut n: i64) -> u64 {
+    if n == 0 { return 1 }
+
+    let mut len = 0;
+    n <<= 1;
+    while n != 0 && n != -1 {
+        n >>= 7;
+        len += 1;
+    }
+    len
+}
+
+pub unsafe fn generate_funcs_debuginfo(output: *mut String_Builder, funcs: Array<Func>, target: Dwarf_Target) {
+    for i in 0..funcs.count {
+        let func = *funcs.items.add(i);
+
+        sb_appendf(output, c!(".uleb128 %lld\n"), TEMPLATE_function);
+        sb_appendf(output, c!(".string \"%s\"\n"), func.name);
+        sb_appendf(output, c!(".quad %s%s\n"), target.symbol_prefix, func.name);
+        sb_appendf(output, c!(".quad %s%s_end\n"), target.local_prefix, func.name);
+        sb_appendf(output, c!(".uleb128 0x1\n")); // .byte (1) = 1
+        sb_appendf(output, c!(".byte %lld\n"), OP_call_frame_cfa);
+
+        for j in 0..func.scope_events.count {
+            match *func.scope_events.items.add(j) {
+                ScopeEvent::Declare { name, index } => {
+                    sb_appendf(output, c!(".uleb128 %lld\n"), TEMPLATE_variable);
+                    sb_appendf(output, c!(".string \"%s\"\n"), name);
+                    sb_appendf(output, c!(".long debug_info_word_type_offset\n"));
+
+                    let offset = (target.auto_var_offset)(index);
+                    sb_appendf(output, c!(".uleb128 %lld\n"), sleb128_length(offset)+1);
+                    sb_appendf(output, c!(".byte %lld\n"), OP_fbreg);
+                    sb_appendf(output, c!(".sleb128 %lld\n"), offset);
+                }
+                ScopeEvent::BlockBegin { index } => {
+                    sb_appendf(output, c!(".uleb128 %lld\n"), TEMPLATE_block);
+                    sb_appendf(output, c!(".quad %s%s_block_start_%zu\n"), target.local_prefix, func.name, index);
+                    sb_appendf(output, c!(".quad %s%s_block_end_%zu\n"), target.local_prefix, func.name, index);
+                }
+                ScopeEvent::BlockEnd { .. } => {
+                    sb_appendf(output, c!(".byte 0\n"));
+                }
+            }
+        }
+
+        sb_appendf(output, c!(".byte 0\n"));
+    }
+}
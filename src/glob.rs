@@ -1,4 +1,5 @@
 use core::ffi::*;
+use crate::nob::*;
 
 #[repr(C)]
 pub enum Glob_Result {
@@ -12,3 +13,17 @@ pub enum Glob_Result {
 extern "C" {
     pub fn glob_utf8(pattern: *const c_char, text: *const c_char) -> Glob_Result;
 }
+
+/// Returns the description of the error if the pattern is malformed
+pub unsafe fn match_glob(pattern: *const c_char, text: *const c_char) -> Result<bool, *const c_char> {
+    let mark = temp_save();
+    let result = match glob_utf8(pattern, text) {
+        Glob_Result::MATCHED        => Ok(true),
+        Glob_Result::UNMATCHED      => Ok(false),
+        Glob_Result::OOM_ERROR      => Err(c!("out of memory")),
+        Glob_Result::ENCODING_ERROR => Err(c!("encoding error")),
+        Glob_Result::SYNTAX_ERROR   => Err(c!("syntax error")),
+    };
+    temp_rewind(mark);
+    result
+}
@@ -1,6 +1,10 @@
 use core::ffi::*;
+use core::mem::zeroed;
 use crate::lexer::*;
 use crate::nob::*;
+use crate::crust::libc::*;
+use crate::arena;
+use crate::diagf;
 
 #[derive(Clone, Copy)]
 pub enum Arg {
@@ -135,17 +139,24 @@ pub struct Program {
     pub asm_funcs: Array<AsmFunc>,
 }
 
-pub unsafe fn dump_arg_call(arg: Arg, output: *mut String_Builder) {
-    match arg {
-        Arg::RefExternal(name) | Arg::External(name) => {
-            sb_appendf(output, c!("call(\"%s\""), name);
-        }
-        arg => {
-            sb_appendf(output, c!("call("));
-            dump_arg(output, arg);
-        }
-    };
 
+// The dump functions below produce the textual IR format which is documented in docs/bir.md.
+// If you change how anything is printed here, don't forget to update parse_program() and the docs.
+
+pub unsafe fn dump_string_literal(output: *mut String_Builder, bytes: *const [u8]) {
+    sb_appendf(output, c!("\""));
+    for i in 0..bytes.len() {
+        match (*bytes)[i] {
+            0    => { sb_appendf(output, c!("\\0")); }
+            b'\n' => { sb_appendf(output, c!("\\n")); }
+            b'\t' => { sb_appendf(output, c!("\\t")); }
+            b'\r' => { sb_appendf(output, c!("\\r")); }
+            b'"'  => { sb_appendf(output, c!("\\\"")); }
+            b'\\' => { sb_appendf(output, c!("\\\\")); }
+            x     => da_append(output, x as c_char),
+        }
+    }
+    sb_appendf(output, c!("\""));
 }
 
 pub unsafe fn dump_arg(output: *mut String_Builder, arg: Arg) {
@@ -161,12 +172,31 @@ pub unsafe fn dump_arg(output: *mut String_Builder, arg: Arg) {
     };
 }
 
+pub const BINOP_TOKENS: *const [(Binop, Token, *const c_char)] = &[
+    (Binop::BitOr,         Token::Or,        c!(" | ")),
+    (Binop::BitAnd,        Token::And,       c!(" & ")),
+    (Binop::BitShl,        Token::Shl,       c!(" << ")),
+    (Binop::BitShr,        Token::Shr,       c!(" >> ")),
+    (Binop::Plus,          Token::Plus,      c!(" + ")),
+    (Binop::Minus,         Token::Minus,     c!(" - ")),
+    (Binop::Mod,           Token::Mod,       c!(" %% ")),
+    (Binop::Div,           Token::Div,       c!(" / ")),
+    (Binop::Mult,          Token::Mul,       c!(" * ")),
+    (Binop::Less,          Token::Less,      c!(" < ")),
+    (Binop::Greater,       Token::Greater,   c!(" > ")),
+    (Binop::Equal,         Token::EqEq,      c!(" == ")),
+    (Binop::NotEqual,      Token::NotEq,     c!(" != ")),
+    (Binop::GreaterEqual,  Token::GreaterEq, c!(" >= ")),
+    (Binop::LessEqual,     Token::LessEq,    c!(" <= ")),
+];
+
 pub unsafe fn dump_op(op: OpWithLocation, output: *mut String_Builder) {
     match op.opcode {
         Op::Bogus => unreachable!("bogus-amogus"),
         Op::Return {arg} => {
-            sb_appendf(output, c!("    return "));
+            sb_appendf(output, c!("    return"));
             if let Some(arg) = arg {
+                sb_appendf(output, c!(" "));
                 dump_arg(output, arg);
             }
             sb_appendf(output, c!("\n"));
@@ -187,9 +217,9 @@ pub unsafe fn dump_op(op: OpWithLocation, output: *mut String_Builder) {
             sb_appendf(output, c!("\n"));
         }
         Op::Negate{result, arg} => {
-            sb_appendf(output, c!("    auto[%zu] = -"), result);
+            sb_appendf(output, c!("    auto[%zu] = -("), result);
             dump_arg(output, arg);
-            sb_appendf(output, c!("\n"));
+            sb_appendf(output, c!(")\n"));
         }
         Op::UnaryNot{result, arg} => {
             sb_appendf(output, c!("    auto[%zu] = !"), result);
@@ -199,29 +229,14 @@ pub unsafe fn dump_op(op: OpWithLocation, output: *mut String_Builder) {
         Op::Binop {binop, index, lhs, rhs} => {
             sb_appendf(output, c!("    auto[%zu] = "), index);
             dump_arg(output, lhs);
-            match binop {
-                Binop::BitOr        => sb_appendf(output, c!(" | ")),
-                Binop::BitAnd       => sb_appendf(output, c!(" & ")),
-                Binop::BitShl       => sb_appendf(output, c!(" << ")),
-                Binop::BitShr       => sb_appendf(output, c!(" >> ")),
-                Binop::Plus         => sb_appendf(output, c!(" + ")),
-                Binop::Minus        => sb_appendf(output, c!(" - ")),
-                Binop::Mod          => sb_appendf(output, c!(" %% ")),
-                Binop::Div          => sb_appendf(output, c!(" / ")),
-                Binop::Mult         => sb_appendf(output, c!(" * ")),
-                Binop::Less         => sb_appendf(output, c!(" < ")),
-                Binop::Greater      => sb_appendf(output, c!(" > ")),
-                Binop::Equal        => sb_appendf(output, c!(" == ")),
-                Binop::NotEqual     => sb_appendf(output, c!(" != ")),
-                Binop::GreaterEqual => sb_appendf(output, c!(" >= ")),
-                Binop::LessEqual    => sb_appendf(output, c!(" <= ")),
-            };
+            let (_, _, text) = *(*BINOP_TOKENS).iter().find(|(x, _, _)| *x == binop).expect("all binops must have a token");
+            sb_appendf(output, text);
             dump_arg(output, rhs);
             sb_appendf(output, c!("\n"));
         }
         Op::Funcall{result, fun, args} => {
-            sb_appendf(output, c!("    auto[%zu] = "), result);
-            dump_arg_call(fun, output);
+            sb_appendf(output, c!("    auto[%zu] = call("), result);
+            dump_arg(output, fun);
             for i in 0..args.count {
                 sb_appendf(output, c!(", "));
                 dump_arg(output, *args.items.add(i));
@@ -229,12 +244,17 @@ pub unsafe fn dump_op(op: OpWithLocation, output: *mut String_Builder) {
             sb_appendf(output, c!(")\n"));
         }
         Op::Asm {stmts} => {
-            sb_appendf(output, c!("   __asm__(\n"));
+            sb_appendf(output, c!("    __asm__(\n"));
             for i in 0..stmts.count {
                 let stmt = *stmts.items.add(i);
-                sb_appendf(output, c!("    %s\n"), stmt.line);
+                sb_appendf(output, c!("        "));
+                dump_string_literal(output, core::slice::from_raw_parts(stmt.line as *const u8, strlen(stmt.line)));
+                if i + 1 < stmts.count {
+                    sb_appendf(output, c!(","));
+                }
+                sb_appendf(output, c!("\n"));
             }
-            sb_appendf(output, c!(")\n"));
+            sb_appendf(output, c!("    )\n"));
         }
 
         Op::Label {label} => {
@@ -281,6 +301,17 @@ pub unsafe fn dump_extrns(output: *mut String_Builder, extrns: *const [*const c_
     }
 }
 
+pub unsafe fn dump_variadics(output: *mut String_Builder, variadics: *const [(*const c_char, Variadic)]) {
+    if variadics.len() > 0 {
+        sb_appendf(output, c!("\n"));
+        sb_appendf(output, c!("-- Variadic Functions --\n\n"));
+        for i in 0..variadics.len() {
+            let (name, variadic) = (*variadics)[i];
+            sb_appendf(output, c!("    __variadic__(%s, %zu)\n"), name, variadic.fixed_args);
+        }
+    }
+}
+
 pub unsafe fn dump_globals(output: *mut String_Builder, globals: *const [Global]) {
     sb_appendf(output, c!("\n"));
     sb_appendf(output, c!("-- Global Variables --\n\n"));
@@ -290,15 +321,15 @@ pub unsafe fn dump_globals(output: *mut String_Builder, globals: *const [Global]
         if global.is_vec {
             sb_appendf(output, c!("[%zu]"), global.minimum_size);
         }
-        sb_appendf(output, c!(": "));
+        sb_appendf(output, c!(":"));
         for j in 0..global.values.count {
             if j > 0 {
-                sb_appendf(output, c!(", "));
+                sb_appendf(output, c!(","));
             }
             match *global.values.items.add(j) {
-                ImmediateValue::Literal(lit) => sb_appendf(output, c!("%zu"), lit),
-                ImmediateValue::Name(name) => sb_appendf(output, c!("%s"), name),
-                ImmediateValue::DataOffset(offset) => sb_appendf(output, c!("data[%zu]"), offset),
+                ImmediateValue::Literal(lit) => sb_appendf(output, c!(" %zu"), lit),
+                ImmediateValue::Name(name) => sb_appendf(output, c!(" %s"), name),
+                ImmediateValue::DataOffset(offset) => sb_appendf(output, c!(" data[%zu]"), offset),
             };
         }
         sb_appendf(output, c!("\n"));
@@ -311,35 +342,21 @@ pub unsafe fn dump_data_section(output: *mut String_Builder, data: *const [u8])
         sb_appendf(output, c!("-- Data Section --\n"));
         sb_appendf(output, c!("\n"));
 
-        const ROW_SIZE: usize = 12;
-        for i in (0..data.len()).step_by(ROW_SIZE) {
-            sb_appendf(output, c!("%04X:"), i as c_uint);
-            for j in i..(i+ROW_SIZE) {
-                if j < data.len() {
-                    sb_appendf(output, c!(" "));
-                    sb_appendf(output, c!("%02X"), (*data)[j] as c_uint);
-                } else {
-                    sb_appendf(output, c!("   "));
-                }
+        // The data section consists mostly of NULL-terminated strings, so we print it as a
+        // sequence of string literals, one per each NULL-terminated chunk.
+        let mut start = 0;
+        while start < data.len() {
+            let mut end = start;
+            while end < data.len() && (*data)[end] != 0 {
+                end += 1;
             }
-
-            sb_appendf(output, c!(" | "));
-            for j in i..(i+ROW_SIZE).min(data.len()) {
-                let ch = (*data)[j] as char;
-                let c = if ch.is_ascii_whitespace() {
-                    // display all whitespace as a regular space
-                    // stops '\t', '\n', '\b' from messing up the formatting
-                    ' '
-                } else if ch.is_ascii_graphic() {
-                    ch
-                } else {
-                    // display all non-printable characters as '.' (eg. NULL)
-                    '.'
-                };
-                sb_appendf(output, c!("%c"), c as c_uint);
+            if end < data.len() {
+                end += 1; // include the NULL-terminator
             }
-
-            sb_appendf(output, c!("\n"));
+            sb_appendf(output, c!("    "));
+            dump_string_literal(output, core::slice::from_raw_parts((data as *const u8).add(start), end - start));
+            sb_appendf(output, c!(" // data[%zu]\n"), start);
+            start = end;
         }
     }
 }
@@ -350,7 +367,9 @@ pub unsafe fn dump_asm_funcs(output: *mut String_Builder, asm_funcs: *const [Asm
         sb_appendf(output, c!("%s(asm):\n"), asm_func.name);
         for j in 0..asm_func.body.count {
             let stmt = *asm_func.body.items.add(j);
-            sb_appendf(output, c!("    %s\n"), stmt.line);
+            sb_appendf(output, c!("    "));
+            dump_string_literal(output, core::slice::from_raw_parts(stmt.line as *const u8, strlen(stmt.line)));
+            sb_appendf(output, c!("\n"));
         }
     }
 }
@@ -359,6 +378,515 @@ pub unsafe fn dump_program(output: *mut String_Builder, p: *const Program) {
     dump_funcs(output, da_slice((*p).funcs));
     dump_asm_funcs(output, da_slice((*p).asm_funcs));
     dump_extrns(output, da_slice((*p).extrns));
+    dump_variadics(output, da_slice((*p).variadics));
     dump_globals(output, da_slice((*p).globals));
     dump_data_section(output, da_slice((*p).data));
 }
+
+#[derive(Clone, Copy)]
+enum Bir_Section {
+    Nothing,
+    Functions,
+    Extrns,
+    Variadics,
+    Globals,
+    Data,
+}
+
+const BIR_SECTIONS: *const [(*const c_char, Bir_Section)] = &[
+    (c!("Functions"),          Bir_Section::Functions),
+    (c!("External Symbols"),   Bir_Section::Extrns),
+    (c!("Variadic Functions"), Bir_Section::Variadics),
+    (c!("Global Variables"),   Bir_Section::Globals),
+    (c!("Data Section"),       Bir_Section::Data),
+];
+
+unsafe fn bir_expect_token(l: *mut Lexer, token: Token) -> Option<()> {
+    get_token(l)?;
+    if (*l).token != token {
+        diagf!((*l).loc, c!("ERROR: expected %s, but got %s\n"), display_token(token), display_token((*l).token));
+        return None;
+    }
+    Some(())
+}
+
+unsafe fn bir_expect_id(l: *mut Lexer, name: *const c_char) -> Option<()> {
+    bir_expect_token(l, Token::ID)?;
+    if strcmp((*l).string, name) != 0 {
+        diagf!((*l).loc, c!("ERROR: expected `%s`, but got `%s`\n"), name, (*l).string);
+        return None;
+    }
+    Some(())
+}
+
+/// Consumes the next token only if it is `token` and it is located on the line `line`.
+/// Most of the constructs of the format can't span several lines, which is how we tell
+/// where one of them ends and the next one begins.
+unsafe fn bir_skip_token(l: *mut Lexer, token: Token, line: c_int) -> Option<bool> {
+    let saved_point = (*l).parse_point;
+    get_token(l)?;
+    if (*l).token == token && (*l).loc.line_number == line {
+        return Some(true);
+    }
+    (*l).parse_point = saved_point;
+    Some(false)
+}
+
+/// Checks if the next token is located on the line `line` without consuming it.
+unsafe fn bir_continues_on_line(l: *mut Lexer, line: c_int) -> Option<bool> {
+    let saved_point = (*l).parse_point;
+    get_token(l)?;
+    let result = (*l).token != Token::EOF && (*l).loc.line_number == line;
+    (*l).parse_point = saved_point;
+    Some(result)
+}
+
+/// Parses the `N]` part of `[N]`
+unsafe fn bir_parse_index_tail(l: *mut Lexer) -> Option<usize> {
+    bir_expect_token(l, Token::IntLit)?;
+    let index = (*l).int_number as usize;
+    bir_expect_token(l, Token::CBracket)?;
+    Some(index)
+}
+
+unsafe fn bir_parse_index(l: *mut Lexer) -> Option<usize> {
+    bir_expect_token(l, Token::OBracket)?;
+    bir_parse_index_tail(l)
+}
+
+unsafe fn bir_parse_string(l: *mut Lexer, a: *mut arena::Arena) -> Option<*const c_char> {
+    bir_expect_token(l, Token::String)?;
+    Some(arena::strdup(a, (*l).string))
+}
+
+unsafe fn bir_parse_arg(l: *mut Lexer, a: *mut arena::Arena, data_base: usize) -> Option<Arg> {
+    get_token(l)?;
+    let line = (*l).loc.line_number;
+    match (*l).token {
+        Token::Auto => Some(Arg::AutoVar(bir_parse_index(l)?)),
+        Token::IntLit => Some(Arg::Literal((*l).int_number)),
+        Token::Minus => {
+            bir_expect_token(l, Token::IntLit)?;
+            Some(Arg::Literal(!(*l).int_number + 1))
+        }
+        Token::ID => {
+            let name = arena::strdup(a, (*l).string);
+            if strcmp(name, c!("deref")) == 0 && bir_skip_token(l, Token::OBracket, line)? {
+                return Some(Arg::Deref(bir_parse_index_tail(l)?));
+            }
+            if strcmp(name, c!("data")) == 0 && bir_skip_token(l, Token::OBracket, line)? {
+                return Some(Arg::DataOffset(data_base + bir_parse_index_tail(l)?));
+            }
+            if strcmp(name, c!("ref")) == 0 {
+                if bir_skip_token(l, Token::Auto, line)? {
+                    return Some(Arg::RefAutoVar(bir_parse_index(l)?));
+                }
+                if bir_skip_token(l, Token::ID, line)? {
+                    return Some(Arg::RefExternal(arena::strdup(a, (*l).string)));
+                }
+            }
+            Some(Arg::External(name))
+        }
+        _ => {
+            diagf!((*l).loc, c!("ERROR: expected an argument, but got %s\n"), display_token((*l).token));
+            None
+        }
+    }
+}
+
+/// Parses everything after `auto[index] =`
+unsafe fn bir_parse_auto_assign(l: *mut Lexer, a: *mut arena::Arena, data_base: usize, index: usize, line: c_int) -> Option<Op> {
+    let saved_point = (*l).parse_point;
+    get_token(l)?;
+    match (*l).token {
+        Token::Not => Some(Op::UnaryNot {result: index, arg: bir_parse_arg(l, a, data_base)?}),
+        Token::Minus if bir_skip_token(l, Token::OParen, line)? => {
+            let arg = bir_parse_arg(l, a, data_base)?;
+            bir_expect_token(l, Token::CParen)?;
+            Some(Op::Negate {result: index, arg})
+        }
+        Token::OParen => {
+            let arg = bir_parse_arg(l, a, data_base)?;
+            bir_expect_token(l, Token::CParen)?;
+            bir_expect_token(l, Token::Plus)?;
+            bir_expect_token(l, Token::OParen)?;
+            let offset = bir_parse_arg(l, a, data_base)?;
+            bir_expect_token(l, Token::Mul)?;
+            bir_expect_id(l, c!("WORD_SIZE"))?;
+            bir_expect_token(l, Token::CParen)?;
+            Some(Op::Index {result: index, arg, offset})
+        }
+        Token::ID if strcmp((*l).string, c!("call")) == 0 && bir_skip_token(l, Token::OParen, line)? => {
+            let fun = bir_parse_arg(l, a, data_base)?;
+            let mut args: Array<Arg> = zeroed();
+            while bir_skip_token(l, Token::Comma, line)? {
+                da_append(&mut args, bir_parse_arg(l, a, data_base)?);
+            }
+            bir_expect_token(l, Token::CParen)?;
+            Some(Op::Funcall {result: index, fun, args})
+        }
+        _ => {
+            (*l).parse_point = saved_point;
+            let lhs = bir_parse_arg(l, a, data_base)?;
+            for i in 0..BINOP_TOKENS.len() {
+                let (binop, token, _) = (*BINOP_TOKENS)[i];
+                if bir_skip_token(l, token, line)? {
+                    let rhs = bir_parse_arg(l, a, data_base)?;
+                    return Some(Op::Binop {binop, index, lhs, rhs});
+                }
+            }
+            Some(Op::AutoAssign {index, arg: lhs})
+        }
+    }
+}
+
+unsafe fn bir_parse_asm_stmts(l: *mut Lexer, a: *mut arena::Arena, stmts: *mut Array<AsmStmt>) -> Option<()> {
+    bir_expect_token(l, Token::OParen)?;
+    loop {
+        let line = bir_parse_string(l, a)?;
+        da_append(stmts, AsmStmt {line, loc: (*l).loc});
+        get_token(l)?;
+        match (*l).token {
+            Token::Comma => continue,
+            Token::CParen => return Some(()),
+            _ => {
+                diagf!((*l).loc, c!("ERROR: expected %s or %s, but got %s\n"), display_token(Token::Comma), display_token(Token::CParen), display_token((*l).token));
+                return None;
+            }
+        }
+    }
+}
+
+unsafe fn bir_parse_func_body(l: *mut Lexer, a: *mut arena::Arena, data_base: usize, body: *mut Array<OpWithLocation>) -> Option<()> {
+    loop {
+        let saved_point = (*l).parse_point;
+        get_token(l)?;
+        let loc = (*l).loc;
+        let line = loc.line_number;
+        let opcode = match (*l).token {
+            Token::EOF | Token::MinusMinus => {
+                (*l).parse_point = saved_point;
+                return Some(());
+            }
+            Token::Auto => {
+                let index = bir_parse_index(l)?;
+                bir_expect_token(l, Token::Eq)?;
+                bir_parse_auto_assign(l, a, data_base, index, line)?
+            }
+            Token::Return => {
+                if bir_continues_on_line(l, line)? {
+                    Op::Return {arg: Some(bir_parse_arg(l, a, data_base)?)}
+                } else {
+                    Op::Return {arg: None}
+                }
+            }
+            Token::Asm => {
+                let mut stmts: Array<AsmStmt> = zeroed();
+                bir_parse_asm_stmts(l, a, &mut stmts)?;
+                Op::Asm {stmts}
+            }
+            Token::ID => {
+                let name = arena::strdup(a, (*l).string);
+                if bir_skip_token(l, Token::OParen, line)? {
+                    // The header of the next function
+                    (*l).parse_point = saved_point;
+                    return Some(());
+                }
+                if bir_skip_token(l, Token::Eq, line)? {
+                    Op::ExternalAssign {name, arg: bir_parse_arg(l, a, data_base)?}
+                } else if strcmp(name, c!("store")) == 0 {
+                    bir_expect_id(l, c!("deref"))?;
+                    let index = bir_parse_index(l)?;
+                    bir_expect_token(l, Token::Comma)?;
+                    Op::Store {index, arg: bir_parse_arg(l, a, data_base)?}
+                } else if strcmp(name, c!("label")) == 0 {
+                    Op::Label {label: bir_parse_index(l)?}
+                } else if strcmp(name, c!("jmp")) == 0 {
+                    bir_expect_id(l, c!("label"))?;
+                    Op::JmpLabel {label: bir_parse_index(l)?}
+                } else if strcmp(name, c!("jmp_if_not")) == 0 {
+                    bir_expect_id(l, c!("label"))?;
+                    let label = bir_parse_index(l)?;
+                    bir_expect_token(l, Token::Comma)?;
+                    Op::JmpIfNotLabel {label, arg: bir_parse_arg(l, a, data_base)?}
+                } else {
+                    diagf!(loc, c!("ERROR: unknown instruction `%s`\n"), name);
+                    return None;
+                }
+            }
+            token => {
+                diagf!(loc, c!("ERROR: expected an instruction, but got %s\n"), display_token(token));
+                return None;
+            }
+        };
+        da_append(body, OpWithLocation {opcode, loc, scope_events_count: 0});
+    }
+}
+
+/// The auto vars are numbered from 1 and there are `auto_vars_count` of them according to the
+/// header of the function.
+unsafe fn bir_check_auto(loc: Loc, index: usize, auto_vars_count: usize) -> Option<()> {
+    if index < 1 || index > auto_vars_count {
+        diagf!(loc, c!("ERROR: auto[%zu] is out of range, the function has %zu auto vars\n"), index, auto_vars_count);
+        return None;
+    }
+    Some(())
+}
+
+unsafe fn bir_check_arg(loc: Loc, arg: Arg, auto_vars_count: usize) -> Option<()> {
+    match arg {
+        Arg::AutoVar(index) | Arg::Deref(index) | Arg::RefAutoVar(index) => bir_check_auto(loc, index, auto_vars_count),
+        _ => Some(()),
+    }
+}
+
+/// The codegens trust the compiler to produce the consistent IR, so everything that could send
+/// them out of bounds is checked here instead.
+unsafe fn bir_check_func_body(params_count: usize, auto_vars_count: usize, loc: Loc, body: *const [OpWithLocation]) -> Option<()> {
+    if params_count > auto_vars_count {
+        diagf!(loc, c!("ERROR: the function has %zu parameters, but only %zu auto vars\n"), params_count, auto_vars_count);
+        return None;
+    }
+
+    let mut defined: Array<bool> = zeroed();
+    for i in 0..body.len() {
+        let op = (*body)[i];
+        if let Op::Label {label} = op.opcode {
+            while defined.count <= label {
+                da_append(&mut defined, false);
+            }
+            if *defined.items.add(label) {
+                diagf!(op.loc, c!("ERROR: label[%zu] is defined more than once\n"), label);
+                return None;
+            }
+            *defined.items.add(label) = true;
+        }
+    }
+
+    for i in 0..body.len() {
+        let op = (*body)[i];
+        let loc = op.loc;
+        match op.opcode {
+            Op::Bogus | Op::Asm {..} | Op::Label {..} | Op::Return {arg: None} => {}
+            Op::Return {arg: Some(arg)} | Op::ExternalAssign {arg, ..} => bir_check_arg(loc, arg, auto_vars_count)?,
+            Op::Store {index, arg} | Op::AutoAssign {index, arg} |
+            Op::UnaryNot {result: index, arg} | Op::Negate {result: index, arg} => {
+                bir_check_auto(loc, index, auto_vars_count)?;
+                bir_check_arg(loc, arg, auto_vars_count)?;
+            }
+            Op::Binop {index, lhs, rhs, ..} | Op::Index {result: index, arg: lhs, offset: rhs} => {
+                bir_check_auto(loc, index, auto_vars_count)?;
+                bir_check_arg(loc, lhs, auto_vars_count)?;
+                bir_check_arg(loc, rhs, auto_vars_count)?;
+            }
+            Op::Funcall {result, fun, args} => {
+                bir_check_auto(loc, result, auto_vars_count)?;
+                bir_check_arg(loc, fun, auto_vars_count)?;
+                for j in 0..args.count {
+                    bir_check_arg(loc, *args.items.add(j), auto_vars_count)?;
+                }
+            }
+            Op::JmpLabel {label} | Op::JmpIfNotLabel {label, ..} => {
+                if label >= defined.count || !*defined.items.add(label) {
+                    diagf!(loc, c!("ERROR: jump to the undefined label[%zu]\n"), label);
+                    return None;
+                }
+                if let Op::JmpIfNotLabel {arg, ..} = op.opcode {
+                    bir_check_arg(loc, arg, auto_vars_count)?;
+                }
+            }
+        }
+    }
+    free(defined.items);
+    Some(())
+}
+
+unsafe fn bir_parse_section_header(l: *mut Lexer) -> Option<Bir_Section> {
+    let loc = (*l).loc;
+    let mut title: String_Builder = zeroed();
+    loop {
+        get_token(l)?;
+        match (*l).token {
+            Token::MinusMinus => break,
+            Token::ID => {
+                if title.count > 0 {
+                    da_append(&mut title, ' ' as c_char);
+                }
+                sb_appendf(&mut title, c!("%s"), (*l).string);
+            }
+            token => {
+                diagf!((*l).loc, c!("ERROR: expected %s or %s, but got %s\n"), display_token(Token::ID), display_token(Token::MinusMinus), display_token(token));
+                return None;
+            }
+        }
+    }
+    da_append(&mut title, 0);
+    let section = crate::crust::assoc_lookup_cstr(BIR_SECTIONS, title.items).map(|section| *section);
+    if section.is_none() {
+        diagf!(loc, c!("ERROR: unknown section `%s`\n"), title.items);
+    }
+    free(title.items);
+    section
+}
+
+unsafe fn bir_parse_immediate_value(l: *mut Lexer, a: *mut arena::Arena, data_base: usize) -> Option<ImmediateValue> {
+    get_token(l)?;
+    let line = (*l).loc.line_number;
+    match (*l).token {
+        Token::IntLit => Some(ImmediateValue::Literal((*l).int_number)),
+        Token::Minus => {
+            bir_expect_token(l, Token::IntLit)?;
+            Some(ImmediateValue::Literal(!(*l).int_number + 1))
+        }
+        Token::ID => {
+            let name = arena::strdup(a, (*l).string);
+            if strcmp(name, c!("data")) == 0 && bir_skip_token(l, Token::OBracket, line)? {
+                return Some(ImmediateValue::DataOffset(data_base + bir_parse_index_tail(l)?));
+            }
+            Some(ImmediateValue::Name(name))
+        }
+        token => {
+            diagf!((*l).loc, c!("ERROR: expected a value, but got %s\n"), display_token(token));
+            None
+        }
+    }
+}
+
+/// Parses the textual IR produced by dump_program() and appends it to `p`. See docs/bir.md for the
+/// description of the format. Several files can be parsed into the same Program, the data offsets
+/// of each file are relocated after the data of the previous ones.
+pub unsafe fn parse_program(l: *mut Lexer, p: *mut Program, a: *mut arena::Arena) -> Option<()> {
+    let data_base = (*p).data.count;
+    let mut section = Bir_Section::Nothing;
+    loop {
+        get_token(l)?;
+        let loc = (*l).loc;
+        let line = loc.line_number;
+        if (*l).token == Token::EOF { return Some(()); }
+        if (*l).token == Token::MinusMinus {
+            section = bir_parse_section_header(l)?;
+            continue;
+        }
+
+        match section {
+            Bir_Section::Nothing => {
+                diagf!(loc, c!("ERROR: expected a section header like `-- Functions --`, but got %s\n"), display_token((*l).token));
+                return None;
+            }
+            Bir_Section::Functions => {
+                if (*l).token != Token::ID {
+                    diagf!(loc, c!("ERROR: expected the name of a function, but got %s\n"), display_token((*l).token));
+                    return None;
+                }
+                let name = arena::strdup(a, (*l).string);
+                bir_expect_token(l, Token::OParen)?;
+                get_token(l)?;
+                match (*l).token {
+                    Token::ID if strcmp((*l).string, c!("asm")) == 0 => {
+                        bir_expect_token(l, Token::CParen)?;
+                        bir_expect_token(l, Token::Colon)?;
+                        let mut body: Array<AsmStmt> = zeroed();
+                        loop {
+                            let saved_point = (*l).parse_point;
+                            get_token(l)?;
+                            if (*l).token != Token::String {
+                                (*l).parse_point = saved_point;
+                                break;
+                            }
+                            da_append(&mut body, AsmStmt {line: arena::strdup(a, (*l).string), loc: (*l).loc});
+                        }
+                        da_append(&mut (*p).asm_funcs, AsmFunc {name, name_loc: loc, body});
+                    }
+                    Token::IntLit => {
+                        let params_count = (*l).int_number as usize;
+                        bir_expect_token(l, Token::Comma)?;
+                        bir_expect_token(l, Token::IntLit)?;
+                        let auto_vars_count = (*l).int_number as usize;
+                        bir_expect_token(l, Token::CParen)?;
+                        bir_expect_token(l, Token::Colon)?;
+                        let mut body: Array<OpWithLocation> = zeroed();
+                        bir_parse_func_body(l, a, data_base, &mut body)?;
+                        bir_check_func_body(params_count, auto_vars_count, loc, da_slice(body))?;
+                        da_append(&mut (*p).funcs, Func {
+                            name,
+                            name_loc: loc,
+                            body,
+                            params_count,
+                            auto_vars_count,
+                            scope_events: zeroed(),
+                        });
+                    }
+                    token => {
+                        diagf!((*l).loc, c!("ERROR: expected the amount of parameters or `asm`, but got %s\n"), display_token(token));
+                        return None;
+                    }
+                }
+            }
+            Bir_Section::Extrns => {
+                if (*l).token != Token::ID {
+                    diagf!(loc, c!("ERROR: expected the name of an external symbol, but got %s\n"), display_token((*l).token));
+                    return None;
+                }
+                let mut exists = false;
+                for i in 0..(*p).extrns.count {
+                    if strcmp(*(*p).extrns.items.add(i), (*l).string) == 0 {
+                        exists = true;
+                        break;
+                    }
+                }
+                if !exists {
+                    da_append(&mut (*p).extrns, arena::strdup(a, (*l).string) as *const c_char);
+                }
+            }
+            Bir_Section::Variadics => {
+                if (*l).token != Token::Variadic {
+                    diagf!(loc, c!("ERROR: expected %s, but got %s\n"), display_token(Token::Variadic), display_token((*l).token));
+                    return None;
+                }
+                bir_expect_token(l, Token::OParen)?;
+                bir_expect_token(l, Token::ID)?;
+                let name = arena::strdup(a, (*l).string);
+                bir_expect_token(l, Token::Comma)?;
+                bir_expect_token(l, Token::IntLit)?;
+                let fixed_args = (*l).int_number as usize;
+                bir_expect_token(l, Token::CParen)?;
+                da_append(&mut (*p).variadics, (name as *const c_char, Variadic {loc, fixed_args}));
+            }
+            Bir_Section::Globals => {
+                if (*l).token != Token::ID {
+                    diagf!(loc, c!("ERROR: expected the name of a global variable, but got %s\n"), display_token((*l).token));
+                    return None;
+                }
+                let mut global = Global {
+                    name: arena::strdup(a, (*l).string),
+                    name_loc: loc,
+                    values: zeroed(),
+                    is_vec: false,
+                    minimum_size: 0,
+                };
+                if bir_skip_token(l, Token::OBracket, line)? {
+                    global.is_vec = true;
+                    global.minimum_size = bir_parse_index_tail(l)?;
+                }
+                bir_expect_token(l, Token::Colon)?;
+                if bir_continues_on_line(l, line)? {
+                    loop {
+                        da_append(&mut global.values, bir_parse_immediate_value(l, a, data_base)?);
+                        if !bir_skip_token(l, Token::Comma, line)? { break; }
+                    }
+                }
+                da_append(&mut (*p).globals, global);
+            }
+            Bir_Section::Data => {
+                if (*l).token != Token::String {
+                    diagf!(loc, c!("ERROR: expected %s, but got %s\n"), display_token(Token::String), display_token((*l).token));
+                    return None;
+                }
+                // The storage contains the NULL-terminator that the lexer appended
+                for i in 0..(*l).string_storage.count - 1 {
+                    da_append(&mut (*p).data, *(*l).string_storage.items.add(i) as u8);
+                }
+            }
+        }
+    }
+}
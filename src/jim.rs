@@ -14,9 +14,12 @@ pub struct Jim {
 
 extern "C" {
     pub fn jim_begin(jim: *mut Jim);
+    pub fn jim_null(jim: *mut Jim);
+    pub fn jim_bool(jim: *mut Jim, boolean: c_int);
     pub fn jim_object_begin(jim: *mut Jim);
     pub fn jim_member_key(jim: *mut Jim, s: *const c_char);
     pub fn jim_object_end(jim: *mut Jim);
+    pub fn jim_integer(jim: *mut Jim, x: c_longlong);
     pub fn jim_string(jim: *mut Jim, s: *const c_char);
     pub fn jim_array_begin(jim: *mut Jim);
     pub fn jim_array_end(jim: *mut Jim);
@@ -20,7 +20,7 @@ pub struct Jimp {
 
 pub unsafe fn jimp_boolean(jimp: *mut Jimp) -> Option<()> {
     extern "C" {
-        #[link_name="jimp_boolean"]
+        #[link_name="jimp_bool"]
         pub fn jimp_boolean_raw(jimp: *mut Jimp) -> bool;
     }
     if jimp_boolean_raw(jimp) {
@@ -102,10 +102,28 @@ pub unsafe fn jimp_array_end(jimp: *mut Jimp) -> Option<()> {
     }
 }
 
+pub unsafe fn jimp_null(jimp: *mut Jimp) -> Option<()> {
+    extern "C" {
+        #[link_name="jimp_null"]
+        pub fn jimp_null_raw(jimp: *mut Jimp) -> bool;
+    }
+    if jimp_null_raw(jimp) {
+        Some(())
+    } else {
+        None
+    }
+}
+
 extern "C" {
     pub fn jimp_begin(jimp: *mut Jimp, file_path: *const c_char, input: *const c_char, input_size: usize);
     pub fn jimp_unknown_member(jimp: *mut Jimp);
     pub fn jimp_diagf(jimp: *mut Jimp, fmt: *const c_char, ...);
     pub fn jimp_object_member(jimp: *mut Jimp) -> bool;
     pub fn jimp_array_item(jimp: *mut Jimp) -> bool;
+    pub fn jimp_is_null_ahead(jimp: *mut Jimp) -> bool;
+    pub fn jimp_is_bool_ahead(jimp: *mut Jimp) -> bool;
+    pub fn jimp_is_number_ahead(jimp: *mut Jimp) -> bool;
+    pub fn jimp_is_string_ahead(jimp: *mut Jimp) -> bool;
+    pub fn jimp_is_array_ahead(jimp: *mut Jimp) -> bool;
+    pub fn jimp_is_object_ahead(jimp: *mut Jimp) -> bool;
 }
@@ -1,7 +1,9 @@
 use core::ffi::*;
 use core::mem::zeroed;
+use core::slice;
 use crate::nob::*;
 use crate::crust::libc::*;
+use crate::jim::*;
 
 #[derive(Clone, Copy)]
 pub struct Loc {
@@ -13,8 +15,7 @@ pub struct Loc {
 #[macro_export]
 macro_rules! diagf {
     ($loc:expr, $($args:tt)*) => {{
-        fprintf(stderr(), c!("%s:%d:%d: "), $loc.input_path, $loc.line_number, $loc.line_offset);
-        fprintf(stderr(), $($args)*);
+        $crate::lexer::diag_report($loc, $crate::nob::temp_sprintf($($args)*));
     }};
 }
 
@@ -22,18 +23,241 @@ macro_rules! diagf {
 macro_rules! missingf {
     ($loc:expr, $($args:tt)*) => {{
         let file = file!();
-        fprintf(stderr(), c!("%s:%d:%d: TODO: "), $loc.input_path, $loc.line_number, $loc.line_offset);
-        fprintf(stderr(), $($args)*);
+        let message = $crate::nob::temp_sprintf($($args)*);
+        $crate::lexer::diag_report($loc, $crate::nob::temp_sprintf(c!("TODO: %s"), message));
         fprintf(stderr(), c!("%.*s:%d: INFO: implementation should go here\n"), file.len(), file.as_ptr(), line!());
+        $crate::lexer::diag_flush();
         abort();
     }}
 }
 
+// Sources of the compiled files, so diagnostics can show the offending lines.
+// The registered buffers must outlive the compilation.
+#[derive(Clone, Copy)]
+pub struct Diag_Source {
+    pub input_path: *const c_char,
+    pub start: *const c_char,
+    pub eof: *const c_char,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Diag_Format {
+    Text,
+    Json,
+    Sarif,
+}
+
+// A diagnostic collected for the machine-readable formats. NOTEs are attached to the diagnostic reported
+// right before them.
+#[derive(Clone, Copy)]
+pub struct Diagnostic {
+    pub loc: Loc,
+    pub length: usize,
+    pub severity: *const c_char, // "error", "warning" or "note"
+    pub message: *const c_char,
+    pub notes: Array<Diagnostic>,
+}
+
+pub static mut diag_sources: Array<Diag_Source> = unsafe { zeroed() };
+pub static mut diag_colored: bool = false;
+pub static mut diag_format: Diag_Format = Diag_Format::Text;
+pub static mut diagnostics: Array<Diagnostic> = unsafe { zeroed() };
+// Where diag_flush() writes the machine-readable document. NULL means stdout.
+pub static mut diag_output_path: *const c_char = core::ptr::null();
+
+pub unsafe fn diag_register_source(input_path: *const c_char, start: *const c_char, eof: *const c_char) {
+    da_append(&raw mut diag_sources, Diag_Source { input_path, start, eof });
+}
+
+// Forgets all the registered sources and collected diagnostics, so the same process can compile
+// the sources again from scratch (see blsp.rs).
+pub unsafe fn diag_reset() {
+    diag_sources.count = 0;
+    diagnostics.count = 0;
+}
+
+// Enables colors according to the `-color` flag. Returns false if the mode is unknown.
+pub unsafe fn diag_set_color_mode(mode: *const c_char) -> bool {
+    if strcmp(mode, c!("always")) == 0 {
+        diag_colored = true;
+    } else if strcmp(mode, c!("never")) == 0 {
+        diag_colored = false;
+    } else if strcmp(mode, c!("auto")) == 0 {
+        diag_colored = isatty(2) != 0;
+    } else {
+        return false;
+    }
+    true
+}
+
+// Switches to collecting the diagnostics according to the `-diagnostics-format` flag. Returns false if the format is unknown.
+pub unsafe fn diag_set_format(format: *const c_char) -> bool {
+    if strcmp(format, c!("text")) == 0 {
+        diag_format = Diag_Format::Text;
+    } else if strcmp(format, c!("json")) == 0 {
+        diag_format = Diag_Format::Json;
+    } else if strcmp(format, c!("sarif")) == 0 {
+        diag_format = Diag_Format::Sarif;
+    } else {
+        return false;
+    }
+    true
+}
+
+const ANSI_RESET: *const c_char = c!("\x1b[0m");
+const ANSI_BOLD:  *const c_char = c!("\x1b[1m");
+const ANSI_BLUE:  *const c_char = c!("\x1b[1;34m");
+
+// Messages are prefixed with their severity like `ERROR: ...` or `LEXER ERROR: ...`.
+// Returns the length of that prefix (including the colon), the color it is highlighted with and
+// the level of the diagnostic for the machine-readable formats.
+unsafe fn diag_severity(message: *const c_char) -> (usize, *const c_char, *const c_char) {
+    let mut i = 0;
+    while *message.add(i) == ' ' as c_char || (*message.add(i) as u8).is_ascii_uppercase() {
+        i += 1;
+    }
+    if i == 0 || *message.add(i) != ':' as c_char {
+        return (0, ANSI_BOLD, c!("error"));
+    }
+    let severity = message.add(i);
+    let mut word = severity;
+    while word > message && *word.sub(1) != ' ' as c_char {
+        word = word.sub(1);
+    }
+    let (color, level) = if strncmp(word, c!("ERROR"), 5) == 0 {
+        (c!("\x1b[1;31m"), c!("error"))
+    } else if strncmp(word, c!("WARNING"), 7) == 0 {
+        (c!("\x1b[1;33m"), c!("warning"))
+    } else if strncmp(word, c!("TODO"), 4) == 0 {
+        (c!("\x1b[1;33m"), c!("error"))
+    } else {
+        (c!("\x1b[1;36m"), c!("note"))
+    };
+    (i + 1, color, level)
+}
+
+// Length of the token that starts at `start`, so the whole thing can be underlined
+unsafe fn diag_token_length(start: *const c_char, end: *const c_char) -> usize {
+    if start >= end { return 1; }
+    let mut current = start;
+    let c = *current;
+    if is_identifier(c) {
+        while current < end && is_identifier(*current) {
+            current = current.add(1);
+        }
+    } else if c == '"' as c_char || c == '\'' as c_char {
+        current = current.add(1);
+        while current < end && *current != c {
+            if *current == '\\' as c_char {
+                current = current.add(1);
+            }
+            current = current.add(1);
+        }
+        if current < end { current = current.add(1); }
+    } else {
+        for i in 0..PUNCTS.len() {
+            let (prefix, _) = (*PUNCTS)[i];
+            let n = strlen(prefix);
+            if n <= end.offset_from(start) as usize && strncmp(start, prefix, n) == 0 {
+                return n;
+            }
+        }
+        return 1;
+    }
+    (current.offset_from(start) as usize).min(end.offset_from(start) as usize).max(1)
+}
+
+// Finds the beginning and the end of the line `loc` points at in the registered sources
+unsafe fn diag_find_line(loc: Loc) -> Option<(*const c_char, *const c_char)> {
+    let mut source: Option<Diag_Source> = None;
+    for i in 0..diag_sources.count {
+        let it = *diag_sources.items.add(i);
+        if strcmp(it.input_path, loc.input_path) == 0 {
+            source = Some(it);
+        }
+    }
+    let source = source?;
+    if loc.line_number <= 0 || loc.line_offset <= 0 { return None; }
+
+    let mut line_start = source.start;
+    let mut line_number = 1;
+    while line_number < loc.line_number {
+        while line_start < source.eof && *line_start != '\n' as c_char {
+            line_start = line_start.add(1);
+        }
+        if line_start >= source.eof { return None; }
+        line_start = line_start.add(1);
+        line_number += 1;
+    }
+    let mut line_end = line_start;
+    while line_end < source.eof && *line_end != '\n' as c_char && *line_end != '\r' as c_char {
+        line_end = line_end.add(1);
+    }
+    Some((line_start, line_end))
+}
+
+// Length of the token `loc` points at, 1 if the source is not available
+unsafe fn diag_span_length(loc: Loc) -> usize {
+    let Some((line_start, line_end)) = diag_find_line(loc) else { return 1 };
+    let column = (loc.line_offset - 1) as usize;
+    let line_len = line_end.offset_from(line_start) as usize;
+    diag_token_length(line_start.add(column.min(line_len)), line_end)
+}
+
+// Prints the line `loc` points at with the location underlined
+unsafe fn diag_snippet(loc: Loc, color: *const c_char) {
+    let Some((line_start, line_end)) = diag_find_line(loc) else { return };
+
+    let column = (loc.line_offset - 1) as usize;
+    let line_len = line_end.offset_from(line_start) as usize;
+    let token_length = diag_span_length(loc);
+
+    let gutter = if diag_colored { ANSI_BLUE } else { c!("") };
+    let reset = if diag_colored { ANSI_RESET } else { c!("") };
+    let mut width = 1;
+    let mut n = loc.line_number;
+    while n >= 10 {
+        n /= 10;
+        width += 1;
+    }
+    fprintf(stderr(), c!("%s%*d |%s %.*s\n"), gutter, width, loc.line_number, reset, line_len as c_int, line_start);
+    fprintf(stderr(), c!("%s%*s |%s "), gutter, width, c!(""), reset);
+    // Keep the tabs so the caret lines up with the source line
+    for i in 0..column {
+        let c = if i < line_len && *line_start.add(i) == '\t' as c_char { '\t' } else { ' ' };
+        fprintf(stderr(), c!("%c"), c as c_int);
+    }
+    if diag_colored { fprintf(stderr(), c!("%s"), color); }
+    fprintf(stderr(), c!("^"));
+    for _ in 1..token_length {
+        fprintf(stderr(), c!("~"));
+    }
+    fprintf(stderr(), c!("%s\n"), reset);
+}
+
+// Backend of diagf!(). Prints `path:line:col: MESSAGE` followed by the source line with the location
+// underlined (if the source of the file is registered).
+pub unsafe fn diag_report(loc: Loc, message: *const c_char) {
+    let (severity_len, color, level) = diag_severity(message);
+    if diag_format != Diag_Format::Text {
+        diag_collect(loc, message.add(severity_len), level);
+        return;
+    }
+    if diag_colored {
+        fprintf(stderr(), c!("%s%s:%d:%d:%s "), ANSI_BOLD, loc.input_path, loc.line_number, loc.line_offset, ANSI_RESET);
+        fprintf(stderr(), c!("%s%.*s%s%s"), color, severity_len as c_int, message, ANSI_RESET, message.add(severity_len));
+    } else {
+        fprintf(stderr(), c!("%s:%d:%d: %s"), loc.input_path, loc.line_number, loc.line_offset, message);
+    }
+    diag_snippet(loc, color);
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum Token {
     // Terminal
     EOF,
     ParseError,
+    Comment, // Only produced when Lexer::comments is enabled (see bfmt.rs)
 
     // Values
     ID,
@@ -93,6 +317,9 @@ pub enum Token {
     Return,
     Asm,
     Variadic,
+    Include,
+    IfTarget,
+    ElseTarget,
 }
 
 pub unsafe fn display_token(token: Token) -> *const c_char {
@@ -100,6 +327,7 @@ pub unsafe fn display_token(token: Token) -> *const c_char {
         // Terminal
         Token::EOF        => c!("end of file"),
         Token::ParseError => c!("parse error"),
+        Token::Comment    => c!("comment"),
 
         // Values
         Token::ID         => c!("identifier"),
@@ -160,6 +388,9 @@ pub unsafe fn display_token(token: Token) -> *const c_char {
         // TODO: document all this magical extension keywords somewhere
         Token::Asm        => c!("keyword `__asm__`"),
         Token::Variadic   => c!("keyword `__variadic__`"),
+        Token::Include    => c!("keyword `__include__`"),
+        Token::IfTarget   => c!("keyword `__if_target__`"),
+        Token::ElseTarget => c!("keyword `__else__`"),
     }
 }
 
@@ -252,7 +483,7 @@ pub const HISTORICAL_PUNCTS: *const [(*const c_char, Token)] = &[
     (c!(">"), Token::Greater),
 ];
 
-const KEYWORDS: *const [(*const c_char, Token)] = &[
+pub const KEYWORDS: *const [(*const c_char, Token)] = &[
     (c!("auto"), Token::Auto),
     (c!("extrn"), Token::Extrn),
     (c!("case"), Token::Case),
@@ -264,6 +495,9 @@ const KEYWORDS: *const [(*const c_char, Token)] = &[
     (c!("return"), Token::Return),
     (c!("__asm__"), Token::Asm),
     (c!("__variadic__"), Token::Variadic),
+    (c!("__include__"), Token::Include),
+    (c!("__if_target__"), Token::IfTarget),
+    (c!("__else__"), Token::ElseTarget),
 ];
 
 #[derive(Clone, Copy)]
@@ -281,11 +515,16 @@ pub struct Lexer {
     pub parse_point: Parse_Point,
 
     pub historical: bool,
+    // Yield comments as Token::Comment instead of skipping them. Only the tools that need to
+    // reproduce the source (like bfmt) enable this. The compiler never sees comment tokens.
+    pub comments: bool,
     pub string_storage: String_Builder,
     pub token: Token,
     pub string: *const c_char,
     pub int_number: u64,
     pub loc: Loc,
+    // Where the source text of the current token starts. It ends at parse_point.current.
+    pub token_start: *const c_char,
 }
 
 pub unsafe fn new(input_path: *const c_char, input_stream: *const c_char, eof: *const c_char, historical: bool) -> Lexer {
@@ -464,6 +703,27 @@ unsafe fn parse_number(l: *mut Lexer, radix: Radix, report_point: Parse_Point) -
     return Some(());
 }
 
+// Makes Token::Comment out of the comment that starts at `start` and ends at the current point.
+// The text of the comment (including `//` or `/* */`, but without the trailing newline) is put into
+// Lexer::string.
+unsafe fn comment_token(l: *mut Lexer, start: Parse_Point) {
+    let mut end = (*l).parse_point.current;
+    if end > start.current && *end.sub(1) == '\n' as c_char && *start.current.add(1) == '/' as c_char {
+        end = end.sub(1);
+    }
+    (*l).token = Token::Comment;
+    (*l).loc = Loc {
+        input_path: (*l).input_path,
+        line_number: start.line_number as i32,
+        line_offset: start.current.offset_from(start.line_start) as i32 + 1,
+    };
+    (*l).token_start = start.current;
+    (*l).string_storage.count = 0;
+    da_append_many(&mut (*l).string_storage, slice::from_raw_parts(start.current, end.offset_from(start.current) as usize));
+    da_append(&mut (*l).string_storage, 0);
+    (*l).string = (*l).string_storage.items;
+}
+
 pub unsafe fn get_token(l: *mut Lexer) -> Option<()> {
     'comments: loop {
         skip_whitespaces(l);
@@ -477,11 +737,17 @@ pub unsafe fn get_token(l: *mut Lexer) -> Option<()> {
                 return None;
             }
             skip_until(l, c!("\n"));
+            if (*l).comments {
+                return Some(comment_token(l, saved_point));
+            }
             continue 'comments;
         }
 
         if skip_prefix(l, c!("/*")) {
             skip_until(l, c!("*/"));
+            if (*l).comments {
+                return Some(comment_token(l, saved_point));
+            }
             continue 'comments;
         }
 
@@ -489,6 +755,7 @@ pub unsafe fn get_token(l: *mut Lexer) -> Option<()> {
     }
 
     (*l).loc = loc(l);
+    (*l).token_start = (*l).parse_point.current;
 
     let Some(x) = peek_char(l) else {
         (*l).token = Token::EOF;
@@ -608,3 +875,174 @@ pub unsafe fn get_token(l: *mut Lexer) -> Option<()> {
     (*l).token = Token::ParseError;
     None
 }
+
+unsafe fn diag_collect(loc: Loc, message: *const c_char, severity: *const c_char) {
+    let mut text = message;
+    while *text == ' ' as c_char { text = text.add(1); }
+    let text = strdup(text);
+    let mut n = strlen(text);
+    while n > 0 && *text.add(n - 1) == '\n' as c_char {
+        n -= 1;
+        *text.add(n) = 0;
+    }
+
+    let diagnostic = Diagnostic {
+        loc: Loc { input_path: strdup(loc.input_path), ..loc },
+        length: diag_span_length(loc),
+        severity,
+        message: text,
+        notes: zeroed(),
+    };
+    if strcmp(severity, c!("note")) == 0 && diagnostics.count > 0 {
+        da_append(&mut (*diagnostics.items.add(diagnostics.count - 1)).notes, diagnostic);
+    } else {
+        da_append(&raw mut diagnostics, diagnostic);
+    }
+}
+
+unsafe fn diag_json_location(jim: *mut Jim, d: Diagnostic) {
+    jim_object_begin(jim);
+        jim_member_key(jim, c!("path"));
+        jim_string(jim, d.loc.input_path);
+        jim_member_key(jim, c!("line"));
+        jim_integer(jim, d.loc.line_number as c_longlong);
+        jim_member_key(jim, c!("column"));
+        jim_integer(jim, d.loc.line_offset as c_longlong);
+        jim_member_key(jim, c!("length"));
+        jim_integer(jim, d.length as c_longlong);
+    jim_object_end(jim);
+}
+
+unsafe fn diag_json(jim: *mut Jim) {
+    jim_array_begin(jim);
+    for i in 0..diagnostics.count {
+        let d = *diagnostics.items.add(i);
+        jim_object_begin(jim);
+            jim_member_key(jim, c!("severity"));
+            jim_string(jim, d.severity);
+            jim_member_key(jim, c!("message"));
+            jim_string(jim, d.message);
+            jim_member_key(jim, c!("location"));
+            diag_json_location(jim, d);
+            jim_member_key(jim, c!("notes"));
+            jim_array_begin(jim);
+            for j in 0..d.notes.count {
+                let note = *d.notes.items.add(j);
+                jim_object_begin(jim);
+                    jim_member_key(jim, c!("message"));
+                    jim_string(jim, note.message);
+                    jim_member_key(jim, c!("location"));
+                    diag_json_location(jim, note);
+                jim_object_end(jim);
+            }
+            jim_array_end(jim);
+        jim_object_end(jim);
+    }
+    jim_array_end(jim);
+}
+
+unsafe fn diag_sarif_location(jim: *mut Jim, d: Diagnostic, with_message: bool) {
+    jim_object_begin(jim);
+        if with_message {
+            jim_member_key(jim, c!("message"));
+            jim_object_begin(jim);
+                jim_member_key(jim, c!("text"));
+                jim_string(jim, d.message);
+            jim_object_end(jim);
+        }
+        jim_member_key(jim, c!("physicalLocation"));
+        jim_object_begin(jim);
+            jim_member_key(jim, c!("artifactLocation"));
+            jim_object_begin(jim);
+                jim_member_key(jim, c!("uri"));
+                jim_string(jim, d.loc.input_path);
+            jim_object_end(jim);
+            jim_member_key(jim, c!("region"));
+            jim_object_begin(jim);
+                jim_member_key(jim, c!("startLine"));
+                jim_integer(jim, d.loc.line_number as c_longlong);
+                jim_member_key(jim, c!("startColumn"));
+                jim_integer(jim, d.loc.line_offset as c_longlong);
+                jim_member_key(jim, c!("endColumn"));
+                jim_integer(jim, (d.loc.line_offset as usize + d.length) as c_longlong);
+            jim_object_end(jim);
+        jim_object_end(jim);
+    jim_object_end(jim);
+}
+
+// https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html
+unsafe fn diag_sarif(jim: *mut Jim) {
+    jim_object_begin(jim);
+        jim_member_key(jim, c!("$schema"));
+        jim_string(jim, c!("https://json.schemastore.org/sarif-2.1.0.json"));
+        jim_member_key(jim, c!("version"));
+        jim_string(jim, c!("2.1.0"));
+        jim_member_key(jim, c!("runs"));
+        jim_array_begin(jim);
+            jim_object_begin(jim);
+                jim_member_key(jim, c!("tool"));
+                jim_object_begin(jim);
+                    jim_member_key(jim, c!("driver"));
+                    jim_object_begin(jim);
+                        jim_member_key(jim, c!("name"));
+                        jim_string(jim, c!("b"));
+                        jim_member_key(jim, c!("informationUri"));
+                        jim_string(jim, c!("https://github.com/bext-lang/b"));
+                    jim_object_end(jim);
+                jim_object_end(jim);
+                jim_member_key(jim, c!("results"));
+                jim_array_begin(jim);
+                for i in 0..diagnostics.count {
+                    let d = *diagnostics.items.add(i);
+                    jim_object_begin(jim);
+                        jim_member_key(jim, c!("level"));
+                        jim_string(jim, d.severity);
+                        jim_member_key(jim, c!("message"));
+                        jim_object_begin(jim);
+                            jim_member_key(jim, c!("text"));
+                            jim_string(jim, d.message);
+                        jim_object_end(jim);
+                        jim_member_key(jim, c!("locations"));
+                        jim_array_begin(jim);
+                            diag_sarif_location(jim, d, false);
+                        jim_array_end(jim);
+                        jim_member_key(jim, c!("relatedLocations"));
+                        jim_array_begin(jim);
+                        for j in 0..d.notes.count {
+                            diag_sarif_location(jim, *d.notes.items.add(j), true);
+                        }
+                        jim_array_end(jim);
+                    jim_object_end(jim);
+                }
+                jim_array_end(jim);
+            jim_object_end(jim);
+        jim_array_end(jim);
+    jim_object_end(jim);
+}
+
+// Outputs the collected diagnostics as a single document to diag_output_path (or stdout) if a machine-readable
+// format is selected. Stdout keeps the document apart from the logs that go to stderr.
+// Safe to call several times, only the first call outputs anything.
+pub unsafe fn diag_flush() {
+    static mut flushed: bool = false;
+    if diag_format == Diag_Format::Text || flushed { return; }
+    flushed = true;
+
+    let mut jim: Jim = zeroed();
+    jim.pp = 4;
+    jim_begin(&mut jim);
+    match diag_format {
+        Diag_Format::Json  => diag_json(&mut jim),
+        Diag_Format::Sarif => diag_sarif(&mut jim),
+        Diag_Format::Text  => unreachable!(),
+    }
+    let output = if diag_output_path.is_null() { stdout() } else { fopen(diag_output_path, c!("wb")) };
+    if output.is_null() {
+        log(Log_Level::ERROR, c!("could not write the diagnostics to %s"), diag_output_path);
+    } else {
+        fwrite(jim.sink as *const c_void, 1, jim.sink_count, output);
+        fprintf(output, c!("\n"));
+        if output == stdout() { fflush(output); } else { fclose(output); }
+    }
+    free(jim.sink);
+}
@@ -85,6 +85,8 @@ macro_rules! cmd_append {
 extern "C" {
     #[link_name = "nob_cmd_run_sync_and_reset"]
     pub fn cmd_run_sync_and_reset(cmd: *mut Cmd) -> bool;
+    #[link_name = "nob_cmd_run_async_and_reset"]
+    pub fn cmd_run_async_and_reset(cmd: *mut Cmd) -> Proc;
 }
 
 pub type String_Builder = Array<c_char>;
@@ -103,6 +105,11 @@ type Fd = *mut c_void;
 #[cfg(not(target_os = "windows"))]
 type Fd = c_int;
 
+#[cfg(target_os = "windows")]
+pub type Proc = *mut c_void;
+#[cfg(not(target_os = "windows"))]
+pub type Proc = c_int;
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct Cmd_Redirect {
@@ -159,6 +166,8 @@ extern "C" {
     pub fn cmd_run_sync_redirect_and_reset(cmd: *mut Cmd, redirect: Cmd_Redirect) -> bool;
     #[link_name = "nob_fd_open_for_write"]
     pub fn fd_open_for_write(path: *const c_char) -> Fd;
+    #[link_name = "nob_fd_open_for_read"]
+    pub fn fd_open_for_read(path: *const c_char) -> Fd;
     #[link_name = "nob_log"]
     pub fn log(level: Log_Level, fmt: *const c_char, ...);
     #[link_name = "nob_minimal_log_level"]
@@ -0,0 +1,799 @@
+// Optional optimization passes over the IR enabled with the -O flag. The only exception is
+// coalesce_program() which runs on every compilation, see compiler_main().
+//
+// The passes are target independent and run before the IR is handed to the codegens, so they must
+// not assume anything about the word size of the target or whether it treats the values as signed
+// or unsigned. See is_portable_literal() for more info.
+//
+// The passes only ever replace the opcode of an existing op or remove ops completely. They never
+// move the ops around. That keeps OpWithLocation::loc pointing at the right place in the source
+// code, and OpWithLocation::scope_events_count non-decreasing along the body. The scope events of a
+// removed op are simply picked up by the next op that survived (or by the end of the function).
+
+use core::mem::zeroed;
+use crate::nob::*;
+use crate::crust::libc::*;
+use crate::ir::*;
+
+/// The biggest literal that behaves the same on all the targets.
+///
+/// The smallest word size among the targets is 16 bits and some of them compare, divide and shift
+/// the words as signed values while others do that as unsigned ones. The values in the range
+/// 0..=PORTABLE_LITERAL_MAX are the only ones that are unaffected by either of those differences.
+pub const PORTABLE_LITERAL_MAX: u64 = 0x7FFF;
+
+pub unsafe fn is_portable_literal(value: u64) -> bool {
+    value <= PORTABLE_LITERAL_MAX
+}
+
+pub unsafe fn portable_literal(arg: Arg) -> Option<u64> {
+    match arg {
+        Arg::Literal(value) if is_portable_literal(value) => Some(value),
+        _ => None,
+    }
+}
+
+pub unsafe fn fold_binop(binop: Binop, lhs: u64, rhs: u64) -> Option<u64> {
+    let result = match binop {
+        Binop::Plus         => lhs + rhs,
+        Binop::Minus        => lhs.checked_sub(rhs)?,
+        Binop::Mult         => lhs * rhs,
+        Binop::Div          => lhs.checked_div(rhs)?,
+        Binop::Mod          => lhs.checked_rem(rhs)?,
+        Binop::Equal        => (lhs == rhs) as u64,
+        Binop::NotEqual     => (lhs != rhs) as u64,
+        Binop::Less         => (lhs < rhs) as u64,
+        Binop::LessEqual    => (lhs <= rhs) as u64,
+        Binop::Greater      => (lhs > rhs) as u64,
+        Binop::GreaterEqual => (lhs >= rhs) as u64,
+        Binop::BitOr        => lhs | rhs,
+        Binop::BitAnd       => lhs & rhs,
+        Binop::BitShl       => if rhs < 16 { lhs << rhs } else { return None },
+        Binop::BitShr       => if rhs < 16 { lhs >> rhs } else { return None },
+    };
+    if !is_portable_literal(result) { return None; }
+    Some(result)
+}
+
+/// Collects pointers to all the arguments the op reads.
+///
+/// The function of Op::Funcall is collected only if `include_fun` is true, because not all the
+/// codegens can call every kind of Arg.
+pub unsafe fn collect_op_args(op: *mut Op, include_fun: bool, args: *mut Array<*mut Arg>) {
+    match *op {
+        Op::Bogus => unreachable!("bogus-amogus"),
+        Op::Return {ref mut arg} => if let Some(arg) = arg {
+            da_append(args, arg);
+        }
+        Op::UnaryNot {ref mut arg, ..} |
+        Op::Negate {ref mut arg, ..} |
+        Op::AutoAssign {ref mut arg, ..} |
+        Op::ExternalAssign {ref mut arg, ..} |
+        Op::Store {ref mut arg, ..} |
+        Op::JmpIfNotLabel {ref mut arg, ..} => da_append(args, arg),
+        Op::Binop {ref mut lhs, ref mut rhs, ..} => {
+            da_append(args, lhs);
+            da_append(args, rhs);
+        }
+        Op::Index {ref mut arg, ref mut offset, ..} => {
+            da_append(args, arg);
+            da_append(args, offset);
+        }
+        Op::Funcall {ref mut fun, args: call_args, ..} => {
+            if include_fun {
+                da_append(args, fun);
+            }
+            for i in 0..call_args.count {
+                da_append(args, call_args.items.add(i));
+            }
+        }
+        Op::Asm {..} | Op::Label {..} | Op::JmpLabel {..} => {}
+    }
+}
+
+/// The auto var the op writes its result into.
+pub unsafe fn op_result(op: Op) -> Option<usize> {
+    match op {
+        Op::AutoAssign {index, ..} | Op::Binop {index, ..} => Some(index),
+        Op::UnaryNot {result, ..} | Op::Negate {result, ..} | Op::Index {result, ..} | Op::Funcall {result, ..} => Some(result),
+        _ => None,
+    }
+}
+
+pub unsafe fn has_asm(body: *const [OpWithLocation]) -> bool {
+    for i in 0..body.len() {
+        if let Op::Asm {..} = (*body)[i].opcode {
+            return true;
+        }
+    }
+    false
+}
+
+/// Removes all the ops for which `keep` is false preserving the order of the rest
+pub unsafe fn retain_ops(body: *mut Array<OpWithLocation>, keep: *const Array<bool>) -> bool {
+    let mut count = 0;
+    for i in 0..(*body).count {
+        if *(*keep).items.add(i) {
+            *(*body).items.add(count) = *(*body).items.add(i);
+            count += 1;
+        }
+    }
+    let changed = count != (*body).count;
+    (*body).count = count;
+    changed
+}
+
+pub unsafe fn fold_constants(body: *mut Array<OpWithLocation>) -> bool {
+    let mut changed = false;
+    let mut keep: Array<bool> = zeroed();
+    for i in 0..(*body).count {
+        let op = (*body).items.add(i);
+        let mut remove = false;
+        let folded = match (*op).opcode {
+            Op::Binop {binop, index, lhs, rhs} => {
+                if let (Some(lhs), Some(rhs)) = (portable_literal(lhs), portable_literal(rhs)) {
+                    fold_binop(binop, lhs, rhs).map(|value| Op::AutoAssign {index, arg: Arg::Literal(value)})
+                } else {
+                    None
+                }
+            }
+            Op::Negate {result, arg: Arg::Literal(value)} => {
+                Some(Op::AutoAssign {index: result, arg: Arg::Literal(value.wrapping_neg())})
+            }
+            Op::UnaryNot {result, arg} => {
+                portable_literal(arg).map(|value| Op::AutoAssign {index: result, arg: Arg::Literal((value == 0) as u64)})
+            }
+            Op::JmpIfNotLabel {label, arg} => match portable_literal(arg) {
+                Some(0) => Some(Op::JmpLabel {label}),
+                Some(_) => {
+                    remove = true;
+                    None
+                }
+                None => None,
+            }
+            _ => None,
+        };
+        if let Some(folded) = folded {
+            (*op).opcode = folded;
+            changed = true;
+        }
+        da_append(&mut keep, !remove);
+    }
+    changed = retain_ops(body, &keep) || changed;
+    free(keep.items);
+    changed
+}
+
+pub unsafe fn find_copy(copies: *const Array<(usize, Arg)>, index: usize) -> Option<Arg> {
+    for i in 0..(*copies).count {
+        let (key, value) = *(*copies).items.add(i);
+        if key == index {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Marks the auto vars that can be accessed through a pointer, which is all of them as soon as the
+/// function takes an address of any auto var with Arg::RefAutoVar. The address gives access to the
+/// neighbouring auto vars through pointer arithmetic in both directions: the positive offsets reach
+/// the auto vars below it (that's how the automatic vectors work, see coalesce_auto_vars()) and the
+/// negative ones reach the auto vars above it.
+pub unsafe fn mark_address_taken(body: *mut Array<OpWithLocation>, marks: *mut Array<bool>) {
+    let mut args: Array<*mut Arg> = zeroed();
+    for i in 0..(*body).count {
+        args.count = 0;
+        collect_op_args(&mut (*(*body).items.add(i)).opcode, true, &mut args);
+        for j in 0..args.count {
+            if let Arg::RefAutoVar(_) = **args.items.add(j) {
+                for index in 0..(*marks).count {
+                    *(*marks).items.add(index) = true;
+                }
+                free(args.items);
+                return;
+            }
+        }
+    }
+    free(args.items);
+}
+
+/// Forgets all the copies that become invalid after the auto var `index` changes its value
+pub unsafe fn kill_copies_of_auto(copies: *mut Array<(usize, Arg)>, index: usize) {
+    let mut i = 0;
+    while i < (*copies).count {
+        let (key, value) = *(*copies).items.add(i);
+        let killed = key == index || match value {
+            Arg::AutoVar(other) | Arg::Deref(other) => other == index,
+            _ => false,
+        };
+        if killed {
+            (*copies).count -= 1;
+            *(*copies).items.add(i) = *(*copies).items.add((*copies).count);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Forgets all the copies that become invalid after something writes into an unknown location
+/// in the memory
+pub unsafe fn kill_copies_of_memory(copies: *mut Array<(usize, Arg)>, address_taken: *const Array<bool>) {
+    let mut i = 0;
+    while i < (*copies).count {
+        let (_, value) = *(*copies).items.add(i);
+        let killed = match value {
+            Arg::Deref(_) | Arg::External(_) => true,
+            Arg::AutoVar(index) => *(*address_taken).items.add(index),
+            _ => false,
+        };
+        if killed {
+            (*copies).count -= 1;
+            *(*copies).items.add(i) = *(*copies).items.add((*copies).count);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Replaces the reads of the auto vars with the values that were assigned to them by Op::AutoAssign
+/// earlier within the same basic block.
+pub unsafe fn propagate_copies(body: *mut Array<OpWithLocation>, auto_vars_count: usize) -> bool {
+    let mut changed = false;
+
+    let mut address_taken: Array<bool> = zeroed();
+    for _ in 0..=auto_vars_count {
+        da_append(&mut address_taken, false);
+    }
+    mark_address_taken(body, &mut address_taken);
+
+    let mut args: Array<*mut Arg> = zeroed();
+    let mut copies: Array<(usize, Arg)> = zeroed();
+    for i in 0..(*body).count {
+        let op = &mut (*(*body).items.add(i)).opcode;
+
+        if let Op::Label {..} = *op {
+            copies.count = 0;
+        }
+
+        args.count = 0;
+        collect_op_args(op, false, &mut args);
+        for j in 0..args.count {
+            let arg = *args.items.add(j);
+            match *arg {
+                Arg::AutoVar(index) => if let Some(value) = find_copy(&copies, index) {
+                    *arg = value;
+                    changed = true;
+                }
+                Arg::Deref(index) => if let Some(Arg::AutoVar(other)) = find_copy(&copies, index) {
+                    *arg = Arg::Deref(other);
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+        match *op {
+            Op::Store {ref mut index, ..} => if let Some(Arg::AutoVar(other)) = find_copy(&copies, *index) {
+                *index = other;
+                changed = true;
+            }
+            Op::Funcall {ref mut fun, ..} => match *fun {
+                Arg::AutoVar(index) => if let Some(Arg::AutoVar(other)) = find_copy(&copies, index) {
+                    *fun = Arg::AutoVar(other);
+                    changed = true;
+                }
+                Arg::Deref(index) => if let Some(Arg::AutoVar(other)) = find_copy(&copies, index) {
+                    *fun = Arg::Deref(other);
+                    changed = true;
+                }
+                _ => {}
+            }
+            _ => {}
+        }
+
+        match *op {
+            Op::Store {..} | Op::ExternalAssign {..} | Op::Funcall {..} => kill_copies_of_memory(&mut copies, &address_taken),
+            Op::Asm {..} | Op::JmpLabel {..} | Op::JmpIfNotLabel {..} | Op::Return {..} => copies.count = 0,
+            _ => {}
+        }
+        if let Some(result) = op_result(*op) {
+            kill_copies_of_auto(&mut copies, result);
+        }
+        if let Op::AutoAssign {index, arg} = *op {
+            let self_reference = match arg {
+                Arg::AutoVar(other) | Arg::Deref(other) => other == index,
+                _ => false,
+            };
+            if !*address_taken.items.add(index) && !self_reference {
+                da_append(&mut copies, (index, arg));
+            }
+        }
+    }
+
+    free(copies.items);
+    free(args.items);
+    free(address_taken.items);
+    changed
+}
+
+pub unsafe fn max_label(body: *const Array<OpWithLocation>) -> usize {
+    let mut result = 0;
+    for i in 0..(*body).count {
+        match (*(*body).items.add(i)).opcode {
+            Op::Label {label} | Op::JmpLabel {label} | Op::JmpIfNotLabel {label, ..} => {
+                if label > result { result = label; }
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Makes the jumps to the labels that are immediately followed by another jump go straight to the
+/// final destination, and removes the jumps to the labels that immediately follow them.
+pub unsafe fn thread_jumps(body: *mut Array<OpWithLocation>) -> bool {
+    let mut changed = false;
+
+    // forward[label] is the label that the jump to `label` eventually ends up at
+    let mut forward: Array<usize> = zeroed();
+    for label in 0..=max_label(body) {
+        da_append(&mut forward, label);
+    }
+    for i in 0..(*body).count {
+        if let Op::Label {label} = (*(*body).items.add(i)).opcode {
+            let mut j = i + 1;
+            while j < (*body).count {
+                match (*(*body).items.add(j)).opcode {
+                    Op::Label {..} => j += 1,
+                    Op::JmpLabel {label: target} => {
+                        *forward.items.add(label) = target;
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+        }
+    }
+    for label in 0..forward.count {
+        // The amount of steps is limited in case of the infinite loops like `a: goto a;`
+        let mut target = label;
+        for _ in 0..forward.count {
+            let next = *forward.items.add(target);
+            if next == target { break; }
+            target = next;
+        }
+        *forward.items.add(label) = target;
+    }
+
+    let mut keep: Array<bool> = zeroed();
+    for i in 0..(*body).count {
+        let op = &mut (*(*body).items.add(i)).opcode;
+        let mut remove = false;
+        match *op {
+            Op::JmpLabel {ref mut label} | Op::JmpIfNotLabel {ref mut label, ..} => {
+                let target = *forward.items.add(*label);
+                if target != *label {
+                    *label = target;
+                    changed = true;
+                }
+                let mut j = i + 1;
+                while j < (*body).count {
+                    match (*(*body).items.add(j)).opcode {
+                        Op::Label {label: next} => {
+                            if next == *label {
+                                remove = true;
+                                break;
+                            }
+                            j += 1;
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            _ => {}
+        }
+        da_append(&mut keep, !remove);
+    }
+
+    changed = retain_ops(body, &keep) || changed;
+    free(keep.items);
+    free(forward.items);
+    changed
+}
+
+/// Removes the labels nobody jumps to and the ops that can't be reached because they follow
+/// Op::Return or Op::JmpLabel.
+pub unsafe fn remove_unreachable_ops(body: *mut Array<OpWithLocation>) -> bool {
+    let mut referenced: Array<bool> = zeroed();
+    for _ in 0..=max_label(body) {
+        da_append(&mut referenced, false);
+    }
+    for i in 0..(*body).count {
+        match (*(*body).items.add(i)).opcode {
+            Op::JmpLabel {label} | Op::JmpIfNotLabel {label, ..} => *referenced.items.add(label) = true,
+            _ => {}
+        }
+    }
+
+    let mut keep: Array<bool> = zeroed();
+    let mut reachable = true;
+    for i in 0..(*body).count {
+        match (*(*body).items.add(i)).opcode {
+            Op::Label {label} => {
+                let referenced = *referenced.items.add(label);
+                reachable = reachable || referenced;
+                da_append(&mut keep, referenced);
+            }
+            Op::Return {..} | Op::JmpLabel {..} => {
+                da_append(&mut keep, reachable);
+                reachable = false;
+            }
+            _ => da_append(&mut keep, reachable),
+        }
+    }
+
+    let changed = retain_ops(body, &keep);
+    free(keep.items);
+    free(referenced.items);
+    changed
+}
+
+/// Removes the side effect free ops which results are never read
+pub unsafe fn remove_dead_ops(body: *mut Array<OpWithLocation>, auto_vars_count: usize) -> bool {
+    let mut read: Array<bool> = zeroed();
+    for _ in 0..=auto_vars_count {
+        da_append(&mut read, false);
+    }
+    // The writes into the auto vars that are accessible through a pointer may be read through it
+    mark_address_taken(body, &mut read);
+
+    let mut args: Array<*mut Arg> = zeroed();
+    for i in 0..(*body).count {
+        let op = &mut (*(*body).items.add(i)).opcode;
+        if let Op::Store {index, ..} = *op {
+            *read.items.add(index) = true;
+        }
+        args.count = 0;
+        collect_op_args(op, true, &mut args);
+        for j in 0..args.count {
+            match **args.items.add(j) {
+                Arg::AutoVar(index) | Arg::Deref(index) | Arg::RefAutoVar(index) => *read.items.add(index) = true,
+                _ => {}
+            }
+        }
+    }
+
+    let mut keep: Array<bool> = zeroed();
+    for i in 0..(*body).count {
+        let dead = match (*(*body).items.add(i)).opcode {
+            Op::AutoAssign {index, ..} | Op::Binop {index, ..} |
+            Op::UnaryNot {result: index, ..} | Op::Negate {result: index, ..} | Op::Index {result: index, ..} => !*read.items.add(index),
+            _ => false,
+        };
+        da_append(&mut keep, !dead);
+    }
+
+    let changed = retain_ops(body, &keep);
+    free(keep.items);
+    free(args.items);
+    free(read.items);
+    changed
+}
+
+/// Numbers the labels that are left after the other passes from 0 without gaps. Some of the codegens
+/// (like uxn) allocate their own labels per Op::Label in order and index them by the IR label.
+pub unsafe fn renumber_labels(body: *mut Array<OpWithLocation>) {
+    let mut renumbered: Array<usize> = zeroed();
+    for _ in 0..=max_label(body) {
+        da_append(&mut renumbered, usize::MAX);
+    }
+    let mut count = 0;
+    for i in 0..(*body).count {
+        if let Op::Label {label} = (*(*body).items.add(i)).opcode {
+            *renumbered.items.add(label) = count;
+            count += 1;
+        }
+    }
+    for i in 0..(*body).count {
+        let op = &mut (*(*body).items.add(i)).opcode;
+        match *op {
+            Op::Label {ref mut label} | Op::JmpLabel {ref mut label} | Op::JmpIfNotLabel {ref mut label, ..} => {
+                *label = *renumbered.items.add(*label);
+            }
+            _ => {}
+        }
+    }
+    free(renumbered.items);
+}
+
+pub unsafe fn optimize_func(func: *mut Func) {
+    // Inline assembly may access the auto vars and jump around behind our back
+    if has_asm(da_slice((*func).body)) { return; }
+
+    loop {
+        let mut changed = false;
+        changed = fold_constants(&mut (*func).body) || changed;
+        changed = propagate_copies(&mut (*func).body, (*func).auto_vars_count) || changed;
+        changed = thread_jumps(&mut (*func).body) || changed;
+        changed = remove_unreachable_ops(&mut (*func).body) || changed;
+        changed = remove_dead_ops(&mut (*func).body, (*func).auto_vars_count) || changed;
+        if !changed { break; }
+    }
+    renumber_labels(&mut (*func).body);
+}
+
+pub unsafe fn optimize_program(p: *mut Program) {
+    for i in 0..(*p).funcs.count {
+        optimize_func((*p).funcs.items.add(i));
+    }
+}
+
+/// Shrinks the stack frames of all the functions with coalesce_auto_vars(). Unlike the rest of the
+/// passes it doesn't change what the program does, so it's not hidden behind -O.
+pub unsafe fn coalesce_program(p: *mut Program) {
+    for i in 0..(*p).funcs.count {
+        let func = (*p).funcs.items.add(i);
+        // Inline assembly may access the auto vars behind our back
+        if has_asm(da_slice((*func).body)) { continue; }
+        coalesce_auto_vars(func);
+    }
+}
+
+/// Like op_result() but gives a pointer to the field of the op so it could be modified
+pub unsafe fn op_result_mut(op: *mut Op) -> Option<*mut usize> {
+    match *op {
+        Op::AutoAssign {ref mut index, ..} | Op::Binop {ref mut index, ..} => Some(index),
+        Op::UnaryNot {ref mut result, ..} | Op::Negate {ref mut result, ..} |
+        Op::Index {ref mut result, ..} | Op::Funcall {ref mut result, ..} => Some(result),
+        _ => None,
+    }
+}
+
+/// Collects the auto vars the op reads. The indices may repeat.
+pub unsafe fn collect_op_reads(op: *mut Op, args: *mut Array<*mut Arg>, reads: *mut Array<usize>) {
+    (*args).count = 0;
+    collect_op_args(op, true, args);
+    for i in 0..(*args).count {
+        match **(*args).items.add(i) {
+            Arg::AutoVar(index) | Arg::Deref(index) | Arg::RefAutoVar(index) => da_append(reads, index),
+            _ => {}
+        }
+    }
+    if let Op::Store {index, ..} = *op {
+        da_append(reads, index);
+    }
+}
+
+pub unsafe fn bitset_get(bits: *const u64, i: usize) -> bool {
+    *bits.add(i/64) & (1 << (i%64)) != 0
+}
+
+pub unsafe fn bitset_set(bits: *mut u64, i: usize) {
+    *bits.add(i/64) |= 1 << (i%64);
+}
+
+pub unsafe fn bitset_unset(bits: *mut u64, i: usize) {
+    *bits.add(i/64) &= !(1 << (i%64));
+}
+
+/// Reassigns the auto vars of the temporaries with disjoint lifetimes to the same slots and
+/// shrinks Func::auto_vars_count accordingly.
+///
+/// Only the auto vars that are not parameters, not named variables (see ScopeEvent::Declare) and
+/// are located above anything that had its address taken with Arg::RefAutoVar are touched. The
+/// latter is because an address of an auto var gives access to all the auto vars below it through
+/// pointer arithmetic. That's how the automatic vectors work, for instance.
+pub unsafe fn coalesce_auto_vars(func: *mut Func) -> bool {
+    let body = &mut (*func).body;
+    let auto_vars_count = (*func).auto_vars_count;
+
+    // The slots that must stay where they are
+    let mut fixed: Array<bool> = zeroed();
+    for index in 0..=auto_vars_count {
+        da_append(&mut fixed, index <= (*func).params_count);
+    }
+    for i in 0..(*func).scope_events.count {
+        if let ScopeEvent::Declare {index, ..} = *(*func).scope_events.items.add(i) {
+            *fixed.items.add(index) = true;
+        }
+    }
+    let mut args: Array<*mut Arg> = zeroed();
+    let mut reads: Array<usize> = zeroed();
+    let mut max_ref = 0;
+    for i in 0..(*body).count {
+        args.count = 0;
+        collect_op_args(&mut (*(*body).items.add(i)).opcode, true, &mut args);
+        for j in 0..args.count {
+            if let Arg::RefAutoVar(index) = **args.items.add(j) {
+                if index > max_ref { max_ref = index; }
+            }
+        }
+    }
+    for index in 0..=max_ref {
+        *fixed.items.add(index) = true;
+    }
+
+    // Dense numbering of the temporaries so the bitsets below stay small
+    let mut temp_of_slot: Array<usize> = zeroed();
+    let mut slot_of_temp: Array<usize> = zeroed();
+    for index in 0..=auto_vars_count {
+        da_append(&mut temp_of_slot, slot_of_temp.count);
+        if !*fixed.items.add(index) {
+            da_append(&mut slot_of_temp, index);
+        }
+    }
+    let temps_count = slot_of_temp.count;
+    let words = (temps_count + 63)/64;
+
+    if temps_count == 0 {
+        free(fixed.items);
+        free(args.items);
+        free(reads.items);
+        free(temp_of_slot.items);
+        free(slot_of_temp.items);
+        return false;
+    }
+
+    let mut label_positions: Array<usize> = zeroed();
+    for _ in 0..=max_label(body) {
+        da_append(&mut label_positions, 0);
+    }
+    for i in 0..(*body).count {
+        if let Op::Label {label} = (*(*body).items.add(i)).opcode {
+            *label_positions.items.add(label) = i;
+        }
+    }
+
+    // live[i] is the set of temporaries that are live right before the op i
+    let mut live: Array<u64> = zeroed();
+    for _ in 0..((*body).count + 1)*words {
+        da_append(&mut live, 0);
+    }
+    let mut live_out: Array<u64> = zeroed();
+    for _ in 0..words {
+        da_append(&mut live_out, 0);
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..(*body).count).rev() {
+            let op = &mut (*(*body).items.add(i)).opcode;
+
+            for w in 0..words {
+                *live_out.items.add(w) = 0;
+            }
+            let next = live.items.add((i + 1)*words);
+            match *op {
+                Op::Return {..} => {}
+                Op::JmpLabel {label} => {
+                    let target = live.items.add(*label_positions.items.add(label)*words);
+                    for w in 0..words { *live_out.items.add(w) |= *target.add(w); }
+                }
+                Op::JmpIfNotLabel {label, ..} => {
+                    let target = live.items.add(*label_positions.items.add(label)*words);
+                    for w in 0..words { *live_out.items.add(w) |= *target.add(w) | *next.add(w); }
+                }
+                _ => for w in 0..words { *live_out.items.add(w) |= *next.add(w); }
+            }
+
+            if let Some(result) = op_result(*op) {
+                if !*fixed.items.add(result) {
+                    bitset_unset(live_out.items, *temp_of_slot.items.add(result));
+                }
+            }
+            reads.count = 0;
+            collect_op_reads(op, &mut args, &mut reads);
+            for j in 0..reads.count {
+                let index = *reads.items.add(j);
+                if !*fixed.items.add(index) {
+                    bitset_set(live_out.items, *temp_of_slot.items.add(index));
+                }
+            }
+
+            let current = live.items.add(i*words);
+            for w in 0..words {
+                if *current.add(w) != *live_out.items.add(w) {
+                    *current.add(w) = *live_out.items.add(w);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    // Two temporaries interfere if one of them is written while the other one is live
+    let mut interference: Array<u64> = zeroed();
+    for _ in 0..temps_count*words {
+        da_append(&mut interference, 0);
+    }
+    for i in 0..(*body).count {
+        let op = (*(*body).items.add(i)).opcode;
+        let Some(result) = op_result(op) else { continue };
+        if *fixed.items.add(result) { continue; }
+        let t = *temp_of_slot.items.add(result);
+
+        // None of the ops that have results jump anywhere, so whatever is live right after them
+        // is live before the next op.
+        let next = live.items.add((i + 1)*words);
+        for other in 0..temps_count {
+            if other != t && bitset_get(next, other) {
+                bitset_set(interference.items.add(t*words), other);
+                bitset_set(interference.items.add(other*words), t);
+            }
+        }
+    }
+
+    // Greedily give every temporary the lowest slot among the slots of the temporaries which
+    // doesn't conflict with the temporaries that were already given that slot.
+    let mut color: Array<usize> = zeroed();
+    for t in 0..temps_count {
+        let mut c = 0;
+        'search: loop {
+            for other in 0..t {
+                if *color.items.add(other) == c && bitset_get(interference.items.add(t*words), other) {
+                    c += 1;
+                    continue 'search;
+                }
+            }
+            break;
+        }
+        da_append(&mut color, c);
+    }
+
+    let mut renamed = false;
+    let mut new_auto_vars_count = 0;
+    for index in 1..=auto_vars_count {
+        if *fixed.items.add(index) {
+            new_auto_vars_count = index;
+        }
+    }
+    for t in 0..temps_count {
+        let slot = *slot_of_temp.items.add(*color.items.add(t));
+        if slot != *slot_of_temp.items.add(t) { renamed = true; }
+        if slot > new_auto_vars_count { new_auto_vars_count = slot; }
+    }
+
+    if renamed {
+        for i in 0..(*body).count {
+            let op = &mut (*(*body).items.add(i)).opcode;
+            args.count = 0;
+            collect_op_args(op, true, &mut args);
+            for j in 0..args.count {
+                let arg = *args.items.add(j);
+                match *arg {
+                    Arg::AutoVar(ref mut index) | Arg::Deref(ref mut index) => if !*fixed.items.add(*index) {
+                        *index = *slot_of_temp.items.add(*color.items.add(*temp_of_slot.items.add(*index)));
+                    }
+                    _ => {}
+                }
+            }
+            let mut slots: Array<*mut usize> = zeroed();
+            if let Op::Store {ref mut index, ..} = *op {
+                da_append(&mut slots, index);
+            }
+            if let Some(result) = op_result_mut(op) {
+                da_append(&mut slots, result);
+            }
+            for j in 0..slots.count {
+                let index = *slots.items.add(j);
+                if !*fixed.items.add(*index) {
+                    *index = *slot_of_temp.items.add(*color.items.add(*temp_of_slot.items.add(*index)));
+                }
+            }
+            free(slots.items);
+        }
+    }
+    let shrunk = new_auto_vars_count < auto_vars_count;
+    (*func).auto_vars_count = new_auto_vars_count;
+
+    free(fixed.items);
+    free(args.items);
+    free(reads.items);
+    free(temp_of_slot.items);
+    free(slot_of_temp.items);
+    free(label_positions.items);
+    free(live.items);
+    free(live_out.items);
+    free(interference.items);
+    free(color.items);
+    renamed || shrunk
+}
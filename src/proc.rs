@@ -0,0 +1,57 @@
+use core::ffi::*;
+use crate::crust::libc::*;
+use crate::nob::*;
+
+extern "C" {
+    pub fn proc_start_and_reset(cmd: *mut Cmd, redirect: Cmd_Redirect) -> Proc;
+    pub fn proc_kill(proc: Proc);
+    pub fn proc_kill_all_on_signals();
+}
+
+pub enum Proc_Status {
+    Exited(c_int),
+    TimedOut,
+    WaitFailed,
+}
+
+/// Waits for the process for at most `timeout_ms`. Pass `u64::MAX` to wait for as long as it takes.
+pub unsafe fn proc_wait_timeout(proc: Proc, timeout_ms: u64) -> Proc_Status {
+    extern "C" {
+        #[link_name = "proc_wait_timeout"]
+        fn proc_wait_timeout_raw(proc: Proc, timeout_ms: u64, exit_code: *mut c_int) -> c_int;
+    }
+    let mut exit_code: c_int = 0;
+    match proc_wait_timeout_raw(proc, timeout_ms, &mut exit_code) {
+        -1 => Proc_Status::TimedOut,
+        0  => Proc_Status::WaitFailed,
+        _  => Proc_Status::Exited(exit_code),
+    }
+}
+
+/// Waits for whichever of the processes exits first for at most `timeout_ms`. Returns its index in `procs` along
+/// with its status, or None if none of them exited in time.
+pub unsafe fn proc_wait_any(procs: *const [Proc], timeout_ms: u64) -> Option<(usize, Proc_Status)> {
+    extern "C" {
+        #[link_name = "proc_wait_any"]
+        fn proc_wait_any_raw(procs: *const Proc, count: usize, timeout_ms: u64, index: *mut usize, exit_code: *mut c_int) -> c_int;
+    }
+    let mut index: usize = 0;
+    let mut exit_code: c_int = 0;
+    match proc_wait_any_raw(procs as *const Proc, procs.len(), timeout_ms, &mut index, &mut exit_code) {
+        -1 => None,
+        0  => Some((index, Proc_Status::WaitFailed)),
+        _  => Some((index, Proc_Status::Exited(exit_code))),
+    }
+}
+
+/// Just like cmd_run_sync_and_reset(), but if the command exits with a non-zero exit code the current process
+/// exits with the same exit code. That's what `b -run` does, so the caller sees the exit code of the program
+/// and not of the compiler.
+pub unsafe fn cmd_run_sync_forwarding_exit_code_and_reset(cmd: *mut Cmd) -> Option<()> {
+    let proc = cmd_run_async_and_reset(cmd);
+    match proc_wait_timeout(proc, u64::MAX) {
+        Proc_Status::Exited(0)    => Some(()),
+        Proc_Status::Exited(code) => exit(code),
+        Proc_Status::TimedOut | Proc_Status::WaitFailed => None,
+    }
+}
@@ -28,7 +28,7 @@ impl Target {
     }
     pub unsafe fn new(self, a: *mut arena::Arena, args: *const [*const c_char]) -> Option<*mut c_void> {
         match self.api {
-            TargetAPI::V1 { new, .. } => new(a, args)
+            TargetAPI::V1 { new, .. } | TargetAPI::V2 { new, .. } => new(a, args)
         }
     }
     pub unsafe fn build (
@@ -41,7 +41,7 @@ impl Target {
         debug: bool,
     ) -> Option<()> {
         match self.api {
-            TargetAPI::V1 { build, .. } => build(gen, program, program_path, garbage_base, nostdlib, debug),
+            TargetAPI::V1 { build, .. } | TargetAPI::V2 { build, .. } => build(gen, program, program_path, garbage_base, nostdlib, debug),
         }
     }
     pub unsafe fn run (
@@ -51,16 +51,64 @@ impl Target {
         run_args: *const [*const c_char],
     ) -> Option<()> {
         match self.api {
-            TargetAPI::V1 { run, .. } => run(gen, program_path, run_args),
+            TargetAPI::V1 { run, .. } | TargetAPI::V2 { run, .. } => run(gen, program_path, run_args),
         }
     }
     pub unsafe fn file_ext(self) -> *const c_char {
         match self.api {
-            TargetAPI::V1 { file_ext, .. } => file_ext,
+            TargetAPI::V1 { file_ext, .. } | TargetAPI::V2 { file_ext, .. } => file_ext,
+        }
+    }
+    /// Whether the target can compile the modules separately (see the -c flag)
+    pub unsafe fn supports_objects(self) -> bool {
+        match self.api {
+            TargetAPI::V1 { .. } => false,
+            TargetAPI::V2 { .. } => true,
+        }
+    }
+    pub unsafe fn build_object (
+        self,
+        gen: *mut c_void,
+        program: *const Program,
+        object_path: *const c_char,
+        garbage_base: *const c_char,
+        debug: bool,
+    ) -> Option<()> {
+        match self.api {
+            TargetAPI::V1 { .. } => unreachable!("Target::build_object() on a target that does not support objects"),
+            TargetAPI::V2 { build_object, .. } => build_object(gen, program, object_path, garbage_base, debug),
+        }
+    }
+    pub unsafe fn link (
+        self,
+        gen: *mut c_void,
+        object_paths: *const [*const c_char],
+        program_path: *const c_char,
+        nostdlib: bool,
+    ) -> Option<()> {
+        match self.api {
+            TargetAPI::V1 { .. } => unreachable!("Target::link() on a target that does not support objects"),
+            TargetAPI::V2 { link, .. } => link(gen, object_paths, program_path, nostdlib),
         }
     }
 }
 
+/// The target the compiler uses when no -t flag is provided, based on the platform the compiler itself is running on
+pub unsafe fn default_target(targets: *const [Target]) -> Option<Target> {
+    // TODO: maybe instead of gas_ the prefix should be gnu_, 'cause that makes more sense.
+    if cfg!(target_arch = "aarch64") && (cfg!(target_os = "linux") || cfg!(target_os = "android")) {
+        Some(Target::by_name(targets, c!("gas-aarch64-linux")).expect("Default target for Linux on AArch64"))
+    } else if cfg!(target_arch = "aarch64") && cfg!(target_os = "macos") {
+        Some(Target::by_name(targets, c!("gas-aarch64-darwin")).expect("Default target for Darwin on AArch64"))
+    } else if cfg!(target_arch = "x86_64") && cfg!(target_os = "linux") {
+        Some(Target::by_name(targets, c!("gas-x86_64-linux")).expect("Default target for Linux on x86_64"))
+    } else if cfg!(target_arch = "x86_64") && cfg!(target_os = "windows") {
+        Some(Target::by_name(targets, c!("gas-x86_64-windows")).expect("Default target for Windows on x86_64"))
+    } else {
+        None
+    }
+}
+
 pub unsafe fn register_apis(targets: *mut Array<Target>, apis: *const [TargetAPI], codegen_name: *const c_char) -> Option<()> {
     for i in 0..apis.len() {
         let api = (*apis)[i];
@@ -102,13 +150,49 @@ pub enum TargetAPI {
             program_path: *const c_char,
             run_args: *const [*const c_char],
         ) -> Option<()>,
-    }
+    },
+    // Everything V1 has plus separate compilation. Every module is compiled into its own object
+    // that exports the functions and globals defined in it, and the objects are linked together.
+    V2 {
+        name: *const c_char,
+        file_ext: *const c_char,
+        new: unsafe fn(
+            a: *mut arena::Arena,
+            args: *const [*const c_char]
+        ) -> Option<*mut c_void>,
+        build: unsafe fn(
+            gen: *mut c_void,
+            program: *const Program,
+            program_path: *const c_char,
+            garbage_base: *const c_char,
+            nostdlib: bool,
+            debug: bool,
+        ) -> Option<()>,
+        run: unsafe fn(
+            gen: *mut c_void,
+            program_path: *const c_char,
+            run_args: *const [*const c_char],
+        ) -> Option<()>,
+        build_object: unsafe fn(
+            gen: *mut c_void,
+            program: *const Program,
+            object_path: *const c_char,
+            garbage_base: *const c_char,
+            debug: bool,
+        ) -> Option<()>,
+        link: unsafe fn(
+            gen: *mut c_void,
+            object_paths: *const [*const c_char],
+            program_path: *const c_char,
+            nostdlib: bool,
+        ) -> Option<()>,
+    },
 }
 
 impl TargetAPI {
     pub unsafe fn name(self) -> *const c_char {
         match self {
-            TargetAPI::V1 { name, .. } => name,
+            TargetAPI::V1 { name, .. } | TargetAPI::V2 { name, .. } => name,
         }
     }
 }
@@ -0,0 +1,11 @@
+// btest: expect-build-fail
+// Every error here is expected. btest records the diagnostics instead of running the program.
+__variadic__(log, 1);
+__variadic__(log, 1);
+
+main() {
+    auto x;
+    x = unknown;
+    &(x + 1);
+case 69: x = 420;
+}
@@ -0,0 +1,6 @@
+main() {
+    extrn printf, exit;
+    printf("exiting with 69\n");
+    exit(69);
+    printf("unreachable\n");
+}
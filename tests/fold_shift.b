@@ -0,0 +1,6 @@
+// Compiled with -O (see tests/fold_shift.flags). The shifts by constants are folded, except for the ones that
+// are out of range on some targets.
+main() {
+    extrn printf;
+    printf("%d %d %d\n", 256 >> 4, 1 << 4, 1 >> 100);
+}
@@ -0,0 +1,14 @@
+__if_target__("gas-*") {
+    kind() return (1);
+} __else__ {
+    kind() return (2);
+}
+
+main() {
+    extrn printf;
+    __if_target__("*-linux") {
+        printf("linux %d\n", kind());
+    } __else__ {
+        printf("not linux %d\n", kind());
+    }
+}
@@ -0,0 +1,8 @@
+// The files in ./include/ are not test cases on their own. They are included from here.
+__include__ "include/twice.b";
+__include__ "include/answer.b";
+
+main() {
+    extrn printf;
+    printf("%d %d\n", twice(21), answer());
+}
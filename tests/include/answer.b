@@ -0,0 +1 @@
+answer() return (42);
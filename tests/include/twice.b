@@ -0,0 +1,4 @@
+// Included from this file and from ../include.b, but compiled only once
+__include__ "../include/answer.b";
+
+twice(x) return (x * 2);
@@ -0,0 +1,9 @@
+// Compiled with -O (see tests/ref_neighbour.flags). An address of an auto var gives access to its neighbours,
+// so the assignment to `b` that is only read through `p` must survive the optimizations.
+main() {
+    extrn printf;
+    auto a, b, p;
+    b = 42;
+    p = &a;
+    printf("%d\n", p[-1]);
+}
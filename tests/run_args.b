@@ -0,0 +1,12 @@
+// The run arguments come from tests/run_args.args
+main(argc, argv) {
+    extrn printf;
+    auto i;
+
+    printf("argc = %d\n", argc);
+    i = 1;
+    while (i < argc) {
+        printf("argv[%d] = %s\n", i, argv[i]);
+        i += 1;
+    }
+}
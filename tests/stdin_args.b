@@ -0,0 +1,17 @@
+// The run arguments come from tests/stdin_args.args and stdin comes from tests/stdin_args.stdin
+main(argc, argv) {
+    extrn printf, getchar, putchar;
+    auto i, c;
+
+    i = 1;
+    while (i < argc) {
+        printf("argv[%d] = %s\n", i, argv[i]);
+        i += 1;
+    }
+
+    // getchar() returns a 32 bit EOF on some targets, so anything that is not a byte is the end of the input
+    while ((c = getchar()) >= 0 & c < 256) {
+        if (c >= 'a' & c <= 'z') c = c - 'a' + 'A';
+        putchar(c);
+    }
+}
@@ -0,0 +1,20 @@
+// btest: expect-build-fail
+// Every warning category is triggered here. With `-W all -W error` (see warnings.flags) they fail
+// the build, so btest records the diagnostics instead of running the program.
+unused_param(x) {
+}
+
+main() {
+    extrn printf, getchar;
+    auto a, b;
+    a = 1;
+    if (a = 2) printf("%d\n", a);
+    {
+        auto a;
+        a = 3;
+        printf("%d\n", a);
+    }
+unused_label:
+    return (0);
+    printf("unreachable\n");
+}
@@ -124,6 +124,19 @@ static int jim_get_utf8_char_len(unsigned char ch)
     }
 }
 
+// Returns the length of the UTF-8 sequence at the beginning of str or 0 if it's not a valid one
+// (stray continuation bytes, truncated sequences, etc).
+static size_t jim_get_valid_utf8_char_len(const unsigned char *str, size_t size)
+{
+    if (str[0] < 0xc2 || str[0] > 0xf4) return 0;
+    size_t cl = jim_get_utf8_char_len(str[0]);
+    if (cl > size) return 0;
+    for (size_t i = 1; i < cl; ++i) {
+        if ((str[i] & 0xc0) != 0x80) return 0;
+    }
+    return cl;
+}
+
 void jim_begin(Jim *jim)
 {
     jim->sink_count = 0;
@@ -256,15 +269,16 @@ static void jim_string_sized_no_element(Jim *jim, const char *str, size_t size)
         if (ch == '"' || ch == '\\') {
             jim_write(jim, "\\", 1);
             jim_write(jim, p + i, 1);
-        } else if (ch >= '\b' && ch <= '\r') {
+        } else if (ch >= '\b' && ch <= '\r' && ch != '\v') { // \v is not a valid escape in JSON
             jim_write(jim, "\\", 1);
             jim_write(jim, &specials[ch - '\b'], 1);
         } else if (0x20 <= ch && ch <= 0x7F) { // is printable
         jim_write(jim, p + i, 1);
-    } else if ((cl = jim_get_utf8_char_len(ch)) == 1) {
+    } else if (ch < 0x80 || (cl = jim_get_valid_utf8_char_len((const unsigned char *) p + i, len - i)) == 0) {
+        // Control characters and the bytes that are not a part of a valid UTF-8 sequence
         jim_write(jim, "\\u00", 4);
-        jim_write(jim, &hex_digits[(ch >> 4) % 0xf], 1);
-        jim_write(jim, &hex_digits[ch % 0xf], 1);
+        jim_write(jim, &hex_digits[ch >> 4], 1);
+        jim_write(jim, &hex_digits[ch & 0xf], 1);
     } else {
         jim_write(jim, p + i, cl);
         i += cl - 1;
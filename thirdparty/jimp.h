@@ -95,6 +95,9 @@ bool jimp_array_end(Jimp *jimp);
 /// Prints diagnostic at the current position of the parser.
 void jimp_diagf(Jimp *jimp, const char *fmt, ...);
 
+/// Parses `null`
+bool jimp_null(Jimp *jimp);
+
 bool jimp_is_null_ahead(Jimp *jimp);
 bool jimp_is_bool_ahead(Jimp *jimp);
 bool jimp_is_number_ahead(Jimp *jimp);
@@ -112,6 +115,8 @@ static const char *jimp__token_kind(Jimp_Token token);
 static bool jimp__get_token(Jimp *jimp);
 static void jimp__skip_whitespaces(Jimp *jimp);
 static void jimp__append_to_string(Jimp *jimp, char x);
+static bool jimp__parse_hex4(Jimp *jimp, unsigned long *codepoint);
+static void jimp__append_utf8(Jimp *jimp, unsigned long codepoint);
 
 static void jimp__append_to_string(Jimp *jimp, char x)
 {
@@ -123,6 +128,40 @@ static void jimp__append_to_string(Jimp *jimp, char x)
     jimp->string[jimp->string_count++] = x;
 }
 
+static bool jimp__parse_hex4(Jimp *jimp, unsigned long *codepoint)
+{
+    *codepoint = 0;
+    for (int i = 0; i < 4; ++i) {
+        if (jimp->point >= jimp->end || !isxdigit(*jimp->point)) {
+            jimp->token_start = jimp->point;
+            jimp_diagf(jimp, "ERROR: expected 4 hex digits in the unicode escape sequence\n");
+            return false;
+        }
+        char x = *jimp->point++;
+        *codepoint = *codepoint*16 + (isdigit(x) ? x - '0' : tolower(x) - 'a' + 10);
+    }
+    return true;
+}
+
+static void jimp__append_utf8(Jimp *jimp, unsigned long codepoint)
+{
+    if (codepoint < 0x80) {
+        jimp__append_to_string(jimp, codepoint);
+    } else if (codepoint < 0x800) {
+        jimp__append_to_string(jimp, 0xC0 | (codepoint >> 6));
+        jimp__append_to_string(jimp, 0x80 | (codepoint & 0x3F));
+    } else if (codepoint < 0x10000) {
+        jimp__append_to_string(jimp, 0xE0 | (codepoint >> 12));
+        jimp__append_to_string(jimp, 0x80 | ((codepoint >> 6) & 0x3F));
+        jimp__append_to_string(jimp, 0x80 | (codepoint & 0x3F));
+    } else {
+        jimp__append_to_string(jimp, 0xF0 | (codepoint >> 18));
+        jimp__append_to_string(jimp, 0x80 | ((codepoint >> 12) & 0x3F));
+        jimp__append_to_string(jimp, 0x80 | ((codepoint >> 6) & 0x3F));
+        jimp__append_to_string(jimp, 0x80 | (codepoint & 0x3F));
+    }
+}
+
 static void jimp__skip_whitespaces(Jimp *jimp)
 {
     while (jimp->point < jimp->end && isspace(*jimp->point)) {
@@ -193,8 +232,6 @@ static bool jimp__get_token(Jimp *jimp)
         jimp->point++;
         jimp->string_count = 0;
         while (jimp->point < jimp->end) {
-            // TODO: support all the JSON escape sequences defined in the spec
-            // Yes, including those dumb suroggate pairs. Spec is spec.
             switch (*jimp->point) {
             case '\\': {
                 jimp->point++;
@@ -224,6 +261,38 @@ static bool jimp__get_token(Jimp *jimp)
                     jimp->point++;
                     jimp__append_to_string(jimp, '"');
                     break;
+                case '/':
+                    jimp->point++;
+                    jimp__append_to_string(jimp, '/');
+                    break;
+                case 'b':
+                    jimp->point++;
+                    jimp__append_to_string(jimp, '\b');
+                    break;
+                case 'f':
+                    jimp->point++;
+                    jimp__append_to_string(jimp, '\f');
+                    break;
+                case 'u': {
+                    jimp->point++;
+                    unsigned long codepoint;
+                    if (!jimp__parse_hex4(jimp, &codepoint)) return false;
+                    // Yes, including those dumb suroggate pairs. Spec is spec.
+                    if (0xD800 <= codepoint && codepoint <= 0xDBFF &&
+                        jimp->end - jimp->point >= 6 && jimp->point[0] == '\\' && jimp->point[1] == 'u') {
+                        const char *point = jimp->point;
+                        jimp->point += 2;
+                        unsigned long low;
+                        if (!jimp__parse_hex4(jimp, &low)) return false;
+                        if (0xDC00 <= low && low <= 0xDFFF) {
+                            codepoint = 0x10000 + ((codepoint - 0xD800) << 10) + (low - 0xDC00);
+                        } else {
+                            jimp->point = point;
+                        }
+                    }
+                    jimp__append_utf8(jimp, codepoint);
+                    break;
+                }
                 default:
                     jimp->token_start = jimp->point;
                     jimp_diagf(jimp, "ERROR: invalid escape sequence\n");
@@ -382,6 +451,11 @@ bool jimp_number(Jimp *jimp)
     return jimp__get_and_expect_token(jimp, JIMP_NUMBER);
 }
 
+bool jimp_null(Jimp *jimp)
+{
+    return jimp__get_and_expect_token(jimp, JIMP_NULL);
+}
+
 bool jimp_is_null_ahead(Jimp *jimp)
 {
     const char *point = jimp->point;
@@ -1,4 +1,9 @@
 #include <stdio.h>
+#ifdef _WIN32
+#include <windows.h>
+#else
+#include <unistd.h>
+#endif
 
 // Linux defines `stdin`, `stdout` and `stderr` as both an external symbol and a macro.
 // however Windows defines them as macros only, and these macros expand to non-const
@@ -9,3 +14,16 @@
 FILE *get_stdin() { return stdin; }
 FILE *get_stdout() { return stdout; }
 FILE *get_stderr() { return stderr; }
+
+// sysconf() takes platform specific constants and Windows doesn't have it at all, so the amount
+// of processors is queried here
+int get_cpu_count() {
+#ifdef _WIN32
+    SYSTEM_INFO info;
+    GetSystemInfo(&info);
+    return info.dwNumberOfProcessors;
+#else
+    long count = sysconf(_SC_NPROCESSORS_ONLN);
+    return count > 0 ? count : 1;
+#endif
+}
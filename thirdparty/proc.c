@@ -0,0 +1,229 @@
+// Process management that nob.h doesn't provide: waiting with a timeout and killing.
+// TODO: Consider making this a part of https://github.com/tsoding/nob.h
+#include <stdlib.h>
+#include <string.h>
+#include <errno.h>
+
+#include "nob.h"
+
+#ifndef _WIN32
+#include <time.h>
+#include <signal.h>
+#include <spawn.h>
+#include <sys/wait.h>
+
+extern char **environ;
+
+// The process groups started by proc_start_and_reset() that were not waited on yet. A slot with 0 is free.
+// It's a fixed array rather than a dynamic one because the signal handler below reads it at any moment.
+#define PROC_GROUPS_CAP 1024
+static volatile sig_atomic_t proc_groups[PROC_GROUPS_CAP];
+
+static void proc_groups_add(pid_t pid)
+{
+    for (size_t i = 0; i < PROC_GROUPS_CAP; ++i) {
+        if (proc_groups[i] == 0) {
+            proc_groups[i] = pid;
+            return;
+        }
+    }
+    nob_log(NOB_WARNING, "too many processes to track, process %d will be left running on Ctrl-C", pid);
+}
+
+static void proc_groups_remove(pid_t pid)
+{
+    for (size_t i = 0; i < PROC_GROUPS_CAP; ++i) {
+        if (proc_groups[i] == pid) proc_groups[i] = 0;
+    }
+}
+
+static void proc_kill_groups_and_die(int sig)
+{
+    for (size_t i = 0; i < PROC_GROUPS_CAP; ++i) {
+        if (proc_groups[i] != 0) kill(-proc_groups[i], SIGKILL);
+    }
+    signal(sig, SIG_DFL);
+    raise(sig);
+}
+#endif // _WIN32
+
+// The processes started by proc_start_and_reset() are in their own process groups, so Ctrl-C in the terminal
+// doesn't reach them. After this call SIGINT and SIGTERM kill all of them before killing the current process.
+// On Windows the children share the console of the current process and get Ctrl-C on their own.
+void proc_kill_all_on_signals(void)
+{
+#ifndef _WIN32
+    struct sigaction sa = {0};
+    sa.sa_handler = proc_kill_groups_and_die;
+    sigemptyset(&sa.sa_mask);
+    sigaction(SIGINT, &sa, NULL);
+    sigaction(SIGTERM, &sa, NULL);
+#endif // _WIN32
+}
+
+// Just like nob_cmd_run_async_redirect_and_reset() but on POSIX the process gets its own process group,
+// so proc_kill() kills everything the process has started too (`b -run` starts the program for instance).
+Nob_Proc proc_start_and_reset(Nob_Cmd *cmd, Nob_Cmd_Redirect redirect)
+{
+#ifdef _WIN32
+    return nob_cmd_run_async_redirect_and_reset(cmd, redirect);
+#else
+    if (cmd->count < 1) {
+        nob_log(NOB_ERROR, "Could not run empty command");
+        return NOB_INVALID_PROC;
+    }
+
+    Nob_String_Builder sb = {0};
+    nob_cmd_render(*cmd, &sb);
+    nob_sb_append_null(&sb);
+    nob_log(NOB_INFO, "CMD: %s", sb.items);
+    nob_sb_free(sb);
+
+    posix_spawn_file_actions_t actions;
+    posix_spawn_file_actions_init(&actions);
+    if (redirect.fdin)  posix_spawn_file_actions_adddup2(&actions, *redirect.fdin,  STDIN_FILENO);
+    if (redirect.fdout) posix_spawn_file_actions_adddup2(&actions, *redirect.fdout, STDOUT_FILENO);
+    if (redirect.fderr) posix_spawn_file_actions_adddup2(&actions, *redirect.fderr, STDERR_FILENO);
+
+    posix_spawnattr_t attr;
+    posix_spawnattr_init(&attr);
+    posix_spawnattr_setflags(&attr, POSIX_SPAWN_SETPGROUP);
+    posix_spawnattr_setpgroup(&attr, 0);
+
+    Nob_Cmd argv = {0};
+    nob_da_append_many(&argv, cmd->items, cmd->count);
+    nob_cmd_append(&argv, NULL);
+
+    pid_t pid;
+    int err = posix_spawnp(&pid, argv.items[0], &actions, &attr, (char * const*) argv.items, environ);
+
+    NOB_FREE(argv.items);
+    posix_spawnattr_destroy(&attr);
+    posix_spawn_file_actions_destroy(&actions);
+
+    cmd->count = 0;
+    if (redirect.fdin)  { nob_fd_close(*redirect.fdin);  *redirect.fdin  = NOB_INVALID_FD; }
+    if (redirect.fdout) { nob_fd_close(*redirect.fdout); *redirect.fdout = NOB_INVALID_FD; }
+    if (redirect.fderr) { nob_fd_close(*redirect.fderr); *redirect.fderr = NOB_INVALID_FD; }
+
+    if (err != 0) {
+        nob_log(NOB_ERROR, "Could not spawn child process for %s: %s", cmd->items[0], strerror(err));
+        return NOB_INVALID_PROC;
+    }
+    proc_groups_add(pid);
+    return pid;
+#endif // _WIN32
+}
+
+// Waits for the process for at most timeout_ms milliseconds, UINT64_MAX means for as long as it takes. Returns 1 if the process exited and stores its
+// exit code into exit_code (a process killed by a signal gets 128 plus the number of the signal, just like in
+// shells), 0 if the process could not be waited on and -1 if it is still running. In that case the process is
+// left alone and it's up to the caller to kill it or to keep waiting.
+int proc_wait_timeout(Nob_Proc proc, uint64_t timeout_ms, int *exit_code)
+{
+    if (proc == NOB_INVALID_PROC) return 0;
+
+#ifdef _WIN32
+    DWORD result = WaitForSingleObject(proc, timeout_ms < INFINITE ? (DWORD) timeout_ms : INFINITE);
+    if (result == WAIT_TIMEOUT) return -1;
+    if (result == WAIT_FAILED) {
+        nob_log(NOB_ERROR, "could not wait on child process: %s", nob_win32_error_message(GetLastError()));
+        return 0;
+    }
+
+    DWORD exit_status;
+    if (!GetExitCodeProcess(proc, &exit_status)) {
+        nob_log(NOB_ERROR, "could not get process exit code: %s", nob_win32_error_message(GetLastError()));
+        return 0;
+    }
+    CloseHandle(proc);
+    *exit_code = (int) exit_status;
+    return 1;
+#else
+    // waitpid() can't time out, so the process is polled unless there is no timeout
+    int options = timeout_ms == UINT64_MAX ? 0 : WNOHANG;
+    struct timespec start, now;
+    clock_gettime(CLOCK_MONOTONIC, &start);
+    for (;;) {
+        int wstatus = 0;
+        pid_t pid = waitpid(proc, &wstatus, options);
+        if (pid < 0) {
+            if (errno == EINTR) continue;
+            nob_log(NOB_ERROR, "could not wait on command (pid %d): %s", proc, strerror(errno));
+            return 0;
+        }
+
+        if (pid == proc) {
+            if (WIFEXITED(wstatus)) {
+                proc_groups_remove(proc);
+                *exit_code = WEXITSTATUS(wstatus);
+                return 1;
+            }
+            if (WIFSIGNALED(wstatus)) {
+                proc_groups_remove(proc);
+                nob_log(NOB_ERROR, "command process was terminated by signal %d", WTERMSIG(wstatus));
+                *exit_code = 128 + WTERMSIG(wstatus);
+                return 1;
+            }
+            continue;
+        }
+
+        clock_gettime(CLOCK_MONOTONIC, &now);
+        uint64_t elapsed_ms = (now.tv_sec - start.tv_sec)*1000 + (now.tv_nsec - start.tv_nsec)/1000000;
+        if (elapsed_ms >= timeout_ms) return -1;
+
+        struct timespec pause = { .tv_sec = 0, .tv_nsec = 1000000 };
+        nanosleep(&pause, NULL);
+    }
+#endif // _WIN32
+}
+
+// Waits for whichever of the processes exits first for at most timeout_ms milliseconds. Returns the same as
+// proc_wait_timeout() and stores the index of the process into index when it's 1 or 0.
+int proc_wait_any(Nob_Proc *procs, size_t count, uint64_t timeout_ms, size_t *index, int *exit_code)
+{
+    // Neither waitpid() nor WaitForMultipleObjects() (limited to 64 handles) fit here, so the processes are polled
+    uint64_t elapsed_ms = 0;
+#ifdef _WIN32
+    ULONGLONG start = GetTickCount64();
+#else
+    struct timespec start, now;
+    clock_gettime(CLOCK_MONOTONIC, &start);
+#endif // _WIN32
+    for (;;) {
+        for (size_t i = 0; i < count; ++i) {
+            int result = proc_wait_timeout(procs[i], 0, exit_code);
+            if (result >= 0) {
+                *index = i;
+                return result;
+            }
+        }
+
+        if (elapsed_ms >= timeout_ms) return -1;
+#ifdef _WIN32
+        Sleep(1);
+        elapsed_ms = GetTickCount64() - start;
+#else
+        struct timespec pause = { .tv_sec = 0, .tv_nsec = 1000000 };
+        nanosleep(&pause, NULL);
+        clock_gettime(CLOCK_MONOTONIC, &now);
+        elapsed_ms = (now.tv_sec - start.tv_sec)*1000 + (now.tv_nsec - start.tv_nsec)/1000000;
+#endif // _WIN32
+    }
+}
+
+// Kills the process started by proc_start_and_reset() along with its process group and waits for it
+void proc_kill(Nob_Proc proc)
+{
+    if (proc == NOB_INVALID_PROC) return;
+
+#ifdef _WIN32
+    TerminateProcess(proc, 1);
+    WaitForSingleObject(proc, INFINITE);
+    CloseHandle(proc);
+#else
+    kill(-proc, SIGKILL);
+    while (waitpid(proc, NULL, 0) < 0 && errno == EINTR);
+    proc_groups_remove(proc);
+#endif // _WIN32
+}